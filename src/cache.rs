@@ -0,0 +1,374 @@
+// Incremental build cache for projects, kept in build/cache next to tsl.toml:
+//
+//   programs/<key>/files       "<hash> <path>" for every source file of the program
+//   programs/<key>/units       the program's C files, one per TSL module
+//   programs/<key>/<unit>.c    the generated C of each module
+//   programs/<key>/interfaces  the checked interfaces the program's modules have
+//   programs/<key>/objects     the objects the program was last linked from
+//   interfaces/<key>           the checked interface of a module, see transpiler::interfaces
+//   objects/<hash>.o           compiled C, keyed by the C and the compiler command line
+//
+// When no source file changed, the stored C is reused as is. Otherwise the program is
+// compiled again, but a module whose source and imports didn't change gets its checked
+// interface restored instead of being type checked. The generated C is split per module,
+// and only the modules whose C changed are recompiled before relinking. Interfaces and
+// objects no stored program uses any more are removed after each build.
+use crate::transpiler::c_bindgen::c_bindgen_prelude;
+use crate::transpiler::interfaces::Interface;
+use crate::{CompileOptions, PRELUDE};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+// Hex digest of the parts, with 64 bit FNV-1a so that keys written by one build of the
+// compiler are the same for the next. Each part is prefixed with its length, so moving
+// bytes from one part to the next changes the hash.
+pub fn hash(parts: &[&[u8]]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part.iter()) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+// Identifies a build of entry, anything else that changes the generated C goes in here
+pub fn program_key(entry: &Path, options: &CompileOptions) -> String {
+    hash(&[
+        env!("CARGO_PKG_VERSION").as_bytes(),
+        PRELUDE.as_bytes(),
+        c_bindgen_prelude().as_bytes(),
+        entry.to_string_lossy().as_bytes(),
+        format!("{:?}", options).as_bytes(),
+    ])
+}
+
+// Identifies a module's checked interface. It depends on the module's fingerprint and on
+// the prelude the module sees.
+pub fn interface_key(fingerprint: &str, options: &CompileOptions) -> String {
+    let prelude = if options.prelude { PRELUDE } else { "" };
+    hash(&[
+        env!("CARGO_PKG_VERSION").as_bytes(),
+        prelude.as_bytes(),
+        fingerprint.as_bytes(),
+    ])
+}
+
+fn unit_file(unit: &str) -> String {
+    // Module names start with a letter, so this can't clash with one
+    if unit.is_empty() {
+        "_main.c".to_string()
+    } else {
+        format!("{}.c", unit)
+    }
+}
+
+impl Cache {
+    pub fn open(dir: &Path) -> Result<Cache, String> {
+        for sub in ["programs", "interfaces", "objects"] {
+            std::fs::create_dir_all(dir.join(sub))
+                .map_err(|e| format!("Failed to create {}: {}", dir.join(sub).display(), e))?;
+        }
+        Ok(Cache {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    // The C files of the program, if none of its source files changed since they were stored
+    pub fn load_program(&self, key: &str) -> Option<Vec<(String, String)>> {
+        let dir = self.dir.join("programs").join(key);
+        let files = std::fs::read_to_string(dir.join("files")).ok()?;
+        for line in files.lines() {
+            let (expected, path) = line.split_once(' ')?;
+            let source = std::fs::read(path).ok()?;
+            if hash(&[&source]) != expected {
+                return None;
+            }
+        }
+        let units = std::fs::read_to_string(dir.join("units")).ok()?;
+        units
+            .lines()
+            .map(|unit| {
+                let c_code = std::fs::read_to_string(dir.join(unit_file(unit))).ok()?;
+                Some((unit.to_string(), c_code))
+            })
+            .collect()
+    }
+
+    // Stores the program's C and the keys of its modules' interfaces, replacing whatever
+    // an older build stored under the key
+    pub fn store_program(
+        &self,
+        key: &str,
+        files: &[PathBuf],
+        units: &[(String, String)],
+        interfaces: &[String],
+    ) -> Result<(), String> {
+        let dir = self.dir.join("programs").join(key);
+        let write = |name: &str, contents: &str| {
+            std::fs::write(dir.join(name), contents)
+                .map_err(|e| format!("Failed to write {}: {}", dir.join(name).display(), e))
+        };
+        // Until `files` is written below, the program can't be mistaken for an older build
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        write("interfaces", &lines(interfaces))?;
+        let mut names = String::new();
+        for (unit, c_code) in units {
+            write(&unit_file(unit), c_code)?;
+            names.push_str(unit);
+            names.push('\n');
+        }
+        write("units", &names)?;
+        // Written last, a program is only reused once it has been stored completely
+        let mut hashes = String::new();
+        for file in files {
+            let source = std::fs::read(file)
+                .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+            hashes.push_str(&format!("{} {}\n", hash(&[&source]), file.display()));
+        }
+        write("files", &hashes)
+    }
+
+    pub fn load_interface(&self, key: &str) -> Option<Interface> {
+        let text = std::fs::read_to_string(self.dir.join("interfaces").join(key)).ok()?;
+        Interface::parse(&text).ok()
+    }
+
+    pub fn store_interface(&self, key: &str, interface: &Interface) -> Result<(), String> {
+        let path = self.dir.join("interfaces").join(key);
+        std::fs::write(&path, interface.to_string())
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    // Where the object for a C file compiled with the given command line is kept
+    pub fn object(&self, c_code: &str, command: &[String]) -> PathBuf {
+        let mut parts = vec![c_code.as_bytes()];
+        parts.extend(command.iter().map(|arg| arg.as_bytes()));
+        self.dir.join("objects").join(format!("{}.o", hash(&parts)))
+    }
+
+    // Records the objects the program was linked from, so evict keeps them
+    pub fn store_objects(&self, key: &str, objects: &[PathBuf]) -> Result<(), String> {
+        let path = self.dir.join("programs").join(key).join("objects");
+        let names: Vec<String> = objects
+            .iter()
+            .filter_map(|object| object.file_stem())
+            .map(|stem| stem.to_string_lossy().to_string())
+            .collect();
+        std::fs::write(&path, lines(&names))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    // Removes the programs whose source files are gone, then the interfaces and objects
+    // (with the C they were compiled from) that no stored program uses
+    pub fn evict(&self) -> Result<(), String> {
+        let read_dir = |dir: &Path| {
+            std::fs::read_dir(dir)
+                .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))
+                .map(|entries| entries.filter_map(|entry| entry.ok().map(|entry| entry.path())))
+        };
+        let mut used = HashSet::new();
+        for program in read_dir(&self.dir.join("programs"))? {
+            let files = std::fs::read_to_string(program.join("files")).unwrap_or_default();
+            let gone = files
+                .lines()
+                .filter_map(|line| line.split_once(' '))
+                .any(|(_, path)| !Path::new(path).exists());
+            if gone {
+                let _ = std::fs::remove_dir_all(&program);
+                continue;
+            }
+            for list in ["interfaces", "objects"] {
+                let names = std::fs::read_to_string(program.join(list)).unwrap_or_default();
+                used.extend(names.lines().map(str::to_string));
+            }
+        }
+        for dir in ["interfaces", "objects"] {
+            for file in read_dir(&self.dir.join(dir))? {
+                let stem = file
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string());
+                if !stem.is_some_and(|stem| used.contains(&stem)) {
+                    std::fs::remove_file(&file)
+                        .map_err(|e| format!("Failed to remove {}: {}", file.display(), e))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn lines(names: &[String]) -> String {
+    names.iter().map(|name| format!("{}\n", name)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash, Cache};
+    use crate::transpiler::interfaces::Interface;
+    use crate::transpiler::type_checker::Type;
+    use std::path::{Path, PathBuf};
+
+    // A cache and a source file in a directory of their own, removed when dropped
+    struct TestCache {
+        dir: PathBuf,
+        cache: Cache,
+    }
+
+    impl TestCache {
+        fn new(test: &str) -> TestCache {
+            let dir =
+                std::env::temp_dir().join(format!("tsl-cache-{}-{}", std::process::id(), test));
+            let _ = std::fs::remove_dir_all(&dir);
+            let cache = Cache::open(&dir.join("cache")).unwrap();
+            std::fs::write(dir.join("main.tsl"), "func main() Int |\n    return 0\n|\n").unwrap();
+            TestCache { dir, cache }
+        }
+
+        fn source(&self) -> PathBuf {
+            self.dir.join("main.tsl")
+        }
+
+        fn store(&self, key: &str, units: &[(String, String)]) {
+            self.cache
+                .store_program(key, &[self.source()], units, &[])
+                .unwrap();
+        }
+
+        fn files(&self, sub: &str) -> Vec<String> {
+            let mut names: Vec<String> = std::fs::read_dir(self.dir.join("cache").join(sub))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for TestCache {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn units() -> Vec<(String, String)> {
+        vec![
+            (String::new(), "int main(void) { return 0; }\n".to_string()),
+            (
+                "util".to_string(),
+                "int util__f(void) { return 1; }\n".to_string(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn hashes_dont_change_between_builds() {
+        assert_eq!(hash(&[]), "cbf29ce484222325");
+        assert_eq!(hash(&[b"tsl"]), "22afa0d2898866e9");
+        assert_ne!(hash(&[b"ab", b"c"]), hash(&[b"a", b"bc"]));
+    }
+
+    #[test]
+    fn a_stored_program_is_loaded_while_its_sources_are_unchanged() {
+        let test = TestCache::new("unchanged");
+        assert_eq!(test.cache.load_program("p"), None);
+        test.store("p", &units());
+        assert_eq!(test.cache.load_program("p"), Some(units()));
+        assert_eq!(test.cache.load_program("other"), None);
+    }
+
+    #[test]
+    fn changing_or_removing_a_source_invalidates_the_program() {
+        let test = TestCache::new("changed");
+        test.store("p", &units());
+        std::fs::write(test.source(), "func main() Int |\n    return 1\n|\n").unwrap();
+        assert_eq!(test.cache.load_program("p"), None);
+
+        test.store("p", &units());
+        assert_eq!(test.cache.load_program("p"), Some(units()));
+        std::fs::remove_file(test.source()).unwrap();
+        assert_eq!(test.cache.load_program("p"), None);
+    }
+
+    #[test]
+    fn a_program_stored_again_loses_its_old_units() {
+        let test = TestCache::new("restored");
+        test.store("p", &units());
+        test.store("p", &units()[..1]);
+        assert_eq!(test.cache.load_program("p"), Some(units()[..1].to_vec()));
+        assert_eq!(
+            test.files("programs/p"),
+            ["_main.c", "files", "interfaces", "units"]
+        );
+    }
+
+    #[test]
+    fn a_program_that_wasnt_stored_completely_isnt_loaded() {
+        let test = TestCache::new("partial");
+        test.store("p", &units());
+        let dir = test.dir.join("cache/programs/p");
+        std::fs::remove_file(dir.join("util.c")).unwrap();
+        assert_eq!(test.cache.load_program("p"), None);
+        std::fs::remove_file(dir.join("files")).unwrap();
+        assert_eq!(test.cache.load_program("p"), None);
+    }
+
+    #[test]
+    fn interfaces_are_loaded_as_they_were_stored() {
+        let test = TestCache::new("interfaces");
+        let interface = Interface {
+            symbols: vec![("util.f::x".to_string(), Type::Integer)],
+            immutable: vec!["util.f::x".to_string()],
+            warnings: Vec::new(),
+        };
+        assert_eq!(test.cache.load_interface("i"), None);
+        test.cache.store_interface("i", &interface).unwrap();
+        assert_eq!(test.cache.load_interface("i"), Some(interface));
+    }
+
+    #[test]
+    fn evict_keeps_only_what_stored_programs_use() {
+        let test = TestCache::new("evict");
+        let command = ["cc".to_string()];
+        let object = |c_code: &str| {
+            let object = test.cache.object(c_code, &command);
+            std::fs::write(&object, "").unwrap();
+            std::fs::write(object.with_extension("c"), c_code).unwrap();
+            object
+        };
+        let (old, new) = (object("int a;"), object("int b;"));
+        for key in ["old", "new"] {
+            test.cache
+                .store_interface(key, &Interface::default())
+                .unwrap();
+        }
+        test.cache
+            .store_program("p", &[test.source()], &units(), &["new".to_string()])
+            .unwrap();
+        test.cache.store_objects("p", std::slice::from_ref(&new)).unwrap();
+        test.cache.evict().unwrap();
+
+        assert_eq!(test.files("interfaces"), ["new"]);
+        let stem = new.file_stem().unwrap().to_string_lossy();
+        assert_eq!(
+            test.files("objects"),
+            [format!("{}.c", stem), format!("{}.o", stem)]
+        );
+        assert!(!old.exists());
+
+        // A program whose sources are gone can't be built again, so it goes too
+        std::fs::remove_file(test.source()).unwrap();
+        test.cache.evict().unwrap();
+        assert_eq!(test.files("programs"), Vec::<String>::new());
+        assert_eq!(test.files("objects"), Vec::<String>::new());
+        assert!(!Path::new(&new).exists());
+    }
+}
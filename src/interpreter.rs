@@ -0,0 +1,1203 @@
+// Interpreter - runs a type-checked AST directly, without generating C or needing a C
+// compiler. Every variable lives in a types::Registry, so `&x` can point at it, and the
+// prelude's C functions are native types::Function closures registered next to them.
+// Ints are 32 bits and wrap, like the int the C backend emits.
+
+use crate::parser::{impl_method_name, method_name, AstNode};
+use crate::transpiler::ir::global_initialiser;
+use crate::types::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+// Deep enough for real recursion, shallow enough to report runaway recursion as an error
+const MAX_DEPTH: usize = 10_000;
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+enum Flow {
+    Next,
+    Return(Box<dyn Value>),
+}
+
+// A function value. A lambda holds its own copies of what it captures, made when it
+// was evaluated, and each call starts from fresh copies of those like the C backend's.
+#[derive(Debug, Clone)]
+enum Closure {
+    Function(String),
+    Lambda {
+        lambda: Rc<AstNode>,
+        captures: Vec<(String, Id)>,
+    },
+}
+
+impl Value for Closure {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+    fn typename(&self) -> TypeName {
+        TypeName::from("Func")
+    }
+}
+
+// A tuple's elements, which are copied along with it
+#[derive(Debug)]
+struct Tuple {
+    elements: Vec<Box<dyn Value>>,
+}
+
+impl Value for Tuple {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+    fn typename(&self) -> TypeName {
+        TypeName::from("Tuple")
+    }
+}
+
+// `Some(value)`, `None`, `Ok(value)` or `Err(error)`, Void variants hold TslNull
+#[derive(Debug)]
+struct Variant {
+    name: String,
+    value: Option<Box<dyn Value>>,
+}
+
+impl Value for Variant {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+    fn typename(&self) -> TypeName {
+        match self.name.as_str() {
+            "Some" | "None" => TypeName::from("Option"),
+            _ => TypeName::from("Result"),
+        }
+    }
+}
+
+pub struct Interpreter {
+    registry: Registry,
+    functions: HashMap<String, Rc<AstNode>>,
+    natives: HashMap<String, Id>,
+    // The ClassBase of each class, its methods are the names of TSL functions
+    classes: HashMap<String, Id>,
+    // The function of each (type as the impl names it, method) some impl provides
+    impls: HashMap<(String, String), String>,
+    globals: HashMap<String, Id>,
+    // The variables of each call in progress, innermost last
+    frames: Vec<HashMap<String, Id>>,
+    // What a `?` returns from the innermost call, on its way there as an Err
+    returning: Option<Box<dyn Value>>,
+}
+
+// Runs the program's main function and returns its exit status
+pub fn interpret(ast: &AstNode) -> Result<i32, String> {
+    with_stack(|| Interpreter::new(ast).run(ast))?
+}
+
+// Runs f on a thread with room for MAX_DEPTH calls. Each TSL call is a few Rust calls
+// deep, so the default stack runs out long before that.
+pub fn with_stack<T: Send>(f: impl FnOnce() -> T + Send) -> Result<T, String> {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .map_err(|e| format!("Failed to start the interpreter: {}", e))?
+            .join()
+            .map_err(|_| "The interpreter crashed".to_string())
+    })
+}
+
+impl Interpreter {
+    pub fn new(ast: &AstNode) -> Self {
+        let mut interpreter = Interpreter {
+            registry: Registry::new(),
+            functions: HashMap::new(),
+            natives: HashMap::new(),
+            classes: HashMap::new(),
+            impls: HashMap::new(),
+            globals: HashMap::new(),
+            frames: Vec::new(),
+            returning: None,
+        };
+        interpreter.collect_functions(ast);
+        interpreter.register_natives();
+        interpreter
+    }
+
+    pub fn run(&mut self, ast: &AstNode) -> Result<i32, String> {
+        self.register_classes(ast)
+            .map_err(|e| format!("Runtime error: {}", e))?;
+        for statement in ast.get_statements() {
+            if let AstNode::Assignment {
+                variable,
+                tp,
+                value,
+                ..
+            } = &statement
+            {
+                global_initialiser(variable, value).map_err(|e| format!("Runtime error: {}", e))?;
+                let value = self.initial_value(tp, value)?;
+                let id = self.registry.register_boxed(value);
+                self.globals.insert(variable.clone(), id);
+            }
+        }
+        let result = self.call("main", Vec::new());
+        let _ = std::io::stdout().flush();
+        match result {
+            Ok(status) => Ok(status
+                .as_ref()
+                .as_any()
+                .downcast_ref::<TslInt>()
+                .map(|status| status.value as i32)
+                .unwrap_or(0)),
+            Err(e) => Err(format!("Runtime error: {}", e)),
+        }
+    }
+
+    // Runs one top-level statement of ast, keeping globals and functions from earlier
+    // ones. Returns the value of an expression statement.
+    pub fn execute_top_level(
+        &mut self,
+        ast: &AstNode,
+        statement: &AstNode,
+    ) -> Result<Option<Box<dyn Value>>, String> {
+        self.collect_functions(ast);
+        self.register_classes(ast)
+            .map_err(|e| format!("Runtime error: {}", e))?;
+        let result = match statement {
+            AstNode::Return { .. } => Err("'return' outside of a function".to_string()),
+            AstNode::Block { .. }
+            | AstNode::Assignment { .. }
+            | AstNode::If { .. }
+            | AstNode::While { .. }
+            | AstNode::Function { .. }
+            | AstNode::Struct { .. }
+            | AstNode::Class { .. }
+            | AstNode::Interface { .. }
+            | AstNode::Impl { .. }
+            | AstNode::Comment { .. }
+            | AstNode::Import { .. }
+            | AstNode::Eof => self.execute(statement).map(|_| None),
+            expression => self.evaluate(expression).map(Some),
+        };
+        let _ = std::io::stdout().flush();
+        // A failed call leaves its frames behind
+        while let Some(frame) = self.frames.pop() {
+            for (_, id) in frame {
+                self.registry.remove_variable(id);
+            }
+        }
+        result.map_err(|e| format!("Runtime error: {}", e))
+    }
+
+    // Nested functions are collected too, they can be called from anywhere after resolution
+    fn collect_functions(&mut self, node: &AstNode) {
+        match node {
+            AstNode::Function { name, body, .. } => {
+                self.functions.insert(name.clone(), Rc::new(node.clone()));
+                self.collect_functions(body);
+            }
+            AstNode::Block { statements } => {
+                for statement in statements {
+                    self.collect_functions(statement);
+                }
+            }
+            AstNode::Class { methods, .. } => {
+                for method in methods {
+                    self.collect_functions(method);
+                }
+            }
+            AstNode::Impl {
+                target, methods, ..
+            } => {
+                for method in methods {
+                    if let AstNode::Function { name, .. } = method {
+                        let key = (target.clone(), impl_method_name(name).to_string());
+                        self.impls.insert(key, name.clone());
+                    }
+                    self.collect_functions(method);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Registers a ClassBase for each class not registered yet, parents first. A struct
+    // is registered as a class with no parent and no methods
+    fn register_classes(&mut self, ast: &AstNode) -> Result<(), String> {
+        for statement in ast.get_statements() {
+            if let AstNode::Struct { name, fields } = statement
+                && !self.classes.contains_key(&name)
+            {
+                let base = ClassBase::new(
+                    name.as_str(),
+                    fields.iter().map(|(_, field)| field.clone()).collect(),
+                );
+                let id = self.registry.register_variable(base);
+                self.classes.insert(name, id);
+            }
+        }
+        let mut pending: Vec<AstNode> = ast
+            .get_statements()
+            .into_iter()
+            .filter(|statement| match statement {
+                AstNode::Class { name, .. } => !self.classes.contains_key(name),
+                _ => false,
+            })
+            .collect();
+        while !pending.is_empty() {
+            let ready = pending.iter().position(|class| match class {
+                AstNode::Class {
+                    parent: Some(parent),
+                    ..
+                } => self.classes.contains_key(parent),
+                _ => true,
+            });
+            let Some(AstNode::Class {
+                name,
+                parent,
+                fields,
+                methods,
+            }) = ready.map(|index| pending.remove(index))
+            else {
+                return Err("A class inherits from an undefined class".to_string());
+            };
+            let mut base = ClassBase::new(
+                name.as_str(),
+                fields.iter().map(|(_, field)| field.clone()).collect(),
+            );
+            if let Some(parent) = parent {
+                base.set_parent(self.classes[&parent].clone());
+            }
+            for method in &methods {
+                if let AstNode::Function { name: function, .. } = method {
+                    let id = self.registry.register_variable(TslString::new(function));
+                    base.add_method(method_name(&name, function), id);
+                }
+            }
+            let id = self.registry.register_variable(base);
+            self.classes.insert(name, id);
+        }
+        Ok(())
+    }
+
+    // The C functions prelude.c defines for prelude.tsl
+    fn register_natives(&mut self) {
+        // scanf("%s") reads a word at a time, so words left over from a line are kept
+        let words: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+        let natives: Vec<(&str, Function)> = vec![
+            (
+                "log",
+                native(|arguments| {
+                    print!("{}", c_string(arguments[0].as_ref()).unwrap_or_default());
+                }),
+            ),
+            (
+                "log_int",
+                native(|arguments| {
+                    print!("{}", int(arguments[0].as_ref()).unwrap_or_default());
+                }),
+            ),
+            (
+                "log_char",
+                native(|arguments| {
+                    if let Some(c) = arguments[0].as_ref().as_any().downcast_ref::<TslChar>() {
+                        let _ = std::io::stdout().write_all(&[c.value]);
+                    }
+                }),
+            ),
+            (
+                "new_buffer",
+                Function {
+                    function: Box::new(|arguments| {
+                        let size = int(arguments[0].as_ref()).unwrap_or(0).max(0);
+                        Box::new(TslBuffer {
+                            bytes: Rc::new(RefCell::new(vec![0; size as usize])),
+                            offset: 0,
+                        })
+                    }),
+                },
+            ),
+            (
+                "input",
+                native(move |arguments| {
+                    let _ = std::io::stdout().flush();
+                    let mut words = words.borrow_mut();
+                    while words.is_empty() {
+                        let mut line = String::new();
+                        match std::io::stdin().lock().read_line(&mut line) {
+                            Ok(0) | Err(_) => return,
+                            Ok(_) => words.extend(line.split_whitespace().rev().map(String::from)),
+                        }
+                    }
+                    let word = words.pop().unwrap_or_default();
+                    if let Some(buffer) = arguments[0].as_ref().as_any().downcast_ref::<TslBuffer>()
+                    {
+                        let mut bytes = buffer.bytes.borrow_mut();
+                        let end = buffer.offset + word.len() + 1;
+                        // C would overflow the buffer, here it grows instead
+                        if bytes.len() < end {
+                            bytes.resize(end, 0);
+                        }
+                        bytes[buffer.offset..end - 1].copy_from_slice(word.as_bytes());
+                        bytes[end - 1] = 0;
+                    }
+                }),
+            ),
+        ];
+        for (name, function) in natives {
+            let id = self.registry.register_variable(function);
+            self.natives.insert(name.to_string(), id);
+        }
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        arguments: Vec<Box<dyn Value>>,
+    ) -> Result<Box<dyn Value>, String> {
+        if let Some(value) = cast(name, &arguments)? {
+            return Ok(value);
+        }
+        if let Some(id) = self.natives.get(name) {
+            let native = self
+                .registry
+                .get_variable(id)
+                .and_then(|(_, native)| native.as_ref().as_any().downcast_ref::<Function>())
+                .ok_or_else(|| format!("Native function '{}' is missing", name))?;
+            return Ok((native.function)(arguments));
+        }
+        let function = self
+            .functions
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("The interpreter has no function '{}'", name))?;
+        let AstNode::Function {
+            arguments: parameters,
+            body,
+            ..
+        } = function.as_ref()
+        else {
+            unreachable!()
+        };
+        self.enter(name, HashMap::new(), parameters, arguments, body)
+    }
+
+    // Calls a function value
+    fn call_value(
+        &mut self,
+        value: &dyn Value,
+        arguments: Vec<Box<dyn Value>>,
+    ) -> Result<Box<dyn Value>, String> {
+        let closure = value
+            .as_any()
+            .downcast_ref::<Closure>()
+            .cloned()
+            .ok_or_else(|| format!("Can't call a {}", value.typename().as_str()))?;
+        let (lambda, captures) = match closure {
+            Closure::Function(name) => return self.call(&name, arguments),
+            Closure::Lambda { lambda, captures } => (lambda, captures),
+        };
+        let AstNode::Lambda {
+            name,
+            arguments: parameters,
+            body,
+            ..
+        } = lambda.as_ref()
+        else {
+            unreachable!()
+        };
+        let mut frame = HashMap::new();
+        for (inner, id) in captures {
+            let value = self.copy_variable(&id, &inner)?;
+            frame.insert(inner, self.registry.register_boxed(value));
+        }
+        self.enter(name, frame, parameters, arguments, body)
+    }
+
+    // Runs the body of function in a new frame holding frame's variables and the arguments
+    fn enter(
+        &mut self,
+        function: &str,
+        mut frame: HashMap<String, Id>,
+        parameters: &[(String, String)],
+        arguments: Vec<Box<dyn Value>>,
+        body: &AstNode,
+    ) -> Result<Box<dyn Value>, String> {
+        if self.frames.len() >= MAX_DEPTH {
+            return Err(format!("Stack overflow calling '{}'", function));
+        }
+        for ((_, parameter), value) in parameters.iter().zip(arguments) {
+            let id = self.registry.register_boxed(value);
+            frame.insert(format!("{}::{}", function, parameter), id);
+        }
+        self.frames.push(frame);
+        let result = self.execute(body);
+        // Pointers to these variables are dangling from now on, like in C
+        for (_, id) in self.frames.pop().unwrap_or_default() {
+            self.registry.remove_variable(id);
+        }
+        if let Some(value) = self.returning.take() {
+            return Ok(value);
+        }
+        match result? {
+            Flow::Return(value) => Ok(value),
+            Flow::Next => Ok(Box::new(TslNull)),
+        }
+    }
+
+    fn execute(&mut self, node: &AstNode) -> Result<Flow, String> {
+        match node {
+            AstNode::Block { statements } => {
+                for statement in statements {
+                    if let Flow::Return(value) = self.execute(statement)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            AstNode::Assignment {
+                variable,
+                tp,
+                value,
+                ..
+            } => {
+                let value = self.initial_value(tp, value)?;
+                self.assign(variable, value);
+            }
+            AstNode::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                if self.condition(condition)? {
+                    return self.execute(consequence);
+                } else if let Some(alternative) = alternative {
+                    return self.execute(alternative);
+                }
+            }
+            AstNode::While { condition, body } => {
+                while self.condition(condition)? {
+                    if let Flow::Return(value) = self.execute(body)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            AstNode::Return { value } => return Ok(Flow::Return(self.evaluate(value)?)),
+            // Definitions were collected up front
+            AstNode::Function { .. }
+            | AstNode::Struct { .. }
+            | AstNode::Class { .. }
+            | AstNode::Interface { .. }
+            | AstNode::Impl { .. }
+            | AstNode::Comment { .. }
+            | AstNode::Import { .. }
+            | AstNode::Eof => {}
+            expression => {
+                self.evaluate(expression)?;
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn evaluate(&mut self, node: &AstNode) -> Result<Box<dyn Value>, String> {
+        match node {
+            AstNode::Number { value } => Ok(Box::new(TslInt { value: *value })),
+            AstNode::Bool { value } => Ok(Box::new(TslBool {
+                value: value == "true",
+            })),
+            AstNode::Char { value } => {
+                let bytes = unescape(value)?;
+                match bytes.as_slice() {
+                    [c] => Ok(Box::new(TslChar { value: *c })),
+                    _ => Err(format!("Invalid char literal '{}'", value)),
+                }
+            }
+            AstNode::String { value } => Ok(Box::new(TslString {
+                value: String::from_utf8_lossy(&unescape(value)?).to_string(),
+            })),
+            AstNode::Null => Ok(Box::new(TslNull)),
+            AstNode::Uninit { tp } => Ok(default_value(tp)),
+            AstNode::Identifier { value } | AstNode::Variable { value } => {
+                let id = match self.lookup(value) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        // A function named as a value
+                        let function = value.rsplit("::").next().unwrap_or(value);
+                        if !self.functions.contains_key(function)
+                            && !self.natives.contains_key(function)
+                        {
+                            return Err(e);
+                        }
+                        return Ok(Box::new(Closure::Function(function.to_string())));
+                    }
+                };
+                self.copy_variable(&id, value)
+            }
+            AstNode::Lambda { captures, .. } => {
+                let mut copies = Vec::new();
+                for (outer, inner) in captures {
+                    let value = self.copy_variable(&self.lookup(outer)?, outer)?;
+                    copies.push((inner.clone(), self.registry.register_boxed(value)));
+                }
+                Ok(Box::new(Closure::Lambda {
+                    lambda: Rc::new(node.clone()),
+                    captures: copies,
+                }))
+            }
+            AstNode::Call { callee, arguments } => {
+                let callee = self.evaluate(callee)?;
+                let mut values = Vec::new();
+                for argument in arguments {
+                    values.push(self.evaluate(argument)?);
+                }
+                self.call_value(callee.as_ref(), values)
+            }
+            AstNode::BinaryOperation {
+                operator,
+                left,
+                right,
+            } => {
+                let left = self.evaluate(left)?;
+                // && and || only evaluate the right side when they need it
+                if let Some(TslBool { value }) = left.as_ref().as_any().downcast_ref::<TslBool>() {
+                    match operator.value.as_str() {
+                        "&&" if !value => return Ok(Box::new(TslBool { value: false })),
+                        "||" if *value => return Ok(Box::new(TslBool { value: true })),
+                        _ => {}
+                    }
+                }
+                let right = self.evaluate(right)?;
+                binary(&operator.value, left.as_ref(), right.as_ref())
+            }
+            AstNode::FunctionCall { name, arguments } => {
+                let mut values = Vec::new();
+                for argument in arguments {
+                    values.push(self.evaluate(argument)?);
+                }
+                // A global holding a function value
+                if !self.functions.contains_key(name)
+                    && !self.natives.contains_key(name)
+                    && let Ok(id) = self.lookup(name)
+                {
+                    let callee = self.copy_variable(&id, name)?;
+                    return self.call_value(callee.as_ref(), values);
+                }
+                self.call(name, values)
+            }
+            AstNode::Pointer { value } => match value.as_ref() {
+                AstNode::Identifier { value } | AstNode::Variable { value } => {
+                    Ok(Box::new(TslPointer {
+                        target: self.lookup(value)?,
+                    }))
+                }
+                _ => Err("Can only take the address of a variable".to_string()),
+            },
+            AstNode::Dereference { value } => {
+                let pointer = self.evaluate(value)?;
+                self.dereference(pointer.as_ref())
+            }
+            AstNode::New { class, arguments } => {
+                let base = self.class(class)?;
+                let mut values = Vec::new();
+                for argument in arguments {
+                    values.push(self.evaluate(argument)?);
+                }
+                let instance = ClassInstance::new(base, values, &mut self.registry)?;
+                Ok(Box::new(TslPointer {
+                    target: self.registry.register_variable(instance),
+                }))
+            }
+            AstNode::Tuple { elements } => {
+                let mut values = Vec::new();
+                for element in elements {
+                    values.push(self.evaluate(element)?);
+                }
+                Ok(Box::new(Tuple { elements: values }))
+            }
+            // `t.0`, class fields can't be named with a number
+            AstNode::FieldAccess { object, field } if field.parse::<usize>().is_ok() => {
+                let tuple = self.evaluate(object)?;
+                let element = tuple
+                    .as_ref()
+                    .as_any()
+                    .downcast_ref::<Tuple>()
+                    .and_then(|tuple| tuple.elements.get(field.parse::<usize>().ok()?))
+                    .ok_or_else(|| {
+                        format!("{} has no element {}", tuple.typename().as_str(), field)
+                    })?;
+                copy(element.as_ref())
+            }
+            AstNode::FieldAccess { object, field } => {
+                let id = self.field(object, field)?;
+                let (_, value) = self
+                    .registry
+                    .get_variable(&id)
+                    .ok_or_else(|| format!("Field '{}' is no longer alive", field))?;
+                copy(value.as_ref())
+            }
+            AstNode::FieldAssignment {
+                object,
+                field,
+                value,
+            } => {
+                let id = self.field(object, field)?;
+                let value = self.evaluate(value)?;
+                self.registry.set_boxed(&id, value);
+                Ok(Box::new(TslNull))
+            }
+            AstNode::Variant { variant, value } => Ok(Box::new(Variant {
+                name: variant.clone(),
+                value: match value {
+                    Some(value) => Some(self.evaluate(value)?),
+                    None => None,
+                },
+            })),
+            AstNode::Try { value } => {
+                let value = self.evaluate(value)?;
+                let Some(variant) = value.as_ref().as_any().downcast_ref::<Variant>() else {
+                    return Err(format!("'?' on a {}", value.typename().as_str()));
+                };
+                match (variant.name.as_str(), &variant.value) {
+                    ("Some" | "Ok", Some(held)) => copy(held.as_ref()),
+                    _ => {
+                        self.returning = Some(copy(variant)?);
+                        Err(format!("'?' returned {}", display(variant)))
+                    }
+                }
+            }
+            AstNode::MethodCall {
+                object,
+                method,
+                arguments,
+            } => {
+                let receiver = self.evaluate(object)?;
+                if let Some(variant) = receiver.as_ref().as_any().downcast_ref::<Variant>() {
+                    let mut values = Vec::new();
+                    for argument in arguments {
+                        values.push(self.evaluate(argument)?);
+                    }
+                    return variant_method(variant, method, values);
+                }
+                let function = self.receiver_method(receiver.as_ref(), method)?;
+                let mut values = vec![receiver];
+                for argument in arguments {
+                    values.push(self.evaluate(argument)?);
+                }
+                self.call(&function, values)
+            }
+            AstNode::SuperCall {
+                class,
+                method,
+                arguments,
+            } => {
+                let parent = self
+                    .registry
+                    .get_class(&self.class(class)?)?
+                    .parent()
+                    .cloned()
+                    .ok_or_else(|| format!("{} has no parent class for 'super'", class))?;
+                let function = self.method_function(&parent, method)?;
+                let mut values = Vec::new();
+                for argument in arguments {
+                    values.push(self.evaluate(argument)?);
+                }
+                self.call(&function, values)
+            }
+            other => Err(format!("Can't evaluate {:?}", other)),
+        }
+    }
+
+    fn initial_value(
+        &mut self,
+        tp: &Option<String>,
+        value: &AstNode,
+    ) -> Result<Box<dyn Value>, String> {
+        match (value, tp) {
+            (AstNode::Uninit { .. }, Some(tp)) => Ok(default_value(tp)),
+            _ => self.evaluate(value),
+        }
+    }
+
+    fn condition(&mut self, condition: &AstNode) -> Result<bool, String> {
+        let value = self.evaluate(condition)?;
+        let value = value.as_ref();
+        value
+            .as_any()
+            .downcast_ref::<TslBool>()
+            .map(|condition| condition.value)
+            .ok_or_else(|| format!("Condition is a {}, not a Bool", value.typename().as_str()))
+    }
+
+    fn copy_variable(&self, id: &Id, name: &str) -> Result<Box<dyn Value>, String> {
+        let (_, value) = self
+            .registry
+            .get_variable(id)
+            .ok_or_else(|| format!("'{}' is no longer alive", name))?;
+        copy(value.as_ref())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Id, String> {
+        self.frames
+            .last()
+            .and_then(|frame| frame.get(name))
+            .or_else(|| self.globals.get(name))
+            .cloned()
+            .ok_or_else(|| format!("Undefined variable '{}'", name))
+    }
+
+    fn assign(&mut self, name: &str, value: Box<dyn Value>) {
+        match self.lookup(name) {
+            Ok(id) => self.registry.set_boxed(&id, value),
+            Err(_) => {
+                let id = self.registry.register_boxed(value);
+                match self.frames.last_mut() {
+                    Some(frame) => frame.insert(name.to_string(), id),
+                    None => self.globals.insert(name.to_string(), id),
+                };
+            }
+        }
+    }
+
+    fn class(&self, name: &str) -> Result<Id, String> {
+        self.classes
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("The interpreter has no class '{}'", name))
+    }
+
+    // The instance a class pointer points at
+    fn instance(&self, pointer: &dyn Value) -> Result<Id, String> {
+        let pointer = pointer
+            .as_any()
+            .downcast_ref::<TslPointer>()
+            .ok_or_else(|| format!("A {} is not an object", pointer.typename().as_str()))?;
+        self.registry.get_instance(&pointer.target)?;
+        Ok(pointer.target.clone())
+    }
+
+    // The variable holding the field of the object
+    fn field(&mut self, object: &AstNode, field: &str) -> Result<Id, String> {
+        let object = self.evaluate(object)?;
+        let instance = self.instance(object.as_ref())?;
+        self.registry
+            .get_instance(&instance)?
+            .field(field)
+            .cloned()
+            .ok_or_else(|| format!("No field named '{}'", field))
+    }
+
+    // The TSL function implementing the method for instances of class
+    fn method_function(&self, class: &Id, method: &str) -> Result<String, String> {
+        let id = self.registry.lookup_method(class, method)?;
+        self.registry
+            .get_variable(&id)
+            .and_then(|(_, value)| value.as_ref().as_any().downcast_ref::<TslString>())
+            .map(|function| function.value.clone())
+            .ok_or_else(|| format!("Method '{}' is not a TSL function", method))
+    }
+
+    // The function a method call on receiver runs. A dyn is just the value it holds, so
+    // this is the same for static and dynamic dispatch: the class's own method, or else
+    // one an impl for its type (or one of its classes) provides.
+    fn receiver_method(&self, receiver: &dyn Value, method: &str) -> Result<String, String> {
+        let types = match self.instance(receiver) {
+            Ok(instance) => {
+                let class = self.registry.get_instance(&instance)?.class_base().clone();
+                if let Ok(function) = self.method_function(&class, method) {
+                    return Ok(function);
+                }
+                let mut names = Vec::new();
+                let mut next = Some(class);
+                while let Some(id) = next {
+                    let base = self.registry.get_class(&id)?;
+                    names.push(base.name().as_str().to_string());
+                    next = base.parent().cloned();
+                }
+                names
+            }
+            Err(_) => vec![match receiver.typename().as_str() {
+                "Integer" => "Int".to_string(),
+                "Buffer" => "String".to_string(),
+                name => name.to_string(),
+            }],
+        };
+        types
+            .iter()
+            .find_map(|tp| self.impls.get(&(tp.clone(), method.to_string())))
+            .cloned()
+            .ok_or_else(|| format!("{} has no method '{}'", types[0], method))
+    }
+
+    fn dereference(&self, pointer: &dyn Value) -> Result<Box<dyn Value>, String> {
+        let any = pointer.as_any();
+        if let Some(pointer) = any.downcast_ref::<TslPointer>() {
+            let (_, value) = self
+                .registry
+                .get_variable(&pointer.target)
+                .ok_or("Dereferenced a pointer to a variable that is no longer alive")?;
+            return copy(value.as_ref());
+        }
+        let value = if let Some(string) = any.downcast_ref::<TslString>() {
+            string.value.bytes().next().unwrap_or(0)
+        } else if let Some(buffer) = any.downcast_ref::<TslBuffer>() {
+            *buffer
+                .bytes
+                .borrow()
+                .get(buffer.offset)
+                .ok_or("Read past the end of a buffer")?
+        } else {
+            return Err(format!(
+                "Can't dereference a {}",
+                pointer.typename().as_str()
+            ));
+        };
+        Ok(Box::new(TslChar { value }))
+    }
+}
+
+fn native(function: impl Fn(Vec<Box<dyn Value>>) + 'static) -> Function {
+    Function {
+        function: Box::new(move |arguments| {
+            function(arguments);
+            Box::new(TslNull)
+        }),
+    }
+}
+
+// The builtin casts the C backend turns into C casts
+fn cast(name: &str, arguments: &[Box<dyn Value>]) -> Result<Option<Box<dyn Value>>, String> {
+    let [value] = arguments else {
+        return Ok(None);
+    };
+    let value = value.as_ref();
+    let any = value.as_any();
+    let value: Box<dyn Value> = match name {
+        "asInt" | "boolToInt" => {
+            if let Some(c) = any.downcast_ref::<TslChar>() {
+                Box::new(TslInt {
+                    value: c.value as i8 as i128,
+                })
+            } else if let Some(b) = any.downcast_ref::<TslBool>() {
+                Box::new(TslInt {
+                    value: b.value as i128,
+                })
+            } else {
+                copy(value)?
+            }
+        }
+        "asChar" => match any.downcast_ref::<TslInt>() {
+            Some(i) => Box::new(TslChar {
+                value: i.value as u8,
+            }),
+            None => copy(value)?,
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+fn binary(operator: &str, left: &dyn Value, right: &dyn Value) -> Result<Box<dyn Value>, String> {
+    let (l, r) = (left.as_any(), right.as_any());
+    if let (Some(a), Some(b)) = (l.downcast_ref::<TslInt>(), r.downcast_ref::<TslInt>()) {
+        let (a, b) = (a.value as i32, b.value as i32);
+        let int = |value: Option<i32>| -> Result<Box<dyn Value>, String> {
+            let value = value.ok_or("Division by zero")?;
+            Ok(Box::new(TslInt {
+                value: value as i128,
+            }))
+        };
+        let boolean =
+            |value: bool| -> Result<Box<dyn Value>, String> { Ok(Box::new(TslBool { value })) };
+        return match operator {
+            "+" => int(Some(a.wrapping_add(b))),
+            "-" => int(Some(a.wrapping_sub(b))),
+            "*" => int(Some(a.wrapping_mul(b))),
+            "/" => int(a.checked_div(b)),
+            "%" => int(a.checked_rem(b)),
+            "==" => boolean(a == b),
+            "!=" => boolean(a != b),
+            "<" => boolean(a < b),
+            ">" => boolean(a > b),
+            "<=" => boolean(a <= b),
+            ">=" => boolean(a >= b),
+            _ => Err(format!("Unsupported operator {} on Int", operator)),
+        };
+    }
+    if let (Some(a), Some(b)) = (l.downcast_ref::<TslChar>(), r.downcast_ref::<TslChar>()) {
+        let value = match operator {
+            "==" => a.value == b.value,
+            "!=" => a.value != b.value,
+            "<" => a.value < b.value,
+            ">" => a.value > b.value,
+            "<=" => a.value <= b.value,
+            ">=" => a.value >= b.value,
+            _ => return Err(format!("Unsupported operator {} on Char", operator)),
+        };
+        return Ok(Box::new(TslBool { value }));
+    }
+    if let (Some(a), Some(b)) = (l.downcast_ref::<TslBool>(), r.downcast_ref::<TslBool>()) {
+        let value = match operator {
+            "==" => a.value == b.value,
+            "!=" => a.value != b.value,
+            "&&" => a.value && b.value,
+            "||" => a.value || b.value,
+            _ => return Err(format!("Unsupported operator {} on Bool", operator)),
+        };
+        return Ok(Box::new(TslBool { value }));
+    }
+    // Pointer arithmetic on strings and buffers
+    if let (Some(offset), "+") = (r.downcast_ref::<TslInt>(), operator) {
+        let offset = usize::try_from(offset.value).map_err(|_| "Negative pointer offset")?;
+        if let Some(string) = l.downcast_ref::<TslString>() {
+            let suffix = string
+                .value
+                .as_bytes()
+                .get(offset..)
+                .ok_or("Pointer moved past the end of a string")?;
+            return Ok(Box::new(TslString {
+                value: String::from_utf8_lossy(suffix).to_string(),
+            }));
+        }
+        if let Some(buffer) = l.downcast_ref::<TslBuffer>() {
+            return Ok(Box::new(TslBuffer {
+                bytes: buffer.bytes.clone(),
+                offset: buffer.offset + offset,
+            }));
+        }
+        if l.downcast_ref::<TslPointer>().is_some() && offset == 0 {
+            return copy(left);
+        }
+    }
+    Err(format!(
+        "Unsupported operator {} on {} and {}",
+        operator,
+        left.typename().as_str(),
+        right.typename().as_str()
+    ))
+}
+
+// Values are copied on every read, like C values
+fn copy(value: &dyn Value) -> Result<Box<dyn Value>, String> {
+    let any = value.as_any();
+    if let Some(v) = any.downcast_ref::<TslInt>() {
+        Ok(Box::new(v.clone()))
+    } else if let Some(v) = any.downcast_ref::<TslBool>() {
+        Ok(Box::new(*v))
+    } else if let Some(v) = any.downcast_ref::<TslChar>() {
+        Ok(Box::new(*v))
+    } else if let Some(v) = any.downcast_ref::<TslString>() {
+        Ok(Box::new(v.clone()))
+    } else if let Some(v) = any.downcast_ref::<TslPointer>() {
+        Ok(Box::new(v.clone()))
+    } else if let Some(v) = any.downcast_ref::<TslBuffer>() {
+        Ok(Box::new(v.clone()))
+    } else if let Some(v) = any.downcast_ref::<Closure>() {
+        Ok(Box::new(v.clone()))
+    } else if let Some(v) = any.downcast_ref::<Tuple>() {
+        let elements = v
+            .elements
+            .iter()
+            .map(|element| copy(element.as_ref()))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Box::new(Tuple { elements }))
+    } else if let Some(v) = any.downcast_ref::<Variant>() {
+        Ok(Box::new(Variant {
+            name: v.name.clone(),
+            value: v
+                .value
+                .as_ref()
+                .map(|value| copy(value.as_ref()))
+                .transpose()?,
+        }))
+    } else if any.downcast_ref::<TslNull>().is_some() {
+        Ok(Box::new(TslNull))
+    } else {
+        Err(format!("Can't copy a {}", value.typename().as_str()))
+    }
+}
+
+// The methods Option and Result have built in, unwrap stops the program like in C
+fn variant_method(
+    variant: &Variant,
+    method: &str,
+    mut arguments: Vec<Box<dyn Value>>,
+) -> Result<Box<dyn Value>, String> {
+    let holding = matches!(variant.name.as_str(), "Some" | "Ok");
+    let held = || match &variant.value {
+        Some(value) => copy(value.as_ref()),
+        None => Ok(Box::new(TslNull) as Box<dyn Value>),
+    };
+    match method {
+        "is_some" | "is_ok" => Ok(Box::new(TslBool { value: holding })),
+        "is_none" | "is_err" => Ok(Box::new(TslBool { value: !holding })),
+        "unwrap" if holding => held(),
+        "unwrap" if variant.name == "None" => Err("Called unwrap() on None".to_string()),
+        "unwrap" => Err("Called unwrap() on an Err".to_string()),
+        "unwrap_err" if !holding => held(),
+        "unwrap_err" => Err("Called unwrap_err() on an Ok".to_string()),
+        "unwrap_or" if holding => held(),
+        "unwrap_or" if arguments.len() == 1 => Ok(arguments.remove(0)),
+        _ => Err(format!(
+            "{} has no method '{}'",
+            variant.typename().as_str(),
+            method
+        )),
+    }
+}
+
+// C leaves uninitialised variables undefined, here they start out zeroed
+fn default_value(tp: &str) -> Box<dyn Value> {
+    match tp {
+        "Int" => Box::new(TslInt { value: 0 }),
+        "Bool" => Box::new(TslBool { value: false }),
+        "Char" => Box::new(TslChar { value: 0 }),
+        "String" => Box::new(TslString {
+            value: String::new(),
+        }),
+        _ => Box::new(TslNull),
+    }
+}
+
+// How the REPL shows a value, strings and chars quoted like TSL literals
+pub fn display(value: &dyn Value) -> String {
+    let any = value.as_any();
+    if let Some(v) = any.downcast_ref::<TslInt>() {
+        v.value.to_string()
+    } else if let Some(v) = any.downcast_ref::<TslBool>() {
+        v.value.to_string()
+    } else if let Some(v) = any.downcast_ref::<TslChar>() {
+        format!("{:?}", v.value as char)
+    } else if let Some(text) = c_string(value) {
+        format!("{:?}", text)
+    } else if any.downcast_ref::<TslPointer>().is_some() {
+        "<pointer>".to_string()
+    } else if let Some(v) = any.downcast_ref::<Tuple>() {
+        let elements: Vec<String> = v
+            .elements
+            .iter()
+            .map(|element| display(element.as_ref()))
+            .collect();
+        format!("({})", elements.join(", "))
+    } else if let Some(v) = any.downcast_ref::<Variant>() {
+        match &v.value {
+            Some(value) => format!("{}({})", v.name, display(value.as_ref())),
+            None => v.name.clone(),
+        }
+    } else {
+        value.typename().as_str().to_string()
+    }
+}
+
+fn int(value: &dyn Value) -> Option<i32> {
+    value
+        .as_any()
+        .downcast_ref::<TslInt>()
+        .map(|int| int.value as i32)
+}
+
+// The text a char* points at, up to the first NUL
+fn c_string(value: &dyn Value) -> Option<String> {
+    let any = value.as_any();
+    let bytes = if let Some(string) = any.downcast_ref::<TslString>() {
+        string.value.as_bytes().to_vec()
+    } else {
+        let buffer = any.downcast_ref::<TslBuffer>()?;
+        let bytes = buffer.bytes.borrow();
+        bytes.get(buffer.offset..).unwrap_or_default().to_vec()
+    };
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    Some(String::from_utf8_lossy(&bytes[..end]).to_string())
+}
+
+// Literals keep their C escapes, since the C backend copies them into the output as is
+fn unescape(literal: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        bytes.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('\'') => b'\'',
+            Some('"') => b'"',
+            other => return Err(format!("Unknown escape '\\{}'", other.unwrap_or(' '))),
+        });
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::interpret;
+    use crate::{compile, CompileOptions};
+
+    // Checks source like `tsl interpret` does and runs it, returning main's status
+    fn run(source: &str) -> Result<i32, String> {
+        let options = CompileOptions {
+            stop_after: Some("typecheck".to_string()),
+            ..Default::default()
+        };
+        let output = compile(source, &options).unwrap_or_else(|e| panic!("{:?}", e));
+        interpret(output.ast.as_ref().unwrap())
+    }
+
+    // The error the C backend gives for source
+    fn compile_error(source: &str) -> String {
+        match compile(source, &CompileOptions::default()) {
+            Ok(_) => panic!("{} compiled", source),
+            Err(diagnostics) => diagnostics.last().unwrap().message.clone(),
+        }
+    }
+
+    #[test]
+    fn globals_start_out_as_their_constants() {
+        let source = "let N = 3\nlet mut count = 1\nlet on = true\nfunc main() Int |\n    count = count + N\n    if on |\n        return count\n    |\n    return 0\n|\n";
+        assert_eq!(run(source), Ok(4));
+    }
+
+    #[test]
+    fn non_constant_initialisers_are_rejected_like_the_c_backend_does() {
+        for (global, name) in [
+            ("let N = 2 * 3", "N"),
+            ("let g = twice", "g"),
+            ("let s = twice(2)", "s"),
+        ] {
+            let source = format!(
+                "func twice(x Int) Int |\n    return x * 2\n|\n{}\nfunc main() Int |\n    return 0\n|\n",
+                global
+            );
+            let error = format!("Top-level initialiser for '{}' must be a constant", name);
+            assert_eq!(run(&source), Err(format!("Runtime error: {}", error)));
+            assert_eq!(compile_error(&source), format!("Lowering error: {}", error));
+        }
+    }
+
+    #[test]
+    fn ints_wrap_at_32_bits() {
+        let source = "func main() Int |\n    let big = 2147483647\n    if big + 1 < 0 |\n        return 1\n    |\n    return 0\n|\n";
+        assert_eq!(run(source), Ok(1));
+    }
+
+    #[test]
+    fn runaway_recursion_is_an_error() {
+        let source = "func down(n Int) Int |\n    return down(n + 1)\n|\nfunc main() Int |\n    return down(0)\n|\n";
+        let error = run(source).unwrap_err();
+        assert!(error.starts_with("Runtime error: "), "{}", error);
+    }
+}
@@ -28,6 +28,10 @@ pub enum TokenType {
     Ampersand,
     Deref, // a caret
     Comment,
+    Attribute, // @name, value is the name
+    Dot,
+    Colon,
+    Question, // `?` after an Option or Result
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -36,6 +40,22 @@ pub struct Token {
     pub value: String,
 }
 
+// Lexes a whole source file, dropping newlines and ending with EOF
+pub fn tokenize(input: String) -> Vec<Token> {
+    let mut lexer = Lexer::new(input);
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token() {
+        if token.token_type != TokenType::Newline {
+            tokens.push(token);
+        }
+    }
+    tokens.push(Token {
+        token_type: TokenType::EOF,
+        value: "".to_string(),
+    });
+    tokens
+}
+
 pub struct Lexer {
     input: String,
     position: usize,
@@ -141,14 +161,20 @@ impl Lexer {
                     value: "<=".to_string(),
                 });
             } else {
+                // `<Name>` is a single token, anything longer like `<A, B>` is left to
+                // the parser
                 if self.peek_char().is_alphabetic() {
+                    let start = self.position;
                     self.position += 1;
                     let tp = self.lex_identifier_or_keyword().value;
-                    self.position += 1;
-                    return Some(Token {
-                        token_type: TokenType::TypeName,
-                        value: tp,
-                    }); // skip the >
+                    if self.current_char() == '>' {
+                        self.position += 1; // skip the >
+                        return Some(Token {
+                            token_type: TokenType::TypeName,
+                            value: tp,
+                        });
+                    }
+                    self.position = start;
                 }
                 self.position += 1;
                 return Some(Token {
@@ -188,6 +214,24 @@ impl Lexer {
                 token_type: TokenType::Comma,
                 value: ",".to_string(),
             });
+        } else if current_char == '.' {
+            self.position += 1;
+            return Some(Token {
+                token_type: TokenType::Dot,
+                value: ".".to_string(),
+            });
+        } else if current_char == '?' {
+            self.position += 1;
+            return Some(Token {
+                token_type: TokenType::Question,
+                value: "?".to_string(),
+            });
+        } else if current_char == ':' {
+            self.position += 1;
+            return Some(Token {
+                token_type: TokenType::Colon,
+                value: ":".to_string(),
+            });
         } else if current_char == '&' {
             self.position += 1;
             return Some(Token {
@@ -218,6 +262,13 @@ impl Lexer {
                 token_type: TokenType::Deref,
                 value: "^".to_string(),
             });
+        } else if current_char == '@' && self.peek_char().is_alphabetic() {
+            self.position += 1; // Skip '@'
+            let name = self.lex_identifier_or_keyword().value;
+            return Some(Token {
+                token_type: TokenType::Attribute,
+                value: name,
+            });
         } else if current_char == '\'' {
             let res = self.lex_char();
             if res.value == "\r" {
@@ -229,15 +280,12 @@ impl Lexer {
                 });
             }
             return Some(res);
-        } else {
-            self.position += 1;
-            return Some(Token {
-                token_type: TokenType::Whitespace,
-                value: current_char.to_string(),
-            });
         }
-
-        None
+        self.position += 1;
+        Some(Token {
+            token_type: TokenType::Whitespace,
+            value: current_char.to_string(),
+        })
     }
 
     fn lex_char(&mut self) -> Token {
@@ -252,15 +300,31 @@ impl Lexer {
 
     fn lex_identifier_or_keyword(&mut self) -> Token {
         let start = self.position;
-        while self.current_char().is_alphanumeric()
-            || self.current_char() == '_'
-            || self.current_char() == '*'
-        {
-            self.position += 1;
+        loop {
+            if self.current_char().is_alphanumeric()
+                || self.current_char() == '_'
+                || self.current_char() == '*'
+            {
+                self.position += 1;
+            } else if self.current_char() == ':'
+                && self.peek_char() == ':'
+                && self
+                    .input
+                    .chars()
+                    .nth(self.position + 2)
+                    .is_some_and(char::is_alphabetic)
+            {
+                // A module qualified name, e.g. `util::add`
+                self.position += 2;
+            } else {
+                break;
+            }
         }
         let value = self.input[start..self.position].to_string();
         let token_type = match value.as_str() {
-            "let" | "if" | "else" | "while" | "func" | "return" | "struct" => TokenType::Keyword,
+            "let" | "if" | "else" | "while" | "func" | "return" | "struct" | "import" | "pub"
+            | "class" | "new" | "super" | "interface" | "impl" | "for" | "dyn" | "mut"
+            | "const" => TokenType::Keyword,
             "true" => TokenType::True,
             "false" => TokenType::False,
             "void" => TokenType::Void,
@@ -0,0 +1,260 @@
+// Library entry point - compiles TSL source to C without touching the filesystem or
+// stdout, so the compiler can be embedded in other tools. The tsl binary is a thin CLI
+// on top of compile.
+
+// Module declarations
+pub mod cache;
+pub mod interpreter;
+pub mod lex;
+pub mod parser;
+pub mod project;
+pub mod repl;
+pub mod transpiler;
+pub mod types;
+
+use lex::Token;
+use parser::AstNode;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use transpiler::interfaces::{Interface, Interfaces};
+use transpiler::ir::Module;
+use transpiler::modules::{Loader, ModuleInfo};
+use transpiler::passes::{ir_pipeline, source_pipeline, Compilation, PassManager};
+use transpiler::type_checker::TypeChecker;
+
+// Prepended to every TSL source file unless CompileOptions::prelude is off
+pub const PRELUDE: &str = include_str!("transpiler/prelude.tsl");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.severity {
+            // Errors already say what kind they are, e.g. "Type error: ..."
+            Severity::Error => write!(f, "{}", self.message),
+            Severity::Warning => write!(f, "Warning: {}", self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    // Prepend prelude.tsl to the source
+    pub prelude: bool,
+    // The source is IR text written by --emit=ir, which already contains the prelude
+    pub ir_input: bool,
+    // Run the IR optimiser
+    pub optimise: bool,
+    // Passes to leave out of the pipeline
+    pub skip: Vec<String>,
+    // Passes to dump the compilation after, see CompileOutput::dumps
+    pub print_after: Vec<String>,
+    // Pass to end the pipeline after, e.g. "typecheck" to only check the program
+    pub stop_after: Option<String>,
+    // Directories to look for imported modules in, e.g. a project's source dirs
+    pub search_path: Vec<PathBuf>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            prelude: true,
+            ir_input: false,
+            optimise: false,
+            skip: Vec::new(),
+            print_after: Vec::new(),
+            stop_after: None,
+            search_path: Vec::new(),
+        }
+    }
+}
+
+pub struct CompileOutput {
+    pub tokens: Vec<Token>,
+    // None when compiling IR text
+    pub ast: Option<AstNode>,
+    // The checker's symbol and function tables. None when compiling IR text
+    pub types: Option<TypeChecker>,
+    // None when the pipeline stopped before lowering
+    pub module: Option<Module>,
+    // None when the pipeline stopped or skipped codegen
+    pub c_code: Option<String>,
+    pub warnings: Vec<Diagnostic>,
+    // The source file and every module it imports, empty for compile() without a path
+    pub files: Vec<PathBuf>,
+    // Every imported module, dependencies first
+    pub modules: Vec<ModuleInfo>,
+    // (fingerprint, checked interface) of each module that was type checked rather than
+    // restored, empty unless compiling with interfaces
+    pub interfaces: Vec<(String, Interface)>,
+    // (pass, dump) for every pass named in CompileOptions::print_after
+    pub dumps: Vec<(String, String)>,
+    pub timings: Vec<(String, Duration)>,
+}
+
+// Compiles a TSL program (or an IR dump) to C. Errors come back with any warnings found
+// before the failing pass. The program can't import other modules, see compile_with_loader.
+pub fn compile(source: &str, options: &CompileOptions) -> Result<CompileOutput, Vec<Diagnostic>> {
+    compile_module(source, Path::new(""), None, None, options)
+}
+
+// Compiles the file at path, reading it and everything it imports from the filesystem
+pub fn compile_file(
+    path: &Path,
+    options: &CompileOptions,
+) -> Result<CompileOutput, Vec<Diagnostic>> {
+    let source = read_module(path).map_err(|e| {
+        vec![Diagnostic::error(format!(
+            "Failed to read {}: {}",
+            path.display(),
+            e
+        ))]
+    })?;
+    compile_module(&source, path, Some(Box::new(read_module)), None, options)
+}
+
+// Like compile_file, but an imported module that interfaces has a checked interface for
+// isn't type checked again, see transpiler::interfaces
+pub fn compile_file_with_interfaces(
+    path: &Path,
+    interfaces: impl FnMut(&str) -> Option<Interface> + 'static,
+    options: &CompileOptions,
+) -> Result<CompileOutput, Vec<Diagnostic>> {
+    let source = read_module(path).map_err(|e| {
+        vec![Diagnostic::error(format!(
+            "Failed to read {}: {}",
+            path.display(),
+            e
+        ))]
+    })?;
+    compile_module(
+        &source,
+        path,
+        Some(Box::new(read_module)),
+        Some(Box::new(interfaces)),
+        options,
+    )
+}
+
+// Compiles source as if it were the file at path, asking loader for the modules it imports
+pub fn compile_with_loader(
+    source: &str,
+    path: &Path,
+    loader: impl FnMut(&Path) -> Result<String, String> + 'static,
+    options: &CompileOptions,
+) -> Result<CompileOutput, Vec<Diagnostic>> {
+    compile_module(source, path, Some(Box::new(loader)), None, options)
+}
+
+// compile_with_loader with the checked interfaces of compile_file_with_interfaces
+pub fn compile_with_interfaces(
+    source: &str,
+    path: &Path,
+    loader: impl FnMut(&Path) -> Result<String, String> + 'static,
+    interfaces: impl FnMut(&str) -> Option<Interface> + 'static,
+    options: &CompileOptions,
+) -> Result<CompileOutput, Vec<Diagnostic>> {
+    compile_module(
+        source,
+        path,
+        Some(Box::new(loader)),
+        Some(Box::new(interfaces)),
+        options,
+    )
+}
+
+fn read_module(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| e.to_string())
+}
+
+fn compile_module(
+    source: &str,
+    path: &Path,
+    loader: Option<Box<Loader>>,
+    interfaces: Option<Box<Interfaces>>,
+    options: &CompileOptions,
+) -> Result<CompileOutput, Vec<Diagnostic>> {
+    let mut manager = if options.ir_input {
+        ir_pipeline(options.optimise)
+    } else {
+        source_pipeline(options.optimise)
+    };
+    configure(&mut manager, options).map_err(|e| vec![Diagnostic::error(e)])?;
+
+    let source = if options.prelude && !options.ir_input {
+        PRELUDE.to_string() + source
+    } else {
+        source.to_string()
+    };
+    let mut compilation = Compilation::new(source);
+    compilation.path = path.to_path_buf();
+    compilation.loader = loader;
+    compilation.interfaces = interfaces;
+    compilation.search_path = options.search_path.clone();
+    let result = manager.run(&mut compilation);
+    let mut diagnostics: Vec<Diagnostic> = compilation
+        .warnings
+        .iter()
+        .map(|warning| Diagnostic::warning(warning.clone()))
+        .collect();
+    if let Err(e) = result {
+        diagnostics.push(Diagnostic::error(e));
+        return Err(diagnostics);
+    }
+    let mut files = compilation.imported;
+    if !path.as_os_str().is_empty() {
+        files.insert(0, path.to_path_buf());
+    }
+    Ok(CompileOutput {
+        files,
+        modules: compilation.modules,
+        interfaces: compilation.checked,
+        tokens: compilation.tokens,
+        ast: compilation.ast,
+        types: compilation.checker,
+        module: compilation.module,
+        c_code: compilation.c_code,
+        warnings: diagnostics,
+        dumps: manager.dumps,
+        timings: manager.timings,
+    })
+}
+
+fn configure(manager: &mut PassManager, options: &CompileOptions) -> Result<(), String> {
+    for pass in &options.skip {
+        manager.skip(pass)?;
+    }
+    for pass in &options.print_after {
+        manager.print_after(pass)?;
+    }
+    if let Some(pass) = &options.stop_after {
+        manager.stop_after(pass)?;
+    }
+    Ok(())
+}
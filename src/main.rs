@@ -1,112 +1,888 @@
-#![allow(unused, clippy::borrowed_box)]
-#![feature(f128)]
-#![feature(let_chains)]
-
-// Module declarations
-mod lex;
-mod parser;
-mod transpiler;
-mod types;
-
 // Use declarations
-use lex::{Lexer, Token, TokenType};
-use types::{Function, *};
-
-// Macro definition
-macro_rules! c_code {
-    // Case with an identifier interpolation
-    ($( $line:tt )* #$var:expr , $( $rest:tt )*) => {{
-        let mut code = stringify!($($line)*);
-        code.push_str(&format!("{}", $var));
-        code.push_str(&c_code!($($rest)*));
-        code
-    }};
-    // Base case: no variables, just a simple string
-    ($($code:tt)*) => {{
-        let code = stringify!($($code)*);
-        code.replace("#", "")
-    }};
-}
+use std::path::{Path, PathBuf};
+use tsl::cache::{self, Cache};
+use tsl::interpreter;
+use tsl::project::{self, Project};
+use tsl::repl::{self, Session};
+use tsl::transpiler::c_bindgen::c_bindgen_units;
+use tsl::transpiler::color::{colorize, Color, Green, Red, Yellow};
+use tsl::{
+    compile_file, compile_file_with_interfaces, CompileOptions, CompileOutput, Diagnostic, Severity,
+};
 
 // Main function
 fn main() {
     cli();
 }
 
+// What --emit prints instead of writing output.c
+#[derive(Clone, Copy, PartialEq)]
+enum Emit {
+    Tokens,
+    Ast,
+    Ir,
+    C,
+}
+
+// Flags that change what transpile does
+#[derive(Clone, Default)]
+struct Options {
+    // --emit=<tokens|ast|ir|c> prints that stage to stdout and stops there
+    emit: Option<Emit>,
+    // -O runs the IR optimiser
+    optimise: bool,
+    // --no-prelude compiles the file without prelude.tsl
+    no_prelude: bool,
+    // --color=<auto|always|never>, resolved to whether diagnostics are colored
+    color: bool,
+    // -q only prints errors, -v also reports each step on stderr
+    quiet: bool,
+    verbose: bool,
+    // --print-after=<pass> dumps the compilation after a pass
+    print_after: Vec<String>,
+    // --skip=<pass> leaves a pass out of the pipeline
+    skip: Vec<String>,
+    // --time-passes prints how long each pass took
+    time_passes: bool,
+    // --no-cache rebuilds a project from scratch instead of using build/cache
+    no_cache: bool,
+    // --run makes watch run the program after each build
+    run: bool,
+    // -o <path> is the executable for build and the C file for transpile
+    output: Option<String>,
+    // --cc <compiler>, falling back to $CC and then gcc
+    cc: Option<String>,
+    // --cflags "<flags>" are passed to the C compiler
+    cflags: Vec<String>,
+    // Everything after `--` is passed to the program by run
+    program_args: Vec<String>,
+    // From tsl.toml: directories to import from, and C sources and libraries to link
+    search_path: Vec<PathBuf>,
+    c_sources: Vec<PathBuf>,
+    libs: Vec<String>,
+}
+
+const USAGE: &str =
+    "Usage: tsl <build|run|transpile|check|watch|interpret|repl> [filename] [options] [-- <program args>]
+Try 'tsl --help' for more information.";
+
+const HELP: &str = "tsl - compiles TSL programs to C
+
+Usage: tsl <command> [filename] [options] [-- <program args>]
+
+Commands:
+  build                   Compile to an executable (./output by default)
+  run                     Compile to a temporary executable and run it
+  transpile               Write the generated C (output.c by default)
+  check                   Only type check the program
+  watch                   Build again whenever the program or its imports change
+  interpret               Run the program with the interpreter, without a C compiler
+  repl                    Evaluate TSL interactively on the interpreter, after running
+                          the file if one is given. :help lists the REPL's commands
+
+Files ending in .ir are read as IR written by --emit=ir.
+
+Without a filename, the project described by the nearest tsl.toml is compiled. Its
+entry file is built to build/<name> next to tsl.toml, with the manifest's source
+dirs, C flags, C sources, libraries and path dependencies. Each module is compiled
+to its own object in build/cache, and only modules whose C changed are recompiled.
+Modules that didn't change and import nothing that did aren't type checked again.
+
+Options:
+  -o <path>               Where build puts the executable and transpile puts the C
+  --emit=<kind>           Print tokens, ast, ir or c to stdout instead of building
+  -O                      Optimise the IR (inlining and constant folding)
+  --no-prelude            Don't prepend prelude.tsl to the program
+  --cc <compiler>         C compiler to use, defaults to $CC and then gcc
+  --cflags <flags>        Extra flags for the C compiler
+  --color=<when>          Color diagnostics: auto, always or never
+  -q, --quiet             Only print errors
+  -v, --verbose           Report each step
+  --print-after=<pass>    Dump the compilation after a pass
+  --skip=<pass>           Leave a pass out of the pipeline
+  --time-passes           Print how long each pass took
+  --no-cache              Rebuild a project without the incremental build cache
+  --run                   With watch, run the program after each build
+  -h, --help              Print this help
+
+Exit status is 0 on success, 1 when compilation fails and 2 for bad usage.
+run and interpret exit with the program's own exit status.";
+
+// Exit statuses, so scripts and CI can tell failures apart
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
 // CLI function
 fn cli() {
-    let args: Vec<String> = std::env::args().collect();
-    // 3 options: build, run, transpile
-    if args.len() != 3 {
-        println!("Usage: cargo run [build|run|transpile] <filename>");
+    let (options, args) = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(parsed)) => parsed,
+        Ok(None) => {
+            println!("{}", HELP);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+    // build, run, transpile or check, then the file or nothing for a project
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("{}", USAGE);
+        std::process::exit(EXIT_USAGE);
+    }
+    let option = args[0].as_str();
+    if !matches!(
+        option,
+        "build" | "run" | "transpile" | "check" | "watch" | "interpret" | "repl"
+    ) {
+        eprintln!("Invalid option: {}\n{}", option, USAGE);
+        std::process::exit(EXIT_USAGE);
+    }
+    if option == "repl" {
+        std::process::exit(repl(args.get(1), &options));
+    }
+    let mut options = options;
+    let mut executable = "./output".to_string();
+    let mut c_file = "output.c".to_string();
+    let mut cache_dir = None;
+    let filename = match args.get(1) {
+        Some(filename) => filename.clone(),
+        None => {
+            let project = find_project(&options);
+            let build_dir = project.root.join("build");
+            if let Err(e) = std::fs::create_dir_all(&build_dir) {
+                fail(
+                    &options,
+                    format!("Failed to create {}: {}", build_dir.display(), e),
+                );
+            }
+            let name = &project.manifest.name;
+            executable = build_dir.join(name).display().to_string();
+            c_file = build_dir.join(format!("{}.c", name)).display().to_string();
+            if !options.no_cache {
+                cache_dir = Some(build_dir.join("cache"));
+            }
+            use_project(&mut options, &project);
+            project.entry().display().to_string()
+        }
+    };
+    let filename = &filename;
+    // IR dumps from --emit=ir already contain the prelude, so they skip straight to codegen
+    let is_ir = filename.ends_with(".ir");
+    if options.verbose {
+        eprintln!("Compiling {}", filename);
+    }
+    if option == "interpret" {
+        std::process::exit(interpret(filename, is_ir, &options));
+    }
+    if option == "watch" {
+        let executable = options.output.as_deref().unwrap_or(&executable);
+        watch(filename, &c_file, executable.as_ref(), &options);
+    }
+    // Projects compile each module on its own, reusing the ones that haven't changed
+    let cache_dir =
+        cache_dir.filter(|_| matches!(option, "build" | "run") && options.emit.is_none());
+    if let Some(cache_dir) = cache_dir {
+        let executable = PathBuf::from(options.output.as_deref().unwrap_or(&executable));
+        build_cached(filename, &cache_dir, &executable, &options);
+        if option == "run" {
+            std::process::exit(execute(&executable, &options));
+        }
+        if options.verbose {
+            let message = format!("Built {}", executable.display());
+            eprintln!("{}", paint(&options, Green, &message));
+        }
         return;
     }
-    let option = &args[1];
-    let filename = &args[2];
-    let input = std::fs::read_to_string(filename).expect("Failed to read the file");
-    let tsl_prelude = include_str!("transpiler/prelude.tsl").to_string();
-    let input = tsl_prelude + &input;
+    let Some(c_code) = transpile(filename, is_ir, option == "check", &options) else {
+        // Checking, or an --emit that has already been printed
+        return;
+    };
     if option == "build" {
-        transpile(input);
-        std::process::Command::new("gcc")
-            .arg("output.c")
-            .arg("-o")
-            .arg("./output")
-            .output()
-            .expect("Failed to compile the C code");
-        // println!("Compiled successfully");
+        if let Err(e) = std::fs::write(&c_file, c_code) {
+            fail(&options, format!("Failed to write {}: {}", c_file, e));
+        }
+        let executable = options.output.as_deref().unwrap_or(&executable);
+        if let Err(e) = compile_c(c_file.as_ref(), executable.as_ref(), &options) {
+            fail(&options, e);
+        }
+        if options.verbose {
+            eprintln!(
+                "{}",
+                paint(&options, Green, &format!("Built {}", executable))
+            );
+        }
         // delete the output.c file
         // std::fs::remove_file("output.c").expect("Failed to delete the output.c file");
-    } else if option == "transpile" {
-        transpile(input);
+    } else if option == "run" {
+        std::process::exit(run(&c_code, &options));
+    } else {
+        let path = options.output.as_deref().unwrap_or(&c_file);
+        if let Err(e) = std::fs::write(path, c_code) {
+            fail(&options, format!("Failed to write {}: {}", path, e));
+        }
+        if options.verbose {
+            eprintln!("{}", paint(&options, Green, &format!("Wrote {}", path)));
+        }
+    }
+}
+
+// The project around the current directory, exits if there isn't one
+fn find_project(options: &Options) -> Project {
+    let dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    match project::discover(&dir) {
+        Ok(Some(project)) => {
+            if options.verbose {
+                eprintln!("Using {}", project.root.join(project::MANIFEST).display());
+            }
+            project
+        }
+        Ok(None) => {
+            eprintln!(
+                "No filename given and no {} found in {} or its parents\n{}",
+                project::MANIFEST,
+                dir.display(),
+                USAGE
+            );
+            std::process::exit(EXIT_USAGE);
+        }
+        Err(e) => fail(options, e),
+    }
+}
+
+// Builds with the manifest's settings. Flags from the command line come last, so they win.
+fn use_project(options: &mut Options, project: &Project) {
+    let mut cflags = project.cflags();
+    cflags.append(&mut options.cflags);
+    options.cflags = cflags;
+    options.search_path = project.search_path();
+    options.c_sources = project.c_sources();
+    options.libs = project.libs();
+}
+
+// Splits the command line into options and positional arguments. Returns None for --help.
+fn parse_args(
+    args: impl Iterator<Item = String>,
+) -> Result<Option<(Options, Vec<String>)>, String> {
+    let mut options = Options::default();
+    let mut color = "auto".to_string();
+    let mut positional = Vec::new();
+    let mut args = args.peekable();
+    // Flags that take a value accept both `--flag value` and `--flag=value`
+    let value = |flag: &str, arg: &str, args: &mut std::iter::Peekable<_>| match arg
+        .strip_prefix(flag)
+        .and_then(|rest| rest.strip_prefix('='))
+    {
+        Some(value) => Ok(value.to_string()),
+        None => args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag)),
+    };
+    while let Some(arg) = args.next() {
+        let is = |flag: &str| arg == flag || arg.starts_with(&format!("{}=", flag));
+        if arg == "--" {
+            options.program_args = args.by_ref().collect();
+        } else if arg == "-h" || arg == "--help" {
+            return Ok(None);
+        } else if arg == "-O" {
+            options.optimise = true;
+        } else if arg == "-q" || arg == "--quiet" {
+            options.quiet = true;
+        } else if arg == "-v" || arg == "--verbose" {
+            options.verbose = true;
+        } else if arg == "--no-prelude" {
+            options.no_prelude = true;
+        } else if arg == "--time-passes" {
+            options.time_passes = true;
+        } else if arg == "--no-cache" {
+            options.no_cache = true;
+        } else if arg == "--run" {
+            options.run = true;
+        } else if let Some(pass) = arg.strip_prefix("--print-after=") {
+            options.print_after.push(pass.to_string());
+        } else if let Some(pass) = arg.strip_prefix("--skip=") {
+            options.skip.push(pass.to_string());
+        } else if is("--emit") {
+            options.emit = Some(match value("--emit", &arg, &mut args)?.as_str() {
+                "tokens" => Emit::Tokens,
+                "ast" => Emit::Ast,
+                "ir" => Emit::Ir,
+                "c" => Emit::C,
+                other => return Err(format!("Unknown --emit kind '{}'", other)),
+            });
+        } else if is("--color") {
+            color = value("--color", &arg, &mut args)?;
+        } else if is("-o") {
+            options.output = Some(value("-o", &arg, &mut args)?);
+        } else if is("--cc") {
+            options.cc = Some(value("--cc", &arg, &mut args)?);
+        } else if is("--cflags") {
+            let flags = value("--cflags", &arg, &mut args)?;
+            options
+                .cflags
+                .extend(flags.split_whitespace().map(str::to_string));
+        } else if arg.starts_with('-') {
+            return Err(format!("Unknown flag: {}", arg));
+        } else {
+            positional.push(arg);
+        }
+    }
+    if options.quiet && options.verbose {
+        return Err("-q and -v can't be used together".to_string());
+    }
+    options.color = match color.as_str() {
+        "always" => true,
+        "never" => false,
+        // Only color a terminal, and respect https://no-color.org
+        "auto" => {
+            use std::io::IsTerminal;
+            std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+        }
+        other => return Err(format!("Unknown --color value '{}'", other)),
+    };
+    Ok(Some((options, positional)))
+}
+
+fn paint<T: Color>(options: &Options, color: T, text: &str) -> String {
+    if options.color {
+        colorize(color, text)
     } else {
-        println!("Invalid option: {}", option);
+        text.to_string()
     }
 }
 
-fn transpile(input: String) {
-    // Lexical analysis
-    let mut lexer = Lexer::new(input);
-    let mut tokens = Vec::new();
-    while let Some(token) = lexer.next_token() {
-        if token.token_type != TokenType::Newline {
-            tokens.push(token);
+fn print_diagnostic(options: &Options, diagnostic: &Diagnostic) {
+    match diagnostic.severity {
+        Severity::Error => eprintln!("{}", paint(options, Red, &diagnostic.to_string())),
+        Severity::Warning if !options.quiet => {
+            eprintln!("{}", paint(options, Yellow, &diagnostic.to_string()))
         }
+        Severity::Warning => {}
+    }
+}
+
+// Reports an error that isn't a compiler diagnostic and exits
+fn fail(options: &Options, message: String) -> ! {
+    print_diagnostic(options, &Diagnostic::error(message));
+    std::process::exit(EXIT_FAILURE);
+}
+
+// Compiles the file and everything it imports, and prints whatever --emit asked for. Returns the C code to build
+// with, or None when there's nothing left to do. Exits if compilation failed.
+fn transpile(filename: &str, is_ir: bool, check: bool, options: &Options) -> Option<String> {
+    let stop_after = match (options.emit, check, is_ir) {
+        (Some(Emit::Tokens | Emit::Ast), _, true) => fail(
+            options,
+            "--emit=tokens and --emit=ast need TSL source, not IR".to_string(),
+        ),
+        (Some(Emit::Tokens), _, _) => Some("lex"),
+        (Some(Emit::Ast), _, _) => Some("parse"),
+        // IR input is verified as soon as it is parsed
+        (_, true, true) => Some("parse-ir"),
+        (_, true, false) => Some("typecheck"),
+        _ => None,
+    };
+    let compile_options = compile_options(is_ir, stop_after, options);
+    let output = compile(filename, &compile_options, options);
+    match options.emit {
+        Some(Emit::Tokens) => {
+            for token in &output.tokens {
+                println!("{:?}", token);
+            }
+        }
+        Some(Emit::Ast) => {
+            if let Some(ast) = &output.ast {
+                println!("{:#?}", ast);
+            }
+        }
+        Some(Emit::Ir) => {
+            if let Some(module) = &output.module {
+                print!("{}", module);
+            }
+        }
+        Some(Emit::C) => {
+            if let Some(c_code) = &output.c_code {
+                print!("{}", c_code);
+            }
+        }
+        None if check => {
+            if options.verbose {
+                eprintln!("{}", paint(options, Green, "No errors found"));
+            }
+        }
+        None => {
+            if output.c_code.is_none() {
+                fail(
+                    options,
+                    "No C code was generated, the codegen pass was skipped".to_string(),
+                );
+            }
+            return output.c_code;
+        }
+    }
+    None
+}
+
+fn compile_options(is_ir: bool, stop_after: Option<&str>, options: &Options) -> CompileOptions {
+    CompileOptions {
+        prelude: !options.no_prelude,
+        ir_input: is_ir,
+        optimise: options.optimise,
+        skip: options.skip.clone(),
+        print_after: options.print_after.clone(),
+        stop_after: stop_after.map(str::to_string),
+        search_path: options.search_path.clone(),
+    }
+}
+
+// Compiles the file and reports dumps, warnings and timings. Exits if compilation failed.
+fn compile(filename: &str, compile_options: &CompileOptions, options: &Options) -> CompileOutput {
+    report(compile_file(filename.as_ref(), compile_options), options)
+}
+
+// Prints what compiling printed, exiting on errors
+fn report(result: Result<CompileOutput, Vec<Diagnostic>>, options: &Options) -> CompileOutput {
+    let output = match result {
+        Ok(output) => output,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                print_diagnostic(options, diagnostic);
+            }
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+    for (pass, dump) in &output.dumps {
+        println!(
+            "// ==================== After {} ====================",
+            pass
+        );
+        print!("{}", dump);
+    }
+    for warning in &output.warnings {
+        print_diagnostic(options, warning);
+    }
+    if options.time_passes {
+        print_timings(&output.timings);
     }
-    tokens.push(Token {
-        token_type: TokenType::EOF,
-        value: "".to_string(),
+    output
+}
+
+// Builds a project from per-module objects, compiling only the ones that aren't cached
+fn build_cached(filename: &str, cache_dir: &Path, executable: &Path, options: &Options) {
+    let cache = Cache::open(cache_dir).unwrap_or_else(|e| fail(options, e));
+    let compile_options = compile_options(false, None, options);
+    let key = cache::program_key(filename.as_ref(), &compile_options);
+    let units = match cache.load_program(&key) {
+        Some(units) => {
+            if options.verbose {
+                eprintln!("No sources changed, reusing the generated C");
+            }
+            units
+        }
+        None => {
+            let found = cache.clone();
+            let lookup_options = compile_options.clone();
+            let result = compile_file_with_interfaces(
+                filename.as_ref(),
+                move |fingerprint| {
+                    found.load_interface(&cache::interface_key(fingerprint, &lookup_options))
+                },
+                &compile_options,
+            );
+            let output = report(result, options);
+            let Some(module) = &output.module else {
+                fail(
+                    options,
+                    "No IR was generated, the lower pass was skipped".to_string(),
+                );
+            };
+            for (fingerprint, interface) in &output.interfaces {
+                let key = cache::interface_key(fingerprint, &compile_options);
+                if let Err(e) = cache.store_interface(&key, interface) {
+                    fail(options, e);
+                }
+            }
+            if options.verbose {
+                for module in &output.modules {
+                    let checked = output
+                        .interfaces
+                        .iter()
+                        .any(|(fingerprint, _)| *fingerprint == module.fingerprint);
+                    if !checked {
+                        eprintln!("Module {} is unchanged, not checking it again", module.name);
+                    }
+                }
+            }
+            let interfaces: Vec<String> = output
+                .modules
+                .iter()
+                .map(|module| cache::interface_key(&module.fingerprint, &compile_options))
+                .collect();
+            let units = c_bindgen_units(module);
+            if let Err(e) = cache.store_program(&key, &output.files, &units, &interfaces) {
+                fail(options, e);
+            }
+            units
+        }
+    };
+    let command: Vec<String> = std::iter::once(cc(options))
+        .chain(options.cflags.iter().cloned())
+        .collect();
+    let mut objects = Vec::new();
+    for (unit, c_code) in &units {
+        let unit = if unit.is_empty() { "main" } else { unit };
+        let object = cache.object(c_code, &command);
+        if object.exists() {
+            if options.verbose {
+                eprintln!("Module {} is up to date", unit);
+            }
+        } else {
+            if options.verbose {
+                eprintln!("Compiling module {}", unit);
+            }
+            let c_file = object.with_extension("c");
+            let result = std::fs::write(&c_file, c_code)
+                .map_err(|e| format!("Failed to write {}: {}", c_file.display(), e))
+                .and_then(|_| compile_object(&c_file, &object, options));
+            if let Err(e) = result {
+                fail(options, e);
+            }
+        }
+        objects.push(object);
+    }
+    if let Err(e) = cache
+        .store_objects(&key, &objects)
+        .and_then(|_| cache.evict())
+    {
+        fail(options, e);
+    }
+    if let Err(e) = link(&objects, executable, options) {
+        fail(options, e);
+    }
+}
+
+// --cc, falling back to $CC and then gcc
+fn cc(options: &Options) -> String {
+    options
+        .cc
+        .clone()
+        .or_else(|| std::env::var("CC").ok().filter(|cc| !cc.is_empty()))
+        .unwrap_or_else(|| "gcc".to_string())
+}
+
+// Compiles and links a single C file
+fn compile_c(c_file: &Path, executable: &Path, options: &Options) -> Result<(), String> {
+    link(&[c_file.to_path_buf()], executable, options)
+}
+
+// Compiles a C file to an object without linking it
+fn compile_object(c_file: &Path, object: &Path, options: &Options) -> Result<(), String> {
+    run_cc(options, |command| {
+        command
+            .arg("-c")
+            .arg(c_file)
+            .arg("-o")
+            .arg(object)
+            .args(&options.cflags);
+    })
+}
+
+// Builds the executable from C files or objects, plus the project's C sources and libraries
+fn link(inputs: &[PathBuf], executable: &Path, options: &Options) -> Result<(), String> {
+    run_cc(options, |command| {
+        command
+            .args(inputs)
+            .args(&options.c_sources)
+            .arg("-o")
+            .arg(executable)
+            .args(&options.cflags)
+            .args(options.libs.iter().map(|lib| format!("-l{}", lib)));
+    })
+}
+
+// Runs the C compiler, passing its diagnostics on if it fails
+fn run_cc(
+    options: &Options,
+    arguments: impl FnOnce(&mut std::process::Command),
+) -> Result<(), String> {
+    let cc = cc(options);
+    let mut command = std::process::Command::new(&cc);
+    arguments(&mut command);
+    let output = command
+        .output()
+        .map_err(|e| format!("Failed to run the C compiler '{}': {}", cc, e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "C compiler '{}' failed ({}):\n{}",
+            cc,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ))
+    }
+}
+
+// Creates a new directory for `run` to build in. The name is random and the directory
+// must not already exist, so nothing else can have put files in it first.
+fn create_run_dir() -> std::io::Result<PathBuf> {
+    use std::hash::{BuildHasher, RandomState};
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    // RandomState is keyed randomly for each process
+    let random = RandomState::new();
+    let mut attempt = 0;
+    loop {
+        let name = format!(
+            "tsl-run-{}-{:016x}",
+            std::process::id(),
+            random.hash_one(attempt)
+        );
+        let dir = std::env::temp_dir().join(name);
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 16 => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Builds the program in a temporary directory and runs it, returning its exit code.
+// The directory is removed afterwards.
+fn run(c_code: &str, options: &Options) -> i32 {
+    let dir = match create_run_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!(
+                "Failed to create a directory in {}: {}",
+                std::env::temp_dir().display(),
+                e
+            );
+            return 1;
+        }
+    };
+    let c_file = dir.join("program.c");
+    let executable = dir.join(format!("program{}", std::env::consts::EXE_SUFFIX));
+    let code = match std::fs::write(&c_file, c_code)
+        .map_err(|e| format!("Failed to write {}: {}", c_file.display(), e))
+        .and_then(|_| compile_c(&c_file, &executable, options))
+    {
+        Ok(()) => execute(&executable, options),
+        Err(e) => {
+            print_diagnostic(options, &Diagnostic::error(e));
+            1
+        }
+    };
+    let _ = std::fs::remove_dir_all(&dir);
+    code
+}
+
+// Type checks the program and runs it on the interpreter, returning its exit status
+fn interpret(filename: &str, is_ir: bool, options: &Options) -> i32 {
+    if is_ir {
+        fail(
+            options,
+            "The interpreter runs TSL source, not IR".to_string(),
+        );
+    }
+    let output = compile(
+        filename,
+        &compile_options(false, Some("typecheck"), options),
+        options,
+    );
+    let Some(ast) = &output.ast else {
+        fail(options, "There is no AST to interpret".to_string());
+    };
+    match interpreter::interpret(ast) {
+        Ok(status) => status,
+        Err(e) => {
+            print_diagnostic(options, &Diagnostic::error(e));
+            EXIT_FAILURE
+        }
+    }
+}
+
+// Reads inputs from stdin until EOF or :quit. A project around the current directory
+// makes its source dirs importable.
+fn repl(filename: Option<&String>, options: &Options) -> i32 {
+    use std::io::Write;
+    let dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let search_path = match project::discover(&dir) {
+        Ok(Some(project)) => project.search_path(),
+        Ok(None) => Vec::new(),
+        Err(e) => fail(options, e),
+    };
+    let result = interpreter::with_stack(|| {
+        let mut session = Session::new(search_path);
+        if let Some(filename) = filename {
+            eval(&mut session, &format!(":load {}", filename), options);
+        }
+        if !options.quiet {
+            println!("TSL {}, :help for help", env!("CARGO_PKG_VERSION"));
+        }
+        let stdin = std::io::stdin();
+        let mut input = String::new();
+        loop {
+            print!("{}", if input.is_empty() { "tsl> " } else { "...> " });
+            let _ = std::io::stdout().flush();
+            let mut line = String::new();
+            match stdin.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            input.push_str(&line);
+            if repl::is_unfinished(&input) {
+                continue;
+            }
+            let trimmed = input.trim();
+            if trimmed == ":quit" || trimmed == ":q" {
+                return;
+            }
+            if !trimmed.is_empty() {
+                eval(&mut session, &input, options);
+            }
+            input.clear();
+        }
+        println!();
     });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            print_diagnostic(options, &Diagnostic::error(e));
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn eval(session: &mut Session, input: &str, options: &Options) {
+    match session.eval(input) {
+        Ok(Some(output)) => println!("{}", output),
+        Ok(None) => {}
+        Err(e) => print_diagnostic(options, &Diagnostic::error(e)),
+    }
+}
+
+// Rebuilds the program whenever it or a module it imports changes, and never exits.
+// Errors are reported and then the files are watched again.
+fn watch(filename: &str, c_file: &str, executable: &Path, options: &Options) -> ! {
+    let compile_options = compile_options(filename.ends_with(".ir"), None, options);
+    // Until the program compiles, the imports aren't known, so only the file is watched
+    let mut files = vec![PathBuf::from(filename)];
+    loop {
+        clear_screen();
+        if let Some(imported) = build_once(filename, &compile_options, c_file, executable, options)
+        {
+            files = imported;
+        }
+        if !options.quiet {
+            eprintln!(
+                "Watching {} file{} for changes, press Ctrl-C to stop",
+                files.len(),
+                if files.len() == 1 { "" } else { "s" }
+            );
+        }
+        wait_for_change(&files);
+    }
+}
 
-    // Parsing
-    let mut parser = parser::Parser::new(tokens);
-    let mut ast = parser.parse();
-    let mut temp_checker = parser.temp_checker;
-    temp_checker.prelude(&ast);
-    // Static dispatch
-    let res = transpiler::overloading::static_dispatch(&mut ast, temp_checker);
-    // println!("{:#?}", ast);
-    if let Err(e) = res {
-        println!("Error: {}", e);
-    }
-
-    // Name resolution
-    ast = transpiler::ir::resolve_names(&ast);
-    // println!("{:#?}", ast);
-    // println!("{:#?}", ast);
-    // Type checking
-    let mut type_checker = transpiler::type_checker::TypeChecker::new();
-    type_checker.prelude(&ast);
-    let res = type_checker.check(&mut ast);
-    match res {
-        Ok(t) => {
-            // C code generation
-            let mut c_code = transpiler::c_bindgen::c_bindgen_prelude()
-                + &*transpiler::c_bindgen::c_bindgen(&ast, 0, false);
-            std::fs::write("output.c", c_code).unwrap();
+// One build for watch. Returns the files the program is made of, if it compiled.
+fn build_once(
+    filename: &str,
+    compile_options: &CompileOptions,
+    c_file: &str,
+    executable: &Path,
+    options: &Options,
+) -> Option<Vec<PathBuf>> {
+    let output = match compile_file(filename.as_ref(), compile_options) {
+        Ok(output) => output,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                print_diagnostic(options, diagnostic);
+            }
+            return None;
         }
+    };
+    for warning in &output.warnings {
+        print_diagnostic(options, warning);
+    }
+    if options.time_passes {
+        print_timings(&output.timings);
+    }
+    let result = match &output.c_code {
+        Some(c_code) => std::fs::write(c_file, c_code)
+            .map_err(|e| format!("Failed to write {}: {}", c_file, e))
+            .and_then(|_| compile_c(c_file.as_ref(), executable, options)),
+        None => Err("No C code was generated, the codegen pass was skipped".to_string()),
+    };
+    match result {
+        Ok(()) if options.run => {
+            let code = execute(executable, options);
+            if !options.quiet {
+                eprintln!("{} exited with status {}", executable.display(), code);
+            }
+        }
+        Ok(()) => {
+            if !options.quiet {
+                let message = format!("Built {}", executable.display());
+                eprintln!("{}", paint(options, Green, &message));
+            }
+        }
+        Err(e) => print_diagnostic(options, &Diagnostic::error(e)),
+    }
+    Some(output.files)
+}
+
+fn clear_screen() {
+    use std::io::{IsTerminal, Write};
+    if std::io::stdout().is_terminal() {
+        print!("\x1b[2J\x1b[H");
+        let _ = std::io::stdout().flush();
+    }
+}
+
+// Polls the files' modification times, returning once one of them changes
+fn wait_for_change(files: &[PathBuf]) {
+    let modified = |files: &[PathBuf]| -> Vec<Option<std::time::SystemTime>> {
+        files
+            .iter()
+            .map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+            .collect()
+    };
+    let before = modified(files);
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(250));
+        if modified(files) != before {
+            return;
+        }
+    }
+}
+
+// Runs a built program with the arguments after `--`, returning its exit code
+fn execute(executable: &Path, options: &Options) -> i32 {
+    // A bare file name would be looked up on $PATH
+    let executable = if executable.is_relative() {
+        Path::new(".").join(executable)
+    } else {
+        executable.to_path_buf()
+    };
+    match std::process::Command::new(&executable)
+        .args(&options.program_args)
+        .status()
+    {
+        // A program killed by a signal has no exit code
+        Ok(status) => status.code().unwrap_or(1),
         Err(e) => {
-            println!("Type error: {}", e);
+            eprintln!("Failed to run {}: {}", executable.display(), e);
+            1
         }
     }
 }
+
+fn print_timings(timings: &[(String, std::time::Duration)]) {
+    let total: std::time::Duration = timings.iter().map(|(_, time)| *time).sum();
+    for (name, time) in timings {
+        eprintln!("{:>12}  {:>10.3}ms", name, time.as_secs_f64() * 1000.0);
+    }
+    eprintln!("{:>12}  {:>10.3}ms", "total", total.as_secs_f64() * 1000.0);
+}
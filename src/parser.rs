@@ -1,12 +1,22 @@
 use crate::lex::*;
 use crate::transpiler::type_checker::*;
 
+// How an Assignment binds its variable: declaring it with `let` or `let mut`, or
+// assigning to one that is already declared
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    Let,
+    LetMut,
+    Assign,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum AstNode {
     Assignment {
         variable: String,
         tp: Option<String>,
         value: Box<AstNode>,
+        binding: Binding,
     },
     BinaryOperation {
         operator: Token,
@@ -21,6 +31,7 @@ pub enum AstNode {
         arguments: Vec<(String, String)>, // (type, name)
         return_type: String,
         body: Box<AstNode>,
+        attributes: Vec<String>, // @inline, @noinline
     },
     FunctionCall {
         name: String,
@@ -73,9 +84,126 @@ pub enum AstNode {
     Comment {
         value: String,
     },
+    Import {
+        path: String, // as written, `util` or "path/util.tsl"
+    },
+    Public {
+        item: Box<AstNode>, // a func, struct, class or let other modules can use
+    },
+    Class {
+        name: String,
+        parent: Option<String>,
+        fields: Vec<(String, String)>, // (type, name), after the parent's
+        methods: Vec<AstNode>,         // Functions named with method_symbol, self first
+    },
+    FieldAccess {
+        object: Box<AstNode>,
+        field: String,
+    },
+    FieldAssignment {
+        object: Box<AstNode>,
+        field: String,
+        value: Box<AstNode>,
+    },
+    MethodCall {
+        object: Box<AstNode>,
+        method: String,
+        arguments: Vec<AstNode>,
+    },
+    // `super.method(...)` in a method of class, arguments start with `self`
+    SuperCall {
+        class: String,
+        method: String,
+        arguments: Vec<AstNode>,
+    },
+    New {
+        class: String,
+        arguments: Vec<AstNode>, // every field, inherited ones first
+    },
+    Interface {
+        name: String,
+        methods: Vec<(String, Vec<String>, String)>, // (name, parameter types after self, return type)
+    },
+    Impl {
+        interface: String,
+        target: String,        // as written, impl_receiver gives the type of self
+        methods: Vec<AstNode>, // Functions named with impl_symbol, self first
+    },
+    // `func(x Int) Int | body |`, a function value. resolve_names names it after the
+    // function it is in and lists the variables it captures as (outer, its own copy).
+    Lambda {
+        name: String,
+        arguments: Vec<(String, String)>, // (type, name)
+        return_type: String,
+        body: Box<AstNode>,
+        captures: Vec<(String, String)>,
+    },
+    // A call through a function value, `f(x)` when f is a variable or `make()(x)`
+    Call {
+        callee: Box<AstNode>,
+        arguments: Vec<AstNode>,
+    },
+    // `(a, b)`, its elements are read with a FieldAccess of their index, `t.0`
+    Tuple {
+        elements: Vec<AstNode>,
+    },
+    // `Some(value)`, `None`, `Ok(value)` or `Err(error)`
+    Variant {
+        variant: String,
+        value: Option<Box<AstNode>>,
+    },
+    // `value?`, what the Option or Result holds, returning early from the function when
+    // it holds nothing or an error
+    Try {
+        value: Box<AstNode>,
+    },
+    // A func, struct or class with type parameters, each with the interface it must
+    // implement if any. Monomorphized away before overloads are resolved.
+    Generic {
+        params: Vec<(String, Option<String>)>,
+        item: Box<AstNode>,
+    },
     Eof,
 }
 
+// Methods are functions named `Class__method`, which C can use as is
+pub fn method_symbol(class: &str, method: &str) -> String {
+    format!("{}__{}", class, method)
+}
+
+// The method a function made by method_symbol implements
+pub fn method_name<'a>(class: &str, function: &'a str) -> &'a str {
+    function
+        .strip_prefix(class)
+        .and_then(|name| name.strip_prefix("__"))
+        .unwrap_or(function)
+}
+
+// Methods of an impl are functions named `Target__Interface__method`
+pub fn impl_symbol(interface: &str, target: &str, method: &str) -> String {
+    let unqualified = |name: &str| name.rsplit("::").next().unwrap_or(name).to_string();
+    format!(
+        "{}__{}__{}",
+        unqualified(target).replace('*', "Ptr"),
+        unqualified(interface),
+        method
+    )
+}
+
+// The method a function made by impl_symbol implements
+pub fn impl_method_name(function: &str) -> &str {
+    function.rsplit("__").next().unwrap_or(function)
+}
+
+// Classes and structs are handled by pointer everywhere else, so an impl for one takes
+// `self` by pointer, other types are taken by value
+pub fn impl_receiver(target: &str) -> String {
+    match Type::from(target) {
+        Type::DataTp(_) => format!("{}*", target),
+        _ => target.to_string(),
+    }
+}
+
 impl AstNode {
     pub fn get_statements(&self) -> Vec<AstNode> {
         match self {
@@ -100,8 +228,14 @@ impl AstNode {
             AstNode::Assignment { value, .. } => vec![value.as_mut()],
             AstNode::BinaryOperation { left, right, .. } => vec![left.as_mut(), right.as_mut()],
             AstNode::Block { statements } => statements.iter_mut().collect(),
-            AstNode::Function { body, .. } => vec![body.as_mut()],
-            AstNode::FunctionCall { arguments, .. } => arguments.iter_mut().collect(),
+            AstNode::Function { body, .. } | AstNode::Lambda { body, .. } => vec![body.as_mut()],
+            AstNode::FunctionCall { arguments, .. }
+            | AstNode::Tuple {
+                elements: arguments,
+            } => arguments.iter_mut().collect(),
+            AstNode::Call { callee, arguments } => std::iter::once(callee.as_mut())
+                .chain(arguments.iter_mut())
+                .collect(),
             AstNode::If {
                 condition,
                 consequence,
@@ -113,7 +247,28 @@ impl AstNode {
                 }
                 children
             }
+            AstNode::While { condition, body } => vec![condition.as_mut(), body.as_mut()],
             AstNode::Return { value } => vec![value.as_mut()],
+            AstNode::Pointer { value }
+            | AstNode::Dereference { value }
+            | AstNode::Try { value } => vec![value.as_mut()],
+            AstNode::Variant { value, .. } => {
+                value.iter_mut().map(|value| value.as_mut()).collect()
+            }
+            AstNode::Generic { item, .. } => vec![item.as_mut()],
+            AstNode::Class { methods, .. } | AstNode::Impl { methods, .. } => {
+                methods.iter_mut().collect()
+            }
+            AstNode::FieldAccess { object, .. } => vec![object.as_mut()],
+            AstNode::FieldAssignment { object, value, .. } => vec![object.as_mut(), value.as_mut()],
+            AstNode::MethodCall {
+                object, arguments, ..
+            } => std::iter::once(object.as_mut())
+                .chain(arguments.iter_mut())
+                .collect(),
+            AstNode::SuperCall { arguments, .. } | AstNode::New { arguments, .. } => {
+                arguments.iter_mut().collect()
+            }
             _ => vec![],
         }
     }
@@ -129,7 +284,7 @@ impl AstNode {
                 }
             }
             AstNode::Variable { .. } => Type::NotMentioned,
-            AstNode::FunctionCall { name, .. } => Type::NotMentioned,
+            AstNode::FunctionCall { .. } => Type::NotMentioned,
             AstNode::BinaryOperation { operator, .. } => Type::from(operator.value.as_str()),
             AstNode::Assignment { tp, .. } => Type::from(tp.as_ref().unwrap().as_str()),
             AstNode::Return { value } => value.get_type(temp_checker),
@@ -141,25 +296,59 @@ impl AstNode {
     }
 }
 
+// Type parameters as (name, interface it must implement)
+type TypeParams = Vec<(String, Option<String>)>;
+
+// A function's name, type parameters, (type, name) arguments and return type
+type Signature = (String, TypeParams, Vec<(String, String)>, String);
+
+// Who a method belongs to: the type its `self` has and how its function is named
+struct Receiver {
+    owner: String,
+    self_type: String,
+    symbol: Box<dyn Fn(&str) -> String>,
+}
+
+#[derive(Clone)]
 pub struct Parser {
     tokens: Vec<Token>,
     position: usize,
     pub temp_checker: TypeChecker,
+    // The class whose methods are being parsed, for `super`
+    class: Option<String>,
+    // Lambdas parsed so far this pass, to name the next one
+    lambdas: usize,
+    // Tuples destructured so far this pass, to name the variable holding the next one
+    tuples: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        let mut temp_checker = TypeChecker::new();
         Parser {
             tokens,
             position: 0,
             temp_checker: TypeChecker::new(),
+            class: None,
+            lambdas: 0,
+            tuples: 0,
         }
     }
 
-    pub fn parse(&mut self) -> AstNode {
-        self.parse_program(); // first pass to get all types
+    pub fn parse(&mut self) -> Result<AstNode, String> {
+        let tokens = std::mem::take(&mut self.tokens);
+        self.parse_more(tokens)
+    }
+
+    // Parses more of a program, like the next REPL input. What the parser learnt from the
+    // earlier parts stays known, and lambdas and tuples go on being numbered after theirs.
+    pub fn parse_more(&mut self, tokens: Vec<Token>) -> Result<AstNode, String> {
+        self.tokens = tokens;
+        self.position = 0;
+        let (lambdas, tuples) = (self.lambdas, self.tuples);
+        self.parse_program()?; // first pass to get all types
         self.position = 0;
+        self.lambdas = lambdas;
+        self.tuples = tuples;
         self.parse_program()
     }
     fn parse_comment(&mut self) -> AstNode {
@@ -167,10 +356,10 @@ impl Parser {
         self.position += 1;
         AstNode::Comment { value }
     }
-    fn parse_program(&mut self) -> AstNode {
+    fn parse_program(&mut self) -> Result<AstNode, String> {
         let mut statements = Vec::new();
         while self.current_token().token_type != TokenType::EOF {
-            if let Some(statement) = self.parse_statement() {
+            if let Some(statement) = self.parse_statement()? {
                 statements.push(statement);
             } else {
                 self.position += 1; // Move to the next token to avoid infinite loop
@@ -180,7 +369,7 @@ impl Parser {
         // Go over the AST and check if all types are defined
         // Manually go down every branch searching for NotMentioned, if found, use check to find the type
         // If check fails, return error
-        AstNode::Block { statements }
+        Ok(AstNode::Block { statements })
     }
 
     fn parse_void(&mut self) -> AstNode {
@@ -188,7 +377,7 @@ impl Parser {
         AstNode::Null
     }
 
-    fn parse_statement(&mut self) -> Option<AstNode> {
+    fn parse_statement(&mut self) -> Result<Option<AstNode>, String> {
         match (
             self.current_token().token_type,
             self.current_token().value.as_str(),
@@ -197,31 +386,40 @@ impl Parser {
                 if self.peek_token().token_type == TokenType::Assignment {
                     self.parse_assignment()
                 } else {
-                    Some(self.parse_expression())
+                    self.parse_expression_statement().map(Some)
                 }
             }
             (TokenType::Keyword, "let") => self.parse_let_statement(),
             (TokenType::Keyword, "if") => self.parse_if_statement(),
             (TokenType::Keyword, "while") => self.parse_while_statement(),
-            (TokenType::Keyword, "func") => self.parse_function(),
+            (TokenType::Keyword, "func")
+                if self.peek_token().token_type != TokenType::LeftParen =>
+            {
+                self.parse_function(vec![], None)
+            }
+            (TokenType::Attribute, _) => self.parse_attributes(),
             (TokenType::Keyword, "return") => self.parse_return_statement(),
             (TokenType::Keyword, "struct") => self.parse_struct(),
-            (TokenType::Keyword, "true") => Some(self.parse_true()),
-            (TokenType::Keyword, "false") => Some(self.parse_false()),
-            (TokenType::Void, _) => Some(self.parse_void()),
-            (TokenType::True, _) => Some(self.parse_true()),
-            (TokenType::False, _) => Some(self.parse_false()),
-            (TokenType::Comment, v) => Some(self.parse_comment()),
-            (TokenType::TypeName, _) => Some(self.parse_type()),
+            (TokenType::Keyword, "class") => self.parse_class(),
+            (TokenType::Keyword, "interface") => self.parse_interface(),
+            (TokenType::Keyword, "impl") => self.parse_impl(),
+            (TokenType::Keyword, "import") => self.parse_import(),
+            (TokenType::Keyword, "pub") => self.parse_public(),
+            (TokenType::Keyword, "true") => Ok(Some(self.parse_true())),
+            (TokenType::Keyword, "false") => Ok(Some(self.parse_false())),
+            (TokenType::Void, _) => Ok(Some(self.parse_void())),
+            (TokenType::True, _) => Ok(Some(self.parse_true())),
+            (TokenType::False, _) => Ok(Some(self.parse_false())),
+            (TokenType::Comment, _) => Ok(Some(self.parse_comment())),
+            (TokenType::TypeName, _) => Ok(Some(self.parse_type())),
             // (TokenType::Block, _) => None,
-            _ => Some(self.parse_expression()),
+            _ => self.parse_expression().map(Some),
         }
     }
     fn parse_type(&mut self) -> AstNode {
         // Depending on type, create a default value of that type
         let tp = self.current_token().value.clone();
         self.position += 1; // Skip type name
-        println!("Type: {}", tp);
         match tp.as_str() {
             "Int" => AstNode::Number { value: 0 },
             "Char" => AstNode::Char {
@@ -236,36 +434,41 @@ impl Parser {
             _ => AstNode::Null,
         }
     }
-    fn parse_let_statement(&mut self) -> Option<AstNode> {
+    fn parse_let_statement(&mut self) -> Result<Option<AstNode>, String> {
         self.position += 1; // Skip 'let'
+        let binding = if self.current_token().value == "mut" {
+            self.position += 1; // Skip 'mut'
+            Binding::LetMut
+        } else {
+            Binding::Let
+        };
+        if self.current_token().token_type == TokenType::LeftParen {
+            return self.parse_destructuring(binding).map(Some);
+        }
         let variable = self.current_token().value.clone();
         self.position += 1; // Skip variable name
-        let tp = if self.current_token().token_type == TokenType::Identifier
-            || self.current_token().token_type == TokenType::TypeName
-        {
-            let tp = self.current_token().value.clone();
-            self.position += 1; // Skip type name
-            Some(tp)
+        let tp = if self.at_type() || self.current_token().token_type == TokenType::TypeName {
+            Some(self.parse_type_name()?)
         } else {
             Some("NotMentioned".to_string())
         };
         self.position += 1; // Skip '='
         if self.current_token().token_type == TokenType::Newline {
             self.position -= 1;
-            return Some(AstNode::Assignment {
+            return Ok(Some(AstNode::Assignment {
                 variable,
                 tp: tp.clone(),
                 value: Box::new(AstNode::Uninit { tp: tp.unwrap() }),
-            });
+                binding,
+            }));
         }
-        let mut value = self.parse_expression();
+        let mut value = self.parse_expression()?;
         let tp = if let Some(tp) = tp.clone() {
             if tp == "NotMentioned" {
-                let value_type = self.temp_checker.check(&mut value);
-                if let Ok(value_type) = value_type {
-                    Some(String::from(value_type))
-                } else {
-                    Some(tp)
+                // A value naming a variable that couldn't be typed yet stays untyped
+                match self.temp_checker.check(&mut value) {
+                    Ok(value_type) if value_type.is_known() => Some(String::from(value_type)),
+                    _ => Some(tp),
                 }
             } else {
                 Some(tp)
@@ -276,12 +479,63 @@ impl Parser {
         self.temp_checker
             .symbol_table
             .insert(variable.clone(), Type::from(tp.clone().unwrap()));
+        self.temp_checker.declare(&variable, binding);
         self.skip_semicolon();
-        Some(AstNode::Assignment {
+        Ok(Some(AstNode::Assignment {
             variable,
             tp,
             value: Box::new(value),
-        })
+            binding,
+        }))
+    }
+
+    // `let (q, r) = value` is a variable holding the tuple, then one for each of its
+    // elements: `let _tuple0 = value`, `let q = _tuple0.0` and `let r = _tuple0.1`
+    fn parse_destructuring(&mut self, binding: Binding) -> Result<AstNode, String> {
+        self.position += 1; // Skip '('
+        let mut variables = Vec::new();
+        while self.current_token().token_type != TokenType::RightParen {
+            variables.push(self.expect_identifier("in 'let (...)'")?);
+            if self.current_token().token_type == TokenType::Comma {
+                self.position += 1; // Skip ','
+            }
+        }
+        self.position += 1; // Skip ')'
+        if variables.len() < 2 {
+            return Err("'let (...)' needs a name for each element of a tuple".to_string());
+        }
+        if self.current_token().token_type != TokenType::Assignment {
+            return Err(format!(
+                "Expected '=' after 'let ({})'",
+                variables.join(", ")
+            ));
+        }
+        self.position += 1; // Skip '='
+        let value = self.parse_expression()?;
+        self.skip_semicolon();
+        let tuple = format!("_tuple{}", self.tuples);
+        self.tuples += 1;
+        let mut statements = vec![AstNode::Assignment {
+            variable: tuple.clone(),
+            tp: Some("NotMentioned".to_string()),
+            value: Box::new(value),
+            binding: Binding::Let,
+        }];
+        for (index, variable) in variables.into_iter().enumerate() {
+            self.temp_checker.declare(&variable, binding);
+            statements.push(AstNode::Assignment {
+                variable,
+                tp: Some("NotMentioned".to_string()),
+                value: Box::new(AstNode::FieldAccess {
+                    object: Box::new(AstNode::Identifier {
+                        value: tuple.clone(),
+                    }),
+                    field: index.to_string(),
+                }),
+                binding,
+            });
+        }
+        Ok(AstNode::Block { statements })
     }
 
     fn parse_true(&mut self) -> AstNode {
@@ -298,32 +552,33 @@ impl Parser {
         }
     }
 
-    fn parse_assignment(&mut self) -> Option<AstNode> {
+    fn parse_assignment(&mut self) -> Result<Option<AstNode>, String> {
         let variable = self.current_token().value.clone();
         self.position += 1; // Skip variable name
-        let tp = if self.current_token().token_type == TokenType::Identifier
-            || self.current_token().token_type == TokenType::TypeName
-        {
-            let tp = self.current_token().value.clone();
-            self.position += 1; // Skip type name
-            Some(tp)
+        let tp = if self.at_type() || self.current_token().token_type == TokenType::TypeName {
+            Some(self.parse_type_name()?)
         } else {
             Some("NotMentioned".to_string())
         };
         self.position += 1; // Skip '='
         if self.current_token().token_type == TokenType::Newline {
             self.position -= 1;
-            return Some(AstNode::Assignment {
+            return Ok(Some(AstNode::Assignment {
                 variable,
                 tp: tp.clone(),
                 value: Box::new(AstNode::Uninit { tp: tp.unwrap() }),
-            });
+                binding: Binding::Assign,
+            }));
         }
-        let mut value = self.parse_expression();
+        let mut value = self.parse_expression()?;
         let tp = if let Some(tp) = tp.clone() {
             if tp == "NotMentioned" {
-                let value_type = self.temp_checker.check(&mut value).unwrap();
-                Some(String::from(value_type))
+                // Left to the type checker when the value can't be typed yet, like a call
+                // to a generic function
+                match self.temp_checker.check(&mut value) {
+                    Ok(value_type) if value_type.is_known() => Some(String::from(value_type)),
+                    _ => Some(tp),
+                }
             } else {
                 Some(tp)
             }
@@ -331,140 +586,719 @@ impl Parser {
             tp
         };
         self.skip_semicolon();
-        Some(AstNode::Assignment {
+        Ok(Some(AstNode::Assignment {
             variable,
             tp,
             value: Box::new(value),
-        })
+            binding: Binding::Assign,
+        }))
     }
 
-    fn parse_if_statement(&mut self) -> Option<AstNode> {
+    fn parse_if_statement(&mut self) -> Result<Option<AstNode>, String> {
         self.position += 1; // Skip 'if'
-        let condition = self.parse_expression();
+        let condition = self.parse_expression()?;
         self.position += 1; // Skip ':'
-        let consequence = self.parse_block();
+        let consequence = self.parse_block()?;
         let alternative = if self.current_token().value == "else" {
             self.position += 2; // Skip 'else' and ':'
-            Some(Box::new(self.parse_block()))
+            Some(Box::new(self.parse_block()?))
         } else {
             None
         };
-        Some(AstNode::If {
+        Ok(Some(AstNode::If {
             condition: Box::new(condition),
             consequence: Box::new(consequence),
             alternative,
-        })
+        }))
     }
 
-    fn parse_while_statement(&mut self) -> Option<AstNode> {
+    fn parse_while_statement(&mut self) -> Result<Option<AstNode>, String> {
         self.position += 1; // Skip 'while'
-        let condition = self.parse_expression();
-        let body = self.parse_block();
-        Some(AstNode::While {
+        let condition = self.parse_expression()?;
+        self.position += 1; // Skip ':'
+        let body = self.parse_block()?;
+        Ok(Some(AstNode::While {
             condition: Box::new(condition),
             body: Box::new(body),
-        })
+        }))
+    }
+
+    fn parse_public(&mut self) -> Result<Option<AstNode>, String> {
+        self.position += 1; // Skip 'pub'
+        let item = match (
+            self.current_token().token_type,
+            self.current_token().value.as_str(),
+        ) {
+            (TokenType::Keyword, "func" | "struct" | "class" | "interface" | "let")
+            | (TokenType::Attribute, _) => self.parse_statement()?,
+            _ => None,
+        };
+        match item {
+            Some(item) => Ok(Some(AstNode::Public {
+                item: Box::new(item),
+            })),
+            None => Err(
+                "'pub' can only be applied to func, struct, class, interface and let".to_string(),
+            ),
+        }
+    }
+
+    fn parse_attributes(&mut self) -> Result<Option<AstNode>, String> {
+        let mut attributes = Vec::new();
+        while self.current_token().token_type == TokenType::Attribute {
+            attributes.push(self.current_token().value.clone());
+            self.position += 1; // Skip attribute
+        }
+        // `@inline pub func` is the same as `pub @inline func`
+        let public = self.current_token().value == "pub";
+        if public {
+            self.position += 1; // Skip 'pub'
+        }
+        if self.current_token().value != "func" {
+            return Err("Attributes can only be applied to functions".to_string());
+        }
+        let function = self.parse_function(attributes, None)?;
+        if public {
+            Ok(function.map(|item| AstNode::Public {
+                item: Box::new(item),
+            }))
+        } else {
+            Ok(function)
+        }
     }
 
-    fn parse_function(&mut self) -> Option<AstNode> {
+    // A method when receiver is given, which takes `self` first
+    fn parse_function(
+        &mut self,
+        attributes: Vec<String>,
+        receiver: Option<&Receiver>,
+    ) -> Result<Option<AstNode>, String> {
+        let (name, params, arguments, return_type) = self.parse_signature(receiver)?;
+        self.position += 1;
+        // The arguments are known while the body is parsed, so `let` can infer from them,
+        // e.g. from `self` in `let x = super.get()`
+        let outer: Vec<(String, Option<Type>)> = arguments
+            .iter()
+            .map(|(tp, arg)| {
+                let outer = self
+                    .temp_checker
+                    .symbol_table
+                    .insert(arg.clone(), Type::from(tp.as_str()));
+                (arg.clone(), outer)
+            })
+            .collect();
+        let body = self.parse_block();
+        for (arg, outer) in outer.into_iter().rev() {
+            match outer {
+                Some(tp) => self.temp_checker.symbol_table.insert(arg, tp),
+                None => self.temp_checker.symbol_table.remove(&arg),
+            };
+        }
+        let body = Box::new(body?);
+        let function = AstNode::Function {
+            name: name.clone(),
+            arguments: arguments.clone(),
+            return_type: return_type.clone(),
+            body,
+            attributes,
+        };
+        // Only instances of a generic function are ever called
+        if !params.is_empty() {
+            return Ok(Some(Self::generic(params, function)));
+        }
+        // Add function to symbol table
+        let mut arg_types = Vec::new();
+        for (arg_type, _) in arguments.iter() {
+            arg_types.push(Type::from(arg_type.as_str()));
+        }
+        self.temp_checker
+            .function_table // Type HashMap<String, (Vec<Type>, Vec<Type>)>,
+            .insert(
+                name.clone(),
+                (arg_types, return_types(Type::from(return_type.as_str()))),
+            );
+        Ok(Some(function))
+    }
+
+    // `func name(arguments) ReturnType`, up to the body
+    fn parse_signature(&mut self, receiver: Option<&Receiver>) -> Result<Signature, String> {
         self.position += 1; // Skip 'func'
-        let name = self.current_token().value.clone();
+        let mut name = self.current_token().value.clone();
         self.position += 1; // Skip function name
+        let params = self.parse_type_params()?;
+        if let (Some(receiver), false) = (receiver, params.is_empty()) {
+            return Err(format!(
+                "Method '{}' of {} can't have type parameters",
+                name, receiver.owner
+            ));
+        }
         self.position += 1; // Skip '('
+        let mut arguments = Vec::new();
+        if let Some(receiver) = receiver {
+            if self.current_token().value != "self" {
+                return Err(format!(
+                    "Method '{}' of {} needs self as its first parameter",
+                    name, receiver.owner
+                ));
+            }
+            self.position += 1; // Skip 'self'
+            arguments.push((receiver.self_type.clone(), "self".to_string()));
+            name = (receiver.symbol)(&name);
+            if self.current_token().token_type == TokenType::Comma {
+                self.position += 1; // Skip ','
+            }
+        }
+        arguments.extend(self.parse_parameters()?);
+        let return_type = self.parse_type_name()?;
+        Ok((name, params, arguments, return_type))
+    }
+
+    // The `name Type` parameters of a func or lambda after the '(', up to the ')'
+    fn parse_parameters(&mut self) -> Result<Vec<(String, String)>, String> {
         let mut arguments = Vec::new();
         while self.current_token().token_type == TokenType::Identifier {
             let arg_name = self.current_token().value.clone();
             self.position += 1; // Skip argument name
-            let arg_type = self.current_token().value.clone();
-            self.position += 1; // Skip argument type
+            if arg_name == "self" {
+                return Err("Only methods can take self, as their first parameter".to_string());
+            }
+            let arg_type = self.parse_type_name()?;
             arguments.push((arg_type, arg_name));
             if self.current_token().token_type == TokenType::Comma {
                 self.position += 1; // Skip ','
             }
         }
         self.position += 1; // Skip ')'
-        let return_type = self.current_token().value.clone();
-        self.position += 1; // Skip return type
-        self.position += 1;
-        let body = Box::new(self.parse_block());
-        // Add function to symbol table
-        let mut arg_types = Vec::new();
-        for (arg_type, _) in arguments.iter() {
-            arg_types.push(Type::from(arg_type.as_str()));
-        }
-        self.temp_checker
-            .function_table // Type HashMap<String, (Vec<Type>, Vec<Type>)>,
-            .insert(
-                name.clone(),
-                (arg_types, vec![Type::from(return_type.as_str())]),
-            );
-        Some(AstNode::Function {
+        Ok(arguments)
+    }
+
+    fn parse_lambda(&mut self) -> Result<AstNode, String> {
+        self.position += 1; // Skip 'func'
+        self.position += 1; // Skip '('
+        let arguments = self.parse_parameters()?;
+        let return_type = self.parse_type_name()?;
+        self.position += 1; // Skip '|'
+        let body = Box::new(self.parse_block()?);
+        let name = format!("lambda{}", self.lambdas);
+        self.lambdas += 1;
+        Ok(AstNode::Lambda {
             name,
             arguments,
             return_type,
             body,
+            captures: vec![],
         })
     }
 
-    fn parse_return_statement(&mut self) -> Option<AstNode> {
+    // `<T>` or `<T: Interface, U>` after the name of a func, struct or class
+    fn parse_type_params(&mut self) -> Result<TypeParams, String> {
+        let token = self.current_token().clone();
+        if token.token_type == TokenType::TypeName {
+            self.position += 1; // Skip '<T>'
+            return Ok(vec![(token.value, None)]);
+        }
+        if token.token_type != TokenType::Comparison || token.value != "<" {
+            return Ok(vec![]);
+        }
+        self.position += 1; // Skip '<'
+        let mut params = Vec::new();
+        loop {
+            let name = self.expect_identifier("for a type parameter")?;
+            let bound = if self.current_token().token_type == TokenType::Colon {
+                self.position += 1; // Skip ':'
+                Some(self.expect_identifier(&format!("after '{}:'", name))?)
+            } else {
+                None
+            };
+            params.push((name, bound));
+            if !self.end_of_type_list("type parameters")? {
+                return Ok(params);
+            }
+        }
+    }
+
+    // The `<Int>` or `<Int, String>` after the name of a generic type
+    fn parse_type_args(&mut self) -> Result<Vec<String>, String> {
+        let token = self.current_token().clone();
+        if token.token_type == TokenType::TypeName {
+            self.position += 1; // Skip '<Type>'
+            return Ok(vec![token.value]);
+        }
+        if token.token_type != TokenType::Comparison || token.value != "<" {
+            return Ok(vec![]);
+        }
+        self.position += 1; // Skip '<'
+        let mut args = Vec::new();
+        loop {
+            args.push(self.parse_type_name()?);
+            if !self.end_of_type_list("type arguments")? {
+                return Ok(args);
+            }
+        }
+    }
+
+    // Skips the ',' or '>' after an item of a `<...>` list, true when more items follow
+    fn end_of_type_list(&mut self, list: &str) -> Result<bool, String> {
+        let token = self.current_token();
+        let more = match (token.token_type, token.value.as_str()) {
+            (TokenType::Comma, _) => true,
+            (TokenType::Comparison, ">") => false,
+            _ => {
+                return Err(format!(
+                    "Expected ',' or '>' in {}, found {:?}",
+                    list, token
+                ))
+            }
+        };
+        self.position += 1; // Skip ',' or '>'
+        Ok(more)
+    }
+
+    // The name of a type with any type arguments, `Pair<Int, String>` is kept as written
+    // until it is monomorphized. The stars of a pointer to one come after the '>'.
+    fn with_type_args(&mut self, name: String) -> Result<String, String> {
+        let args = self.parse_type_args()?;
+        if args.is_empty() {
+            return Ok(name);
+        }
+        let mut tp = format!("{}<{}>", name, args.join(", "));
+        while self.current_token().token_type == TokenType::Operator
+            && self.current_token().value == "*"
+        {
+            tp.push('*');
+            self.position += 1; // Skip '*'
+        }
+        Ok(tp)
+    }
+
+    // A type in a declaration, a single name, `dyn Interface`, `Func(Int, Int) Int`, a
+    // tuple `(Int, String)` or a pointer that can't change what it points at, `const Int*`
+    fn parse_type_name(&mut self) -> Result<String, String> {
+        if self.current_token().value == "const" {
+            self.position += 1; // Skip 'const'
+            let tp = self.parse_type_name()?;
+            if !tp.ends_with('*') {
+                return Err(format!(
+                    "Only pointer types can be const, found 'const {}'",
+                    tp
+                ));
+            }
+            return Ok(format!("const {}", tp));
+        }
+        if self.current_token().token_type == TokenType::LeftParen {
+            self.position += 1; // Skip '('
+            let mut types = Vec::new();
+            while self.current_token().token_type != TokenType::RightParen {
+                if self.current_token().token_type == TokenType::EOF {
+                    return Err("Unexpected end of file in a tuple type".to_string());
+                }
+                types.push(self.parse_type_name()?);
+                if self.current_token().token_type == TokenType::Comma {
+                    self.position += 1; // Skip ','
+                }
+            }
+            self.position += 1; // Skip ')'
+            if types.len() < 2 {
+                return Err(format!(
+                    "A tuple type needs two or more types, found ({})",
+                    types.join(", ")
+                ));
+            }
+            return Ok(format!("({})", types.join(", ")));
+        }
+        if self.current_token().value == "dyn" {
+            self.position += 1; // Skip 'dyn'
+            let interface = self.expect_identifier("after 'dyn'")?;
+            return Ok(format!("dyn {}", interface));
+        }
+        let tp = self.current_token().value.clone();
+        self.position += 1; // Skip type name
+        if tp == "Func" && self.current_token().token_type == TokenType::LeftParen {
+            self.position += 1; // Skip '('
+            let mut params = Vec::new();
+            while self.current_token().token_type != TokenType::RightParen {
+                if self.current_token().token_type == TokenType::EOF {
+                    return Err("Unexpected end of file in a Func type".to_string());
+                }
+                params.push(self.parse_type_name()?);
+                if self.current_token().token_type == TokenType::Comma {
+                    self.position += 1; // Skip ','
+                }
+            }
+            self.position += 1; // Skip ')'
+            let ret = self.parse_type_name()?;
+            return Ok(format!("Func({}) {}", params.join(", "), ret));
+        }
+        self.with_type_args(tp)
+    }
+
+    fn at_type(&self) -> bool {
+        let token = self.current_token();
+        token.token_type == TokenType::Identifier
+            || token.token_type == TokenType::LeftParen
+            || (token.token_type == TokenType::Keyword
+                && matches!(token.value.as_str(), "dyn" | "const"))
+    }
+
+    fn parse_return_statement(&mut self) -> Result<Option<AstNode>, String> {
         self.position += 1; // Skip 'return'
-        let value = self.parse_expression();
+        let mut value = self.parse_expression()?;
+        // `return a, b` returns the tuple `(a, b)`
+        if self.current_token().token_type == TokenType::Comma {
+            let mut elements = vec![value];
+            while self.current_token().token_type == TokenType::Comma {
+                self.position += 1; // Skip ','
+                elements.push(self.parse_expression()?);
+            }
+            value = AstNode::Tuple { elements };
+        }
         self.skip_semicolon();
-        Some(AstNode::Return {
+        Ok(Some(AstNode::Return {
             value: Box::new(value),
-        })
+        }))
     }
 
-    fn parse_struct(&mut self) -> Option<AstNode> {
+    fn parse_struct(&mut self) -> Result<Option<AstNode>, String> {
         self.position += 1; // Skip 'struct'
         let name = self.current_token().value.clone();
         self.position += 1; // Skip struct name
+        let params = self.parse_type_params()?;
         self.position += 1; // Skip '|'
         let mut fields = Vec::new();
-        while self.current_token().token_type == TokenType::Identifier {
-            let field_type = self.current_token().value.clone();
-            self.position += 1; // Skip field type
+        while self.at_type() {
+            let field_type = self.parse_type_name()?;
             let field_name = self.current_token().value.clone();
             self.position += 1; // Skip field name
             fields.push((field_type, field_name));
         }
         self.position += 1; // Skip '|'
-        Some(AstNode::Struct { name, fields })
+        let def = Self::generic(params, AstNode::Struct { name, fields });
+        self.temp_checker.prelude(&def);
+        Ok(Some(def))
+    }
+
+    fn parse_class(&mut self) -> Result<Option<AstNode>, String> {
+        self.position += 1; // Skip 'class'
+        let name = self.current_token().value.clone();
+        self.position += 1; // Skip class name
+        let params = self.parse_type_params()?;
+        let parent = if self.current_token().token_type == TokenType::Colon {
+            self.position += 1; // Skip ':'
+            let parent = self.current_token().value.clone();
+            self.position += 1; // Skip parent name
+            Some(self.with_type_args(parent)?)
+        } else {
+            None
+        };
+        self.position += 1; // Skip '|'
+        let mut fields = Vec::new();
+        let mut methods = Vec::new();
+        // Inside a generic class, the class itself is `Name<T>`
+        let this = if params.is_empty() {
+            name.clone()
+        } else {
+            let names: Vec<&str> = params.iter().map(|(param, _)| param.as_str()).collect();
+            format!("{}<{}>", name, names.join(", "))
+        };
+        let outer = self.class.replace(this.clone());
+        let receiver = Receiver {
+            owner: format!("class {}", name),
+            self_type: format!("{}*", this),
+            symbol: Box::new({
+                let name = name.clone();
+                move |method| method_symbol(&name, method)
+            }),
+        };
+        loop {
+            let token = self.current_token().clone();
+            match (token.token_type, token.value.as_str()) {
+                _ if self.at_type() => {
+                    let field_type = self.parse_type_name()?;
+                    let field_name = self.current_token().value.clone();
+                    self.position += 1; // Skip field name
+                    fields.push((field_type, field_name));
+                }
+                (TokenType::Keyword, "func") | (TokenType::Attribute, _) => {
+                    let mut attributes = Vec::new();
+                    while self.current_token().token_type == TokenType::Attribute {
+                        attributes.push(self.current_token().value.clone());
+                        self.position += 1; // Skip attribute
+                    }
+                    if self.current_token().value != "func" {
+                        return Err("Attributes can only be applied to functions".to_string());
+                    }
+                    methods.extend(self.parse_function(attributes, Some(&receiver))?);
+                }
+                (TokenType::Comment, _) => self.position += 1,
+                (TokenType::Block, _) => {
+                    self.position += 1; // Skip '|'
+                    break;
+                }
+                _ => {
+                    return Err(format!(
+                        "Expected a field or method in class {}, found {:?}",
+                        name, token
+                    ))
+                }
+            }
+        }
+        self.class = outer;
+        let class = Self::generic(
+            params,
+            AstNode::Class {
+                name,
+                parent,
+                fields,
+                methods,
+            },
+        );
+        self.temp_checker.prelude(&class);
+        Ok(Some(class))
+    }
+
+    fn generic(params: TypeParams, item: AstNode) -> AstNode {
+        if params.is_empty() {
+            item
+        } else {
+            AstNode::Generic {
+                params,
+                item: Box::new(item),
+            }
+        }
+    }
+
+    // `interface Name | func method(self, ...) ReturnType ... |`
+    fn parse_interface(&mut self) -> Result<Option<AstNode>, String> {
+        self.position += 1; // Skip 'interface'
+        let name = self.expect_identifier("after 'interface'")?;
+        self.position += 1; // Skip '|'
+        let receiver = Receiver {
+            owner: format!("interface {}", name),
+            self_type: format!("dyn {}", name),
+            symbol: Box::new(|method| method.to_string()),
+        };
+        let mut methods = Vec::new();
+        loop {
+            let token = self.current_token().clone();
+            match (token.token_type, token.value.as_str()) {
+                (TokenType::Keyword, "func") => {
+                    let (method, _, arguments, return_type) =
+                        self.parse_signature(Some(&receiver))?;
+                    let params = arguments.into_iter().skip(1).map(|(tp, _)| tp).collect();
+                    methods.push((method, params, return_type));
+                }
+                (TokenType::Comment, _) => self.position += 1,
+                (TokenType::Block, _) => {
+                    self.position += 1; // Skip '|'
+                    break;
+                }
+                _ => {
+                    return Err(format!(
+                        "Expected a method signature in interface {}, found {:?}",
+                        name, token
+                    ))
+                }
+            }
+        }
+        let interface = AstNode::Interface { name, methods };
+        self.temp_checker.prelude(&interface);
+        Ok(Some(interface))
     }
 
-    fn parse_block(&mut self) -> AstNode {
+    // `impl Interface for Type | func method(self, ...) ReturnType | ... | |`
+    fn parse_impl(&mut self) -> Result<Option<AstNode>, String> {
+        self.position += 1; // Skip 'impl'
+        let interface = self.expect_identifier("after 'impl'")?;
+        if self.current_token().value != "for" {
+            return Err(format!("Expected 'for' after 'impl {}'", interface));
+        }
+        self.position += 1; // Skip 'for'
+        let target = self.expect_identifier(&format!("after 'impl {} for'", interface))?;
+        let target = self.with_type_args(target)?;
+        self.position += 1; // Skip '|'
+        let receiver = Receiver {
+            owner: format!("impl {} for {}", interface, target),
+            self_type: impl_receiver(&target),
+            symbol: Box::new({
+                let (interface, target) = (interface.clone(), target.clone());
+                move |method| impl_symbol(&interface, &target, method)
+            }),
+        };
+        let mut methods = Vec::new();
+        loop {
+            let token = self.current_token().clone();
+            match (token.token_type, token.value.as_str()) {
+                (TokenType::Keyword, "func") | (TokenType::Attribute, _) => {
+                    let mut attributes = Vec::new();
+                    while self.current_token().token_type == TokenType::Attribute {
+                        attributes.push(self.current_token().value.clone());
+                        self.position += 1; // Skip attribute
+                    }
+                    if self.current_token().value != "func" {
+                        return Err("Attributes can only be applied to functions".to_string());
+                    }
+                    methods.extend(self.parse_function(attributes, Some(&receiver))?);
+                }
+                (TokenType::Comment, _) => self.position += 1,
+                (TokenType::Block, _) => {
+                    self.position += 1; // Skip '|'
+                    break;
+                }
+                _ => {
+                    return Err(format!(
+                        "Expected a method in impl {} for {}, found {:?}",
+                        interface, target, token
+                    ))
+                }
+            }
+        }
+        let implementation = AstNode::Impl {
+            interface,
+            target,
+            methods,
+        };
+        self.temp_checker.prelude(&implementation);
+        Ok(Some(implementation))
+    }
+
+    fn parse_import(&mut self) -> Result<Option<AstNode>, String> {
+        self.position += 1; // Skip 'import'
+        let token = self.current_token();
+        if !matches!(
+            token.token_type,
+            TokenType::StringLiteral | TokenType::Identifier
+        ) {
+            return Err(format!(
+                "Expected a module after 'import', found {:?}",
+                token
+            ));
+        }
+        let path = token.value.clone();
+        self.position += 1; // Skip module
+        self.skip_semicolon();
+        Ok(Some(AstNode::Import { path }))
+    }
+
+    fn parse_block(&mut self) -> Result<AstNode, String> {
         let mut statements = Vec::new();
         while self.current_token().token_type != TokenType::Block {
-            if let Some(statement) = self.parse_statement() {
+            if self.current_token().token_type == TokenType::EOF {
+                return Err("Unexpected end of file, expected the end of a block".to_string());
+            }
+            if let Some(statement) = self.parse_statement()? {
                 statements.push(statement);
             } else {
                 self.position += 1; // Move to the next token to avoid infinite loop
             }
         }
         self.position += 1; // Skip '|'
-        AstNode::Block { statements }
+        Ok(AstNode::Block { statements })
     }
 
-    fn parse_expression(&mut self) -> AstNode {
-        let left = self.parse_primary();
-        if self.position < self.tokens.len()
+    fn parse_expression(&mut self) -> Result<AstNode, String> {
+        self.parse_binary(0)
+    }
+
+    // An expression, or an assignment to a field like `self.name = name`
+    fn parse_expression_statement(&mut self) -> Result<AstNode, String> {
+        let expression = self.parse_expression()?;
+        if self.current_token().token_type != TokenType::Assignment {
+            return Ok(expression);
+        }
+        let AstNode::FieldAccess { object, field } = expression else {
+            return Err(format!("Can't assign to {:?}", expression));
+        };
+        self.position += 1; // Skip '='
+        let value = self.parse_expression()?;
+        self.skip_semicolon();
+        Ok(AstNode::FieldAssignment {
+            object,
+            field,
+            value: Box::new(value),
+        })
+    }
+
+    // Precedence climbing, so the tree has the same shape C would give the expression
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<AstNode, String> {
+        let mut left = self.parse_primary()?;
+        while self.position < self.tokens.len()
             && self.is_operator(self.current_token().value.as_str())
         {
+            let precedence = self.precedence(self.current_token().value.as_str());
+            if precedence < min_precedence {
+                break;
+            }
             let operator = self.current_token().clone();
             self.position += 1; // Skip operator
-            let right = self.parse_expression();
-            AstNode::BinaryOperation {
+            let right = self.parse_binary(precedence + 1)?;
+            left = AstNode::BinaryOperation {
                 operator,
                 left: Box::new(left),
                 right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    // An operand followed by any number of `.field`, `.method(...)`, `(...)` and `?`
+    fn parse_primary(&mut self) -> Result<AstNode, String> {
+        let mut node = self.parse_operand()?;
+        loop {
+            if self.current_token().token_type == TokenType::Question {
+                self.position += 1; // Skip '?'
+                node = AstNode::Try {
+                    value: Box::new(node),
+                };
+                continue;
             }
-        } else {
-            left
+            if self.current_token().token_type == TokenType::LeftParen {
+                node = AstNode::Call {
+                    callee: Box::new(node),
+                    arguments: self.parse_arguments()?,
+                };
+                continue;
+            }
+            if self.current_token().token_type != TokenType::Dot {
+                break;
+            }
+            self.position += 1; // Skip '.'
+                                // `t.0` is the first element of a tuple
+            if self.current_token().token_type == TokenType::Number {
+                let field = self.current_token().value.clone();
+                self.position += 1; // Skip the index
+                node = AstNode::FieldAccess {
+                    object: Box::new(node),
+                    field,
+                };
+                continue;
+            }
+            let name = self.expect_identifier("after '.'")?;
+            node = if self.current_token().token_type == TokenType::LeftParen {
+                AstNode::MethodCall {
+                    object: Box::new(node),
+                    method: name,
+                    arguments: self.parse_arguments()?,
+                }
+            } else {
+                AstNode::FieldAccess {
+                    object: Box::new(node),
+                    field: name,
+                }
+            };
         }
+        Ok(node)
     }
 
-    fn parse_primary(&mut self) -> AstNode {
+    fn parse_operand(&mut self) -> Result<AstNode, String> {
         match self.current_token().token_type {
+            TokenType::Identifier
+                if matches!(
+                    self.current_token().value.as_str(),
+                    "Some" | "None" | "Ok" | "Err"
+                ) =>
+            {
+                self.parse_variant()
+            }
             TokenType::Identifier => {
                 let identifier = self.parse_identifier();
                 if self.current_token().token_type == TokenType::LeftParen {
@@ -474,74 +1308,156 @@ impl Parser {
                     let mut arguments = Vec::new();
                     // until newline or EOF parse arguments
                     while self.current_token().token_type != TokenType::SemiColon {
-                        let argument = self.parse_expression();
+                        let argument = self.parse_expression()?;
                         arguments.push(argument);
                         if self.current_token().token_type == TokenType::Comma {
                             self.position += 1; // Skip ','
                         }
                     }
                     self.position += 1; // Skip ';'
-                    AstNode::FunctionCall {
-                        name: match identifier {
-                            AstNode::Identifier { value } => value,
-                            _ => panic!("Expected identifier for function call"),
-                        },
-                        arguments,
-                    }
+                    let AstNode::Identifier { value: name } = identifier else {
+                        return Err("Expected identifier for function call".to_string());
+                    };
+                    Ok(AstNode::FunctionCall { name, arguments })
                 } else {
-                    identifier
+                    Ok(identifier)
                 }
             }
             TokenType::Number => self.parse_number(),
-            TokenType::Char => self.parse_char(),
-            TokenType::StringLiteral => self.parse_string(),
+            TokenType::Char => Ok(self.parse_char()),
+            TokenType::StringLiteral => Ok(self.parse_string()),
             TokenType::LeftParen => self.parse_grouped_expression(),
-            TokenType::True => self.parse_true(),
-            TokenType::False => self.parse_false(),
-            TokenType::Void => self.parse_void(),
+            TokenType::True => Ok(self.parse_true()),
+            TokenType::False => Ok(self.parse_false()),
+            TokenType::Void => Ok(self.parse_void()),
             TokenType::Ampersand => self.parse_pointer(),
             TokenType::Deref => self.parse_deref(),
-            TokenType::TypeName => self.parse_type(),
-            _ => panic!("Unexpected token: {:?}", self.current_token()),
+            TokenType::TypeName => Ok(self.parse_type()),
+            TokenType::Keyword if self.current_token().value == "new" => self.parse_new(),
+            TokenType::Keyword if self.current_token().value == "super" => self.parse_super(),
+            TokenType::Keyword if self.current_token().value == "func" => self.parse_lambda(),
+            _ => Err(format!("Unexpected token: {:?}", self.current_token())),
         }
     }
-    fn parse_pointer(&mut self) -> AstNode {
+
+    fn parse_variant(&mut self) -> Result<AstNode, String> {
+        let variant = self.current_token().value.clone();
+        self.position += 1; // Skip the variant
+        if variant == "None" {
+            return Ok(AstNode::Variant {
+                variant,
+                value: None,
+            });
+        }
+        if self.current_token().token_type != TokenType::LeftParen {
+            return Err(format!("Expected '(' and a value after '{}'", variant));
+        }
+        let mut values = self.parse_arguments()?;
+        if values.len() != 1 {
+            return Err(format!(
+                "{}(...) holds one value, found {}",
+                variant,
+                values.len()
+            ));
+        }
+        Ok(AstNode::Variant {
+            variant,
+            value: Some(Box::new(values.remove(0))),
+        })
+    }
+
+    fn parse_new(&mut self) -> Result<AstNode, String> {
+        self.position += 1; // Skip 'new'
+        let class = self.expect_identifier("after 'new'")?;
+        let class = self.with_type_args(class)?;
+        if self.current_token().token_type != TokenType::LeftParen {
+            return Err(format!(
+                "Expected the fields of {} after 'new {}'",
+                class, class
+            ));
+        }
+        Ok(AstNode::New {
+            class,
+            arguments: self.parse_arguments()?,
+        })
+    }
+
+    fn parse_super(&mut self) -> Result<AstNode, String> {
+        let Some(class) = self.class.clone() else {
+            return Err("'super' can only be used in a method".to_string());
+        };
+        self.position += 1; // Skip 'super'
+        if self.current_token().token_type != TokenType::Dot {
+            return Err("Expected '.method(...)' after 'super'".to_string());
+        }
+        self.position += 1; // Skip '.'
+        let method = self.expect_identifier("after 'super.'")?;
+        if self.current_token().token_type != TokenType::LeftParen {
+            return Err(format!("Expected '(' after 'super.{}'", method));
+        }
+        let mut arguments = vec![AstNode::Identifier {
+            value: "self".to_string(),
+        }];
+        arguments.extend(self.parse_arguments()?);
+        Ok(AstNode::SuperCall {
+            class,
+            method,
+            arguments,
+        })
+    }
+
+    fn expect_identifier(&mut self, context: &str) -> Result<String, String> {
+        let token = self.current_token();
+        if token.token_type != TokenType::Identifier {
+            return Err(format!("Expected a name {}, found {:?}", context, token));
+        }
+        let name = token.value.clone();
+        self.position += 1;
+        Ok(name)
+    }
+    fn parse_pointer(&mut self) -> Result<AstNode, String> {
         self.position += 1; // Skip '&'
-        let value = self.parse_expression();
-        AstNode::Pointer {
+        let value = self.parse_primary()?;
+        Ok(AstNode::Pointer {
             value: Box::new(value),
-        }
+        })
     }
-    fn parse_deref(&mut self) -> AstNode {
-        self.position += 1; // Skip '*'
-        let value = self.parse_expression();
-        AstNode::Dereference {
+    fn parse_deref(&mut self) -> Result<AstNode, String> {
+        self.position += 1; // Skip '^'
+        let value = self.parse_primary()?;
+        Ok(AstNode::Dereference {
             value: Box::new(value),
-        }
+        })
     }
-    fn parse_function_call(&mut self, identifier: AstNode) -> AstNode {
-        let name = if let AstNode::Identifier { value } = identifier {
-            value
-        } else {
-            panic!("Expected identifier for function call");
+    fn parse_function_call(&mut self, identifier: AstNode) -> Result<AstNode, String> {
+        let AstNode::Identifier { value: name } = identifier else {
+            return Err("Expected identifier for function call".to_string());
         };
+        let arguments = self.parse_arguments()?;
+        Ok(AstNode::FunctionCall { name, arguments })
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<AstNode>, String> {
         let mut arguments = Vec::new();
         self.position += 1; // Skip '('
         while self.current_token().token_type != TokenType::RightParen {
-            let argument = self.parse_expression();
+            let argument = self.parse_expression()?;
             arguments.push(argument);
             if self.current_token().token_type == TokenType::Comma {
                 self.position += 1; // Skip ','
             }
         }
         self.position += 1; // Skip ')'
-        AstNode::FunctionCall { name, arguments }
+        Ok(arguments)
     }
 
-    fn parse_number(&mut self) -> AstNode {
-        let value = self.current_token().value.parse().unwrap();
+    fn parse_number(&mut self) -> Result<AstNode, String> {
+        let token = &self.current_token().value;
+        let value = token
+            .parse()
+            .map_err(|_| format!("Invalid number literal '{}'", token))?;
         self.position += 1;
-        AstNode::Number { value }
+        Ok(AstNode::Number { value })
     }
 
     fn parse_string(&mut self) -> AstNode {
@@ -562,28 +1478,44 @@ impl Parser {
         AstNode::Char { value }
     }
 
-    fn parse_grouped_expression(&mut self) -> AstNode {
+    // `(a + b)`, or the tuple `(a, b)` when there is a comma
+    fn parse_grouped_expression(&mut self) -> Result<AstNode, String> {
         self.position += 1; // Skip '('
-        let expression = self.parse_expression();
+        let expression = self.parse_expression()?;
+        if self.current_token().token_type != TokenType::Comma {
+            self.position += 1; // Skip ')'
+            return Ok(expression);
+        }
+        let mut elements = vec![expression];
+        while self.current_token().token_type == TokenType::Comma {
+            self.position += 1; // Skip ','
+            elements.push(self.parse_expression()?);
+        }
+        if self.current_token().token_type != TokenType::RightParen {
+            return Err(format!(
+                "Expected ')' after the elements of a tuple, found {:?}",
+                self.current_token()
+            ));
+        }
         self.position += 1; // Skip ')'
-        expression
+        Ok(AstNode::Tuple { elements })
     }
 
+    // Running off the end of the tokens reads the trailing EOF again, so a truncated
+    // program is reported as an unexpected token instead of panicking
     fn current_token(&self) -> &Token {
-        &self.tokens[self.position]
+        self.tokens
+            .get(self.position)
+            .unwrap_or_else(|| self.tokens.last().expect("the token stream ends with EOF"))
     }
 
     fn peek_token(&self) -> &Token {
         if self.position + 1 >= self.tokens.len() {
-            return &self.tokens[self.position];
+            return self.current_token();
         }
         &self.tokens[self.position + 1]
     }
 
-    fn next_token(&mut self) {
-        self.position += 1;
-    }
-
     fn skip_semicolon(&mut self) {
         if self.position < self.tokens.len()
             && self.current_token().token_type == TokenType::SemiColon
@@ -592,6 +1524,15 @@ impl Parser {
         }
     }
 
+    fn precedence(&self, value: &str) -> u8 {
+        match value {
+            "==" | "!=" => 0,
+            ">" | "<" => 1,
+            "+" | "-" => 2,
+            _ => 3, // "*" | "/"
+        }
+    }
+
     fn is_operator(&self, value: &str) -> bool {
         matches! {
             value,
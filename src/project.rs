@@ -0,0 +1,582 @@
+// Projects - a directory with a tsl.toml manifest, so a program spread over several
+// files and directories can be built with a bare `tsl build`. The manifest is a small
+// subset of TOML:
+//
+//   [package]
+//   name = "game"
+//   entry = "src/main.tsl"          # defaults to src/main.tsl
+//   source-dirs = ["src", "lib"]    # where imports are looked for, defaults to ["src"]
+//
+//   [build]
+//   cflags = ["-O2"]
+//   c-sources = ["native/audio.c"]  # compiled and linked with the generated C
+//   libs = ["m"]                    # linked with -l
+//
+//   [dependencies]
+//   shapes = { path = "../shapes" } # another project, its source dirs become importable
+//
+// Paths are relative to the directory holding tsl.toml.
+
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST: &str = "tsl.toml";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Manifest {
+    pub name: String,
+    pub entry: PathBuf,
+    pub source_dirs: Vec<PathBuf>,
+    pub cflags: Vec<String>,
+    pub c_sources: Vec<PathBuf>,
+    pub libs: Vec<String>,
+    // (name, path) of every path dependency
+    pub dependencies: Vec<(String, PathBuf)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Project {
+    // The directory holding tsl.toml
+    pub root: PathBuf,
+    pub manifest: Manifest,
+    // Every project this one depends on, directly or not, each one once
+    pub dependencies: Vec<Project>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Array(Vec<String>),
+    Table(Vec<(String, String)>),
+}
+
+// Looks for tsl.toml in dir and then in each of its parents
+pub fn discover(dir: &Path) -> Result<Option<Project>, String> {
+    let dir = std::fs::canonicalize(dir)
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    for ancestor in dir.ancestors() {
+        if ancestor.join(MANIFEST).is_file() {
+            return Project::load(ancestor).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+impl Project {
+    // Loads the project in root and all of its dependencies
+    pub fn load(root: &Path) -> Result<Project, String> {
+        let mut loaded = Vec::new();
+        let mut project = load_project(root, &mut vec![], &mut loaded)?;
+        project.dependencies = loaded;
+        Ok(project)
+    }
+
+    pub fn entry(&self) -> PathBuf {
+        self.root.join(&self.manifest.entry)
+    }
+
+    // Where imports are looked for: this project's source dirs, then its dependencies'
+    pub fn search_path(&self) -> Vec<PathBuf> {
+        self.all()
+            .flat_map(|project| {
+                project
+                    .manifest
+                    .source_dirs
+                    .iter()
+                    .map(|dir| project.root.join(dir))
+            })
+            .collect()
+    }
+
+    pub fn cflags(&self) -> Vec<String> {
+        self.all()
+            .flat_map(|project| project.manifest.cflags.iter().cloned())
+            .collect()
+    }
+
+    pub fn c_sources(&self) -> Vec<PathBuf> {
+        self.all()
+            .flat_map(|project| {
+                project
+                    .manifest
+                    .c_sources
+                    .iter()
+                    .map(|source| project.root.join(source))
+            })
+            .collect()
+    }
+
+    pub fn libs(&self) -> Vec<String> {
+        let mut libs: Vec<String> = Vec::new();
+        for lib in self.all().flat_map(|project| project.manifest.libs.iter()) {
+            if !libs.contains(lib) {
+                libs.push(lib.clone());
+            }
+        }
+        libs
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Project> {
+        std::iter::once(self).chain(self.dependencies.iter())
+    }
+}
+
+// stack holds the projects being loaded to catch cycles, loaded collects the
+// dependencies in the order they were found
+fn load_project(
+    root: &Path,
+    stack: &mut Vec<PathBuf>,
+    loaded: &mut Vec<Project>,
+) -> Result<Project, String> {
+    let root = std::fs::canonicalize(root)
+        .map_err(|e| format!("Failed to find project {}: {}", root.display(), e))?;
+    let path = root.join(MANIFEST);
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let manifest = parse_manifest(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    stack.push(root.clone());
+    for (name, dependency) in &manifest.dependencies {
+        let dependency = std::fs::canonicalize(root.join(dependency)).map_err(|e| {
+            format!(
+                "Failed to find dependency '{}' of {} at {}: {}",
+                name,
+                manifest.name,
+                dependency.display(),
+                e
+            )
+        })?;
+        if stack.contains(&dependency) {
+            return Err(format!(
+                "Dependency cycle between projects: {} -> {}",
+                stack
+                    .iter()
+                    .map(|root| root.display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(" -> "),
+                dependency.display()
+            ));
+        }
+        if loaded.iter().any(|project| project.root == dependency) {
+            continue;
+        }
+        let project = load_project(&dependency, stack, loaded)?;
+        if project.manifest.name != *name {
+            return Err(format!(
+                "Dependency '{}' of {} is a project named '{}'",
+                name, manifest.name, project.manifest.name
+            ));
+        }
+        loaded.push(project);
+    }
+    stack.pop();
+
+    Ok(Project {
+        root,
+        manifest,
+        dependencies: Vec::new(),
+    })
+}
+
+pub fn parse_manifest(text: &str) -> Result<Manifest, String> {
+    let mut manifest = Manifest {
+        entry: PathBuf::from("src/main.tsl"),
+        source_dirs: vec![PathBuf::from("src")],
+        ..Default::default()
+    };
+    let mut name = None;
+    let mut section = String::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let number = index + 1;
+        let mut line = strip_comment(line).trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = header.trim().to_string();
+            if !matches!(section.as_str(), "package" | "build" | "dependencies") {
+                return Err(format!("line {}: unknown section [{}]", number, section));
+            }
+            continue;
+        }
+        // Arrays can span several lines
+        while is_unfinished_array(&line) {
+            match lines.next() {
+                Some((_, next)) => line.push_str(strip_comment(next).trim()),
+                None => return Err(format!("line {}: unterminated array", number)),
+            }
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected 'key = value'", number))?;
+        let key = key.trim();
+        let value = parse_value(value.trim()).map_err(|e| format!("line {}: {}", number, e))?;
+        let wrong_type = || format!("line {}: unexpected value for '{}'", number, key);
+        match (section.as_str(), key, value) {
+            ("package", "name", Value::String(value)) => name = Some(value),
+            ("package", "entry", Value::String(value)) => manifest.entry = value.into(),
+            ("package", "source-dirs", Value::Array(dirs)) => {
+                manifest.source_dirs = dirs.into_iter().map(PathBuf::from).collect()
+            }
+            ("build", "cflags", Value::Array(flags)) => manifest.cflags = flags,
+            ("build", "c-sources", Value::Array(sources)) => {
+                manifest.c_sources = sources.into_iter().map(PathBuf::from).collect()
+            }
+            ("build", "libs", Value::Array(libs)) => manifest.libs = libs,
+            ("dependencies", name, value) => {
+                let path = match &value {
+                    Value::Table(fields) if fields.len() == 1 && fields[0].0 == "path" => {
+                        &fields[0].1
+                    }
+                    _ => {
+                        return Err(format!(
+                            "line {}: dependencies look like {} = {{ path = \"../{}\" }}",
+                            number, name, name
+                        ))
+                    }
+                };
+                manifest.dependencies.push((name.to_string(), path.into()));
+            }
+            ("package", "name" | "entry" | "source-dirs", _)
+            | ("build", "cflags" | "c-sources" | "libs", _) => return Err(wrong_type()),
+            ("", _, _) => return Err(format!("line {}: '{}' must be in a section", number, key)),
+            (section, key, _) => {
+                return Err(format!(
+                    "line {}: unknown key '{}' in [{}]",
+                    number, key, section
+                ))
+            }
+        }
+    }
+    manifest.name = name.ok_or("[package] needs a name")?;
+    Ok(manifest)
+}
+
+// Whether the value of a `key = value` line is an array whose `]` is on a later line
+fn is_unfinished_array(line: &str) -> bool {
+    line.split_once('=').is_some_and(|(_, value)| {
+        let value = value.trim();
+        value.starts_with('[') && !value.ends_with(']')
+    })
+}
+
+// Removes a `#` comment, unless the `#` is inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '\\' if in_string && !escaped => {
+                escaped = true;
+                continue;
+            }
+            '"' if !escaped => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(items) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let mut values = Vec::new();
+        for item in split_items(items) {
+            values.push(parse_string(item)?);
+        }
+        Ok(Value::Array(values))
+    } else if let Some(fields) = text.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+        let mut table = Vec::new();
+        for field in split_items(fields) {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("expected 'key = value', found '{}'", field))?;
+            table.push((key.trim().to_string(), parse_string(value.trim())?));
+        }
+        Ok(Value::Table(table))
+    } else {
+        parse_string(text).map(Value::String)
+    }
+}
+
+// Splits on commas outside strings, allowing a trailing comma
+fn split_items(text: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            '\\' if in_string && !escaped => {
+                escaped = true;
+                continue;
+            }
+            '"' if !escaped => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(text[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    items.push(text[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+fn parse_string(text: &str) -> Result<String, String> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a string, found '{}'", text))?;
+    let mut value = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => value.push('"'),
+            Some('\\') => value.push('\\'),
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            other => return Err(format!("unknown escape '\\{}'", other.unwrap_or(' '))),
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_manifest, Manifest, Project};
+    use std::path::{Path, PathBuf};
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn only_a_name_is_needed() {
+        assert_eq!(
+            parse_manifest("[package]\nname = \"game\"\n"),
+            Ok(Manifest {
+                name: "game".to_string(),
+                entry: PathBuf::from("src/main.tsl"),
+                source_dirs: paths(&["src"]),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            parse_manifest("[package]\nentry = \"main.tsl\"\n"),
+            Err("[package] needs a name".to_string())
+        );
+    }
+
+    #[test]
+    fn every_section_and_key_is_read() {
+        let manifest = parse_manifest(
+            "# A game\n[package]\nname = \"game\"  # the name\nentry = \"src/game.tsl\"\nsource-dirs = [\"src\", \"lib\"]\n\n[build]\ncflags = [\"-O2\", \"-Wall\",]\nc-sources = [\"native/audio.c\"]\nlibs = []\n\n[dependencies]\nshapes = { path = \"../shapes\" }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            manifest,
+            Manifest {
+                name: "game".to_string(),
+                entry: PathBuf::from("src/game.tsl"),
+                source_dirs: paths(&["src", "lib"]),
+                cflags: strings(&["-O2", "-Wall"]),
+                c_sources: paths(&["native/audio.c"]),
+                libs: vec![],
+                dependencies: vec![("shapes".to_string(), PathBuf::from("../shapes"))],
+            }
+        );
+    }
+
+    #[test]
+    fn arrays_can_span_several_lines() {
+        for text in [
+            "[build]\ncflags = [\n    \"-O2\",  # optimise\n    \"-g\",\n]\n",
+            "[build]\ncflags=[\n\"-O2\",\n\"-g\"]\n",
+            "[build]\ncflags =\t[\"-O2\",\n\"-g\"]\n",
+        ] {
+            let manifest = parse_manifest(&format!("[package]\nname = \"p\"\n{}", text));
+            assert_eq!(
+                manifest.unwrap().cflags,
+                strings(&["-O2", "-g"]),
+                "{:?}",
+                text
+            );
+        }
+        assert_eq!(
+            parse_manifest("[package]\nname = \"p\"\n[build]\nlibs = [\n\"m\",\n"),
+            Err("line 4: unterminated array".to_string())
+        );
+    }
+
+    #[test]
+    fn strings_are_unescaped_and_can_hold_comment_and_comma_characters() {
+        let manifest = parse_manifest(
+            "[package]\nname = \"a \\\"b\\\" \\\\ c\"\n[build]\ncflags = [\"-DX=\\\"#1, 2\\\"\", \"-DTAB=\\t\"] # flags\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.name, "a \"b\" \\ c");
+        assert_eq!(manifest.cflags, strings(&["-DX=\"#1, 2\"", "-DTAB=\t"]));
+        assert_eq!(
+            parse_manifest("[package]\nname = \"a\\qb\"\n"),
+            Err("line 2: unknown escape '\\q'".to_string())
+        );
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_line() {
+        for (text, error) in [
+            ("name = \"p\"", "line 1: 'name' must be in a section"),
+            ("[package]\n[tools]", "line 2: unknown section [tools]"),
+            (
+                "[package]\nversion = \"1\"",
+                "line 2: unknown key 'version' in [package]",
+            ),
+            ("[package]\nname", "line 2: expected 'key = value'"),
+            (
+                "[package]\nname = game",
+                "line 2: expected a string, found 'game'",
+            ),
+            (
+                "[package]\nname = [\"p\"]",
+                "line 2: unexpected value for 'name'",
+            ),
+            (
+                "[build]\nlibs = \"m\"",
+                "line 2: unexpected value for 'libs'",
+            ),
+            (
+                "[dependencies]\nshapes = \"../shapes\"",
+                "line 2: dependencies look like shapes = { path = \"../shapes\" }",
+            ),
+            (
+                "[dependencies]\nshapes = { git = \"x\" }",
+                "line 2: dependencies look like shapes = { path = \"../shapes\" }",
+            ),
+        ] {
+            assert_eq!(parse_manifest(text), Err(error.to_string()), "{:?}", text);
+        }
+    }
+
+    // A directory holding a project for each (directory, manifest), removed when dropped
+    struct Projects(PathBuf);
+
+    impl Projects {
+        fn new(test: &str, projects: &[(&str, &str)]) -> Projects {
+            let root =
+                std::env::temp_dir().join(format!("tsl-project-{}-{}", std::process::id(), test));
+            let _ = std::fs::remove_dir_all(&root);
+            for (dir, manifest) in projects {
+                std::fs::create_dir_all(root.join(dir)).unwrap();
+                std::fs::write(root.join(dir).join("tsl.toml"), manifest).unwrap();
+            }
+            Projects(root.canonicalize().unwrap())
+        }
+
+        fn load(&self, dir: &str) -> Result<Project, String> {
+            Project::load(&self.0.join(dir))
+        }
+
+        fn path(&self, dir: &str) -> String {
+            self.0.join(dir).display().to_string()
+        }
+    }
+
+    impl Drop for Projects {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn manifest(name: &str, dependencies: &[&str]) -> String {
+        let mut text = format!("[package]\nname = \"{}\"\n[dependencies]\n", name);
+        for dependency in dependencies {
+            text += &format!("{0} = {{ path = \"../{0}\" }}\n", dependency);
+        }
+        text
+    }
+
+    fn names(project: &Project) -> Vec<&str> {
+        project
+            .dependencies
+            .iter()
+            .map(|p| p.manifest.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn shared_dependencies_are_loaded_once() {
+        let projects = Projects::new(
+            "diamond",
+            &[
+                ("app", &manifest("app", &["ui", "net"])),
+                ("ui", &manifest("ui", &["core"])),
+                ("net", &manifest("net", &["core"])),
+                ("core", &manifest("core", &[])),
+            ],
+        );
+        let app = projects.load("app").unwrap();
+        assert_eq!(names(&app), ["core", "ui", "net"]);
+        assert_eq!(
+            app.search_path(),
+            ["app", "core", "ui", "net"].map(|dir| Path::new(&projects.path(dir)).join("src"))
+        );
+    }
+
+    #[test]
+    fn dependency_cycles_are_an_error() {
+        let projects = Projects::new(
+            "cycle",
+            &[
+                ("a", &manifest("a", &["b"])),
+                ("b", &manifest("b", &["c"])),
+                ("c", &manifest("c", &["a"])),
+            ],
+        );
+        assert_eq!(
+            projects.load("a").unwrap_err(),
+            format!(
+                "Dependency cycle between projects: {} -> {} -> {} -> {}",
+                projects.path("a"),
+                projects.path("b"),
+                projects.path("c"),
+                projects.path("a")
+            )
+        );
+        let projects = Projects::new("self", &[("a", &manifest("a", &["a"]))]);
+        assert_eq!(
+            projects.load("a").unwrap_err(),
+            format!(
+                "Dependency cycle between projects: {} -> {}",
+                projects.path("a"),
+                projects.path("a")
+            )
+        );
+    }
+
+    #[test]
+    fn dependency_must_be_the_project_it_names() {
+        let projects = Projects::new(
+            "misnamed",
+            &[
+                ("a", &manifest("a", &["b"])),
+                ("b", &manifest("other", &[])),
+            ],
+        );
+        assert_eq!(
+            projects.load("a").unwrap_err(),
+            "Dependency 'b' of a is a project named 'other'"
+        );
+    }
+}
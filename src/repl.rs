@@ -0,0 +1,367 @@
+// REPL - evaluates TSL a line at a time on the interpreter, so no C compiler is needed.
+// Each input goes through the passes a program does up to type checking, but every pass
+// keeps what it learnt from the inputs before: the parser, the imported modules, the
+// generics and overloads, the globals and the type checker's symbol and function tables.
+// So only the new statements are checked, against the definitions of earlier inputs. An
+// input is checked on a copy of that state, which is only kept when the input runs, so a
+// failed input is forgotten. Only the new statements run, on an interpreter that keeps
+// its globals between inputs.
+
+use crate::interpreter::{display, Interpreter};
+use crate::lex::{tokenize, TokenType};
+use crate::parser::{AstNode, Parser};
+use crate::transpiler::c_bindgen::AsC;
+use crate::transpiler::generics::Monomorphizer;
+use crate::transpiler::ir;
+use crate::transpiler::modules::{self, Loaded};
+use crate::transpiler::overloading::Overloads;
+use crate::transpiler::type_checker::{Type, TypeChecker};
+use crate::PRELUDE;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+pub const HELP: &str = "Enter statements, expressions or function definitions. An unclosed | or {
+continues the input on the next line.
+
+  :type <expr>    Show the type of an expression
+  :ast <input>    Show the checked AST of the input
+  :c <input>      Show the C generated for the input
+  :load <file>    Run a file, keeping its definitions
+  :help           Show this help
+  :quit           Leave the REPL";
+
+// The function :c lowers the input's statements into
+const INPUT: &str = "repl_input";
+
+pub struct Session {
+    front: FrontEnd,
+    // The checked statements of every input run so far, the prelude's first
+    program: Vec<AstNode>,
+    interpreter: Interpreter,
+    search_path: Vec<PathBuf>,
+}
+
+// What each pass up to type checking knows of the inputs so far
+#[derive(Clone)]
+struct FrontEnd {
+    parser: Parser,
+    modules: Loaded,
+    generics: Monomorphizer,
+    overloads: Overloads,
+    globals: HashSet<String>,
+    checker: TypeChecker,
+}
+
+// The input still has an open block, so the next line belongs to it
+pub fn is_unfinished(input: &str) -> bool {
+    let blocks = tokenize(input.to_string())
+        .iter()
+        .filter(|token| token.token_type == TokenType::Block)
+        .count();
+    blocks % 2 == 1
+}
+
+impl Session {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        let mut session = Session {
+            front: FrontEnd {
+                parser: Parser::new(Vec::new()),
+                modules: Loaded::default(),
+                generics: Monomorphizer::default(),
+                overloads: Overloads::default(),
+                globals: HashSet::new(),
+                checker: TypeChecker::new(),
+            },
+            program: Vec::new(),
+            interpreter: Interpreter::new(&AstNode::Null),
+            search_path,
+        };
+        session.run(PRELUDE).expect("the prelude runs");
+        session
+    }
+
+    // Runs a line (or several, for a block) or a command, returning what to print
+    pub fn eval(&mut self, input: &str) -> Result<Option<String>, String> {
+        let trimmed = input.trim();
+        let Some(command) = trimmed.strip_prefix(':') else {
+            return self.run(input);
+        };
+        let (command, argument) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        let argument = argument.trim();
+        match command {
+            "type" | "t" => {
+                let mut front = self.front.clone();
+                let mut statements = front.check(argument, &self.search_path)?;
+                let statement = statements.last_mut().ok_or(":type needs an expression")?;
+                Ok(Some(String::from(
+                    front.checker.check_expression(statement)?,
+                )))
+            }
+            "ast" => {
+                let statements = self.front.clone().check(argument, &self.search_path)?;
+                Ok(Some(
+                    statements
+                        .iter()
+                        .map(|statement| format!("{:#?}", statement))
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                ))
+            }
+            "c" => self.c(argument).map(Some),
+            "load" | "l" => {
+                let source = std::fs::read_to_string(argument)
+                    .map_err(|e| format!("Failed to read {}: {}", argument, e))?;
+                self.run(&source)
+            }
+            "help" | "h" => Ok(Some(HELP.to_string())),
+            _ => Err(format!("Unknown command ':{}', try :help", command)),
+        }
+    }
+
+    fn run(&mut self, input: &str) -> Result<Option<String>, String> {
+        let mut front = self.front.clone();
+        let statements = front.check(input, &self.search_path)?;
+        let ast = AstNode::Block {
+            statements: statements.clone(),
+        };
+        let mut results = Vec::new();
+        for statement in &statements {
+            let Some(value) = self.interpreter.execute_top_level(&ast, statement)? else {
+                continue;
+            };
+            let tp = front.checker.check_expression(&mut statement.clone())?;
+            if tp != Type::Void {
+                results.push(format!(
+                    "{} : {}",
+                    display(value.as_ref()),
+                    String::from(tp)
+                ));
+            }
+        }
+        self.front = front;
+        self.program.extend(statements);
+        Ok(Some(results.join("\n")).filter(|results| !results.is_empty()))
+    }
+
+    // The C the compiler generates for the input. Its statements are lowered as the body
+    // of a function, which returns the value of a final expression, and the globals of
+    // earlier inputs are declared without their initialisers, which may not be constant.
+    // Only the functions the input adds are shown.
+    fn c(&self, input: &str) -> Result<String, String> {
+        let mut front = self.front.clone();
+        let statements = front.check(input, &self.search_path)?;
+        let checker = &mut front.checker;
+        let mut items = Vec::new();
+        for statement in self.program.iter().flat_map(AstNode::get_statements) {
+            match statement {
+                AstNode::Assignment {
+                    variable, binding, ..
+                } => {
+                    let tp = checker
+                        .symbol_table
+                        .get(&variable)
+                        .cloned()
+                        .map(String::from)
+                        .ok_or_else(|| format!("Undefined variable: {}", variable))?;
+                    items.push(AstNode::Assignment {
+                        variable,
+                        tp: Some(tp.clone()),
+                        value: Box::new(AstNode::Uninit { tp }),
+                        binding,
+                    });
+                }
+                statement if is_item(&statement) => items.push(statement),
+                _ => {}
+            }
+        }
+        let lower = |items: &[AstNode], checker: &TypeChecker| {
+            let program = AstNode::Block {
+                statements: items.to_vec(),
+            };
+            ir::lower(&program, checker).map_err(|e| format!("Lowering error: {}", e))
+        };
+        let known: HashSet<String> = lower(&items, checker)?
+            .functions
+            .into_iter()
+            .map(|function| function.name)
+            .collect();
+        let mut body = Vec::new();
+        for statement in statements {
+            match statement {
+                statement if is_item(&statement) => items.push(statement),
+                statement => body.push(statement),
+            }
+        }
+        let mut return_type = Type::Void;
+        if let Some(last) = body.last_mut()
+            && !matches!(
+                last,
+                AstNode::Assignment { .. }
+                    | AstNode::FieldAssignment { .. }
+                    | AstNode::If { .. }
+                    | AstNode::While { .. }
+                    | AstNode::Block { .. }
+                    | AstNode::Return { .. }
+            )
+        {
+            return_type = checker.check_expression(&mut last.clone())?;
+            if return_type != Type::Void {
+                *last = AstNode::Return {
+                    value: Box::new(last.clone()),
+                };
+            }
+        }
+        if !body.is_empty() {
+            items.push(AstNode::Function {
+                name: INPUT.to_string(),
+                arguments: Vec::new(),
+                return_type: String::from(return_type),
+                body: Box::new(AstNode::Block { statements: body }),
+                attributes: Vec::new(),
+            });
+        }
+        Ok(lower(&items, checker)?
+            .functions
+            .iter()
+            .filter(|function| !known.contains(&function.name))
+            .map(|function| function.as_c())
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+}
+
+impl FrontEnd {
+    // Takes the input through every pass up to type checking, returning its statements as
+    // the checker left them. Instances of generics of earlier inputs that it uses come
+    // first.
+    fn check(&mut self, input: &str, search_path: &[PathBuf]) -> Result<Vec<AstNode>, String> {
+        let ast = self
+            .parser
+            .parse_more(tokenize(input.to_string()))
+            .map_err(|e| format!("Parse error: {}", e))?;
+        let mut read = |path: &Path| std::fs::read_to_string(path).map_err(|e| e.to_string());
+        let mut ast = modules::resolve_more_imports(
+            ast,
+            Path::new("repl.tsl"),
+            search_path,
+            &mut read,
+            &mut self.modules,
+        )
+        .map_err(|e| format!("Import error: {}", e))?
+        .ast;
+        self.generics
+            .monomorphize(&mut ast)
+            .map_err(|e| format!("Type error: {}", e))?;
+        self.parser.temp_checker.prelude(&ast);
+        self.overloads.dispatch(&mut ast, &self.parser.temp_checker);
+        let mut ast = ir::resolve_names_with(&ast, &mut self.globals);
+        self.checker.prelude(&ast);
+        self.checker
+            .check_expression(&mut ast)
+            .map_err(|e| format!("Type error: {}", e))?;
+        self.checker.warnings.clear();
+        Ok(ast
+            .get_statements()
+            .into_iter()
+            .filter(|statement| *statement != AstNode::Eof)
+            .collect())
+    }
+}
+
+// Definitions, which are lowered as they are rather than run
+fn is_item(statement: &AstNode) -> bool {
+    matches!(
+        statement,
+        AstNode::Function { .. }
+            | AstNode::Class { .. }
+            | AstNode::Struct { .. }
+            | AstNode::Interface { .. }
+            | AstNode::Impl { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+
+    // What each input prints, or its error
+    fn eval(inputs: &[&str]) -> Vec<String> {
+        let mut session = Session::new(Vec::new());
+        inputs
+            .iter()
+            .map(|input| match session.eval(input) {
+                Ok(output) => output.unwrap_or_default(),
+                Err(e) => e,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn definitions_are_kept_between_inputs() {
+        assert_eq!(
+            eval(&[
+                "let x = 4",
+                "func square(n Int) Int |\n    return n * n + x\n|\n",
+                "square(x)",
+                ":type square",
+            ]),
+            ["", "", "20 : Int", "Func(Int) Int"]
+        );
+    }
+
+    #[test]
+    fn a_function_defined_again_replaces_the_old_one() {
+        assert_eq!(
+            eval(&[
+                "func f(n Int) Int |\n    return n\n|\n",
+                "func f(n Bool) Bool |\n    return n\n|\n",
+                "func f(n Int) Int |\n    return n + 1\n|\n",
+                "f(1)",
+                "f(true)",
+            ])[3..],
+            ["2 : Int", "true : Bool"]
+        );
+    }
+
+    #[test]
+    fn generics_of_earlier_inputs_are_instantiated_for_later_ones() {
+        assert_eq!(
+            eval(&[
+                "func first<T>(a T, b T) T |\n    return a\n|\n",
+                "first(1, 2)",
+                "first(true, false)",
+                "first(3, 4)",
+            ])[1..],
+            ["1 : Int", "true : Bool", "3 : Int"]
+        );
+    }
+
+    #[test]
+    fn a_failed_input_is_forgotten() {
+        assert_eq!(
+            eval(&[
+                "func bad() Int |\n    return true\n|\n",
+                "bad()",
+                "let y Int = true",
+                "let y = 2",
+                "y",
+            ])[1..],
+            [
+                "Type error: Undefined function: bad",
+                "Type error: Type error: cannot assign Bool to variable of type Integer",
+                "",
+                "2 : Int",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_the_parser_makes_up_dont_clash_between_inputs() {
+        assert_eq!(
+            eval(&["let (a, b) = (1, 2)", "let (c, d) = (3, 4)", "a + d"])[2],
+            "5 : Int"
+        );
+    }
+}
@@ -1,11 +1,13 @@
-use super::type_checker::Type;
-use crate::lex::*;
-use crate::parser::*;
+use super::ir::*;
+use super::type_checker::{option_is_pointer, Type};
+use std::collections::{HashMap, HashSet};
 
 pub trait AsC {
     fn as_c(&self) -> String;
 }
 
+// A C type. A const pointer is `int const*`, so the const qualifies what it points at
+// and nests, `int* const*` is a const pointer to an `int*`.
 pub struct CType {
     pub name: String,
     pub is_pointer: bool,
@@ -25,12 +27,12 @@ impl CType {
 impl AsC for CType {
     fn as_c(&self) -> String {
         let mut result = self.name.clone();
-        if self.is_pointer {
-            result.push('*');
-        }
         if self.is_const {
             result.push_str(" const");
         }
+        if self.is_pointer {
+            result.push('*');
+        }
         result
     }
 }
@@ -41,171 +43,933 @@ pub fn c_bindgen_prelude() -> String {
     prelude.to_string()
 }
 
-pub fn c_bindgen(ast: &AstNode, indent: usize, is_in_function: bool) -> String {
-    let mut result = String::new();
-    let indent_str = "    ".repeat(indent);
+impl AsC for Constant {
+    fn as_c(&self) -> String {
+        match self {
+            Constant::Int(value) => value.to_string(),
+            Constant::Bool(value) => value.to_string(),
+            Constant::Char(value) => format!("'{}'", value),
+            Constant::Str(value) => format!("\"{}\"", value),
+            Constant::Null => "Void".to_string(),
+        }
+    }
+}
 
-    match ast {
-        AstNode::Block { statements } => {
-            for stmt in statements {
-                result.push_str(&c_bindgen(stmt, indent, is_in_function));
-            }
+impl AsC for Temp {
+    fn as_c(&self) -> String {
+        format!("_t{}", self.id)
+    }
+}
+
+impl AsC for Operand {
+    fn as_c(&self) -> String {
+        match self {
+            Operand::Temp(temp) => temp.as_c(),
+            Operand::Const(constant) => constant.as_c(),
         }
-        AstNode::Function {
-            name,
-            arguments,
-            body,
-            return_type,
-        } => {
-            result.push_str(&format!(
-                "{}{} {}({}) {{\n",
-                indent_str,
-                Type::from(return_type.clone()).as_c(),
-                name,
-                arguments
+    }
+}
+
+impl AsC for Instruction {
+    fn as_c(&self) -> String {
+        match self {
+            Instruction::Load { dest, var } => format!("{} = {};", dest.as_c(), var.c_name()),
+            Instruction::Store { var, value } => format!("{} = {};", var.c_name(), value.as_c()),
+            Instruction::Binary {
+                dest,
+                op,
+                left,
+                right,
+            } => format!(
+                "{} = {} {} {};",
+                dest.as_c(),
+                left.as_c(),
+                op.as_str(),
+                right.as_c()
+            ),
+            Instruction::Cast { dest, value } => {
+                format!("{} = ({}){};", dest.as_c(), dest.tp.as_c(), value.as_c())
+            }
+            Instruction::Call {
+                dest,
+                function,
+                arguments,
+            } => {
+                let call = format!(
+                    "{}({});",
+                    c_symbol(function),
+                    arguments
+                        .iter()
+                        .map(|arg| arg.as_c())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+                match dest {
+                    Some(dest) => format!("{} = {}", dest.as_c(), call),
+                    None => call,
+                }
+            }
+            Instruction::AddressOf { dest, var } => {
+                format!("{} = &{};", dest.as_c(), var.c_name())
+            }
+            Instruction::Deref { dest, pointer } => {
+                format!("{} = *{};", dest.as_c(), pointer.as_c())
+            }
+            Instruction::New { dest, class } => {
+                format!("{} = {}__new();", dest.as_c(), c_symbol(class))
+            }
+            Instruction::GetField {
+                dest,
+                object,
+                field,
+            } => format!("{} = {}->{};", dest.as_c(), object.as_c(), field),
+            Instruction::SetField {
+                object,
+                field,
+                value,
+            } => format!("{}->{} = {};", object.as_c(), field, value.as_c()),
+            Instruction::CallMethod {
+                dest,
+                class,
+                method,
+                arguments,
+            } => {
+                let call = format!(
+                    "{}__vtable_of({})->{}({});",
+                    c_symbol(class),
+                    arguments[0].as_c(),
+                    method,
+                    arguments
+                        .iter()
+                        .map(|arg| arg.as_c())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+                match dest {
+                    Some(dest) => format!("{} = {}", dest.as_c(), call),
+                    None => call,
+                }
+            }
+            Instruction::ToDyn { dest, value } => {
+                let Type::Dyn(interface) = &dest.tp else {
+                    return format!("{} = {};", dest.as_c(), value.as_c());
+                };
+                let tp = value.tp();
+                let data = if boxed(&tp) {
+                    format!(
+                        "tsl_box(&({0}){{{1}}}, sizeof({0}))",
+                        tp.as_c(),
+                        value.as_c()
+                    )
+                } else {
+                    format!("(void*){}", value.as_c())
+                };
+                format!(
+                    "{} = ({}){{{}, &{}__vtable}};",
+                    dest.as_c(),
+                    dest.tp.as_c(),
+                    data,
+                    impl_c_name(interface, &tp)
+                )
+            }
+            Instruction::CallDyn {
+                dest,
+                method,
+                arguments,
+                ..
+            } => {
+                let receiver = arguments[0].as_c();
+                let call = format!(
+                    "{}.vtable->{}({});",
+                    receiver,
+                    method,
+                    std::iter::once(format!("{}.self", receiver))
+                        .chain(arguments[1..].iter().map(|arg| arg.as_c()))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+                match dest {
+                    Some(dest) => format!("{} = {}", dest.as_c(), call),
+                    None => call,
+                }
+            }
+            Instruction::MakeClosure {
+                dest,
+                function,
+                captures,
+            } => {
+                let env = if captures.is_empty() {
+                    "NULL".to_string()
+                } else {
+                    format!(
+                        "tsl_box(&({0}){{{1}}}, sizeof({0}))",
+                        c_symbol(&env_symbol(function)),
+                        captures
+                            .iter()
+                            .map(|capture| capture.as_c())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    )
+                };
+                format!(
+                    "{} = (tsl_closure){{(void (*)(void)){}, {}}};",
+                    dest.as_c(),
+                    c_symbol(function),
+                    env
+                )
+            }
+            Instruction::MakeTuple { dest, elements } => format!(
+                "{} = ({}){{{}}};",
+                dest.as_c(),
+                dest.tp.as_c(),
+                elements
                     .iter()
-                    .map(|(ty, name)| format!("{} {}", Type::from(ty.clone()).as_c(), name))
+                    .map(|element| element.as_c())
                     .collect::<Vec<String>>()
                     .join(", ")
-            ));
-            result.push_str(&c_bindgen(body, indent + 1, is_in_function));
-            result.push_str(&format!("{}}}\n", indent_str));
+            ),
+            Instruction::GetElement { dest, tuple, index } => {
+                format!("{} = {}._{};", dest.as_c(), tuple.as_c(), index)
+            }
+            // An Option of a pointer is the pointer, with Void as None
+            Instruction::MakeVariant {
+                dest,
+                variant,
+                value,
+            } => match (&dest.tp, value) {
+                (Type::Option(held), _) if option_is_pointer(held) => format!(
+                    "{} = {};",
+                    dest.as_c(),
+                    value
+                        .as_ref()
+                        .map_or("Void".to_string(), |value| value.as_c())
+                ),
+                _ => {
+                    let tag = match variant {
+                        Variant::Some => ".some = true",
+                        Variant::None => ".some = false",
+                        Variant::Ok => ".ok = true",
+                        Variant::Err => ".ok = false",
+                    };
+                    let field = if *variant == Variant::Err {
+                        "error"
+                    } else {
+                        "value"
+                    };
+                    let value = value
+                        .as_ref()
+                        .map(|value| format!(", .{} = {}", field, value.as_c()))
+                        .unwrap_or_default();
+                    format!(
+                        "{} = ({}){{{}{}}};",
+                        dest.as_c(),
+                        dest.tp.as_c(),
+                        tag,
+                        value
+                    )
+                }
+            },
+            Instruction::IsVariant {
+                dest,
+                value,
+                variant,
+            } => {
+                let test = match (value.tp(), variant) {
+                    (Type::Option(held), Variant::Some) if option_is_pointer(&held) => {
+                        format!("{} != Void", value.as_c())
+                    }
+                    (Type::Option(held), _) if option_is_pointer(&held) => {
+                        format!("{} == Void", value.as_c())
+                    }
+                    (_, Variant::Some) => format!("{}.some", value.as_c()),
+                    (_, Variant::None) => format!("!{}.some", value.as_c()),
+                    (_, Variant::Ok) => format!("{}.ok", value.as_c()),
+                    (_, Variant::Err) => format!("!{}.ok", value.as_c()),
+                };
+                format!("{} = {};", dest.as_c(), test)
+            }
+            Instruction::Unwrap {
+                dest,
+                value,
+                variant,
+            } => match (value.tp(), variant) {
+                (Type::Option(held), _) if option_is_pointer(&held) => {
+                    format!("{} = {};", dest.as_c(), value.as_c())
+                }
+                (_, Variant::Err) => format!("{} = {}.error;", dest.as_c(), value.as_c()),
+                _ => format!("{} = {}.value;", dest.as_c(), value.as_c()),
+            },
+            Instruction::CallClosure { dest, arguments } => {
+                let (params, return_type) = match arguments[0].tp() {
+                    Type::Function(params, return_type) => (params, *return_type),
+                    _ => (Vec::new(), Type::Void),
+                };
+                let closure = arguments[0].as_c();
+                let call = format!(
+                    "(({} (*)({})){}.function)({});",
+                    return_type.as_c(),
+                    std::iter::once("void*".to_string())
+                        .chain(params.iter().map(|param| param.as_c()))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    closure,
+                    std::iter::once(format!("{}.env", closure))
+                        .chain(arguments[1..].iter().map(|arg| arg.as_c()))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+                match dest {
+                    Some(dest) => format!("{} = {}", dest.as_c(), call),
+                    None => call,
+                }
+            }
         }
-        AstNode::Identifier { value } => {
-            result.push_str(&format!(
-                "{}{}",
-                indent_str,
-                value.split("::").last().unwrap()
-            ));
+    }
+}
+
+fn block_label(id: BlockId) -> String {
+    format!("bb{}", id.0)
+}
+
+impl AsC for Terminator {
+    fn as_c(&self) -> String {
+        match self {
+            Terminator::Return(Some(value)) => format!("return {};", value.as_c()),
+            Terminator::Return(None) => "return;".to_string(),
+            Terminator::Jump(target) => format!("goto {};", block_label(*target)),
+            Terminator::Branch {
+                condition,
+                then_block,
+                else_block,
+            } => format!(
+                "if ({}) goto {}; else goto {};",
+                condition.as_c(),
+                block_label(*then_block),
+                block_label(*else_block)
+            ),
+            Terminator::Unreachable => "abort();".to_string(),
         }
-        AstNode::BinaryOperation {
-            operator,
-            left,
-            right,
-        } => {
-            result.push_str(&c_bindgen(left, 0, true));
-            result.push_str(&operator.value);
-            result.push_str(&c_bindgen(right, 0, true));
+    }
+}
+
+// C's `main` returns the exit status, so it is always `int main(void)` and a Void main
+// exits with 0
+fn c_signature(function: &Function) -> String {
+    let params = function
+        .params
+        .iter()
+        .map(|param| format!("{} {}", param.tp.as_c(), param.c_name()))
+        .collect::<Vec<String>>()
+        .join(", ");
+    if is_void_main(function) {
+        return format!(
+            "int main({})",
+            if params.is_empty() { "void" } else { &params }
+        );
+    }
+    format!(
+        "{}{} {}({})",
+        linkage(function.public),
+        function.return_type.as_c(),
+        function.c_name(),
+        params
+    )
+}
+
+fn is_void_main(function: &Function) -> bool {
+    function.name == "main" && function.return_type == Type::Void
+}
+
+// Private items are `static`, so only `pub` ones are visible to other C files
+fn linkage(public: bool) -> &'static str {
+    if public {
+        ""
+    } else {
+        "static "
+    }
+}
+
+impl AsC for Function {
+    fn as_c(&self) -> String {
+        let mut result = format!("{} {{\n", c_signature(self));
+        for local in &self.locals {
+            result.push_str(&format!("    {} {};\n", local.tp.as_c(), local.c_name()));
         }
-        AstNode::Variable { value } => {
-            result.push_str(&format!(
-                "{}{}",
-                indent_str,
-                value.split("::").last().unwrap()
-            ));
+        for temp in self.temps() {
+            result.push_str(&format!("    {} {};\n", temp.tp.as_c(), temp.as_c()));
         }
-        AstNode::Return { value } => {
-            if **value == AstNode::Null {
-                result.push_str(&format!("{}return;\n", indent_str));
-            } else {
-                result.push_str(&format!(
-                    "{}return {};\n",
-                    indent_str,
-                    c_bindgen(value, 0, is_in_function)
-                ));
+        // Only blocks something jumps to get a label, an unused one is a C warning
+        let targets: HashSet<BlockId> = self
+            .blocks
+            .iter()
+            .flat_map(|block| block.terminator.successors())
+            .collect();
+        for block in &self.blocks {
+            if targets.contains(&block.id) {
+                result.push_str(&format!("{}:\n", block_label(block.id)));
+            }
+            for instruction in &block.instructions {
+                result.push_str(&format!("    {}\n", instruction.as_c()));
+            }
+            match &block.terminator {
+                Terminator::Return(None) if is_void_main(self) => {
+                    result.push_str("    return 0;\n")
+                }
+                terminator => result.push_str(&format!("    {}\n", terminator.as_c())),
             }
         }
-        AstNode::If {
-            condition,
-            consequence,
-            alternative,
-        } => {
+        result.push_str("}\n");
+        result
+    }
+}
+
+// Generates C from the lowered IR - structs, globals and prototypes come first so
+// functions can be defined in any order
+pub fn c_bindgen_module(module: &Module) -> String {
+    let instances: String = instantiated(module)
+        .iter()
+        .map(|class| c_instance(module, class, false))
+        .chain(module.dyn_impls().into_iter().map(|i| c_impl(module, i)))
+        .collect();
+    c_structs(module) + &c_items(&module.globals, &module.functions, &instances)
+}
+
+// A class `X` is a struct whose first member is its parent, so a pointer to it is also
+// a pointer to each of its parents. The root class starts with a pointer to the vtable,
+// `X__vtable`, which is laid out the same way: the parent's vtable first, then a slot
+// for each method the class adds. A `dyn I` is an `I__dyn`, a pointer to the value
+// paired with the `I__vtable` of the value's impl. A tuple is a struct with a field for
+// each element, `_0` and so on. An Option is a `some` flag and the value, a Result an
+// `ok` flag, the value and the error. Each comes after any struct it holds.
+fn c_structs(module: &Module) -> String {
+    let mut result = String::new();
+    let values = module.value_types();
+    for tp in &values {
+        let name = tp.as_c();
+        result.push_str(&format!("typedef struct {} {};\n", name, name));
+    }
+    for interface in &module.interfaces {
+        let name = c_symbol(&interface.name);
+        result.push_str(&format!(
+            "typedef struct {0}__vtable {0}__vtable;\ntypedef struct {0}__dyn {0}__dyn;\n",
+            name
+        ));
+    }
+    for def in &module.structs {
+        let name = c_symbol(&def.name);
+        result.push_str(&format!("typedef struct {} {};\n", name, name));
+    }
+    for class in &module.classes {
+        let name = c_symbol(&class.name);
+        result.push_str(&format!("typedef struct {} {};\n", name, name));
+        result.push_str(&format!(
+            "typedef struct {}__vtable {}__vtable;\n",
+            name, name
+        ));
+    }
+    // Structs and classes can hold a dyn by value, so its struct comes first
+    for interface in &module.interfaces {
+        let name = c_symbol(&interface.name);
+        result.push_str(&format!("struct {}__vtable {{\n", name));
+        if interface.methods.is_empty() {
+            result.push_str("    char unused;\n");
+        }
+        for (method, params, return_type) in &interface.methods {
             result.push_str(&format!(
-                "{}if ({}) {{\n",
-                indent_str,
-                c_bindgen(condition, 0, is_in_function)
+                "    {} (*{})({});\n",
+                return_type.as_c(),
+                method,
+                std::iter::once("void*".to_string())
+                    .chain(params.iter().map(|param| param.as_c()))
+                    .collect::<Vec<String>>()
+                    .join(", ")
             ));
-            result.push_str(&c_bindgen(consequence, indent + 1, is_in_function));
-            result.push_str(&format!("{}}}\n", indent_str));
-            if let Some(else_body) = alternative {
-                result.push_str(&format!("{}else {{\n", indent_str));
-                result.push_str(&c_bindgen(else_body, indent + 1, is_in_function));
-                result.push_str(&format!("{}}}\n", indent_str));
-            }
         }
-        AstNode::While { condition, body } => {
+        result.push_str("};\n");
+        result.push_str(&format!(
+            "struct {0}__dyn {{\n    void* self;\n    const {0}__vtable* vtable;\n}};\n",
+            name
+        ));
+    }
+    let (late, early): (Vec<&Type>, Vec<&Type>) = values.iter().partition(|tp| holds_struct(tp));
+    for tp in early {
+        result.push_str(&c_value_struct(tp));
+    }
+    let created = created(module);
+    for def in &module.structs {
+        let name = c_symbol(&def.name);
+        result.push_str(&format!("struct {} {{\n", name));
+        for field in &def.fields {
+            result.push_str(&format!("    {} {};\n", field.tp.as_c(), field.name));
+        }
+        result.push_str("};\n");
+        // A struct has no vtable, so every unit can allocate one itself
+        if created.contains(def.name.as_str()) {
             result.push_str(&format!(
-                "{}while ({}) {{\n",
-                indent_str,
-                c_bindgen(condition, 0, is_in_function)
+                "static inline {0}* {0}__new(void) {{\n    return calloc(1, sizeof({0}));\n}}\n",
+                name
             ));
-            result.push_str(&c_bindgen(body, indent + 1, is_in_function));
-            result.push_str(&format!("{}}}\n", indent_str));
-        }
-        AstNode::Assignment {
-            value,
-            tp,
-            variable,
-        } => {
+        }
+    }
+    for tp in late {
+        result.push_str(&c_value_struct(tp));
+    }
+    for class in &module.classes {
+        let name = c_symbol(&class.name);
+        let root = c_symbol(&module.ancestry(&class.name).last().unwrap().name);
+        result.push_str(&format!("struct {}__vtable {{\n", name));
+        let slots: Vec<&Method> = class
+            .vtable
+            .iter()
+            .filter(|slot| slot.declared_by() == class.name)
+            .collect();
+        match &class.parent {
+            Some(parent) => result.push_str(&format!("    {}__vtable base;\n", c_symbol(parent))),
+            // C structs can't be empty
+            None if slots.is_empty() => result.push_str("    char unused;\n"),
+            None => {}
+        }
+        for slot in slots {
             result.push_str(&format!(
-                "{}{} {} = {};\n",
-                indent_str,
-                Type::from(tp.clone().unwrap()).as_c(),
-                variable.split("::").last().unwrap(),
-                c_bindgen(value, 0, true)
+                "    {} (*{})({});\n",
+                slot.return_type.as_c(),
+                slot.name,
+                slot.params
+                    .iter()
+                    .map(|param| param.as_c())
+                    .collect::<Vec<String>>()
+                    .join(", ")
             ));
         }
-        AstNode::FunctionCall { name, arguments } => {
-            // check if the name is toChar or toInt
-            if name == "asChar" {
-                result.push_str(&format!(
-                    "{}(char){}",
-                    indent_str,
-                    c_bindgen(arguments.first().unwrap(), 0, is_in_function)
-                ));
-            } else if name == "asInt" || name == "boolToInt" {
-                result.push_str(&format!(
-                    "{}(int){}",
-                    indent_str,
-                    c_bindgen(arguments.first().unwrap(), 0, is_in_function)
-                ));
-            } else {
-                result.push_str(&format!(
-                    "{}{}({});\n",
-                    indent_str,
-                    name.split("::").last().unwrap(),
-                    arguments
-                        .iter()
-                        .map(|arg| c_bindgen(arg, 0, true))
-                        .collect::<Vec<String>>()
-                        .join(", ")
-                ));
-            }
+        result.push_str("};\n");
+        result.push_str(&format!("struct {} {{\n", name));
+        match &class.parent {
+            Some(parent) => result.push_str(&format!("    {} base;\n", c_symbol(parent))),
+            None => result.push_str(&format!("    const {}__vtable* vtable;\n", name)),
         }
-        AstNode::Number { value } => {
-            result.push_str(&format!("{}{}", indent_str, value));
+        for field in &class.fields {
+            result.push_str(&format!("    {} {};\n", field.tp.as_c(), field.name));
         }
-        AstNode::String { value } => {
-            result.push_str(&format!("{}\"{}\"", indent_str, value));
-        }
-        AstNode::Bool { value } => {
-            result.push_str(&format!("{}{}", indent_str, value));
+        result.push_str("};\n");
+        result.push_str(&format!(
+            "static inline const {0}__vtable* {0}__vtable_of({0}* self) {{\n    return (const {0}__vtable*)(({1}*)self)->vtable;\n}}\n",
+            name, root
+        ));
+    }
+    result
+}
+
+// The struct of a tuple, Option or Result, Void values take no field
+fn c_value_struct(tp: &Type) -> String {
+    let fields: Vec<(String, &Type)> = match tp {
+        Type::Tuple(types) => types
+            .iter()
+            .enumerate()
+            .map(|(index, tp)| (format!("_{}", index), tp))
+            .collect(),
+        Type::Option(held) => vec![
+            ("some".to_string(), &Type::Bool),
+            ("value".to_string(), held),
+        ],
+        Type::Result(held, error) => vec![
+            ("ok".to_string(), &Type::Bool),
+            ("value".to_string(), held),
+            ("error".to_string(), error),
+        ],
+        _ => Vec::new(),
+    };
+    let mut result = format!("struct {} {{\n", tp.as_c());
+    for (name, field) in fields {
+        if *field != Type::Void || matches!(tp, Type::Tuple(_)) {
+            result.push_str(&format!("    {} {};\n", field.as_c(), name));
         }
-        AstNode::Null => {
-            result.push_str(&format!("{}Void", indent_str));
+    }
+    result.push_str("};\n");
+    result
+}
+
+// Whether a tuple, Option or Result holds a struct by value, so it has to come after
+// the structs
+fn holds_struct(tp: &Type) -> bool {
+    match tp {
+        Type::DataTp(_) => true,
+        Type::Tuple(types) => types.iter().any(holds_struct),
+        Type::Option(held) => holds_struct(held),
+        Type::Result(held, error) => holds_struct(held) || holds_struct(error),
+        _ => false,
+    }
+}
+
+// Classes and structs some function creates an instance of
+fn created(module: &Module) -> HashSet<&str> {
+    module
+        .functions
+        .iter()
+        .flat_map(|function| &function.blocks)
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instruction| match instruction {
+            Instruction::New { class, .. } => Some(class.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+// Classes some function creates an instance of
+fn instantiated(module: &Module) -> Vec<&ClassDef> {
+    let created = created(module);
+    module
+        .classes
+        .iter()
+        .filter(|class| created.contains(class.name.as_str()))
+        .collect()
+}
+
+fn c_allocator_signature(class: &ClassDef, public: bool) -> String {
+    let name = c_symbol(&class.name);
+    format!("{}{}* {}__new(void)", linkage(public), name, name)
+}
+
+// The class's vtable and the function that allocates an instance using it
+fn c_instance(module: &Module, class: &ClassDef, public: bool) -> String {
+    let name = c_symbol(&class.name);
+    let root = c_symbol(&module.ancestry(&class.name).last().unwrap().name);
+    format!(
+        "static const {0}__vtable {0}__class = {2};\n{3} {{\n    {0}* self = calloc(1, sizeof({0}));\n    (({1}*)self)->vtable = (const {1}__vtable*)&{0}__class;\n    return self;\n}}\n",
+        name,
+        root,
+        c_vtable(module, class, &class.name),
+        c_allocator_signature(class, public)
+    )
+}
+
+// The initialiser for the part of class's vtable that level (the class or one of its
+// parents) lays out
+fn c_vtable(module: &Module, class: &ClassDef, level: &str) -> String {
+    let mut members = Vec::new();
+    let parent = module
+        .class(level)
+        .and_then(|level| level.parent.as_deref());
+    if let Some(parent) = parent {
+        members.push(format!(".base = {}", c_vtable(module, class, parent)));
+    }
+    for slot in class
+        .vtable
+        .iter()
+        .filter(|slot| slot.declared_by() == level)
+    {
+        members.push(format!(".{} = {}", slot.name, c_symbol(&slot.function)));
+    }
+    if members.is_empty() {
+        "{0}".to_string()
+    } else {
+        format!("{{{}}}", members.join(", "))
+    }
+}
+
+// Values of pointer types are already a pointer a dyn can hold, others are copied to the heap
+fn boxed(tp: &Type) -> bool {
+    !matches!(tp, Type::Pointer(_) | Type::String)
+}
+
+// The prefix of the thunks and vtable an impl gets when it is used as a dyn
+fn impl_c_name(interface: &str, target: &Type) -> String {
+    format!(
+        "{}__{}",
+        c_symbol(&String::from(target.clone())).replace('*', "Ptr"),
+        c_symbol(interface)
+    )
+}
+
+// A thunk for each method, taking the dyn's data pointer in place of self, and the
+// interface's vtable filled with them
+fn c_impl(module: &Module, implementation: &ImplDef) -> String {
+    let name = impl_c_name(&implementation.interface, &implementation.target);
+    let target = implementation.target.as_c();
+    let receiver = if boxed(&implementation.target) {
+        format!("*({}*)self", target)
+    } else {
+        format!("({})self", target)
+    };
+    let Some(interface) = module.interface(&implementation.interface) else {
+        return String::new();
+    };
+    let mut result = String::new();
+    let mut members = Vec::new();
+    for ((method, params, return_type), (_, function)) in
+        interface.methods.iter().zip(&implementation.methods)
+    {
+        let arguments: Vec<String> = (0..params.len()).map(|i| format!("a{}", i)).collect();
+        let call = format!(
+            "{}({})",
+            c_symbol(function),
+            std::iter::once(receiver.clone())
+                .chain(arguments.iter().cloned())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        result.push_str(&format!(
+            "static {} {}__{}__thunk({}) {{\n    {}{};\n}}\n",
+            return_type.as_c(),
+            name,
+            method,
+            std::iter::once("void* self".to_string())
+                .chain(
+                    params
+                        .iter()
+                        .zip(&arguments)
+                        .map(|(tp, arg)| format!("{} {}", tp.as_c(), arg))
+                )
+                .collect::<Vec<String>>()
+                .join(", "),
+            if *return_type == Type::Void { "" } else { "return " },
+            call
+        ));
+        members.push(format!(".{} = {}__{}__thunk", method, name, method));
+    }
+    let members = if members.is_empty() {
+        "0".to_string()
+    } else {
+        members.join(", ")
+    };
+    result.push_str(&format!(
+        "static const {}__vtable {}__vtable = {{{}}};\n",
+        c_symbol(&interface.name),
+        name,
+        members
+    ));
+    result
+}
+
+fn c_items(globals: &[Global], functions: &[Function], instances: &str) -> String {
+    let mut result = String::new();
+    for global in globals {
+        match &global.value {
+            Some(value) => result.push_str(&format!(
+                "{}{} {} = {};\n",
+                linkage(global.public),
+                global_type(global),
+                global.var.c_name(),
+                value.as_c()
+            )),
+            None => result.push_str(&format!(
+                "{}{} {};\n",
+                linkage(global.public),
+                global_type(global),
+                global.var.c_name()
+            )),
         }
-        AstNode::Pointer { value } => {
-            result.push_str(&format!(
-                "{}&{}",
-                indent_str,
-                c_bindgen(value, 0, is_in_function)
-            ));
+    }
+    for function in functions {
+        result.push_str(&format!("{};\n", c_signature(function)));
+    }
+    result.push_str(instances);
+    for function in functions {
+        result.push('\n');
+        result.push_str(&function.as_c());
+    }
+    result
+}
+
+// A global declared with `let` is const
+fn global_type(global: &Global) -> String {
+    CType::new(global.var.tp.as_c(), false, !global.mutable).as_c()
+}
+
+// The TSL module an IR name belongs to, "" for the main file and the prelude
+pub fn unit_of(name: &str) -> &str {
+    let name = name.split("::").next().unwrap_or(name);
+    name.split_once('.').map(|(module, _)| module).unwrap_or("")
+}
+
+// Generates one C file per TSL module, so each can be compiled and cached on its own.
+// Every file gets the C prelude, all structs and all classes. Items used from another
+// file lose their `static`, whatever their TSL visibility, and are declared where they
+// are used. A class's vtable and allocator live in the file of its module, an impl's
+// vtable in each file making a dyn with it.
+pub fn c_bindgen_units(module: &Module) -> Vec<(String, String)> {
+    let globals: HashMap<&str, &Global> = module
+        .globals
+        .iter()
+        .map(|global| (global.var.name.as_str(), global))
+        .collect();
+    let functions: HashMap<&str, &Function> = module
+        .functions
+        .iter()
+        .map(|function| (function.name.as_str(), function))
+        .collect();
+
+    // What each unit uses from the others
+    let mut units: Vec<&str> = vec![""];
+    let mut foreign_globals: HashMap<&str, Vec<&Global>> = HashMap::new();
+    let mut foreign_functions: HashMap<&str, Vec<&Function>> = HashMap::new();
+    let mut foreign_allocators: HashMap<&str, Vec<&ClassDef>> = HashMap::new();
+    let mut exported: HashSet<&str> = HashSet::new();
+    let items = module
+        .globals
+        .iter()
+        .map(|global| global.var.name.as_str())
+        .chain(
+            module
+                .functions
+                .iter()
+                .map(|function| function.name.as_str()),
+        )
+        .chain(module.classes.iter().map(|class| class.name.as_str()));
+    for name in items {
+        if !units.contains(&unit_of(name)) {
+            units.push(unit_of(name));
         }
-        AstNode::Char { value } => {
-            result.push_str(&format!("{}'{}'", indent_str, value));
+    }
+    for function in &module.functions {
+        let unit = unit_of(&function.name);
+        for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+            let used = match instruction {
+                Instruction::Load { var, .. }
+                | Instruction::Store { var, .. }
+                | Instruction::AddressOf { var, .. } => var.name.as_str(),
+                Instruction::Call { function, .. } | Instruction::MakeClosure { function, .. } => {
+                    function.as_str()
+                }
+                // A class is allocated by the unit of the module defining it
+                Instruction::New { class, .. } => {
+                    if unit_of(class) != unit && module.class(class).is_some() {
+                        let list = foreign_allocators.entry(unit).or_default();
+                        if !list.iter().any(|c| c.name == *class) {
+                            list.extend(module.class(class));
+                        }
+                        exported.insert(class.as_str());
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            if unit_of(used) == unit {
+                continue;
+            }
+            if let Some(global) = globals.get(used) {
+                let list = foreign_globals.entry(unit).or_default();
+                if !list.iter().any(|g| g.var.name == used) {
+                    list.push(global);
+                }
+            } else if let Some(callee) = functions.get(used) {
+                let list = foreign_functions.entry(unit).or_default();
+                if !list.iter().any(|f| f.name == used) {
+                    list.push(callee);
+                }
+            } else {
+                // A C prelude function
+                continue;
+            }
+            exported.insert(used);
         }
-        AstNode::Dereference { value } => {
-            result.push_str(&format!(
-                "{}*{}",
-                indent_str,
-                c_bindgen(value, 0, is_in_function)
-            ));
+    }
+
+    // Each unit making a dyn gets its own copy of the impl's vtable, which calls the
+    // impl's functions wherever they are
+    let mut unit_impls: HashMap<&str, Vec<&ImplDef>> = HashMap::new();
+    for function in &module.functions {
+        let unit = unit_of(&function.name);
+        for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+            let Instruction::ToDyn { dest, value } = instruction else {
+                continue;
+            };
+            let Type::Dyn(interface) = &dest.tp else {
+                continue;
+            };
+            let Some(implementation) = module.impl_of(interface, &value.tp()) else {
+                continue;
+            };
+            let list = unit_impls.entry(unit).or_default();
+            if list.iter().any(|other| std::ptr::eq(*other, implementation)) {
+                continue;
+            }
+            list.push(implementation);
+            for (_, name) in &implementation.methods {
+                let Some(callee) = functions.get(name.as_str()) else {
+                    continue;
+                };
+                if unit_of(name) != unit {
+                    let list = foreign_functions.entry(unit).or_default();
+                    if !list.iter().any(|f| f.name == *name) {
+                        list.push(callee);
+                    }
+                    exported.insert(callee.name.as_str());
+                }
+            }
         }
-        AstNode::Comment { value } => {
-            result.push_str(&format!("{}// {}\n", indent_str, value));
+    }
+
+    // A vtable can be filled with methods inherited from a class in another module
+    let instantiated = instantiated(module);
+    for class in &instantiated {
+        let unit = unit_of(&class.name);
+        for slot in &class.vtable {
+            let Some(function) = functions.get(slot.function.as_str()) else {
+                continue;
+            };
+            if unit_of(&function.name) != unit {
+                let list = foreign_functions.entry(unit).or_default();
+                if !list.iter().any(|f| f.name == function.name) {
+                    list.push(function);
+                }
+                exported.insert(function.name.as_str());
+            }
         }
-        _ => {}
     }
-    // If result added ' ' at the end, print the node
-    result
+
+    units
+        .into_iter()
+        .map(|unit| {
+            let mut result = c_bindgen_prelude() + &c_structs(module);
+            for global in foreign_globals.get(unit).into_iter().flatten() {
+                result.push_str(&format!(
+                    "extern {} {};\n",
+                    global_type(global),
+                    global.var.c_name()
+                ));
+            }
+            for function in foreign_functions.get(unit).into_iter().flatten() {
+                let function = Function {
+                    public: true,
+                    ..(*function).clone()
+                };
+                result.push_str(&format!("{};\n", c_signature(&function)));
+            }
+            for class in foreign_allocators.get(unit).into_iter().flatten() {
+                result.push_str(&format!("{};\n", c_allocator_signature(class, true)));
+            }
+            let instances: String = instantiated
+                .iter()
+                .filter(|class| unit_of(&class.name) == unit)
+                .map(|class| c_instance(module, class, exported.contains(class.name.as_str())))
+                .chain(
+                    unit_impls
+                        .get(unit)
+                        .into_iter()
+                        .flatten()
+                        .map(|i| c_impl(module, i)),
+                )
+                .collect();
+            let own_globals: Vec<Global> = module
+                .globals
+                .iter()
+                .filter(|global| unit_of(&global.var.name) == unit)
+                .map(|global| Global {
+                    public: global.public || exported.contains(global.var.name.as_str()),
+                    ..global.clone()
+                })
+                .collect();
+            let own_functions: Vec<Function> = module
+                .functions
+                .iter()
+                .filter(|function| unit_of(&function.name) == unit)
+                .map(|function| Function {
+                    public: function.public || exported.contains(function.name.as_str()),
+                    ..function.clone()
+                })
+                .collect();
+            result.push_str(&c_items(&own_globals, &own_functions, &instances));
+            (unit.to_string(), result)
+        })
+        .collect()
 }
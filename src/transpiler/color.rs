@@ -38,6 +38,6 @@ pub fn colorize<T: Color>(color: T, text: &str) -> String {
 #[macro_export]
 macro_rules! colorize {
     ($color:ident, $text:expr) => {
-        $crate::color::colorize($color, $text)
+        $crate::transpiler::color::colorize($crate::transpiler::color::$color, $text)
     };
 }
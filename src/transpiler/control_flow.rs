@@ -0,0 +1,96 @@
+use crate::parser::*;
+
+// Control flow analysis - works out whether a statement is guaranteed to leave the
+// function, and which statements can never run because of it.
+
+// Returns true if every path through the node ends in a return (or never finishes).
+pub fn always_returns(node: &AstNode) -> bool {
+    match node {
+        AstNode::Return { .. } => true,
+        AstNode::Block { statements } => statements.iter().any(always_returns),
+        AstNode::If {
+            consequence,
+            alternative: Some(alternative),
+            ..
+        } => always_returns(consequence) && always_returns(alternative),
+        // There is no way to break out of a loop, so `while true` can only be left by returning
+        AstNode::While { condition, .. } => is_always_true(condition),
+        _ => false,
+    }
+}
+
+// Collects a short description of every statement that comes after a statement which always returns
+pub fn unreachable_statements(node: &AstNode) -> Vec<String> {
+    let mut unreachable = Vec::new();
+    collect_unreachable(node, &mut unreachable);
+    unreachable
+}
+
+fn collect_unreachable(node: &AstNode, unreachable: &mut Vec<String>) {
+    match node {
+        AstNode::Block { statements } => {
+            let mut returned = false;
+            for statement in statements {
+                if returned {
+                    if let Some(description) = describe(statement) {
+                        unreachable.push(description);
+                    }
+                    continue;
+                }
+                collect_unreachable(statement, unreachable);
+                returned = always_returns(statement);
+            }
+        }
+        AstNode::If {
+            consequence,
+            alternative,
+            ..
+        } => {
+            collect_unreachable(consequence, unreachable);
+            if let Some(alt) = alternative {
+                collect_unreachable(alt, unreachable);
+            }
+        }
+        AstNode::While { body, .. } => collect_unreachable(body, unreachable),
+        _ => {}
+    }
+}
+
+fn is_always_true(condition: &AstNode) -> bool {
+    matches!(condition, AstNode::Bool { value } if value == "true")
+}
+
+// Comments and the end of file marker are not statements, so they are never reported
+pub fn describe(node: &AstNode) -> Option<String> {
+    let description = match node {
+        AstNode::Comment { .. } | AstNode::Eof => return None,
+        AstNode::Assignment { variable, .. } => format!(
+            "assignment to '{}'",
+            variable.split("::").last().unwrap_or(variable)
+        ),
+        AstNode::FunctionCall { name, .. } => format!("call to '{}'", source_name(name)),
+        AstNode::MethodCall { method, .. } | AstNode::SuperCall { method, .. } => {
+            format!("call to '{}'", method)
+        }
+        AstNode::FieldAssignment { field, .. } => format!("assignment to '{}'", field),
+        AstNode::Return { .. } => "return statement".to_string(),
+        AstNode::If { .. } => "if statement".to_string(),
+        AstNode::While { .. } => "while loop".to_string(),
+        AstNode::Function { name, .. } => format!("function '{}'", source_name(name)),
+        AstNode::Block { .. } => "block".to_string(),
+        _ => "expression".to_string(),
+    };
+    Some(description)
+}
+
+// Strips the `_N` suffix added by static dispatch, so messages use the name from the source
+pub fn source_name(name: &str) -> &str {
+    match name.rsplit_once('_') {
+        Some((base, suffix))
+            if suffix.chars().all(|c| c.is_ascii_digit()) && !suffix.is_empty() =>
+        {
+            base
+        }
+        _ => name,
+    }
+}
@@ -0,0 +1,944 @@
+// Generics - funcs, structs and classes with type parameters are instantiated for every
+// set of type arguments the program uses, before overloads are resolved, so every later
+// pass only sees ordinary items. `max<T>` called with Ints becomes the function
+// `max__Int`, and `Pair<Int, String>` the struct `Pair__Int__String`. The type arguments
+// of a call are inferred from its arguments. A generic is only checked through its
+// instances, so one the program never uses is never checked.
+
+use super::type_checker::{
+    builtin_method, returned, split_function_type, split_types, Type, TypeChecker,
+};
+use crate::parser::{method_name, method_symbol, AstNode, Binding};
+use std::collections::{HashMap, HashSet};
+
+// Stops a generic that instantiates itself with ever larger types, like `f<Box<T>>` in `f<T>`
+const MAX_INSTANCES: usize = 1000;
+
+#[derive(Clone)]
+struct Template {
+    params: Vec<(String, Option<String>)>,
+    item: AstNode,
+}
+
+#[derive(Clone, Default)]
+pub struct Monomorphizer {
+    functions: HashMap<String, Template>,
+    types: HashMap<String, Template>,
+    // (parameter types, return type) of every overload of each ordinary function
+    overloads: HashMap<String, Vec<(Vec<Type>, Type)>>,
+    globals: HashMap<String, Type>,
+    // Knows the classes and impls, instances are added as they are made
+    checker: TypeChecker,
+    // The instances of each generic, in the order they were made
+    instances: HashMap<String, Vec<AstNode>>,
+    made: HashSet<String>,
+}
+
+// Replaces each generic item with its instances and the uses of generics with the names
+// of those instances
+pub fn monomorphize(ast: &mut AstNode) -> Result<(), String> {
+    Monomorphizer::default().monomorphize(ast)
+}
+
+fn item_name(item: &AstNode) -> String {
+    match item {
+        AstNode::Function { name, .. }
+        | AstNode::Struct { name, .. }
+        | AstNode::Class { name, .. } => name.clone(),
+        _ => "item".to_string(),
+    }
+}
+
+impl Monomorphizer {
+    // Monomorphizes one more part of a program, like the next REPL input. The generics,
+    // functions and globals of the earlier parts stay known, and the instances of their
+    // generics this part needs are put in front of its statements.
+    pub fn monomorphize(&mut self, ast: &mut AstNode) -> Result<(), String> {
+        let AstNode::Block { statements } = ast else {
+            return Ok(());
+        };
+        // Generics are taken out, leaving their name where their instances go
+        let mut items: Vec<Result<AstNode, String>> = Vec::new();
+        for statement in statements.drain(..) {
+            let AstNode::Generic { params, item } = statement else {
+                self.checker.prelude(&statement);
+                self.collect_overloads(&statement);
+                items.push(Ok(statement));
+                continue;
+            };
+            let name = item_name(&item);
+            let template = Template {
+                params,
+                item: *item,
+            };
+            let generics = match template.item {
+                AstNode::Function { .. } => &mut self.functions,
+                _ => &mut self.types,
+            };
+            if generics.insert(name.clone(), template).is_some() {
+                return Err(format!("Generic {} is defined more than once", name));
+            }
+            items.push(Err(name));
+        }
+        for item in items.iter_mut().flatten() {
+            if let AstNode::Assignment {
+                variable,
+                tp,
+                value,
+                ..
+            } = item
+            {
+                let tp = match tp.as_deref() {
+                    Some("NotMentioned") | None => self.type_of(value, &HashMap::new()),
+                    Some(tp) => Some(Type::from(tp)),
+                };
+                // A global whose type isn't known yet is still known to be there
+                self.globals
+                    .insert(variable.clone(), tp.unwrap_or(Type::NotMentioned));
+            }
+        }
+        let mut errors = Vec::new();
+        for item in items.iter_mut().flatten() {
+            if let Err(e) = self.walk(item, &mut HashMap::new()) {
+                errors.push(e);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+        for item in items {
+            match item {
+                Ok(item) => statements.push(item),
+                Err(name) => statements.extend(self.instances.remove(&name).unwrap_or_default()),
+            }
+        }
+        // What is left are instances of the generics of earlier parts
+        let mut earlier: Vec<(String, Vec<AstNode>)> = self.instances.drain().collect();
+        earlier.sort_by(|a, b| a.0.cmp(&b.0));
+        statements.splice(
+            0..0,
+            earlier.into_iter().flat_map(|(_, instances)| instances),
+        );
+        mangle_names(ast);
+        Ok(())
+    }
+
+    // Every function, nested ones and methods included
+    fn collect_overloads(&mut self, node: &AstNode) {
+        if let AstNode::Function {
+            name,
+            arguments,
+            return_type,
+            ..
+        } = node
+        {
+            let params = arguments
+                .iter()
+                .map(|(tp, _)| Type::from(tp.as_str()))
+                .collect();
+            self.overloads
+                .entry(name.clone())
+                .or_default()
+                .push((params, Type::from(return_type.as_str())));
+        }
+        let children = match node {
+            AstNode::Function { body, .. } => body.get_statements(),
+            AstNode::Block { statements } => statements.clone(),
+            AstNode::Class { methods, .. } | AstNode::Impl { methods, .. } => methods.clone(),
+            _ => vec![],
+        };
+        for child in &children {
+            self.collect_overloads(child);
+        }
+    }
+
+    // Instantiates the generics a node and its children use. Locals are in scope from the
+    // assignment that declares them on.
+    fn walk(
+        &mut self,
+        node: &mut AstNode,
+        scope: &mut HashMap<String, Type>,
+    ) -> Result<(), String> {
+        for tp in types_of(node) {
+            self.require(tp)?;
+        }
+        match node {
+            AstNode::Generic { item, .. } => Err(format!(
+                "{} can only have type parameters at the top level",
+                item_name(item)
+            )),
+            AstNode::Function {
+                arguments, body, ..
+            } => {
+                for (_, argument) in arguments.iter() {
+                    self.checker.declare(argument, Binding::LetMut);
+                }
+                let mut scope = arguments
+                    .iter()
+                    .map(|(tp, argument)| (argument.clone(), Type::from(tp.as_str())))
+                    .collect();
+                self.walk(body, &mut scope)
+            }
+            // Sees the locals of the function it is in, which it can capture
+            AstNode::Lambda {
+                arguments, body, ..
+            } => {
+                for (_, argument) in arguments.iter() {
+                    self.checker.declare(argument, Binding::LetMut);
+                }
+                let mut scope = scope.clone();
+                scope.extend(
+                    arguments
+                        .iter()
+                        .map(|(tp, argument)| (argument.clone(), Type::from(tp.as_str()))),
+                );
+                self.walk(body, &mut scope)
+            }
+            AstNode::Block { statements } => {
+                let errors: Vec<String> = statements
+                    .iter_mut()
+                    .filter_map(|statement| self.walk(statement, scope).err())
+                    .collect();
+                if errors.is_empty() {
+                    Ok(())
+                } else {
+                    Err(errors.join("\n"))
+                }
+            }
+            AstNode::Assignment {
+                variable,
+                tp,
+                value,
+                binding,
+            } => {
+                self.walk(value, scope)?;
+                self.checker.declare(variable, *binding);
+                if matches!(tp.as_deref(), Some("NotMentioned") | None) {
+                    *tp = self.type_of(value, scope).map(String::from).or(tp.take());
+                }
+                // So is a local
+                let known = scope.entry(variable.clone()).or_insert(Type::NotMentioned);
+                if let Some(tp) = tp.as_deref().filter(|tp| *tp != "NotMentioned")
+                    && *known == Type::NotMentioned
+                {
+                    *known = Type::from(tp);
+                }
+                Ok(())
+            }
+            _ => {
+                for child in node.children_mut() {
+                    self.walk(child, scope)?;
+                }
+                if let AstNode::FunctionCall { name, arguments } = node
+                    && self.functions.contains_key(name.as_str())
+                {
+                    let types: Vec<Option<Type>> = arguments
+                        .iter()
+                        .map(|argument| self.type_of(argument, scope))
+                        .collect();
+                    // An ordinary overload taking exactly these types wins over the
+                    // generic, and is left to static dispatch when the generic doesn't fit
+                    if self.overload(name, &types).is_none() {
+                        match self.instantiate_call(name, &types) {
+                            Ok(instance) => *name = instance,
+                            Err(_) if self.overloads.contains_key(name.as_str()) => {}
+                            // The type checker reports the argument that names nothing
+                            Err(_)
+                                if arguments
+                                    .iter()
+                                    .any(|argument| self.is_undefined(argument, scope)) => {}
+                            Err(e) => return Err(e),
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Instantiates each generic type a type mentions, e.g. `Box<Pair<Int, Int>>*`
+    fn require(&mut self, tp: &str) -> Result<(), String> {
+        let base = tp.trim_start_matches("dyn ").trim_end_matches('*');
+        if let Some((params, ret)) = split_function_type(base) {
+            for tp in params.iter().chain(std::iter::once(&ret)) {
+                self.require(tp)?;
+            }
+            return Ok(());
+        }
+        if let Some(types) = base
+            .strip_prefix('(')
+            .and_then(|base| base.strip_suffix(')'))
+        {
+            for tp in split_types(types) {
+                self.require(&tp)?;
+            }
+            return Ok(());
+        }
+        let Some((name, args)) = split_generic(base) else {
+            if self.types.contains_key(base) {
+                return Err(format!("Generic {} is used without type arguments", base));
+            }
+            return Ok(());
+        };
+        for arg in &args {
+            self.require(arg)?;
+        }
+        if is_builtin(name) {
+            return Ok(());
+        }
+        let instance = format!("{}<{}>", name, args.join(", "));
+        if self.made.contains(&instance) {
+            return Ok(());
+        }
+        let template = self
+            .types
+            .get(name)
+            .ok_or_else(|| format!("{} is not a generic struct or class", name))?;
+        let bindings = self.bind(name, &template.params, &args)?;
+        let mut item = template.item.clone();
+        substitute(&mut item, &bindings);
+        match &mut item {
+            AstNode::Struct { name, .. } => *name = instance.clone(),
+            AstNode::Class { name, methods, .. } => {
+                for method in methods.iter_mut() {
+                    if let AstNode::Function { name: function, .. } = method {
+                        *function = method_symbol(&instance, method_name(name, function));
+                    }
+                }
+                *name = instance.clone();
+            }
+            _ => {}
+        }
+        self.add_instance(name, &instance, item)
+    }
+
+    // The instance of a generic function the arguments of a call fit
+    fn instantiate_call(&mut self, name: &str, types: &[Option<Type>]) -> Result<String, String> {
+        let template = &self.functions[name];
+        let AstNode::Function { arguments, .. } = &template.item else {
+            return Err(format!("{} is not a function", name));
+        };
+        if arguments.len() != types.len() {
+            return Err(format!(
+                "Function {} Expected {} arguments but found {}",
+                name,
+                arguments.len(),
+                types.len()
+            ));
+        }
+        let vars: Vec<String> = template.params.iter().map(|(var, _)| var.clone()).collect();
+        let mut bindings = HashMap::new();
+        for ((param, _), actual) in arguments.iter().zip(types) {
+            if let Some(actual) = actual {
+                unify(&Type::from(param.as_str()), actual, &vars, &mut bindings)
+                    .map_err(|e| format!("Call to {}: {}", name, e))?;
+            }
+        }
+        let args = vars
+            .iter()
+            .map(|var| {
+                bindings.get(var).cloned().ok_or_else(|| {
+                    format!(
+                        "Can't infer type parameter {} of {} from its arguments",
+                        var, name
+                    )
+                })
+            })
+            .collect::<Result<Vec<String>, String>>()?;
+        let instance = format!("{}<{}>", name, args.join(", "));
+        if self.made.contains(&instance) {
+            return Ok(instance);
+        }
+        let template = &self.functions[name];
+        let bindings = self.bind(name, &template.params, &args)?;
+        let mut item = template.item.clone();
+        substitute(&mut item, &bindings);
+        if let AstNode::Function {
+            name,
+            arguments,
+            return_type,
+            ..
+        } = &mut item
+        {
+            *name = instance.clone();
+            let params = arguments
+                .iter()
+                .map(|(tp, _)| Type::from(tp.as_str()))
+                .collect();
+            self.overloads.insert(
+                instance.clone(),
+                vec![(params, Type::from(return_type.as_str()))],
+            );
+        }
+        self.add_instance(name, &instance, item)?;
+        Ok(instance)
+    }
+
+    // The type each parameter stands for, once the arguments are checked against its bound
+    fn bind(
+        &self,
+        name: &str,
+        params: &[(String, Option<String>)],
+        args: &[String],
+    ) -> Result<HashMap<String, String>, String> {
+        if params.len() != args.len() {
+            return Err(format!(
+                "{} takes {} type arguments but was given {}",
+                name,
+                params.len(),
+                args.len()
+            ));
+        }
+        let mut bindings = HashMap::new();
+        for ((param, bound), arg) in params.iter().zip(args) {
+            if let Some(bound) = bound
+                && !self.checker.implements(&Type::from(arg.as_str()), bound)
+            {
+                return Err(format!(
+                    "{} does not implement {}, which type parameter {} of {} needs",
+                    arg, bound, param, name
+                ));
+            }
+            bindings.insert(param.clone(), arg.clone());
+        }
+        Ok(bindings)
+    }
+
+    // Makes the instance known before walking it, so a generic can use itself
+    fn add_instance(
+        &mut self,
+        generic: &str,
+        instance: &str,
+        mut item: AstNode,
+    ) -> Result<(), String> {
+        if self.made.len() >= MAX_INSTANCES {
+            return Err(format!(
+                "Too many instances of generics, {} keeps instantiating itself",
+                generic
+            ));
+        }
+        self.made.insert(instance.to_string());
+        self.checker.prelude(&item);
+        self.walk(&mut item, &mut HashMap::new())?;
+        self.instances
+            .entry(generic.to_string())
+            .or_default()
+            .push(item);
+        Ok(())
+    }
+
+    // The ordinary overload taking exactly these argument types
+    fn overload(&self, name: &str, types: &[Option<Type>]) -> Option<&(Vec<Type>, Type)> {
+        self.overloads.get(name)?.iter().find(|(params, _)| {
+            params.len() == types.len()
+                && params
+                    .iter()
+                    .zip(types)
+                    .all(|(param, tp)| tp.as_ref() == Some(param))
+        })
+    }
+
+    fn return_type(
+        &self,
+        name: &str,
+        arguments: &[AstNode],
+        scope: &HashMap<String, Type>,
+    ) -> Option<Type> {
+        let types: Vec<Option<Type>> = arguments
+            .iter()
+            .map(|argument| self.type_of(argument, scope))
+            .collect();
+        if let Some((_, return_type)) = self.overload(name, &types) {
+            return Some(return_type.clone());
+        }
+        // Overloads can differ in their return types, which only helps when they don't
+        let returns: Vec<Type> = match self.overloads.get(name) {
+            Some(overloads) => overloads
+                .iter()
+                .filter(|(params, _)| params.len() == types.len())
+                .map(|(_, return_type)| return_type.clone())
+                .collect(),
+            None => self
+                .checker
+                .function_table
+                .get(name)
+                .map(|(_, returns)| vec![returned(returns)])
+                .unwrap_or_default(),
+        };
+        let first = returns.first()?;
+        returns
+            .iter()
+            .all(|return_type| return_type == first)
+            .then(|| first.clone())
+    }
+
+    // The type of an expression, as far as it can be known before type checking
+    fn type_of(&self, node: &AstNode, scope: &HashMap<String, Type>) -> Option<Type> {
+        match node {
+            AstNode::Number { .. } => Some(Type::Integer),
+            AstNode::String { .. } => Some(Type::String),
+            AstNode::Char { .. } => Some(Type::Char),
+            AstNode::Bool { .. } => Some(Type::Bool),
+            AstNode::Identifier { value } | AstNode::Variable { value } => scope
+                .get(value)
+                .or_else(|| self.globals.get(value))
+                .filter(|tp| **tp != Type::NotMentioned)
+                .cloned()
+                .or_else(|| self.function_value(value)),
+            AstNode::Lambda {
+                arguments,
+                return_type,
+                ..
+            } => Some(Type::Function(
+                arguments
+                    .iter()
+                    .map(|(tp, _)| Type::from(tp.as_str()))
+                    .collect(),
+                Box::new(Type::from(return_type.as_str())),
+            )),
+            AstNode::Call { callee, .. } => match self.type_of(callee, scope)? {
+                Type::Function(_, ret) => Some(*ret),
+                _ => None,
+            },
+            AstNode::Pointer { value } => {
+                let tp = Box::new(self.type_of(value, scope)?);
+                match value.as_ref() {
+                    AstNode::Identifier { value } if self.checker.is_immutable(value) => {
+                        Some(Type::ConstPointer(tp))
+                    }
+                    _ => Some(Type::Pointer(tp)),
+                }
+            }
+            AstNode::Dereference { value } => match self.type_of(value, scope)? {
+                Type::Pointer(tp) if *tp == Type::DataTp("Str".to_owned()) => Some(Type::Char),
+                Type::Pointer(tp) | Type::ConstPointer(tp) => Some(*tp),
+                Type::String => Some(Type::Char),
+                _ => None,
+            },
+            AstNode::BinaryOperation { operator, left, .. } => match operator.value.as_str() {
+                "+" | "-" | "*" | "/" | "%" => match self.type_of(left, scope)? {
+                    tp @ (Type::Pointer(_) | Type::ConstPointer(_) | Type::String) => Some(tp),
+                    _ => Some(Type::Integer),
+                },
+                _ => Some(Type::Bool),
+            },
+            AstNode::FunctionCall { name, arguments } => {
+                match scope.get(name).or_else(|| self.globals.get(name)) {
+                    Some(Type::Function(_, ret)) => Some(*ret.clone()),
+                    _ => self.return_type(name, arguments, scope),
+                }
+            }
+            AstNode::New { class, .. } => {
+                Some(Type::Pointer(Box::new(Type::DataTp(class.clone()))))
+            }
+            // Ok and Err leave out a type, which only the type checker can work out
+            AstNode::Variant { variant, value } if variant == "Some" => Some(Type::Option(
+                Box::new(self.type_of(value.as_ref()?, scope)?),
+            )),
+            AstNode::Try { value } => match self.type_of(value, scope)? {
+                Type::Option(tp) | Type::Result(tp, _) => Some(*tp),
+                _ => None,
+            },
+            AstNode::Tuple { elements } => Some(Type::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.type_of(element, scope))
+                    .collect::<Option<Vec<Type>>>()?,
+            )),
+            AstNode::FieldAccess { object, field } => {
+                let tp = self.type_of(object, scope)?;
+                self.checker.field_type(&tp, field)
+            }
+            AstNode::MethodCall { object, method, .. } => {
+                let tp = self.type_of(object, scope)?;
+                if let Some((_, return_type)) = builtin_method(&tp, method) {
+                    return Some(return_type);
+                }
+                if let Some(class) = self.class_of(&tp)
+                    && let Ok((_, return_type)) = self.checker.method_signature(&class, method)
+                {
+                    return Some(return_type);
+                }
+                if let Type::Dyn(interface) = &tp {
+                    return Some(self.checker.interface_method(interface, method).ok()?.1);
+                }
+                let (_, function) = self.checker.impl_method(&tp, method).ok()??;
+                Some(returned(&self.checker.function_table.get(&function)?.1))
+            }
+            AstNode::SuperCall { class, method, .. } => {
+                let parent = self.checker.classes.get(class)?.parent.clone()?;
+                Some(self.checker.method_signature(&parent, method).ok()?.1)
+            }
+            _ => None,
+        }
+    }
+
+    // A variable that isn't declared anywhere the node is, or a function that isn't defined
+    fn is_undefined(&self, node: &AstNode, scope: &HashMap<String, Type>) -> bool {
+        match node {
+            AstNode::Identifier { value } | AstNode::Variable { value } => {
+                !scope.contains_key(value)
+                    && !self.globals.contains_key(value)
+                    && !self.checker.symbol_table.contains_key(value)
+                    && !self.checker.function_table.contains_key(value)
+                    && !self.overloads.contains_key(value)
+                    && !self.functions.contains_key(value)
+            }
+            _ => false,
+        }
+    }
+
+    // The type of a function named as a value, which has to have a single overload
+    fn function_value(&self, name: &str) -> Option<Type> {
+        let (params, ret) = match self.overloads.get(name) {
+            Some(overloads) if overloads.len() == 1 => overloads[0].clone(),
+            Some(_) => return None,
+            None => {
+                let (params, returns) = self.checker.function_table.get(name)?;
+                (params.clone(), returned(returns))
+            }
+        };
+        Some(Type::Function(params, Box::new(ret)))
+    }
+
+    fn class_of(&self, tp: &Type) -> Option<String> {
+        match tp {
+            Type::Pointer(tp) | Type::ConstPointer(tp) => match tp.as_ref() {
+                Type::DataTp(name) if self.checker.classes.contains_key(name) => Some(name.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+// Binds the type parameters in param to the parts of actual they stand for. Anything
+// that isn't a type parameter is left for the type checker to compare.
+fn unify(
+    param: &Type,
+    actual: &Type,
+    vars: &[String],
+    bindings: &mut HashMap<String, String>,
+) -> Result<(), String> {
+    match (param, actual) {
+        (Type::DataTp(var), _) if vars.contains(var) => {
+            let actual = String::from(actual.clone());
+            match bindings.get(var) {
+                Some(bound) if *bound != actual => Err(format!(
+                    "type parameter {} can't be both {} and {}",
+                    var, bound, actual
+                )),
+                _ => {
+                    bindings.insert(var.clone(), actual);
+                    Ok(())
+                }
+            }
+        }
+        (Type::Pointer(param), Type::Pointer(actual))
+        | (Type::ConstPointer(param), Type::Pointer(actual) | Type::ConstPointer(actual))
+        | (Type::Option(param), Type::Option(actual)) => unify(param, actual, vars, bindings),
+        (Type::Result(param, param_error), Type::Result(actual, actual_error)) => {
+            unify(param, actual, vars, bindings)?;
+            unify(param_error, actual_error, vars, bindings)
+        }
+        (Type::Function(params, ret), Type::Function(actuals, actual_ret))
+            if params.len() == actuals.len() =>
+        {
+            for (param, actual) in params.iter().zip(actuals) {
+                unify(param, actual, vars, bindings)?;
+            }
+            unify(ret, actual_ret, vars, bindings)
+        }
+        (Type::Tuple(params), Type::Tuple(actuals)) if params.len() == actuals.len() => {
+            for (param, actual) in params.iter().zip(actuals) {
+                unify(param, actual, vars, bindings)?;
+            }
+            Ok(())
+        }
+        (Type::DataTp(param), Type::DataTp(actual)) => {
+            match (split_generic(param), split_generic(actual)) {
+                (Some((name, params)), Some((other, actuals)))
+                    if name == other && params.len() == actuals.len() =>
+                {
+                    for (param, actual) in params.iter().zip(&actuals) {
+                        let (param, actual) =
+                            (Type::from(param.as_str()), Type::from(actual.as_str()));
+                        unify(&param, &actual, vars, bindings)?;
+                    }
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+// The types written in a node itself, not in its children
+fn types_of(node: &mut AstNode) -> Vec<&mut String> {
+    match node {
+        AstNode::Function {
+            arguments,
+            return_type,
+            ..
+        }
+        | AstNode::Lambda {
+            arguments,
+            return_type,
+            ..
+        } => arguments
+            .iter_mut()
+            .map(|(tp, _)| tp)
+            .chain(std::iter::once(return_type))
+            .collect(),
+        AstNode::Assignment { tp: Some(tp), .. } | AstNode::Uninit { tp } => vec![tp],
+        AstNode::New { class, .. } | AstNode::SuperCall { class, .. } => vec![class],
+        AstNode::Struct { fields, .. } => fields.iter_mut().map(|(tp, _)| tp).collect(),
+        AstNode::Class { parent, fields, .. } => parent
+            .iter_mut()
+            .chain(fields.iter_mut().map(|(tp, _)| tp))
+            .collect(),
+        AstNode::Impl { target, .. } => vec![target],
+        AstNode::Interface { methods, .. } => methods
+            .iter_mut()
+            .flat_map(|(_, params, return_type)| {
+                params.iter_mut().chain(std::iter::once(return_type))
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// Writes the type each type parameter is bound to in its place
+fn substitute(node: &mut AstNode, bindings: &HashMap<String, String>) {
+    for tp in types_of(node) {
+        *tp = substitute_type(tp, bindings);
+    }
+    for child in node.children_mut() {
+        substitute(child, bindings);
+    }
+}
+
+fn substitute_type(tp: &str, bindings: &HashMap<String, String>) -> String {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_' || c == '.' || c == ':';
+    let mut substituted = String::new();
+    let mut rest = tp;
+    while let Some(start) = rest.find(is_name) {
+        substituted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_name(c)).unwrap_or(rest.len());
+        let name = &rest[..end];
+        substituted.push_str(bindings.get(name).map_or(name, String::as_str));
+        rest = &rest[end..];
+    }
+    substituted + rest
+}
+
+fn mangle_names(node: &mut AstNode) {
+    for tp in types_of(node) {
+        *tp = mangle(tp);
+    }
+    if let AstNode::Function { name, .. }
+    | AstNode::FunctionCall { name, .. }
+    | AstNode::Struct { name, .. }
+    | AstNode::Class { name, .. } = node
+    {
+        *name = mangle(name);
+    }
+    for child in node.children_mut() {
+        mangle_names(child);
+    }
+}
+
+// `Pair<Int, String*>` as a name C can use, `Pair__Int__StringPtr`. The stars of a
+// pointer to an instance stay where they are, and `Func(Int) Int` becomes `Func_Int__Int`.
+// Option and Result are built in, so only the types they hold are mangled.
+pub fn mangle(name: &str) -> String {
+    let Some(open) = name.find('<') else {
+        return name.to_string();
+    };
+    let close = matching_close(name, open);
+    let generic = name[..open]
+        .rsplit(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default();
+    if is_builtin(generic) {
+        let args: Vec<String> = split_types(&name[open + 1..close])
+            .iter()
+            .map(|arg| mangle(arg))
+            .collect();
+        return format!(
+            "{}<{}>{}",
+            &name[..open],
+            args.join(", "),
+            mangle(name.get(close + 1..).unwrap_or(""))
+        );
+    }
+    let args: Vec<String> = split_types(&name[open + 1..close])
+        .iter()
+        .map(|arg| {
+            mangle(arg)
+                .replace('*', "Ptr")
+                .replace("dyn ", "Dyn")
+                .replace(['(', ')', ',', ' ', '<', '>'], "_")
+        })
+        .collect();
+    format!(
+        "{}__{}{}",
+        &name[..open],
+        args.join("__"),
+        mangle(name.get(close + 1..).unwrap_or(""))
+    )
+}
+
+// Option and Result, which the type checker knows without any instances
+fn is_builtin(generic: &str) -> bool {
+    matches!(generic, "Option" | "Result")
+}
+
+// The name and type arguments of a generic type, `Pair<Int, Box<Int>>` gives
+// ("Pair", ["Int", "Box<Int>"])
+pub fn split_generic(tp: &str) -> Option<(&str, Vec<String>)> {
+    let open = tp.find('<')?;
+    if matching_close(tp, open) + 1 != tp.len() {
+        return None;
+    }
+    Some((&tp[..open], split_types(&tp[open + 1..tp.len() - 1])))
+}
+
+fn matching_close(tp: &str, open: usize) -> usize {
+    let mut depth = 0;
+    for (index, c) in tp.char_indices().skip(open) {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return index;
+                }
+            }
+            _ => {}
+        }
+    }
+    tp.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::interpret;
+    use crate::transpiler::ir::{Instruction, Var};
+    use crate::transpiler::type_checker::Type;
+    use crate::{compile, CompileOptions, CompileOutput};
+
+    fn compiled(source: &str) -> CompileOutput {
+        compile(source, &CompileOptions::default()).unwrap_or_else(|errors| panic!("{}", errors[0]))
+    }
+
+    // What main returns when the program is run on the interpreter
+    fn status(output: &CompileOutput) -> i32 {
+        interpret(output.ast.as_ref().unwrap()).unwrap()
+    }
+
+    fn error(source: &str) -> String {
+        match compile(source, &CompileOptions::default()) {
+            Ok(_) => panic!("{:?} compiled", source),
+            Err(errors) => errors[0].to_string(),
+        }
+    }
+
+    fn field(name: &str, tp: Type) -> Var {
+        Var {
+            name: name.to_string(),
+            tp,
+        }
+    }
+
+    #[test]
+    fn generic_struct_is_built_and_read() {
+        let output = compiled(
+            "struct Pair<T> |\n    T a\n    T b\n|\nfunc main() Int |\n    let p = new Pair<Int>(1, 2)\n    let q = new Pair<Bool>(true, false)\n    return p.b\n|\n",
+        );
+        assert_eq!(status(&output), 2);
+        let module = output.module.unwrap();
+        let structs: Vec<(&str, &[Var])> = module
+            .structs
+            .iter()
+            .map(|def| (def.name.as_str(), &def.fields[..]))
+            .collect();
+        assert_eq!(
+            structs,
+            [
+                (
+                    "Pair__Int",
+                    &[field("a", Type::Integer), field("b", Type::Integer)][..]
+                ),
+                (
+                    "Pair__Bool",
+                    &[field("a", Type::Bool), field("b", Type::Bool)][..]
+                ),
+            ]
+        );
+        let main = module.functions.iter().find(|f| f.name == "main").unwrap();
+        let created: Vec<&str> = main.blocks[0]
+            .instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::New { class, .. } => Some(class.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(created, ["Pair__Int", "Pair__Bool"]);
+    }
+
+    #[test]
+    fn generic_function_is_instantiated_for_each_type_it_is_called_with() {
+        let output = compiled(
+            "func first<T>(a T, b T) T |\n    return a\n|\nfunc main() Int |\n    let b = first(true, false)\n    return first(7, 8)\n|\n",
+        );
+        assert_eq!(status(&output), 7);
+        let module = output.module.unwrap();
+        let mut names: Vec<&str> = module.functions.iter().map(|f| f.name.as_str()).collect();
+        names.retain(|name| name.starts_with("first"));
+        names.sort();
+        assert_eq!(names, ["first__Bool", "first__Int"]);
+    }
+
+    #[test]
+    fn conflicting_type_arguments_are_an_error() {
+        assert_eq!(
+            error("func first<T>(a T, b T) T |\n    return a\n|\nfunc main() Int |\n    return first(1, true)\n|\n"),
+            "Type error: Call to first: type parameter T can't be both Int and Bool"
+        );
+    }
+
+    #[test]
+    fn type_parameter_the_arguments_dont_mention_is_an_error() {
+        assert_eq!(
+            error(
+                "func none<T>() Int |\n    return 1\n|\nfunc main() Int |\n    return none()\n|\n"
+            ),
+            "Type error: Can't infer type parameter T of none from its arguments"
+        );
+    }
+
+    #[test]
+    fn an_undefined_argument_is_reported_rather_than_the_type_it_cant_give() {
+        assert_eq!(
+            error("func main() Int |\n    println(nope)\n    return 0\n|\n"),
+            "Type error: Undefined variable: main::nope"
+        );
+        // A variable whose type isn't known before type checking is still defined
+        assert_eq!(
+            error("func none<T>(t T) Int |\n    return 1\n|\nfunc main() Int |\n    let r = Ok(1)\n    return none(r)\n|\n"),
+            "Type error: Can't infer type parameter T of none from its arguments"
+        );
+    }
+
+    #[test]
+    fn wrong_number_of_type_arguments_is_an_error() {
+        assert_eq!(
+            error("struct Pair<T> |\n    T a\n|\nfunc main() Int |\n    let p = new Pair<Int, Int>(1)\n    return 0\n|\n"),
+            "Type error: Pair takes 1 type arguments but was given 2"
+        );
+    }
+}
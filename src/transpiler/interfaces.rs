@@ -0,0 +1,315 @@
+// Checked interfaces - what type checking an imported module leaves in the checker: the
+// types of its globals and of its functions' arguments and locals, which of them can't be
+// assigned again and the warnings checking it gave. A module's interface only depends on
+// its source and on the modules it imports, which is what its fingerprint hashes, so a
+// build that still has the interface of a module with the same fingerprint restores it
+// instead of checking the module again.
+//
+// The main file and the prelude are always checked, since overloads of prelude functions
+// are renamed by what the main file defines, and so are generic instances, whose type
+// arguments can come from any module. Lowering still works on the whole program.
+
+use super::modules::ModuleInfo;
+use super::type_checker::{Type, TypeChecker};
+use crate::parser::{AstNode, Binding};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Interface {
+    pub symbols: Vec<(String, Type)>,
+    pub immutable: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+// Finds the interface an earlier build checked for the module with a fingerprint
+pub type Interfaces = dyn FnMut(&str) -> Option<Interface>;
+
+// One line per entry, e.g. `symbol util.scale::factor Int`
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, tp) in &self.symbols {
+            let tp = match tp {
+                Type::NotMentioned => "NotMentioned".to_string(),
+                tp => String::from(tp.clone()),
+            };
+            writeln!(f, "symbol {} {}", name, tp)?;
+        }
+        for name in &self.immutable {
+            writeln!(f, "immutable {}", name)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+impl Interface {
+    pub fn parse(text: &str) -> Result<Interface, String> {
+        let mut interface = Interface::default();
+        for (number, line) in text.lines().enumerate() {
+            let entry = match line.split_once(' ') {
+                Some(("symbol", rest)) => rest
+                    .split_once(' ')
+                    .map(|(name, tp)| interface.symbols.push((name.to_string(), Type::from(tp)))),
+                Some(("immutable", name)) => {
+                    interface.immutable.push(name.to_string());
+                    Some(())
+                }
+                Some(("warning", warning)) => {
+                    interface.warnings.push(warning.to_string());
+                    Some(())
+                }
+                _ => None,
+            };
+            if entry.is_none() {
+                return Err(format!("Line {}: can't read '{}'", number + 1, line));
+            }
+        }
+        Ok(interface)
+    }
+
+    fn restore(self, checker: &mut TypeChecker) {
+        checker.symbol_table.extend(self.symbols);
+        for name in &self.immutable {
+            checker.declare(name, Binding::Let);
+        }
+        checker.warnings.extend(self.warnings);
+    }
+}
+
+// Checks the program like TypeChecker::check, except for the items of the modules whose
+// interface is found, which is restored instead. Returns the interface of every module it
+// did check, with the module's fingerprint.
+pub fn check_program(
+    checker: &mut TypeChecker,
+    ast: &mut AstNode,
+    modules: &[ModuleInfo],
+    interfaces: &mut Interfaces,
+) -> Result<Vec<(String, Interface)>, String> {
+    let AstNode::Block { statements } = ast else {
+        checker.check(ast)?;
+        return Ok(Vec::new());
+    };
+    let mut restored = vec![false; modules.len()];
+    for (index, module) in modules.iter().enumerate() {
+        if let Some(interface) = interfaces(&module.fingerprint) {
+            interface.restore(checker);
+            restored[index] = true;
+        }
+    }
+
+    let mut checked = vec![Interface::default(); modules.len()];
+    let mut errors = Vec::new();
+    for statement in statements {
+        let owner = owner(statement, modules);
+        if owner.is_some_and(|index| restored[index]) {
+            continue;
+        }
+        let warnings = checker.warnings.len();
+        if let Err(e) = checker.check(statement) {
+            errors.push(e);
+        }
+        if let Some(index) = owner {
+            checked[index]
+                .warnings
+                .extend_from_slice(&checker.warnings[warnings..]);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    // Everything a module's checking declared is named after the module, `util.name`
+    let index: HashMap<&str, usize> = modules
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !restored[*index])
+        .map(|(index, module)| (module.name.as_str(), index))
+        .collect();
+    let module_of = |name: &str| {
+        name.split_once('.')
+            .and_then(|(module, _)| index.get(module).copied())
+    };
+    for (name, tp) in &checker.symbol_table {
+        if let Some(index) = module_of(name) {
+            checked[index].symbols.push((name.clone(), tp.clone()));
+        }
+    }
+    for name in checker.immutables() {
+        if let Some(index) = module_of(name) {
+            checked[index].immutable.push(name.clone());
+        }
+    }
+    Ok(modules
+        .iter()
+        .zip(checked)
+        .zip(restored)
+        .filter(|(_, restored)| !restored)
+        .map(|((module, mut interface), _)| {
+            // The tables are hash maps, sorting keeps the same interface the same text
+            interface.symbols.sort_by(|a, b| a.0.cmp(&b.0));
+            interface.immutable.sort();
+            (module.fingerprint.clone(), interface)
+        })
+        .collect())
+}
+
+// The module a top-level statement is an item of. Overloads renamed `name_1` belong to the
+// module of `name`, generic instances to nobody.
+fn owner(statement: &AstNode, modules: &[ModuleInfo]) -> Option<usize> {
+    let name = match statement {
+        AstNode::Function { name, .. }
+        | AstNode::Class { name, .. }
+        | AstNode::Struct { name, .. }
+        | AstNode::Interface { name, .. }
+        | AstNode::Assignment { variable: name, .. } => name,
+        AstNode::Impl { methods, .. } => match methods.first() {
+            Some(AstNode::Function { name, .. }) => name,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let overloaded = name
+        .rsplit_once('_')
+        .filter(|(_, number)| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+        .map(|(name, _)| name);
+    modules.iter().position(|module| {
+        module.items.contains(name) || overloaded.is_some_and(|name| module.items.contains(name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Interface;
+    use crate::transpiler::type_checker::Type;
+    use crate::{compile_with_interfaces, CompileOptions, CompileOutput};
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    // main imports shapes, which imports util, and stats imports nothing
+    const MAIN: &str = "import shapes\nimport stats\nfunc main() Int |\n    return shapes::area(2) + stats::mean(4, 6)\n|\n";
+    const SHAPES: &str = "import util\npub func area(side Int) Int |\n    let squared = util::square(side)\n    return squared\n|\n";
+    const UTIL: &str = "pub func square(n Int) Int |\n    let mut total = n * n\n    return total\n    total = 0\n|\n";
+    const STATS: &str = "pub func mean(a Int, b Int) Int |\n    return (a + b) / 2\n|\n";
+
+    fn compile(
+        files: &[(&'static str, &str)],
+        cache: &HashMap<String, Interface>,
+    ) -> CompileOutput {
+        let sources: HashMap<&str, String> = files
+            .iter()
+            .map(|(name, source)| (*name, source.to_string()))
+            .collect();
+        let loader = move |path: &Path| {
+            sources
+                .get(path.to_str().unwrap())
+                .cloned()
+                .ok_or_else(|| "No such file".to_string())
+        };
+        let cache = cache.clone();
+        compile_with_interfaces(
+            MAIN,
+            Path::new("main.tsl"),
+            loader,
+            move |fingerprint: &str| cache.get(fingerprint).cloned(),
+            &CompileOptions::default(),
+        )
+        .unwrap_or_else(|e| panic!("{:?}", e))
+    }
+
+    // The names of the modules that were checked rather than restored
+    fn checked(output: &CompileOutput) -> Vec<&str> {
+        output
+            .modules
+            .iter()
+            .filter(|module| {
+                output
+                    .interfaces
+                    .iter()
+                    .any(|(fingerprint, _)| *fingerprint == module.fingerprint)
+            })
+            .map(|module| module.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn only_changed_modules_and_their_dependents_are_checked_again() {
+        let files = [
+            ("shapes.tsl", SHAPES),
+            ("util.tsl", UTIL),
+            ("stats.tsl", STATS),
+        ];
+        let first = compile(&files, &HashMap::new());
+        assert_eq!(checked(&first), ["util", "shapes", "stats"]);
+        let cache: HashMap<String, Interface> = first.interfaces.iter().cloned().collect();
+
+        let again = compile(&files, &cache);
+        assert_eq!(checked(&again), Vec::<&str>::new());
+        assert_eq!(again.module, first.module);
+
+        let changed = UTIL.replace("n * n", "n * n * 1");
+        let files = [
+            ("shapes.tsl", SHAPES),
+            ("util.tsl", changed.as_str()),
+            ("stats.tsl", STATS),
+        ];
+        assert_eq!(checked(&compile(&files, &cache)), ["util", "shapes"]);
+    }
+
+    #[test]
+    fn a_restored_interface_gives_the_types_and_warnings_of_the_module() {
+        let files = [
+            ("shapes.tsl", SHAPES),
+            ("util.tsl", UTIL),
+            ("stats.tsl", STATS),
+        ];
+        let first = compile(&files, &HashMap::new());
+        let (_, util) = &first.interfaces[0];
+        assert!(util
+            .symbols
+            .contains(&("util.square::total".to_string(), Type::Integer)));
+        assert_eq!(util.immutable, Vec::<String>::new());
+        assert_eq!(
+            util.warnings,
+            ["Unreachable assignment to 'total' in function 'util.square'"]
+        );
+        let (_, shapes) = &first.interfaces[1];
+        assert_eq!(shapes.immutable, ["shapes.area::squared"]);
+
+        let cache = first.interfaces.iter().cloned().collect();
+        let again = compile(&files, &cache);
+        assert_eq!(again.warnings, first.warnings);
+        let types = again.types.unwrap();
+        assert_eq!(
+            types.symbol_table.get("shapes.area::squared"),
+            Some(&Type::Integer)
+        );
+        assert!(types.is_immutable("shapes.area::squared"));
+    }
+
+    #[test]
+    fn interfaces_survive_being_written_out() {
+        let interface = Interface {
+            symbols: vec![
+                ("util.f::x".to_string(), Type::Integer),
+                (
+                    "util.f::g".to_string(),
+                    Type::Function(vec![Type::Integer], Box::new(Type::Bool)),
+                ),
+                (
+                    "util.f::p".to_string(),
+                    Type::Option(Box::new(Type::DataTp("util.Point".to_string()))),
+                ),
+            ],
+            immutable: vec!["util.f::x".to_string()],
+            warnings: vec!["Unreachable return in function 'f'".to_string()],
+        };
+        assert_eq!(Interface::parse(&interface.to_string()), Ok(interface));
+        assert_eq!(
+            Interface::parse("symbol util.x Int\nsomething else\n"),
+            Err("Line 2: can't read 'something else'".to_string())
+        );
+    }
+}
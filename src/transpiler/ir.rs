@@ -1,24 +1,136 @@
 use super::type_checker::*;
 use crate::parser::*;
+use std::collections::{HashMap, HashSet};
 
 // Typed IR - Values aren't stored in the IR, only the types.
-#[allow(clippy::only_used_in_recursion)]
 pub fn resolve_names(ast: &AstNode) -> AstNode {
+    resolve_names_with(ast, &mut HashSet::new())
+}
+
+// Resolves one more part of a program, like a REPL input, after the parts that defined
+// globals. The globals this part defines are added to them.
+#[allow(clippy::only_used_in_recursion)]
+pub fn resolve_names_with(ast: &AstNode, globals: &mut HashSet<String>) -> AstNode {
     let mut scopes: Vec<String> = vec![];
-    let mut scope_argnames: Vec<Vec<String>> = vec![];
-    let mut statements: Vec<AstNode> = vec![];
+    let mut scope_argnames: Vec<ScopeNames> = vec![];
+    // Top-level assignments define globals, which keep their names in every function
+    globals.extend(
+        ast.get_statements()
+            .into_iter()
+            .filter_map(|statement| match statement {
+                AstNode::Assignment { variable, .. } => Some(variable),
+                _ => None,
+            }),
+    );
+    // For each scope, the variables it captures when it is a lambda's
+    let mut captures: Vec<Option<Vec<(String, String)>>> = vec![];
+
+    // The variables of a function or lambda, its arguments first. A `let` of a variable
+    // the function already has gets a name of its own, `x.1`, up to the end of the `if`
+    // or `while` block it is in, so it doesn't overwrite the variable it hides.
+    struct ScopeNames {
+        names: Vec<String>,
+        renamed: HashMap<String, String>,
+        hidden: usize,
+    }
+
+    impl ScopeNames {
+        fn new(arguments: &[(String, String)]) -> Self {
+            ScopeNames {
+                names: arguments.iter().map(|arg| arg.1.clone()).collect(),
+                renamed: HashMap::new(),
+                hidden: 0,
+            }
+        }
+
+        fn contains(&self, value: &str) -> bool {
+            self.names.iter().any(|name| name == value)
+        }
+
+        // The name a variable of the scope has, without the scope's prefix
+        fn name<'a>(&'a self, value: &'a str) -> &'a str {
+            self.renamed.get(value).map_or(value, String::as_str)
+        }
+
+        // Declares a variable with `let`, giving it a new name when it hides another
+        fn declare(&mut self, value: &str) -> String {
+            if self.contains(value) {
+                self.hidden += 1;
+                let name = format!("{}.{}", value, self.hidden);
+                self.renamed.insert(value.to_string(), name.clone());
+                name
+            } else {
+                self.names.push(value.to_string());
+                value.to_string()
+            }
+        }
+    }
+
+    // Resolves a block of an `if` or `while`, whose `let`s hide variables only inside it
+    fn resolve_block(
+        block: &AstNode,
+        scopes: &mut Vec<String>,
+        globals: &HashSet<String>,
+        scope_argnames: &mut Vec<ScopeNames>,
+        captures: &mut Vec<Option<Vec<(String, String)>>>,
+    ) -> AstNode {
+        let renamed = scope_argnames.last().map(|scope| scope.renamed.clone());
+        let block = resolve_names_helper(block, scopes, globals, scope_argnames, captures);
+        if let (Some(scope), Some(renamed)) = (scope_argnames.last_mut(), renamed) {
+            scope.renamed = renamed;
+        }
+        block
+    }
+
+    // The name a variable of scope level has, when it is one. A lambda captures the
+    // variables of the scopes around it that it uses, a function can't.
+    fn scoped(
+        value: &str,
+        level: usize,
+        scopes: &[String],
+        scope_argnames: &mut [ScopeNames],
+        captures: &mut [Option<Vec<(String, String)>>],
+    ) -> Option<String> {
+        let name = format!("{}::{}", scopes[level], scope_argnames[level].name(value));
+        if scope_argnames[level].contains(value) {
+            return Some(name);
+        }
+        if level == 0 || captures[level].is_none() {
+            return None;
+        }
+        let outer = scoped(value, level - 1, scopes, scope_argnames, captures)?;
+        scope_argnames[level].names.push(value.to_string());
+        captures[level].as_mut()?.push((outer, name.clone()));
+        Some(name)
+    }
+
+    // Whether a variable of scope level is one, without capturing it
+    fn is_local(
+        value: &str,
+        level: usize,
+        scope_argnames: &[ScopeNames],
+        captures: &[Option<Vec<(String, String)>>],
+    ) -> bool {
+        scope_argnames[level].contains(value)
+            || (level > 0
+                && captures[level].is_some()
+                && is_local(value, level - 1, scope_argnames, captures))
+    }
 
     fn resolve_names_helper(
         ast: &AstNode,
         scopes: &mut Vec<String>,
-        statements: &mut Vec<AstNode>,
-        scope_argnames: &mut Vec<Vec<String>>,
+        globals: &HashSet<String>,
+        scope_argnames: &mut Vec<ScopeNames>,
+        captures: &mut Vec<Option<Vec<(String, String)>>>,
     ) -> AstNode {
         match ast {
             AstNode::Block { statements: stmts } => {
                 let new_statements: Vec<AstNode> = stmts
                     .iter()
-                    .map(|stmt| resolve_names_helper(stmt, scopes, statements, scope_argnames))
+                    .map(|stmt| {
+                        resolve_names_helper(stmt, scopes, globals, scope_argnames, captures)
+                    })
                     .collect();
                 AstNode::Block {
                     statements: new_statements,
@@ -29,45 +141,65 @@ pub fn resolve_names(ast: &AstNode) -> AstNode {
                 arguments,
                 body,
                 return_type,
+                attributes,
             } => {
                 scopes.push(name.clone());
-                scope_argnames.push(arguments.iter().map(|arg| arg.0.clone()).collect());
-                let new_body = resolve_names_helper(body, scopes, statements, scope_argnames);
+                scope_argnames.push(ScopeNames::new(arguments));
+                captures.push(None);
+                let new_body =
+                    resolve_names_helper(body, scopes, globals, scope_argnames, captures);
 
                 scopes.pop();
                 scope_argnames.pop();
+                captures.pop();
 
                 AstNode::Function {
                     name: name.clone(),
                     arguments: arguments.clone(),
                     body: Box::new(new_body),
                     return_type: return_type.clone(),
+                    attributes: attributes.clone(),
                 }
             }
-            AstNode::Identifier { value }
-                if scope_argnames.last().unwrap_or(&Vec::new()).contains(value) =>
-            {
-                AstNode::Identifier {
-                    value: format!("{}::{}", scopes.last().unwrap_or(&String::new()), value),
-                }
-            }
-            AstNode::Identifier { value } => {
-                if let Some(scope) = scopes.last() {
-                    if scope.is_empty() {
-                        AstNode::Identifier {
-                            value: value.clone(),
-                        }
-                    } else {
-                        AstNode::Identifier {
-                            value: format!("{}::{}", scope, value),
-                        }
-                    }
-                } else {
-                    AstNode::Identifier {
-                        value: value.clone(),
-                    }
+            AstNode::Lambda {
+                name,
+                arguments,
+                return_type,
+                body,
+                ..
+            } => {
+                // Named after the function it is in, so every lambda has its own name
+                let name = match scopes.last() {
+                    Some(scope) => format!("{}__{}", scope, name),
+                    None => name.clone(),
+                };
+                scopes.push(name.clone());
+                scope_argnames.push(ScopeNames::new(arguments));
+                captures.push(Some(vec![]));
+                let new_body =
+                    resolve_names_helper(body, scopes, globals, scope_argnames, captures);
+
+                scopes.pop();
+                scope_argnames.pop();
+
+                AstNode::Lambda {
+                    name,
+                    arguments: arguments.clone(),
+                    return_type: return_type.clone(),
+                    body: Box::new(new_body),
+                    captures: captures.pop().flatten().unwrap_or_default(),
                 }
             }
+            AstNode::Identifier { value } => match scopes.len() {
+                0 => AstNode::Identifier {
+                    value: value.clone(),
+                },
+                n => AstNode::Identifier {
+                    value: scoped(value, n - 1, scopes, scope_argnames, captures)
+                        .or_else(|| globals.contains(value).then(|| value.clone()))
+                        .unwrap_or_else(|| format!("{}::{}", scopes[n - 1], value)),
+                },
+            },
             AstNode::BinaryOperation {
                 operator,
                 left,
@@ -77,22 +209,25 @@ pub fn resolve_names(ast: &AstNode) -> AstNode {
                 left: Box::new(resolve_names_helper(
                     left,
                     scopes,
-                    statements,
+                    globals,
                     scope_argnames,
+                    captures,
                 )),
                 right: Box::new(resolve_names_helper(
                     right,
                     scopes,
-                    statements,
+                    globals,
                     scope_argnames,
+                    captures,
                 )),
             },
             AstNode::Return { value } => AstNode::Return {
                 value: Box::new(resolve_names_helper(
                     value,
                     scopes,
-                    statements,
+                    globals,
                     scope_argnames,
+                    captures,
                 )),
             },
             AstNode::If {
@@ -101,12 +236,12 @@ pub fn resolve_names(ast: &AstNode) -> AstNode {
                 alternative,
             } => {
                 let new_condition =
-                    resolve_names_helper(condition, scopes, statements, scope_argnames);
+                    resolve_names_helper(condition, scopes, globals, scope_argnames, captures);
                 let new_consequence =
-                    resolve_names_helper(consequence, scopes, statements, scope_argnames);
+                    resolve_block(consequence, scopes, globals, scope_argnames, captures);
                 let new_alternative = alternative
                     .as_ref()
-                    .map(|alt| resolve_names_helper(alt, scopes, statements, scope_argnames));
+                    .map(|alt| resolve_block(alt, scopes, globals, scope_argnames, captures));
 
                 AstNode::If {
                     condition: Box::new(new_condition),
@@ -114,39 +249,74 @@ pub fn resolve_names(ast: &AstNode) -> AstNode {
                     alternative: new_alternative.map(Box::new),
                 }
             }
+            AstNode::While { condition, body } => AstNode::While {
+                condition: Box::new(resolve_names_helper(
+                    condition,
+                    scopes,
+                    globals,
+                    scope_argnames,
+                    captures,
+                )),
+                body: Box::new(resolve_block(
+                    body,
+                    scopes,
+                    globals,
+                    scope_argnames,
+                    captures,
+                )),
+            },
+            // The value first, so `x = x + 1` in a lambda captures x before assigning it
             AstNode::Assignment {
                 value,
                 variable,
                 tp,
+                binding,
             } => AstNode::Assignment {
-                variable: {
-                    let scope = scopes.last().unwrap_or(&String::new()).clone();
-                    if scope.is_empty() {
-                        variable.clone()
-                    } else {
-                        // add variable name to scope_argnames
-                        scope_argnames.last_mut().unwrap().push(variable.clone());
-
-                        format!("{}::{}", scope, variable)
-                    }
-                },
+                binding: *binding,
                 value: Box::new(resolve_names_helper(
                     value,
                     scopes,
-                    statements,
+                    globals,
                     scope_argnames,
+                    captures,
                 )),
+                variable: {
+                    let scope = scopes.last().unwrap_or(&String::new()).clone();
+                    // Assigning to a global that no local hides
+                    let global = !scope.is_empty()
+                        && *binding == Binding::Assign
+                        && globals.contains(variable)
+                        && !is_local(variable, scopes.len() - 1, scope_argnames, captures);
+                    match scope_argnames.last_mut() {
+                        Some(names) if !scope.is_empty() && !global => {
+                            let name = if *binding == Binding::Assign {
+                                if !names.contains(variable) {
+                                    names.names.push(variable.clone());
+                                }
+                                names.name(variable).to_string()
+                            } else {
+                                names.declare(variable)
+                            };
+                            format!("{}::{}", scope, name)
+                        }
+                        _ => variable.clone(),
+                    }
+                },
                 tp: tp.clone(),
             },
             AstNode::Variable { value } => {
                 let scope = scopes.last().unwrap_or(&String::new()).clone();
-                if scope.is_empty() {
+                let global = !scope.is_empty()
+                    && globals.contains(value)
+                    && !is_local(value, scopes.len() - 1, scope_argnames, captures);
+                if scope.is_empty() || global {
                     AstNode::Variable {
                         value: value.clone(),
                     }
                 } else {
+                    let names = &scope_argnames[scopes.len() - 1];
                     AstNode::Variable {
-                        value: format!("{}::{}", scope, value),
+                        value: format!("{}::{}", scope, names.name(value)),
                     }
                 }
             }
@@ -154,8 +324,17 @@ pub fn resolve_names(ast: &AstNode) -> AstNode {
                 //Keep the name the same, but arguments need to be resolved
                 let new_args: Vec<AstNode> = arguments
                     .iter()
-                    .map(|arg| resolve_names_helper(arg, scopes, statements, scope_argnames))
+                    .map(|arg| resolve_names_helper(arg, scopes, globals, scope_argnames, captures))
                     .collect();
+                // A variable holding a function value, which hides a function of its name
+                if let Some(level) = scopes.len().checked_sub(1)
+                    && let Some(value) = scoped(name, level, scopes, scope_argnames, captures)
+                {
+                    return AstNode::Call {
+                        callee: Box::new(AstNode::Identifier { value }),
+                        arguments: new_args,
+                    };
+                }
                 AstNode::FunctionCall {
                     name: name.clone(),
                     arguments: new_args,
@@ -165,21 +344,2466 @@ pub fn resolve_names(ast: &AstNode) -> AstNode {
                 value: Box::new(resolve_names_helper(
                     value,
                     scopes,
-                    statements,
+                    globals,
                     scope_argnames,
+                    captures,
                 )),
             },
             AstNode::Dereference { value } => AstNode::Dereference {
                 value: Box::new(resolve_names_helper(
                     value,
                     scopes,
-                    statements,
+                    globals,
+                    scope_argnames,
+                    captures,
+                )),
+            },
+            AstNode::Class {
+                name,
+                parent,
+                fields,
+                methods,
+            } => AstNode::Class {
+                name: name.clone(),
+                parent: parent.clone(),
+                fields: fields.clone(),
+                methods: methods
+                    .iter()
+                    .map(|method| {
+                        resolve_names_helper(method, scopes, globals, scope_argnames, captures)
+                    })
+                    .collect(),
+            },
+            AstNode::Impl {
+                interface,
+                target,
+                methods,
+            } => AstNode::Impl {
+                interface: interface.clone(),
+                target: target.clone(),
+                methods: methods
+                    .iter()
+                    .map(|method| {
+                        resolve_names_helper(method, scopes, globals, scope_argnames, captures)
+                    })
+                    .collect(),
+            },
+            AstNode::FieldAccess { object, field } => AstNode::FieldAccess {
+                object: Box::new(resolve_names_helper(
+                    object,
+                    scopes,
+                    globals,
+                    scope_argnames,
+                    captures,
+                )),
+                field: field.clone(),
+            },
+            AstNode::FieldAssignment {
+                object,
+                field,
+                value,
+            } => AstNode::FieldAssignment {
+                object: Box::new(resolve_names_helper(
+                    object,
+                    scopes,
+                    globals,
+                    scope_argnames,
+                    captures,
+                )),
+                field: field.clone(),
+                value: Box::new(resolve_names_helper(
+                    value,
+                    scopes,
+                    globals,
+                    scope_argnames,
+                    captures,
+                )),
+            },
+            AstNode::MethodCall {
+                object,
+                method,
+                arguments,
+            } => AstNode::MethodCall {
+                object: Box::new(resolve_names_helper(
+                    object,
+                    scopes,
+                    globals,
+                    scope_argnames,
+                    captures,
+                )),
+                method: method.clone(),
+                arguments: arguments
+                    .iter()
+                    .map(|arg| resolve_names_helper(arg, scopes, globals, scope_argnames, captures))
+                    .collect(),
+            },
+            AstNode::SuperCall {
+                class,
+                method,
+                arguments,
+            } => AstNode::SuperCall {
+                class: class.clone(),
+                method: method.clone(),
+                arguments: arguments
+                    .iter()
+                    .map(|arg| resolve_names_helper(arg, scopes, globals, scope_argnames, captures))
+                    .collect(),
+            },
+            AstNode::New { class, arguments } => AstNode::New {
+                class: class.clone(),
+                arguments: arguments
+                    .iter()
+                    .map(|arg| resolve_names_helper(arg, scopes, globals, scope_argnames, captures))
+                    .collect(),
+            },
+            AstNode::Call { callee, arguments } => AstNode::Call {
+                callee: Box::new(resolve_names_helper(
+                    callee,
+                    scopes,
+                    globals,
+                    scope_argnames,
+                    captures,
+                )),
+                arguments: arguments
+                    .iter()
+                    .map(|arg| resolve_names_helper(arg, scopes, globals, scope_argnames, captures))
+                    .collect(),
+            },
+            AstNode::Tuple { elements } => AstNode::Tuple {
+                elements: elements
+                    .iter()
+                    .map(|element| {
+                        resolve_names_helper(element, scopes, globals, scope_argnames, captures)
+                    })
+                    .collect(),
+            },
+            AstNode::Variant { variant, value } => AstNode::Variant {
+                variant: variant.clone(),
+                value: value.as_ref().map(|value| {
+                    Box::new(resolve_names_helper(
+                        value,
+                        scopes,
+                        globals,
+                        scope_argnames,
+                        captures,
+                    ))
+                }),
+            },
+            AstNode::Try { value } => AstNode::Try {
+                value: Box::new(resolve_names_helper(
+                    value,
+                    scopes,
+                    globals,
                     scope_argnames,
+                    captures,
                 )),
             },
             _ => ast.clone(),
         }
     }
 
-    resolve_names_helper(ast, &mut scopes, &mut statements, &mut scope_argnames)
+    resolve_names_helper(
+        ast,
+        &mut scopes,
+        globals,
+        &mut scope_argnames,
+        &mut captures,
+    )
+}
+
+// Lowered IR - each function is a list of basic blocks made of typed temporaries and
+// explicit jumps. Types are resolved once during lowering, so nothing after this point
+// needs to look at type names again.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Temp {
+    pub id: usize,
+    pub tp: Type,
+}
+
+// A named storage slot - a parameter, local or global variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Var {
+    pub name: String,
+    pub tp: Type,
+}
+
+// The C symbol for a name. Variables are scoped as `function::name` and C only needs the
+// last part, while module items (`util.name`) become `util__name`.
+pub fn c_symbol(name: &str) -> String {
+    name.split("::").last().unwrap().replace('.', "__")
+}
+
+// The struct holding what the lambda function captures
+pub fn env_symbol(function: &str) -> String {
+    format!("{}__env", function)
+}
+
+// The function a function value of function calls, which takes an environment first like
+// every lambda and passes the rest of its arguments on
+pub fn thunk_symbol(function: &str) -> String {
+    format!("{}__thunk", function)
+}
+
+impl Var {
+    pub fn c_name(&self) -> String {
+        c_symbol(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i128),
+    Bool(bool),
+    Char(String),
+    Str(String),
+    Null,
+}
+
+impl Constant {
+    pub fn tp(&self) -> Type {
+        match self {
+            Constant::Int(_) => Type::Integer,
+            Constant::Bool(_) => Type::Bool,
+            Constant::Char(_) => Type::Char,
+            Constant::Str(_) => Type::String,
+            Constant::Null => Type::Void,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Temp(Temp),
+    Const(Constant),
+}
+
+impl Operand {
+    pub fn tp(&self) -> Type {
+        match self {
+            Operand::Temp(temp) => temp.tp.clone(),
+            Operand::Const(constant) => constant.tp(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    pub fn from_operator(operator: &str) -> Option<Self> {
+        match operator {
+            "+" => Some(BinOp::Add),
+            "-" => Some(BinOp::Sub),
+            "*" => Some(BinOp::Mul),
+            "/" => Some(BinOp::Div),
+            "%" => Some(BinOp::Rem),
+            "==" => Some(BinOp::Eq),
+            "!=" => Some(BinOp::Ne),
+            "<" => Some(BinOp::Lt),
+            ">" => Some(BinOp::Gt),
+            "<=" => Some(BinOp::Le),
+            ">=" => Some(BinOp::Ge),
+            "&&" => Some(BinOp::And),
+            "||" => Some(BinOp::Or),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Gt => ">",
+            BinOp::Le => "<=",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        }
+    }
+
+    // Same rules as TypeChecker::check for BinaryOperation
+    pub fn result_type(&self, left: &Type, right: &Type) -> Result<Type, String> {
+        match self {
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem => match (left, right) {
+                (Type::Integer, Type::Integer) => Ok(Type::Integer),
+                (Type::Pointer(_) | Type::ConstPointer(_), Type::Integer)
+                | (Type::String, Type::Integer) => Ok(left.clone()),
+                _ => Err(format!(
+                    "cannot apply {} to {:?} and {:?}",
+                    self.as_str(),
+                    left,
+                    right
+                )),
+            },
+            BinOp::Eq | BinOp::Ne if left == right => Ok(Type::Bool),
+            BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge
+                if *left == Type::Integer && *right == Type::Integer =>
+            {
+                Ok(Type::Bool)
+            }
+            BinOp::And | BinOp::Or if *left == Type::Bool && *right == Type::Bool => Ok(Type::Bool),
+            _ => Err(format!("cannot compare {:?} and {:?}", left, right)),
+        }
+    }
+}
+
+// Which of its two cases an Option or Result is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Some,
+    None,
+    Ok,
+    Err,
+}
+
+impl Variant {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Some" => Some(Variant::Some),
+            "None" => Some(Variant::None),
+            "Ok" => Some(Variant::Ok),
+            "Err" => Some(Variant::Err),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Variant::Some => "some",
+            Variant::None => "none",
+            Variant::Ok => "ok",
+            Variant::Err => "err",
+        }
+    }
+
+    // The type the variant holds in an Option or Result of type tp, None holds nothing
+    pub fn held<'a>(&self, tp: &'a Type) -> Option<&'a Type> {
+        match (self, tp) {
+            (Variant::Some, Type::Option(held))
+            | (Variant::Ok, Type::Result(held, _))
+            | (Variant::Err, Type::Result(_, held)) => Some(held),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Load {
+        dest: Temp,
+        var: Var,
+    },
+    Store {
+        var: Var,
+        value: Operand,
+    },
+    Binary {
+        dest: Temp,
+        op: BinOp,
+        left: Operand,
+        right: Operand,
+    },
+    Cast {
+        dest: Temp,
+        value: Operand,
+    },
+    Call {
+        dest: Option<Temp>,
+        function: String,
+        arguments: Vec<Operand>,
+    },
+    AddressOf {
+        dest: Temp,
+        var: Var,
+    },
+    Deref {
+        dest: Temp,
+        pointer: Operand,
+    },
+    // Allocates an instance of the class with its vtable set and every field zeroed
+    New {
+        dest: Temp,
+        class: String,
+    },
+    // The object is a pointer to the class declaring the field
+    GetField {
+        dest: Temp,
+        object: Operand,
+        field: String,
+    },
+    SetField {
+        object: Operand,
+        field: String,
+        value: Operand,
+    },
+    // Calls through the vtable slot of a method declared by class, the receiver comes
+    // first in the arguments as a pointer to that class
+    CallMethod {
+        dest: Option<Temp>,
+        class: String,
+        method: String,
+        arguments: Vec<Operand>,
+    },
+    // Pairs a value with the vtable of its type's impl of the interface dest is a dyn of
+    ToDyn {
+        dest: Temp,
+        value: Operand,
+    },
+    // Calls a method of interface through the vtable of the dyn that comes first in the
+    // arguments
+    CallDyn {
+        dest: Option<Temp>,
+        interface: String,
+        method: String,
+        arguments: Vec<Operand>,
+    },
+    // A function value of function, with the values it captures copied into an
+    // environment of the struct env_symbol names. function takes that environment first.
+    MakeClosure {
+        dest: Temp,
+        function: String,
+        captures: Vec<Operand>,
+    },
+    // Calls the function value that comes first in the arguments
+    CallClosure {
+        dest: Option<Temp>,
+        arguments: Vec<Operand>,
+    },
+    // A tuple of the type dest has, a struct made of the elements
+    MakeTuple {
+        dest: Temp,
+        elements: Vec<Operand>,
+    },
+    GetElement {
+        dest: Temp,
+        tuple: Operand,
+        index: usize,
+    },
+    // An Option or Result of the type dest has, holding value. None holds no value, and
+    // neither does a variant holding Void.
+    MakeVariant {
+        dest: Temp,
+        variant: Variant,
+        value: Option<Operand>,
+    },
+    // Whether the Option or Result is the variant
+    IsVariant {
+        dest: Temp,
+        value: Operand,
+        variant: Variant,
+    },
+    // What the Option or Result holds as the variant, which it has to be
+    Unwrap {
+        dest: Temp,
+        value: Operand,
+        variant: Variant,
+    },
+}
+
+impl Instruction {
+    pub fn dest(&self) -> Option<&Temp> {
+        match self {
+            Instruction::Load { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Cast { dest, .. }
+            | Instruction::AddressOf { dest, .. }
+            | Instruction::Deref { dest, .. }
+            | Instruction::New { dest, .. }
+            | Instruction::GetField { dest, .. }
+            | Instruction::ToDyn { dest, .. }
+            | Instruction::MakeClosure { dest, .. }
+            | Instruction::MakeTuple { dest, .. }
+            | Instruction::GetElement { dest, .. }
+            | Instruction::MakeVariant { dest, .. }
+            | Instruction::IsVariant { dest, .. }
+            | Instruction::Unwrap { dest, .. } => Some(dest),
+            Instruction::Call { dest, .. }
+            | Instruction::CallMethod { dest, .. }
+            | Instruction::CallDyn { dest, .. }
+            | Instruction::CallClosure { dest, .. } => dest.as_ref(),
+            Instruction::Store { .. } | Instruction::SetField { .. } => None,
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Instruction::Store { value, .. }
+            | Instruction::Cast { value, .. }
+            | Instruction::Deref { pointer: value, .. }
+            | Instruction::GetField { object: value, .. }
+            | Instruction::ToDyn { value, .. }
+            | Instruction::GetElement { tuple: value, .. }
+            | Instruction::IsVariant { value, .. }
+            | Instruction::Unwrap { value, .. } => vec![value],
+            Instruction::MakeVariant { value, .. } => value.iter().collect(),
+            Instruction::Binary { left, right, .. }
+            | Instruction::SetField {
+                object: left,
+                value: right,
+                ..
+            } => vec![left, right],
+            Instruction::Call { arguments, .. }
+            | Instruction::CallMethod { arguments, .. }
+            | Instruction::CallDyn { arguments, .. }
+            | Instruction::CallClosure { arguments, .. }
+            | Instruction::MakeClosure {
+                captures: arguments,
+                ..
+            }
+            | Instruction::MakeTuple {
+                elements: arguments,
+                ..
+            } => arguments.iter().collect(),
+            Instruction::Load { .. } | Instruction::AddressOf { .. } | Instruction::New { .. } => {
+                vec![]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Return(Option<Operand>),
+    Jump(BlockId),
+    Branch {
+        condition: Operand,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    Unreachable,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![*then_block, *else_block],
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub id: BlockId,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<Var>,
+    pub return_type: Type,
+    pub locals: Vec<Var>,
+    pub blocks: Vec<BasicBlock>,
+    pub attributes: Vec<String>,
+    // Declared `pub` (or main), other functions are `static` in C
+    pub public: bool,
+}
+
+impl Function {
+    pub fn c_name(&self) -> String {
+        c_symbol(&self.name)
+    }
+
+    // Every temporary defined in the function, in definition order
+    pub fn temps(&self) -> Vec<&Temp> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.instructions.iter())
+            .filter_map(|instruction| instruction.dest())
+            .collect()
+    }
+
+    pub fn has_attribute(&self, attribute: &str) -> bool {
+        self.attributes.iter().any(|a| a == attribute)
+    }
+
+    pub fn block(&self, id: BlockId) -> Option<&BasicBlock> {
+        self.blocks.iter().find(|block| block.id == id)
+    }
+
+    // Drops blocks that can't be reached from the entry block and renumbers the rest
+    pub fn remove_unreachable_blocks(&mut self) {
+        let Some(entry) = self.blocks.first().map(|block| block.id) else {
+            return;
+        };
+        let mut reachable = HashSet::new();
+        let mut stack = vec![entry];
+        while let Some(id) = stack.pop() {
+            if !reachable.insert(id) {
+                continue;
+            }
+            if let Some(block) = self.block(id) {
+                stack.extend(block.terminator.successors());
+            }
+        }
+        self.blocks.retain(|block| reachable.contains(&block.id));
+        let renumbered: HashMap<BlockId, BlockId> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| (block.id, BlockId(index)))
+            .collect();
+        let renumber = |id: &mut BlockId| *id = renumbered[id];
+        for block in &mut self.blocks {
+            renumber(&mut block.id);
+            match &mut block.terminator {
+                Terminator::Jump(target) => renumber(target),
+                Terminator::Branch {
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    renumber(then_block);
+                    renumber(else_block);
+                }
+                Terminator::Return(_) | Terminator::Unreachable => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDef {
+    pub name: String,
+    pub fields: Vec<Var>,
+}
+
+// A vtable slot. The receiver is a pointer to the class that first declared the method,
+// so every override fits the same slot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Method {
+    pub name: String,
+    // The implementation used by instances of the class owning the vtable
+    pub function: String,
+    pub params: Vec<Type>,
+    pub return_type: Type,
+}
+
+impl Method {
+    // The class that declared the method, which its receiver points at
+    pub fn declared_by(&self) -> &str {
+        match &self.params[0] {
+            Type::Pointer(tp) => match tp.as_ref() {
+                Type::DataTp(class) => class,
+                _ => "",
+            },
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassDef {
+    pub name: String,
+    pub parent: Option<String>,
+    // Only the class's own fields, inherited ones live in the parent
+    pub fields: Vec<Var>,
+    // Inherited slots first, in the order the parent has them
+    pub vtable: Vec<Method>,
+}
+
+// The vtable layout every `dyn` of the interface shares, each slot takes the dyn's data
+// pointer in place of self
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterfaceDef {
+    pub name: String,
+    // (method, parameter types after self, return type)
+    pub methods: Vec<(String, Vec<Type>, Type)>,
+}
+
+// The functions filling the interface's vtable for values of type target
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImplDef {
+    pub interface: String,
+    pub target: Type,
+    // (method, function), in the interface's order
+    pub methods: Vec<(String, String)>,
+}
+
+impl Module {
+    pub fn class(&self, name: &str) -> Option<&ClassDef> {
+        self.classes.iter().find(|class| class.name == name)
+    }
+
+    pub fn interface(&self, name: &str) -> Option<&InterfaceDef> {
+        self.interfaces
+            .iter()
+            .find(|interface| interface.name == name)
+    }
+
+    pub fn impl_of(&self, interface: &str, target: &Type) -> Option<&ImplDef> {
+        self.impls
+            .iter()
+            .find(|i| i.interface == interface && i.target == *target)
+    }
+
+    // Impls some value is turned into a dyn with, only these need a vtable
+    pub fn dyn_impls(&self) -> Vec<&ImplDef> {
+        let used: Vec<(&str, Type)> = self
+            .functions
+            .iter()
+            .flat_map(|function| &function.blocks)
+            .flat_map(|block| &block.instructions)
+            .filter_map(|instruction| match instruction {
+                Instruction::ToDyn { dest, value } => match &dest.tp {
+                    Type::Dyn(interface) => Some((interface.as_str(), value.tp())),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        self.impls
+            .iter()
+            .filter(|i| {
+                used.iter()
+                    .any(|(interface, target)| i.interface == *interface && i.target == *target)
+            })
+            .collect()
+    }
+
+    // The class followed by its parents
+    pub fn ancestry(&self, name: &str) -> Vec<&ClassDef> {
+        let mut ancestry: Vec<&ClassDef> = Vec::new();
+        let mut next = self.class(name);
+        while let Some(class) = next {
+            if ancestry.iter().any(|seen| seen.name == class.name) {
+                break;
+            }
+            ancestry.push(class);
+            next = class
+                .parent
+                .as_deref()
+                .and_then(|parent| self.class(parent));
+        }
+        ancestry
+    }
+
+    // The tuple, Option and Result types used anywhere in the module, which C needs a
+    // struct for, each after the ones it holds. An Option of a pointer is the pointer.
+    pub fn value_types(&self) -> Vec<Type> {
+        fn visit(tp: &Type, found: &mut Vec<Type>) {
+            let held: Vec<&Type> = match tp {
+                Type::Pointer(tp) | Type::ConstPointer(tp) => return visit(tp, found),
+                Type::Function(params, return_type) => {
+                    for tp in params.iter().chain(std::iter::once(return_type.as_ref())) {
+                        visit(tp, found);
+                    }
+                    return;
+                }
+                Type::Option(held) if option_is_pointer(held) => return visit(held, found),
+                Type::Tuple(types) => types.iter().collect(),
+                Type::Option(held) => vec![held],
+                Type::Result(held, error) => vec![held, error],
+                _ => return,
+            };
+            for held in held {
+                visit(held, found);
+            }
+            if !found.contains(tp) {
+                found.push(tp.clone());
+            }
+        }
+        let mut found = Vec::new();
+        let fields = self
+            .structs
+            .iter()
+            .flat_map(|def| &def.fields)
+            .chain(self.classes.iter().flat_map(|class| &class.fields))
+            .chain(self.globals.iter().map(|global| &global.var));
+        for var in fields {
+            visit(&var.tp, &mut found);
+        }
+        for (_, params, return_type) in self.interfaces.iter().flat_map(|i| &i.methods) {
+            for tp in params.iter().chain(std::iter::once(return_type)) {
+                visit(tp, &mut found);
+            }
+        }
+        for function in &self.functions {
+            visit(&function.return_type, &mut found);
+            for var in function.params.iter().chain(&function.locals) {
+                visit(&var.tp, &mut found);
+            }
+            let dests = function
+                .blocks
+                .iter()
+                .flat_map(|block| &block.instructions)
+                .filter_map(Instruction::dest);
+            for dest in dests {
+                visit(&dest.tp, &mut found);
+            }
+        }
+        found
+    }
+
+    pub fn is_subclass(&self, class: &str, ancestor: &str) -> bool {
+        self.ancestry(class)
+            .iter()
+            .any(|class| class.name == ancestor)
+    }
+}
+
+// The class a type points at, if it is a pointer to one
+pub fn class_pointer<'a>(tp: &'a Type, module: &Module) -> Option<&'a str> {
+    match tp {
+        Type::Pointer(tp) | Type::ConstPointer(tp) => match tp.as_ref() {
+            Type::DataTp(name) if module.class(name).is_some() => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub var: Var,
+    pub value: Option<Constant>,
+    pub public: bool,
+    // Declared with `let mut`, a `let` global is const in C
+    pub mutable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Module {
+    pub structs: Vec<StructDef>,
+    // Parents come before their subclasses
+    pub classes: Vec<ClassDef>,
+    pub interfaces: Vec<InterfaceDef>,
+    pub impls: Vec<ImplDef>,
+    pub globals: Vec<Global>,
+    pub functions: Vec<Function>,
+}
+
+// Lowers a name-resolved and type-checked AST into a Module
+pub fn lower(ast: &AstNode, checker: &TypeChecker) -> Result<Module, String> {
+    let mut module = Module::default();
+    let mut errors = Vec::new();
+    lower_item(ast, checker, &mut module, &mut errors);
+    // A subclass's C struct embeds its parent's, so parents have to come first
+    let mut classes = std::mem::take(&mut module.classes);
+    while !classes.is_empty() {
+        let ready = classes
+            .iter()
+            .position(|class| match &class.parent {
+                Some(parent) => module.class(parent).is_some(),
+                None => true,
+            })
+            .unwrap_or(0);
+        module.classes.push(classes.remove(ready));
+    }
+    let mut thunks: Vec<String> = Vec::new();
+    for instruction in module
+        .functions
+        .iter()
+        .flat_map(|function| function.blocks.iter())
+        .flat_map(|block| block.instructions.iter())
+    {
+        if let Instruction::MakeClosure { function, .. } = instruction
+            && let Some(function) = function.strip_suffix("__thunk")
+            && !thunks.iter().any(|thunk| thunk == function)
+        {
+            thunks.push(function.to_string());
+        }
+    }
+    for function in thunks {
+        match thunk(&function, checker, &module) {
+            Ok(thunk) => module.functions.push(thunk),
+            Err(e) => errors.push(e),
+        }
+    }
+    if errors.is_empty() {
+        Ok(module)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn lower_item(
+    node: &AstNode,
+    checker: &TypeChecker,
+    module: &mut Module,
+    errors: &mut Vec<String>,
+) {
+    match node {
+        AstNode::Block { statements } => {
+            for statement in statements {
+                lower_item(statement, checker, module, errors);
+            }
+        }
+        AstNode::Function { .. } => lower_function(node, None, checker, module, errors),
+        AstNode::Class {
+            name,
+            parent,
+            fields,
+            methods,
+        } => {
+            match class_def(name, parent, fields, checker) {
+                Ok(class) => module.classes.push(class),
+                Err(e) => errors.push(e),
+            }
+            for method in methods {
+                lower_function(method, Some(name), checker, module, errors);
+            }
+        }
+        AstNode::Interface { name, .. } => match checker.interfaces.get(name) {
+            Some(interface) => module.interfaces.push(InterfaceDef {
+                name: name.clone(),
+                methods: interface.methods.clone(),
+            }),
+            None => errors.push(format!("Undefined interface: {}", name)),
+        },
+        AstNode::Impl {
+            interface,
+            target,
+            methods,
+        } => {
+            let target = Type::from(impl_receiver(target));
+            let order = checker
+                .interfaces
+                .get(interface)
+                .map(|info| info.methods.clone())
+                .unwrap_or_default();
+            let functions: Vec<(String, String)> = order
+                .iter()
+                .filter_map(|(method, _, _)| {
+                    methods.iter().find_map(|function| match function {
+                        AstNode::Function { name, .. } if impl_method_name(name) == method => {
+                            Some((method.clone(), name.clone()))
+                        }
+                        _ => None,
+                    })
+                })
+                .collect();
+            module.impls.push(ImplDef {
+                interface: interface.clone(),
+                target,
+                methods: functions,
+            });
+            for method in methods {
+                lower_function(method, None, checker, module, errors);
+            }
+        }
+        AstNode::Struct { name, fields } => module.structs.push(StructDef {
+            name: name.clone(),
+            fields: fields
+                .iter()
+                .map(|(tp, field)| Var {
+                    name: field.clone(),
+                    tp: Type::from(tp.clone()),
+                })
+                .collect(),
+        }),
+        AstNode::Assignment {
+            variable,
+            tp,
+            value,
+            binding,
+        } => {
+            let value = match global_initialiser(variable, value) {
+                Ok(value) => value,
+                Err(e) => {
+                    errors.push(e);
+                    return;
+                }
+            };
+            let tp = match tp.as_deref().map(Type::from) {
+                Some(Type::NotMentioned) | None => value
+                    .as_ref()
+                    .map(Constant::tp)
+                    .unwrap_or(Type::NotMentioned),
+                Some(tp) => tp,
+            };
+            module.globals.push(Global {
+                var: Var {
+                    name: variable.clone(),
+                    tp,
+                },
+                value,
+                public: false,
+                mutable: *binding != Binding::Let,
+            });
+        }
+        AstNode::Comment { .. } | AstNode::Eof => {}
+        other => errors.push(format!("Unsupported top-level statement: {:?}", other)),
+    }
+}
+
+fn lower_function(
+    node: &AstNode,
+    class: Option<&str>,
+    checker: &TypeChecker,
+    module: &mut Module,
+    errors: &mut Vec<String>,
+) {
+    let AstNode::Function {
+        name,
+        arguments,
+        return_type,
+        body,
+        attributes,
+    } = node
+    else {
+        return;
+    };
+    let mut builder = FunctionBuilder::new(name, Type::from(return_type.clone()), checker, module);
+    for (tp, argname) in arguments {
+        builder.params.push(Var {
+            name: format!("{}::{}", name, argname),
+            tp: Type::from(tp.clone()),
+        });
+    }
+    // An override receives self as a pointer to the class that declared the method, like
+    // every implementation of its vtable slot, and narrows it back on entry
+    if let Some(class) = class {
+        match checker.method_root(class, method_name(class, name)) {
+            Ok(root) if root != class => {
+                let self_type = builder.params[0].tp.clone();
+                builder.params[0] = Var {
+                    name: format!("{}::_self", name),
+                    tp: Type::Pointer(Box::new(Type::DataTp(root))),
+                };
+                let receiver = builder.params[0].clone();
+                let this = builder.declare(&format!("{}::self", name), self_type.clone());
+                let loaded = builder.new_temp(receiver.tp.clone());
+                builder.emit(Instruction::Load {
+                    dest: loaded.clone(),
+                    var: receiver,
+                });
+                let narrowed = builder.new_temp(self_type);
+                builder.emit(Instruction::Cast {
+                    dest: narrowed.clone(),
+                    value: Operand::Temp(loaded),
+                });
+                builder.emit(Instruction::Store {
+                    var: this,
+                    value: Operand::Temp(narrowed),
+                });
+            }
+            Ok(_) => {}
+            Err(e) => return errors.push(e),
+        }
+    }
+    match builder.lower_body(body) {
+        Ok(function) => module.functions.push(Function {
+            attributes: attributes.clone(),
+            ..function
+        }),
+        Err(e) => errors.push(e),
+    }
+    // Nested functions are hoisted out, C has no nested functions
+    hoist_nested_functions(body, checker, module, errors);
+    lift_lambdas(&mut body.as_ref().clone(), checker, module, errors);
+}
+
+// Lambdas become functions of their own, which copy what they capture out of the
+// environment they are passed first
+fn lift_lambdas(
+    node: &mut AstNode,
+    checker: &TypeChecker,
+    module: &mut Module,
+    errors: &mut Vec<String>,
+) {
+    match node {
+        AstNode::Lambda { .. } => lower_lambda(node, checker, module, errors),
+        // Lifted when hoist_nested_functions lowers them
+        AstNode::Function { .. } => {}
+        _ => {
+            for child in node.children_mut() {
+                lift_lambdas(child, checker, module, errors);
+            }
+        }
+    }
+}
+
+fn lower_lambda(
+    node: &mut AstNode,
+    checker: &TypeChecker,
+    module: &mut Module,
+    errors: &mut Vec<String>,
+) {
+    let AstNode::Lambda {
+        name,
+        arguments,
+        return_type,
+        body,
+        captures,
+    } = node
+    else {
+        return;
+    };
+    let mut fields = Vec::new();
+    for (_, inner) in captures.iter() {
+        match checker.symbol_table.get(inner) {
+            Some(tp) => fields.push(Var {
+                name: c_symbol(inner),
+                tp: tp.clone(),
+            }),
+            None => return errors.push(format!("Undefined variable: {}", inner)),
+        }
+    }
+    let mut builder = FunctionBuilder::new(name, Type::from(return_type.clone()), checker, module);
+    let env = Var {
+        name: format!("{}::_env", name),
+        tp: Type::Pointer(Box::new(Type::Void)),
+    };
+    builder.params.push(env.clone());
+    for (tp, argname) in arguments.iter() {
+        builder.params.push(Var {
+            name: format!("{}::{}", name, argname),
+            tp: Type::from(tp.clone()),
+        });
+    }
+    if !fields.is_empty() {
+        let loaded = builder.new_temp(env.tp.clone());
+        builder.emit(Instruction::Load {
+            dest: loaded.clone(),
+            var: env,
+        });
+        let pointer = builder.new_temp(Type::Pointer(Box::new(Type::DataTp(env_symbol(name)))));
+        builder.emit(Instruction::Cast {
+            dest: pointer.clone(),
+            value: Operand::Temp(loaded),
+        });
+        for ((_, inner), field) in captures.iter().zip(&fields) {
+            let value = builder.new_temp(field.tp.clone());
+            builder.emit(Instruction::GetField {
+                dest: value.clone(),
+                object: Operand::Temp(pointer.clone()),
+                field: field.name.clone(),
+            });
+            let var = builder.declare(inner, field.tp.clone());
+            builder.emit(Instruction::Store {
+                var,
+                value: Operand::Temp(value),
+            });
+        }
+    }
+    let function = builder.lower_body(body);
+    if !fields.is_empty() {
+        module.structs.push(StructDef {
+            name: env_symbol(name),
+            fields,
+        });
+    }
+    match function {
+        Ok(function) => module.functions.push(function),
+        Err(e) => errors.push(e),
+    }
+    lift_lambdas(body, checker, module, errors);
+}
+
+fn thunk(function: &str, checker: &TypeChecker, module: &Module) -> Result<Function, String> {
+    let (params, returns) = checker
+        .function_table
+        .get(function)
+        .ok_or_else(|| format!("Undefined function: {}", function))?;
+    let name = thunk_symbol(function);
+    let return_type = returned(returns);
+    let mut builder = FunctionBuilder::new(&name, return_type.clone(), checker, module);
+    builder.params.push(Var {
+        name: format!("{}::_env", name),
+        tp: Type::Pointer(Box::new(Type::Void)),
+    });
+    let mut arguments = Vec::new();
+    for (index, tp) in params.iter().enumerate() {
+        let param = Var {
+            name: format!("{}::arg{}", name, index),
+            tp: tp.clone(),
+        };
+        builder.params.push(param.clone());
+        let value = builder.new_temp(tp.clone());
+        builder.emit(Instruction::Load {
+            dest: value.clone(),
+            var: param,
+        });
+        arguments.push(Operand::Temp(value));
+    }
+    let dest = (return_type != Type::Void).then(|| builder.new_temp(return_type));
+    builder.emit(Instruction::Call {
+        dest: dest.clone(),
+        function: function.to_string(),
+        arguments,
+    });
+    builder.terminate(Terminator::Return(dest.map(Operand::Temp)));
+    Ok(builder.finish())
+}
+
+fn class_def(
+    name: &str,
+    parent: &Option<String>,
+    fields: &[(String, String)],
+    checker: &TypeChecker,
+) -> Result<ClassDef, String> {
+    let mut vtable = Vec::new();
+    for (class, info) in checker.ancestry(name)?.iter().rev() {
+        for method in &info.methods {
+            if checker.method_root(class, method)? != *class {
+                continue;
+            }
+            let (mut params, return_type) = checker.method_signature(name, method)?;
+            params[0] = Type::Pointer(Box::new(Type::DataTp(class.to_string())));
+            vtable.push(Method {
+                name: method.clone(),
+                function: method_symbol(&checker.method_owner(name, method)?, method),
+                params,
+                return_type,
+            });
+        }
+    }
+    Ok(ClassDef {
+        name: name.to_string(),
+        parent: parent.clone(),
+        fields: fields
+            .iter()
+            .map(|(tp, field)| Var {
+                name: field.clone(),
+                tp: Type::from(tp.clone()),
+            })
+            .collect(),
+        vtable,
+    })
+}
+
+fn hoist_nested_functions(
+    node: &AstNode,
+    checker: &TypeChecker,
+    module: &mut Module,
+    errors: &mut Vec<String>,
+) {
+    match node {
+        AstNode::Function { .. } => lower_item(node, checker, module, errors),
+        AstNode::Block { statements } => {
+            for statement in statements {
+                hoist_nested_functions(statement, checker, module, errors);
+            }
+        }
+        AstNode::If {
+            consequence,
+            alternative,
+            ..
+        } => {
+            hoist_nested_functions(consequence, checker, module, errors);
+            if let Some(alt) = alternative {
+                hoist_nested_functions(alt, checker, module, errors);
+            }
+        }
+        AstNode::While { body, .. } => hoist_nested_functions(body, checker, module, errors),
+        _ => {}
+    }
+}
+
+// Globals are set before main runs, so they can only start out as constants. The
+// interpreter keeps to the same rule.
+pub fn global_initialiser(variable: &str, value: &AstNode) -> Result<Option<Constant>, String> {
+    match value {
+        AstNode::Uninit { .. } => Ok(None),
+        value => constant(value).map(Some).ok_or_else(|| {
+            format!(
+                "Top-level initialiser for '{}' must be a constant",
+                variable
+            )
+        }),
+    }
+}
+
+fn constant(node: &AstNode) -> Option<Constant> {
+    match node {
+        AstNode::Number { value } => Some(Constant::Int(*value)),
+        AstNode::Bool { value } => Some(Constant::Bool(value == "true")),
+        AstNode::Char { value } => Some(Constant::Char(value.clone())),
+        AstNode::String { value } => Some(Constant::Str(value.clone())),
+        AstNode::Null => Some(Constant::Null),
+        _ => None,
+    }
+}
+
+struct FunctionBuilder<'a> {
+    name: String,
+    return_type: Type,
+    params: Vec<Var>,
+    locals: Vec<Var>,
+    blocks: Vec<(Vec<Instruction>, Option<Terminator>)>,
+    current: usize,
+    next_temp: usize,
+    checker: &'a TypeChecker,
+    module: &'a Module,
+}
+
+impl<'a> FunctionBuilder<'a> {
+    fn new(name: &str, return_type: Type, checker: &'a TypeChecker, module: &'a Module) -> Self {
+        FunctionBuilder {
+            name: name.to_string(),
+            return_type,
+            params: Vec::new(),
+            locals: Vec::new(),
+            blocks: vec![(Vec::new(), None)],
+            current: 0,
+            next_temp: 0,
+            checker,
+            module,
+        }
+    }
+
+    fn lower_body(mut self, body: &AstNode) -> Result<Function, String> {
+        self.lower_statement(body)?;
+        if self.blocks[self.current].1.is_none() {
+            // Control flow analysis has already proven the end of a non-Void function is unreachable
+            let terminator = if self.return_type == Type::Void {
+                Terminator::Return(None)
+            } else {
+                Terminator::Unreachable
+            };
+            self.terminate(terminator);
+        }
+        Ok(self.finish())
+    }
+
+    fn finish(self) -> Function {
+        let blocks = self
+            .blocks
+            .into_iter()
+            .enumerate()
+            .map(|(index, (instructions, terminator))| BasicBlock {
+                id: BlockId(index),
+                instructions,
+                terminator: terminator.unwrap_or(Terminator::Unreachable),
+            })
+            .collect();
+        let mut function = Function {
+            name: self.name,
+            params: self.params,
+            return_type: self.return_type,
+            locals: self.locals,
+            blocks,
+            attributes: Vec::new(),
+            public: false,
+        };
+        function.remove_unreachable_blocks();
+        function
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push((Vec::new(), None));
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn switch_to(&mut self, block: BlockId) {
+        self.current = block.0;
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        if self.blocks[self.current].1.is_none() {
+            self.blocks[self.current].1 = Some(terminator);
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.blocks[self.current].0.push(instruction);
+    }
+
+    fn new_temp(&mut self, tp: Type) -> Temp {
+        self.next_temp += 1;
+        Temp {
+            id: self.next_temp - 1,
+            tp,
+        }
+    }
+
+    fn local(&self, name: &str) -> Option<Var> {
+        self.params
+            .iter()
+            .chain(self.locals.iter())
+            .find(|var| var.name == name)
+            .cloned()
+    }
+
+    fn lookup(&self, name: &str) -> Result<Var, String> {
+        if let Some(var) = self.local(name) {
+            return Ok(var);
+        }
+        // Not a local, so drop the function scope and look for a global
+        let unscoped = name
+            .strip_prefix(&format!("{}::", self.name))
+            .unwrap_or_else(|| name.split("::").last().unwrap());
+        if let Some(global) = self
+            .module
+            .globals
+            .iter()
+            .find(|global| global.var.name == unscoped)
+        {
+            return Ok(global.var.clone());
+        }
+        if let Some(tp) = self.checker.symbol_table.get(unscoped) {
+            return Ok(Var {
+                name: unscoped.to_string(),
+                tp: tp.clone(),
+            });
+        }
+        Err(format!("Undefined variable: {}", unscoped))
+    }
+
+    fn lower_statement(&mut self, node: &AstNode) -> Result<(), String> {
+        match node {
+            AstNode::Block { statements } => {
+                for statement in statements {
+                    self.lower_statement(statement)?;
+                }
+            }
+            AstNode::Assignment {
+                variable,
+                tp,
+                value,
+                binding,
+            } => {
+                let declared = tp
+                    .as_deref()
+                    .map(Type::from)
+                    .filter(|tp| *tp != Type::NotMentioned);
+                if let AstNode::Uninit { tp } = value.as_ref() {
+                    self.declare(variable, declared.unwrap_or(Type::from(tp.clone())));
+                    return Ok(());
+                }
+                // `let` declares a local even when a global has its name
+                let existing = match binding {
+                    Binding::Assign => self.lookup(variable).ok(),
+                    Binding::Let | Binding::LetMut => self.local(variable),
+                };
+                let expected = existing.clone().map(|var| var.tp).or(declared);
+                let value = match &expected {
+                    Some(tp) => self.lower_value(value, tp)?,
+                    None => self.lower_expression(value)?,
+                };
+                let var = match existing {
+                    Some(var) => var,
+                    None => self.declare(variable, expected.unwrap_or(value.tp())),
+                };
+                let value = self.coerce(value, &var.tp);
+                self.emit(Instruction::Store { var, value });
+            }
+            AstNode::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                let condition = self.lower_expression(condition)?;
+                let then_block = self.new_block();
+                let else_block = self.new_block();
+                let join = match alternative {
+                    Some(_) => self.new_block(),
+                    None => else_block,
+                };
+                self.terminate(Terminator::Branch {
+                    condition,
+                    then_block,
+                    else_block,
+                });
+                self.switch_to(then_block);
+                self.lower_statement(consequence)?;
+                self.terminate(Terminator::Jump(join));
+                if let Some(alt) = alternative {
+                    self.switch_to(else_block);
+                    self.lower_statement(alt)?;
+                    self.terminate(Terminator::Jump(join));
+                }
+                self.switch_to(join);
+            }
+            AstNode::While { condition, body } => {
+                let header = self.new_block();
+                let body_block = self.new_block();
+                let exit = self.new_block();
+                self.terminate(Terminator::Jump(header));
+                self.switch_to(header);
+                let condition = self.lower_expression(condition)?;
+                self.terminate(Terminator::Branch {
+                    condition,
+                    then_block: body_block,
+                    else_block: exit,
+                });
+                self.switch_to(body_block);
+                self.lower_statement(body)?;
+                self.terminate(Terminator::Jump(header));
+                self.switch_to(exit);
+            }
+            AstNode::Return { value } => {
+                let value = match value.as_ref() {
+                    AstNode::Null if self.return_type == Type::Void => None,
+                    value => Some(self.lower_value(value, &self.return_type.clone())?),
+                };
+                self.terminate(Terminator::Return(value));
+                // Anything after a return goes into a block that is dropped in finish
+                let dead = self.new_block();
+                self.switch_to(dead);
+            }
+            // Lowered separately by hoist_nested_functions
+            AstNode::Function { .. } => {}
+            AstNode::Struct { .. } => {
+                return Err(format!(
+                    "Structs must be declared at the top level, found one in '{}'",
+                    self.name
+                ))
+            }
+            AstNode::Comment { .. } | AstNode::Eof => {}
+            expression => {
+                self.lower_expression(expression)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn declare(&mut self, name: &str, tp: Type) -> Var {
+        let var = Var {
+            name: name.to_string(),
+            tp,
+        };
+        self.locals.push(var.clone());
+        var
+    }
+
+    // Upcasts a class pointer to the pointer type expected and wraps a value into the dyn
+    // expected, anything else is left alone
+    fn coerce(&mut self, value: Operand, tp: &Type) -> Operand {
+        let value_type = value.tp();
+        if let (Type::Option(_), Type::Option(_)) | (Type::Result(..), Type::Result(..)) =
+            (tp, &value_type)
+            && value_type != *tp
+        {
+            return self.coerce_variant(value, tp);
+        }
+        // A tuple is taken apart to coerce each element and put back together
+        if let (Type::Tuple(types), Type::Tuple(value_types)) = (tp, &value_type)
+            && value_type != *tp
+            && types.len() == value_types.len()
+        {
+            let mut elements = Vec::new();
+            for (index, (tp, value_type)) in types.iter().zip(value_types).enumerate() {
+                let element = self.new_temp(value_type.clone());
+                self.emit(Instruction::GetElement {
+                    dest: element.clone(),
+                    tuple: value.clone(),
+                    index,
+                });
+                elements.push(self.coerce(Operand::Temp(element), tp));
+            }
+            let dest = self.new_temp(tp.clone());
+            self.emit(Instruction::MakeTuple {
+                dest: dest.clone(),
+                elements,
+            });
+            return Operand::Temp(dest);
+        }
+        if let Type::Dyn(interface) = tp {
+            let Some(target) = (value_type != *tp)
+                .then(|| self.checker.impl_of(&value_type, interface))
+                .flatten()
+                .map(|implementation| implementation.target.clone())
+            else {
+                return value;
+            };
+            let value = self.coerce(value, &target);
+            let dest = self.new_temp(tp.clone());
+            self.emit(Instruction::ToDyn {
+                dest: dest.clone(),
+                value,
+            });
+            return Operand::Temp(dest);
+        }
+        if value_type == *tp
+            || class_pointer(&value_type, self.module).is_none()
+            || class_pointer(tp, self.module).is_none()
+        {
+            return value;
+        }
+        let dest = self.new_temp(tp.clone());
+        self.emit(Instruction::Cast {
+            dest: dest.clone(),
+            value,
+        });
+        Operand::Temp(dest)
+    }
+
+    fn class_type(class: &str) -> Type {
+        Type::Pointer(Box::new(Type::DataTp(class.to_string())))
+    }
+
+    // The object's class, the class declaring the field, and the field's type
+    fn field(&mut self, object: &Operand, field: &str) -> Result<(String, Type), String> {
+        let class = match &object.tp() {
+            Type::Pointer(tp) | Type::ConstPointer(tp) => match tp.as_ref() {
+                Type::DataTp(class) => class.clone(),
+                tp => return Err(format!("Can't access field '{}' of {:?}", field, tp)),
+            },
+            tp => return Err(format!("Can't access field '{}' of {:?}", field, tp)),
+        };
+        self.checker
+            .class_fields(&class)?
+            .into_iter()
+            .find(|(_, name, _)| name == field)
+            .map(|(owner, _, tp)| (owner, tp))
+            .ok_or_else(|| format!("{} has no field '{}'", class, field))
+    }
+
+    fn lower_arguments(
+        &mut self,
+        arguments: &[AstNode],
+        params: &[Type],
+    ) -> Result<Vec<Operand>, String> {
+        let mut operands = Vec::new();
+        for (argument, tp) in arguments.iter().zip(params) {
+            operands.push(self.lower_value(argument, tp)?);
+        }
+        Ok(operands)
+    }
+
+    // Lowers node where a value of type tp is expected, which is what tells None, Ok
+    // and Err the type they are
+    fn lower_value(&mut self, node: &AstNode, tp: &Type) -> Result<Operand, String> {
+        match (node, tp) {
+            (AstNode::Variant { variant, value }, Type::Option(_) | Type::Result(..)) => {
+                self.lower_variant(variant, value.as_deref(), tp)
+            }
+            (AstNode::Tuple { elements }, Type::Tuple(types)) if elements.len() == types.len() => {
+                let mut operands = Vec::new();
+                for (element, tp) in elements.iter().zip(types) {
+                    operands.push(self.lower_value(element, tp)?);
+                }
+                let dest = self.new_temp(tp.clone());
+                self.emit(Instruction::MakeTuple {
+                    dest: dest.clone(),
+                    elements: operands,
+                });
+                Ok(Operand::Temp(dest))
+            }
+            _ => {
+                let value = self.lower_expression(node)?;
+                Ok(self.coerce(value, tp))
+            }
+        }
+    }
+
+    // `Some(value)`, `None`, `Ok(value)` or `Err(error)` as the Option or Result type tp
+    fn lower_variant(
+        &mut self,
+        name: &str,
+        value: Option<&AstNode>,
+        tp: &Type,
+    ) -> Result<Operand, String> {
+        let variant =
+            Variant::from_name(name).ok_or_else(|| format!("Unknown variant {}", name))?;
+        let value = match (value, variant.held(tp)) {
+            (Some(value), Some(held)) => {
+                let value = self.lower_value(value, &held.clone())?;
+                (*held != Type::Void).then_some(value)
+            }
+            (None, None) => None,
+            _ => return Err(format!("{} can't be a {:?}", name, tp)),
+        };
+        let dest = self.new_temp(tp.clone());
+        self.emit(Instruction::MakeVariant {
+            dest: dest.clone(),
+            variant,
+            value,
+        });
+        Ok(Operand::Temp(dest))
+    }
+
+    // What value holds as the variant, Void has nothing to unwrap
+    fn unwrap(&mut self, value: &Operand, variant: Variant) -> Operand {
+        let held = variant.held(&value.tp()).cloned().unwrap_or(Type::Void);
+        if held == Type::Void {
+            return Operand::Const(Constant::Null);
+        }
+        let dest = self.new_temp(held);
+        self.emit(Instruction::Unwrap {
+            dest: dest.clone(),
+            value: value.clone(),
+            variant,
+        });
+        Operand::Temp(dest)
+    }
+
+    // Branches on whether value is the variant, leaving the builder in neither block
+    fn branch_on(&mut self, value: &Operand, variant: Variant) -> (BlockId, BlockId) {
+        let condition = self.new_temp(Type::Bool);
+        self.emit(Instruction::IsVariant {
+            dest: condition.clone(),
+            value: value.clone(),
+            variant,
+        });
+        let then_block = self.new_block();
+        let else_block = self.new_block();
+        self.terminate(Terminator::Branch {
+            condition: Operand::Temp(condition),
+            then_block,
+            else_block,
+        });
+        (then_block, else_block)
+    }
+
+    // `value?` continues with what value holds, or returns None or value's error
+    fn lower_try(&mut self, value: &AstNode) -> Result<Operand, String> {
+        let value = self.lower_expression(value)?;
+        let holding = match value.tp() {
+            Type::Option(_) => Variant::Some,
+            Type::Result(..) => Variant::Ok,
+            tp => return Err(format!("'?' needs an Option or Result, found {:?}", tp)),
+        };
+        let (then_block, else_block) = self.branch_on(&value, holding);
+        self.switch_to(else_block);
+        let return_type = self.return_type.clone();
+        let returned = match (&return_type, holding) {
+            (Type::Result(_, error), Variant::Ok) => {
+                let held = self.unwrap(&value, Variant::Err);
+                let held = self.coerce(held, error);
+                (**error != Type::Void).then_some(held)
+            }
+            _ => None,
+        };
+        let dest = self.new_temp(return_type);
+        self.emit(Instruction::MakeVariant {
+            dest: dest.clone(),
+            variant: if returned.is_some() || holding == Variant::Ok {
+                Variant::Err
+            } else {
+                Variant::None
+            },
+            value: returned,
+        });
+        self.terminate(Terminator::Return(Some(Operand::Temp(dest))));
+        self.switch_to(then_block);
+        Ok(self.unwrap(&value, holding))
+    }
+
+    // The methods builtin_method gives Option and Result
+    fn lower_builtin_method(
+        &mut self,
+        value: Operand,
+        method: &str,
+        arguments: &[AstNode],
+    ) -> Result<Operand, String> {
+        let tp = value.tp();
+        let (params, return_type) = builtin_method(&tp, method)
+            .ok_or_else(|| format!("Can't call method '{}' on {:?}", method, tp))?;
+        let (holding, empty) = match tp {
+            Type::Option(_) => (Variant::Some, Variant::None),
+            _ => (Variant::Ok, Variant::Err),
+        };
+        let variant = match method {
+            "is_some" | "is_ok" => holding,
+            "is_none" | "is_err" => empty,
+            "unwrap_err" => Variant::Err,
+            _ => holding,
+        };
+        if return_type == Type::Bool && method.starts_with("is_") {
+            let dest = self.new_temp(Type::Bool);
+            self.emit(Instruction::IsVariant {
+                dest: dest.clone(),
+                value,
+                variant,
+            });
+            return Ok(Operand::Temp(dest));
+        }
+        let default = match (arguments, params.as_slice()) {
+            ([default], [tp]) => Some(self.lower_value(default, tp)?),
+            _ => None,
+        };
+        let (then_block, else_block) = self.branch_on(&value, variant);
+        let Some(default) = default else {
+            // unwrap and unwrap_err stop the program when value isn't the variant
+            self.switch_to(else_block);
+            let message = match variant {
+                Variant::Err => "Called unwrap_err() on an Ok",
+                _ if holding == Variant::Some => "Called unwrap() on None",
+                _ => "Called unwrap() on an Err",
+            };
+            self.emit(Instruction::Call {
+                dest: None,
+                function: "tsl_panic".to_string(),
+                arguments: vec![Operand::Const(Constant::Str(message.to_string()))],
+            });
+            self.terminate(Terminator::Unreachable);
+            self.switch_to(then_block);
+            return Ok(self.unwrap(&value, variant));
+        };
+        let result = self.declare(
+            &format!("{}::_unwrapped{}", self.name, self.locals.len()),
+            return_type,
+        );
+        let join = self.new_block();
+        self.switch_to(else_block);
+        self.emit(Instruction::Store {
+            var: result.clone(),
+            value: default,
+        });
+        self.terminate(Terminator::Jump(join));
+        self.switch_to(then_block);
+        let held = self.unwrap(&value, variant);
+        self.emit(Instruction::Store {
+            var: result.clone(),
+            value: held,
+        });
+        self.terminate(Terminator::Jump(join));
+        self.switch_to(join);
+        let dest = self.new_temp(result.tp.clone());
+        self.emit(Instruction::Load {
+            dest: dest.clone(),
+            var: result,
+        });
+        Ok(Operand::Temp(dest))
+    }
+
+    // An Option or Result taken apart and put back together as the type tp, coercing
+    // what it holds, like `Some(dog)` used as an `Option<Animal*>`
+    fn coerce_variant(&mut self, value: Operand, tp: &Type) -> Operand {
+        let variants = match tp {
+            Type::Option(_) => [Variant::Some, Variant::None],
+            _ => [Variant::Ok, Variant::Err],
+        };
+        let result = self.declare(
+            &format!("{}::_variant{}", self.name, self.locals.len()),
+            tp.clone(),
+        );
+        let (then_block, else_block) = self.branch_on(&value, variants[0]);
+        let join = self.new_block();
+        for (variant, block) in variants.into_iter().zip([then_block, else_block]) {
+            self.switch_to(block);
+            let held = match variant.held(tp) {
+                Some(held) if *held != Type::Void => {
+                    let value = self.unwrap(&value, variant);
+                    Some(self.coerce(value, &held.clone()))
+                }
+                _ => None,
+            };
+            let dest = self.new_temp(tp.clone());
+            self.emit(Instruction::MakeVariant {
+                dest: dest.clone(),
+                variant,
+                value: held,
+            });
+            self.emit(Instruction::Store {
+                var: result.clone(),
+                value: Operand::Temp(dest),
+            });
+            self.terminate(Terminator::Jump(join));
+        }
+        self.switch_to(join);
+        let dest = self.new_temp(tp.clone());
+        self.emit(Instruction::Load {
+            dest: dest.clone(),
+            var: result,
+        });
+        Operand::Temp(dest)
+    }
+
+    fn lower_expression(&mut self, node: &AstNode) -> Result<Operand, String> {
+        if let Some(constant) = constant(node) {
+            return Ok(Operand::Const(constant));
+        }
+        match node {
+            AstNode::Identifier { value } | AstNode::Variable { value } => {
+                let var = match self.lookup(value) {
+                    Ok(var) => var,
+                    Err(e) => return self.lower_function_value(value).ok_or(e),
+                };
+                let dest = self.new_temp(var.tp.clone());
+                self.emit(Instruction::Load {
+                    dest: dest.clone(),
+                    var,
+                });
+                Ok(Operand::Temp(dest))
+            }
+            AstNode::BinaryOperation {
+                operator,
+                left,
+                right,
+            } => {
+                let op = BinOp::from_operator(&operator.value)
+                    .ok_or_else(|| format!("Unsupported operator {}", operator.value))?;
+                let left = self.lower_expression(left)?;
+                let right = self.lower_expression(right)?;
+                let dest = self.new_temp(op.result_type(&left.tp(), &right.tp())?);
+                self.emit(Instruction::Binary {
+                    dest: dest.clone(),
+                    op,
+                    left,
+                    right,
+                });
+                Ok(Operand::Temp(dest))
+            }
+            AstNode::FunctionCall { name, arguments } => self.lower_call(name, arguments),
+            AstNode::Pointer { value } => {
+                let var = match value.as_ref() {
+                    AstNode::Identifier { value } | AstNode::Variable { value } => {
+                        self.lookup(value)?
+                    }
+                    // Taking the address of a temporary needs somewhere to keep it
+                    value => {
+                        let value = self.lower_expression(value)?;
+                        let var = self.declare(
+                            &format!("{}::_spill{}", self.name, self.locals.len()),
+                            value.tp(),
+                        );
+                        self.emit(Instruction::Store {
+                            var: var.clone(),
+                            value,
+                        });
+                        var
+                    }
+                };
+                // Like TypeChecker::check, a `let` variable's address is a const pointer
+                let tp = Box::new(var.tp.clone());
+                let dest = self.new_temp(if self.checker.is_immutable(&var.name) {
+                    Type::ConstPointer(tp)
+                } else {
+                    Type::Pointer(tp)
+                });
+                self.emit(Instruction::AddressOf {
+                    dest: dest.clone(),
+                    var,
+                });
+                Ok(Operand::Temp(dest))
+            }
+            AstNode::Dereference { value } => {
+                let pointer = self.lower_expression(value)?;
+                let tp = match pointer.tp() {
+                    Type::Pointer(tp) if *tp == Type::DataTp("Str".to_owned()) => Type::Char,
+                    Type::Pointer(tp) | Type::ConstPointer(tp) => *tp,
+                    Type::String => Type::Char,
+                    _ => return Err("Cannot dereference non-pointer type".to_string()),
+                };
+                let dest = self.new_temp(tp);
+                self.emit(Instruction::Deref {
+                    dest: dest.clone(),
+                    pointer,
+                });
+                Ok(Operand::Temp(dest))
+            }
+            AstNode::Tuple { elements } => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.lower_expression(element))
+                    .collect::<Result<Vec<Operand>, String>>()?;
+                let dest = self.new_temp(Type::Tuple(elements.iter().map(Operand::tp).collect()));
+                self.emit(Instruction::MakeTuple {
+                    dest: dest.clone(),
+                    elements,
+                });
+                Ok(Operand::Temp(dest))
+            }
+            AstNode::FieldAccess { object, field } => {
+                let object = self.lower_expression(object)?;
+                if let Type::Tuple(types) = object.tp() {
+                    let index = field
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|index| *index < types.len())
+                        .ok_or_else(|| format!("{:?} has no element {}", object.tp(), field))?;
+                    let dest = self.new_temp(types[index].clone());
+                    self.emit(Instruction::GetElement {
+                        dest: dest.clone(),
+                        tuple: object,
+                        index,
+                    });
+                    return Ok(Operand::Temp(dest));
+                }
+                let (owner, tp) = self.field(&object, field)?;
+                let object = self.coerce(object, &Self::class_type(&owner));
+                let dest = self.new_temp(tp);
+                self.emit(Instruction::GetField {
+                    dest: dest.clone(),
+                    object,
+                    field: field.clone(),
+                });
+                Ok(Operand::Temp(dest))
+            }
+            AstNode::FieldAssignment {
+                object,
+                field,
+                value,
+            } => {
+                let object = self.lower_expression(object)?;
+                let (owner, tp) = self.field(&object, field)?;
+                let object = self.coerce(object, &Self::class_type(&owner));
+                let value = self.lower_value(value, &tp)?;
+                self.emit(Instruction::SetField {
+                    object,
+                    field: field.clone(),
+                    value,
+                });
+                Ok(Operand::Const(Constant::Null))
+            }
+            AstNode::MethodCall {
+                object,
+                method,
+                arguments,
+            } => {
+                let object = self.lower_expression(object)?;
+                if builtin_method(&object.tp(), method).is_some() {
+                    return self.lower_builtin_method(object, method, arguments);
+                }
+                let class = match &object.tp() {
+                    Type::Pointer(tp) => match tp.as_ref() {
+                        Type::DataTp(class) if self.checker.method_owner(class, method).is_ok() => {
+                            class.clone()
+                        }
+                        _ => return self.lower_impl_call(object, method, arguments),
+                    },
+                    _ => return self.lower_impl_call(object, method, arguments),
+                };
+                let root = self.checker.method_root(&class, method)?;
+                let (params, return_type) = self.checker.method_signature(&class, method)?;
+                let mut operands = vec![self.coerce(object, &Self::class_type(&root))];
+                operands.extend(self.lower_arguments(arguments, &params[1..])?);
+                let dest = (return_type != Type::Void).then(|| self.new_temp(return_type));
+                self.emit(Instruction::CallMethod {
+                    dest: dest.clone(),
+                    class: root,
+                    method: method.clone(),
+                    arguments: operands,
+                });
+                Ok(dest
+                    .map(Operand::Temp)
+                    .unwrap_or(Operand::Const(Constant::Null)))
+            }
+            // Calls the parent's implementation directly, without the vtable
+            AstNode::SuperCall {
+                class,
+                method,
+                arguments,
+            } => {
+                let parent = self
+                    .checker
+                    .classes
+                    .get(class)
+                    .and_then(|info| info.parent.clone())
+                    .ok_or_else(|| format!("{} has no parent class for 'super'", class))?;
+                let owner = self.checker.method_owner(&parent, method)?;
+                let root = self.checker.method_root(&parent, method)?;
+                let (mut params, return_type) = self.checker.method_signature(&parent, method)?;
+                params[0] = Self::class_type(&root);
+                let operands = self.lower_arguments(arguments, &params)?;
+                let dest = (return_type != Type::Void).then(|| self.new_temp(return_type));
+                self.emit(Instruction::Call {
+                    dest: dest.clone(),
+                    function: method_symbol(&owner, method),
+                    arguments: operands,
+                });
+                Ok(dest
+                    .map(Operand::Temp)
+                    .unwrap_or(Operand::Const(Constant::Null)))
+            }
+            AstNode::New { class, arguments } => {
+                let fields = self.checker.class_fields(class)?;
+                let types: Vec<Type> = fields.iter().map(|(_, _, tp)| tp.clone()).collect();
+                let values = self.lower_arguments(arguments, &types)?;
+                let dest = self.new_temp(Self::class_type(class));
+                self.emit(Instruction::New {
+                    dest: dest.clone(),
+                    class: class.clone(),
+                });
+                for ((owner, field, _), value) in fields.into_iter().zip(values) {
+                    let object =
+                        self.coerce(Operand::Temp(dest.clone()), &Self::class_type(&owner));
+                    self.emit(Instruction::SetField {
+                        object,
+                        field,
+                        value,
+                    });
+                }
+                Ok(Operand::Temp(dest))
+            }
+            AstNode::Lambda {
+                name,
+                arguments,
+                return_type,
+                captures,
+                ..
+            } => {
+                let mut values = Vec::new();
+                for (outer, _) in captures {
+                    values.push(self.lower_expression(&AstNode::Identifier {
+                        value: outer.clone(),
+                    })?);
+                }
+                let dest = self.new_temp(Type::Function(
+                    arguments
+                        .iter()
+                        .map(|(tp, _)| Type::from(tp.clone()))
+                        .collect(),
+                    Box::new(Type::from(return_type.clone())),
+                ));
+                self.emit(Instruction::MakeClosure {
+                    dest: dest.clone(),
+                    function: name.clone(),
+                    captures: values,
+                });
+                Ok(Operand::Temp(dest))
+            }
+            AstNode::Call { callee, arguments } => {
+                let closure = self.lower_expression(callee)?;
+                self.lower_closure_call(closure, arguments)
+            }
+            // Only Some can tell its type from what it holds
+            AstNode::Variant { variant, value } => {
+                let tp = match value {
+                    Some(value) if variant == "Some" => {
+                        let value = self.lower_expression(value)?;
+                        let dest = self.new_temp(Type::Option(Box::new(value.tp())));
+                        self.emit(Instruction::MakeVariant {
+                            dest: dest.clone(),
+                            variant: Variant::Some,
+                            value: (value.tp() != Type::Void).then_some(value),
+                        });
+                        return Ok(Operand::Temp(dest));
+                    }
+                    _ => variant,
+                };
+                Err(format!(
+                    "Can't tell the type of {} here, use it where its type is known",
+                    tp
+                ))
+            }
+            AstNode::Try { value } => self.lower_try(value),
+            other => Err(format!("Unsupported expression: {:?}", other)),
+        }
+    }
+
+    // A function named as a value, which captures nothing
+    fn lower_function_value(&mut self, name: &str) -> Option<Operand> {
+        let function = name.rsplit("::").next()?;
+        let (params, returns) = self.checker.function_table.get(function)?;
+        let dest = self.new_temp(Type::Function(params.clone(), Box::new(returned(returns))));
+        self.emit(Instruction::MakeClosure {
+            dest: dest.clone(),
+            function: thunk_symbol(function),
+            captures: vec![],
+        });
+        Some(Operand::Temp(dest))
+    }
+
+    fn lower_closure_call(
+        &mut self,
+        closure: Operand,
+        arguments: &[AstNode],
+    ) -> Result<Operand, String> {
+        let Type::Function(params, return_type) = closure.tp() else {
+            return Err(format!("Can't call a value of type {:?}", closure.tp()));
+        };
+        let mut operands = vec![closure];
+        operands.extend(self.lower_arguments(arguments, &params)?);
+        let dest = (*return_type != Type::Void).then(|| self.new_temp(*return_type));
+        self.emit(Instruction::CallClosure {
+            dest: dest.clone(),
+            arguments: operands,
+        });
+        Ok(dest
+            .map(Operand::Temp)
+            .unwrap_or(Operand::Const(Constant::Null)))
+    }
+
+    // A method of an interface, through the vtable of a dyn or straight to the impl's
+    // function when the type is known
+    fn lower_impl_call(
+        &mut self,
+        object: Operand,
+        method: &str,
+        arguments: &[AstNode],
+    ) -> Result<Operand, String> {
+        let tp = object.tp();
+        let (dest, instruction) = if let Type::Dyn(interface) = &tp {
+            let (params, return_type) = self.checker.interface_method(interface, method)?;
+            let mut operands = vec![object];
+            operands.extend(self.lower_arguments(arguments, &params)?);
+            let dest = (return_type != Type::Void).then(|| self.new_temp(return_type));
+            let instruction = Instruction::CallDyn {
+                dest: dest.clone(),
+                interface: interface.clone(),
+                method: method.to_string(),
+                arguments: operands,
+            };
+            (dest, instruction)
+        } else {
+            let (target, function) = self
+                .checker
+                .impl_method(&tp, method)?
+                .ok_or_else(|| format!("Can't call method '{}' on {:?}", method, tp))?;
+            let (params, returns) = self.checker.function_table[&function].clone();
+            let mut operands = vec![self.coerce(object, &target)];
+            operands.extend(self.lower_arguments(arguments, &params[1..])?);
+            let return_type = returned(&returns);
+            let dest = (return_type != Type::Void).then(|| self.new_temp(return_type));
+            let instruction = Instruction::Call {
+                dest: dest.clone(),
+                function,
+                arguments: operands,
+            };
+            (dest, instruction)
+        };
+        self.emit(instruction);
+        Ok(dest
+            .map(Operand::Temp)
+            .unwrap_or(Operand::Const(Constant::Null)))
+    }
+
+    fn lower_call(&mut self, name: &str, arguments: &[AstNode]) -> Result<Operand, String> {
+        // A global holding a function value
+        if !self.checker.function_table.contains_key(name)
+            && let Ok(var) = self.lookup(name)
+            && matches!(var.tp, Type::Function(..))
+        {
+            let closure = self.lower_expression(&AstNode::Identifier {
+                value: name.to_string(),
+            })?;
+            return self.lower_closure_call(closure, arguments);
+        }
+        let params = self
+            .checker
+            .function_table
+            .get(name)
+            .map(|(params, _)| params);
+        let mut operands = Vec::new();
+        for (index, argument) in arguments.iter().enumerate() {
+            let value = match params.and_then(|params| params.get(index)) {
+                Some(tp) => self.lower_value(argument, &tp.clone())?,
+                None => self.lower_expression(argument)?,
+            };
+            operands.push(value);
+        }
+        let cast_to = match name {
+            "asChar" => Some(Type::Char),
+            "asInt" | "boolToInt" => Some(Type::Integer),
+            _ => None,
+        };
+        if let Some(tp) = cast_to {
+            let value = operands
+                .pop()
+                .ok_or_else(|| format!("{} expects one argument", name))?;
+            let dest = self.new_temp(tp);
+            self.emit(Instruction::Cast {
+                dest: dest.clone(),
+                value,
+            });
+            return Ok(Operand::Temp(dest));
+        }
+        let return_type = match name {
+            "newStr" => Type::Pointer(Box::new(Type::DataTp("Str".to_owned()))),
+            _ => self
+                .checker
+                .function_table
+                .get(name)
+                .map(|(_, return_types)| returned(return_types))
+                .ok_or_else(|| format!("Undefined function: {}", name))?,
+        };
+        let dest = if return_type == Type::Void {
+            None
+        } else {
+            Some(self.new_temp(return_type))
+        };
+        self.emit(Instruction::Call {
+            dest: dest.clone(),
+            function: name.to_string(),
+            arguments: operands,
+        });
+        Ok(dest
+            .map(Operand::Temp)
+            .unwrap_or(Operand::Const(Constant::Null)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_names, Instruction, Module};
+    use crate::interpreter::interpret;
+    use crate::lex::tokenize;
+    use crate::parser::{AstNode, Parser};
+    use crate::{compile, CompileOptions};
+
+    // The statements of function name in source, after name resolution
+    fn resolved_body(source: &str, name: &str) -> Vec<AstNode> {
+        let ast = Parser::new(tokenize(source.to_string())).parse().unwrap();
+        resolve_names(&ast)
+            .get_statements()
+            .into_iter()
+            .find_map(|statement| match statement {
+                AstNode::Function {
+                    name: function,
+                    body,
+                    ..
+                } if function == name => Some(body.get_statements()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn assigned(statement: &AstNode) -> &str {
+        match statement {
+            AstNode::Assignment { variable, .. } => variable,
+            statement => panic!("{:?} is not an assignment", statement),
+        }
+    }
+
+    #[test]
+    fn let_in_a_block_hides_a_variable_up_to_the_end_of_the_block() {
+        let body = resolved_body(
+            "func main() Int |\n    let x = 1\n    if x == 1 |\n        let x = 2\n        log_int(x)\n    |\n    log_int(x)\n    return 0\n|\n",
+            "main",
+        );
+        assert_eq!(assigned(&body[0]), "main::x");
+        let AstNode::If { consequence, .. } = &body[1] else {
+            panic!("{:?} is not an if", body[1]);
+        };
+        let inner = consequence.get_statements();
+        assert_eq!(assigned(&inner[0]), "main::x.1");
+        let read = |statement: &AstNode| match statement {
+            AstNode::FunctionCall { arguments, .. } => arguments[0].clone(),
+            statement => panic!("{:?} is not a call", statement),
+        };
+        let identifier = |value: &str| AstNode::Identifier {
+            value: value.to_string(),
+        };
+        assert_eq!(read(&inner[1]), identifier("main::x.1"));
+        assert_eq!(read(&body[2]), identifier("main::x"));
+    }
+
+    #[test]
+    fn let_in_the_same_block_reads_the_variable_it_hides() {
+        let body = resolved_body(
+            "func main() Int |\n    let x = 1\n    let x = x + 1\n    return x\n|\n",
+            "main",
+        );
+        let AstNode::Assignment {
+            variable, value, ..
+        } = &body[1]
+        else {
+            panic!("{:?} is not an assignment", body[1]);
+        };
+        assert_eq!(variable, "main::x.1");
+        let AstNode::BinaryOperation { left, .. } = value.as_ref() else {
+            panic!("{:?} is not x + 1", value);
+        };
+        assert_eq!(
+            **left,
+            AstNode::Identifier {
+                value: "main::x".to_string()
+            }
+        );
+    }
+
+    // Every instruction of the named function
+    fn instructions<'a>(module: &'a Module, name: &str) -> Vec<&'a Instruction> {
+        let function = module.functions.iter().find(|f| f.name == name).unwrap();
+        function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .collect()
+    }
+
+    #[test]
+    fn impl_for_struct_is_called_directly_and_through_dyn() {
+        let source = "interface Printable |\n    func show(self) Int\n|\nstruct Point |\n    Int x\n    Int y\n|\nimpl Printable for Point |\n    func show(self) Int |\n        return self.x + self.y\n    |\n|\nfunc show_dyn(p dyn Printable) Int |\n    return p.show()\n|\nfunc main() Int |\n    let p = new Point(3, 4)\n    return p.show() + show_dyn(p)\n|\n";
+        let output = compile(source, &CompileOptions::default())
+            .unwrap_or_else(|errors| panic!("{}", errors[0]));
+        assert_eq!(interpret(output.ast.as_ref().unwrap()), Ok(14));
+        let module = output.module.expect("a lowered module");
+        let main = instructions(&module, "main");
+        assert!(main.iter().any(|instruction| matches!(
+            instruction,
+            Instruction::Call { function, .. } if function == "Point__Printable__show"
+        )));
+        assert!(main
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::ToDyn { .. })));
+        assert!(instructions(&module, "show_dyn").iter().any(|instruction| matches!(
+            instruction,
+            Instruction::CallDyn { interface, method, .. } if interface == "Printable" && method == "show"
+        )));
+    }
+
+    const ANIMALS: &str = "class Animal |\n    Int legs\n    func sound(self) Int |\n        return 1\n    |\n    func describe(self) Int |\n        return self.sound() * 10 + self.legs\n    |\n|\nclass Dog : Animal |\n    func sound(self) Int |\n        return 2\n    |\n|\nclass Puppy : Dog |\n    func sound(self) Int |\n        return super.sound() + 1\n    |\n|\n";
+
+    fn class_error(source: &str) -> String {
+        match compile(&(ANIMALS.to_string() + source), &CompileOptions::default()) {
+            Ok(_) => panic!("{:?} compiled", source),
+            Err(errors) => errors[0].to_string(),
+        }
+    }
+
+    #[test]
+    fn methods_are_called_through_the_vtable_of_the_object() {
+        let source = ANIMALS.to_string()
+            + "func describe_it(animal Animal*) Int |\n    return animal.describe()\n|\nfunc main() Int |\n    let dog Animal* = new Dog(4)\n    return describe_it(new Animal(2)) + describe_it(dog) * 100\n|\n";
+        let output = compile(&source, &CompileOptions::default())
+            .unwrap_or_else(|errors| panic!("{}", errors[0]));
+        assert_eq!(interpret(output.ast.as_ref().unwrap()), Ok(12 + 2400));
+        let module = output.module.expect("a lowered module");
+        assert!(instructions(&module, "describe_it").iter().any(|instruction| matches!(
+            instruction,
+            Instruction::CallMethod { class, method, .. } if class == "Animal" && method == "describe"
+        )));
+    }
+
+    #[test]
+    fn super_calls_go_up_one_class_at_a_time() {
+        let source = ANIMALS.to_string()
+            + "class Runt : Puppy |\n    func sound(self) Int |\n        return super.sound() * 10\n    |\n|\nfunc main() Int |\n    let runt Animal* = new Runt(3)\n    return runt.describe()\n|\n";
+        let output = compile(&source, &CompileOptions::default())
+            .unwrap_or_else(|errors| panic!("{}", errors[0]));
+        // Runt's sound is Puppy's, 2 + 1, times 10
+        assert_eq!(interpret(output.ast.as_ref().unwrap()), Ok(303));
+    }
+
+    #[test]
+    fn an_override_has_to_keep_the_signature() {
+        assert_eq!(
+            class_error("class Cat : Animal |\n    func sound(self) Bool |\n        return true\n    |\n|\nfunc main() Int |\n    return 0\n|\n"),
+            "Type error: Method 'sound' of Cat has a different signature than the one it overrides in Animal"
+        );
+        assert_eq!(
+            class_error("class Cat : Dog |\n    func sound(self, loud Bool) Int |\n        return 3\n    |\n|\nfunc main() Int |\n    return 0\n|\n"),
+            "Type error: Method 'sound' of Cat has a different signature than the one it overrides in Dog"
+        );
+    }
+
+    #[test]
+    fn a_class_converts_to_its_ancestors_but_not_back() {
+        let upcast = ANIMALS.to_string()
+            + "func main() Int |\n    let puppy = new Puppy(4)\n    let animal Animal* = puppy\n    return animal.sound()\n|\n";
+        let output = compile(&upcast, &CompileOptions::default())
+            .unwrap_or_else(|errors| panic!("{}", errors[0]));
+        assert_eq!(interpret(output.ast.as_ref().unwrap()), Ok(3));
+        assert_eq!(
+            class_error("func main() Int |\n    let animal Animal* = new Dog(4)\n    let dog Dog* = animal\n    return 0\n|\n"),
+            "Type error: Expected type Pointer(DataTp(\"Dog\")) but found Pointer(DataTp(\"Animal\"))"
+        );
+    }
 }
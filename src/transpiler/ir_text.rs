@@ -0,0 +1,1015 @@
+// Text form of the IR - printed by `--emit=ir`, and parsed back by parse_module so a
+// dump can be edited by hand and fed through the rest of the pipeline.
+//
+// func fact(fact::n: Int) -> Int {
+// bb0:
+//     %0: Int = load fact::n
+//     %1: Bool = lt %0, 2
+//     branch %1, bb1, bb2
+// ...
+// }
+
+use super::ir::*;
+use super::type_checker::{split_types, Type};
+use std::collections::HashMap;
+use std::fmt;
+
+impl BinOp {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::Div => "div",
+            BinOp::Rem => "rem",
+            BinOp::Eq => "eq",
+            BinOp::Ne => "ne",
+            BinOp::Lt => "lt",
+            BinOp::Gt => "gt",
+            BinOp::Le => "le",
+            BinOp::Ge => "ge",
+            BinOp::And => "and",
+            BinOp::Or => "or",
+        }
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        [
+            BinOp::Add,
+            BinOp::Sub,
+            BinOp::Mul,
+            BinOp::Div,
+            BinOp::Rem,
+            BinOp::Eq,
+            BinOp::Ne,
+            BinOp::Lt,
+            BinOp::Gt,
+            BinOp::Le,
+            BinOp::Ge,
+            BinOp::And,
+            BinOp::Or,
+        ]
+        .into_iter()
+        .find(|op| op.mnemonic() == mnemonic)
+    }
+}
+
+fn type_name(tp: &Type) -> String {
+    String::from(tp.clone())
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.id)
+    }
+}
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, type_name(&self.tp))
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Int(value) => write!(f, "{}", value),
+            Constant::Bool(value) => write!(f, "{}", value),
+            Constant::Char(value) => write!(f, "'{}'", value),
+            Constant::Str(value) => write!(f, "\"{}\"", value),
+            Constant::Null => write!(f, "void"),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Temp(temp) => write!(f, "{}", temp),
+            Operand::Const(constant) => write!(f, "{}", constant),
+        }
+    }
+}
+
+fn join<T: fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(dest) = self.dest() {
+            write!(f, "{}: {} = ", dest, type_name(&dest.tp))?;
+        }
+        match self {
+            Instruction::Load { var, .. } => write!(f, "load {}", var.name),
+            Instruction::Store { var, value } => write!(f, "store {}, {}", var.name, value),
+            Instruction::Binary {
+                op, left, right, ..
+            } => write!(f, "{} {}, {}", op.mnemonic(), left, right),
+            Instruction::Cast { value, .. } => write!(f, "cast {}", value),
+            Instruction::Call {
+                function,
+                arguments,
+                ..
+            } => write!(f, "call {}({})", function, join(arguments)),
+            Instruction::AddressOf { var, .. } => write!(f, "addr {}", var.name),
+            Instruction::Deref { pointer, .. } => write!(f, "deref {}", pointer),
+            Instruction::New { class, .. } => write!(f, "new {}", class),
+            Instruction::GetField { object, field, .. } => {
+                write!(f, "getfield {}, {}", object, field)
+            }
+            Instruction::SetField {
+                object,
+                field,
+                value,
+            } => write!(f, "setfield {}, {}, {}", object, field, value),
+            Instruction::CallMethod {
+                class,
+                method,
+                arguments,
+                ..
+            } => write!(f, "vcall {}.{}({})", class, method, join(arguments)),
+            Instruction::ToDyn { value, .. } => write!(f, "todyn {}", value),
+            Instruction::CallDyn {
+                interface,
+                method,
+                arguments,
+                ..
+            } => write!(f, "dcall {}.{}({})", interface, method, join(arguments)),
+            Instruction::MakeClosure {
+                function, captures, ..
+            } => write!(f, "closure {}({})", function, join(captures)),
+            Instruction::CallClosure { arguments, .. } => {
+                write!(f, "ccall {}({})", arguments[0], join(&arguments[1..]))
+            }
+            Instruction::MakeTuple { elements, .. } => write!(f, "tuple {}", join(elements)),
+            Instruction::GetElement { tuple, index, .. } => {
+                write!(f, "getelem {}, {}", tuple, index)
+            }
+            Instruction::MakeVariant { variant, value, .. } => match value {
+                Some(value) => write!(f, "variant {} {}", variant.as_str(), value),
+                None => write!(f, "variant {}", variant.as_str()),
+            },
+            Instruction::IsVariant { value, variant, .. } => {
+                write!(f, "is {} {}", variant.as_str(), value)
+            }
+            Instruction::Unwrap { value, variant, .. } => {
+                write!(f, "unwrap {} {}", variant.as_str(), value)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Return(Some(value)) => write!(f, "ret {}", value),
+            Terminator::Return(None) => write!(f, "ret"),
+            Terminator::Jump(target) => write!(f, "jump {}", target),
+            Terminator::Branch {
+                condition,
+                then_block,
+                else_block,
+            } => write!(f, "branch {}, {}, {}", condition, then_block, else_block),
+            Terminator::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for attribute in &self.attributes {
+            writeln!(f, "@{}", attribute)?;
+        }
+        writeln!(
+            f,
+            "{}func {}({}) -> {} {{",
+            if self.public { "pub " } else { "" },
+            self.name,
+            join(&self.params),
+            type_name(&self.return_type)
+        )?;
+        for local in &self.locals {
+            writeln!(f, "    local {}", local)?;
+        }
+        for block in &self.blocks {
+            writeln!(f, "{}:", block.id)?;
+            for instruction in &block.instructions {
+                writeln!(f, "    {}", instruction)?;
+            }
+            writeln!(f, "    {}", block.terminator)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for ClassDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.parent {
+            Some(parent) => writeln!(f, "class {} : {} {{", self.name, parent)?,
+            None => writeln!(f, "class {} {{", self.name)?,
+        }
+        for field in &self.fields {
+            writeln!(f, "    {}", field)?;
+        }
+        for method in &self.vtable {
+            let params: Vec<String> = method.params.iter().map(type_name).collect();
+            writeln!(
+                f,
+                "    method {}({}) -> {} = {}",
+                method.name,
+                params.join(", "),
+                type_name(&method.return_type),
+                method.function
+            )?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for InterfaceDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "interface {} {{", self.name)?;
+        for (method, params, return_type) in &self.methods {
+            let params: Vec<String> = params.iter().map(type_name).collect();
+            writeln!(
+                f,
+                "    method {}({}) -> {}",
+                method,
+                params.join(", "),
+                type_name(return_type)
+            )?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for ImplDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "impl {} for {} {{",
+            self.interface,
+            type_name(&self.target)
+        )?;
+        for (method, function) in &self.methods {
+            writeln!(f, "    {} = {}", method, function)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for def in &self.structs {
+            writeln!(f, "struct {} {{", def.name)?;
+            for field in &def.fields {
+                writeln!(f, "    {}", field)?;
+            }
+            writeln!(f, "}}")?;
+        }
+        for class in &self.classes {
+            write!(f, "{}", class)?;
+        }
+        for interface in &self.interfaces {
+            write!(f, "{}", interface)?;
+        }
+        for implementation in &self.impls {
+            write!(f, "{}", implementation)?;
+        }
+        for global in &self.globals {
+            if global.public {
+                write!(f, "pub ")?;
+            }
+            write!(f, "global ")?;
+            if global.mutable {
+                write!(f, "mut ")?;
+            }
+            match &global.value {
+                Some(value) => writeln!(f, "{} = {}", global.var, value)?,
+                None => writeln!(f, "{}", global.var)?,
+            }
+        }
+        for function in &self.functions {
+            writeln!(f)?;
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
+}
+
+// Parses the output of Module's Display impl
+pub fn parse_module(text: &str) -> Result<Module, String> {
+    let mut module = Module::default();
+    let mut attributes = Vec::new();
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+    while let Some((number, line)) = lines.next() {
+        let (public, line) = match line.strip_prefix("pub ") {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let result = if let Some(attribute) = line.strip_prefix('@') {
+            attributes.push(attribute.to_string());
+            Ok(())
+        } else if let Some(rest) = line.strip_prefix("struct ") {
+            parse_struct(rest, &mut lines).map(|def| module.structs.push(def))
+        } else if let Some(rest) = line.strip_prefix("class ") {
+            parse_class(rest, &mut lines).map(|class| module.classes.push(class))
+        } else if let Some(rest) = line.strip_prefix("interface ") {
+            parse_interface(rest, &mut lines).map(|interface| module.interfaces.push(interface))
+        } else if let Some(rest) = line.strip_prefix("impl ") {
+            parse_impl(rest, &mut lines).map(|implementation| module.impls.push(implementation))
+        } else if let Some(rest) = line.strip_prefix("global ") {
+            parse_global(rest).map(|global| module.globals.push(Global { public, ..global }))
+        } else if let Some(rest) = line.strip_prefix("func ") {
+            parse_function(rest, &mut lines, &module.globals).map(|function| {
+                module.functions.push(Function {
+                    attributes: std::mem::take(&mut attributes),
+                    public,
+                    ..function
+                })
+            })
+        } else {
+            Err(format!(
+                "expected struct, class, interface, impl, global or func, found '{}'",
+                line
+            ))
+        };
+        result.map_err(|e| format!("line {}: {}", number, e))?;
+    }
+    Ok(module)
+}
+
+fn parse_var(text: &str) -> Result<Var, String> {
+    let (name, tp) = text
+        .split_once(": ")
+        .ok_or_else(|| format!("expected 'name: Type', found '{}'", text))?;
+    Ok(Var {
+        name: name.trim().to_string(),
+        tp: Type::from(tp.trim()),
+    })
+}
+
+fn parse_struct<'a>(
+    header: &str,
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<StructDef, String> {
+    let name = header.trim_end_matches('{').trim().to_string();
+    let mut fields = Vec::new();
+    for (number, line) in lines.by_ref() {
+        if line == "}" {
+            return Ok(StructDef { name, fields });
+        }
+        fields.push(parse_var(line).map_err(|e| format!("line {}: {}", number, e))?);
+    }
+    Err(format!("struct {} is missing a closing '}}'", name))
+}
+
+fn parse_class<'a>(
+    header: &str,
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<ClassDef, String> {
+    let header = header.trim_end_matches('{').trim();
+    let (name, parent) = match header.split_once(" : ") {
+        Some((name, parent)) => (name.to_string(), Some(parent.to_string())),
+        None => (header.to_string(), None),
+    };
+    let mut class = ClassDef {
+        name,
+        parent,
+        fields: Vec::new(),
+        vtable: Vec::new(),
+    };
+    for (number, line) in lines.by_ref() {
+        if line == "}" {
+            return Ok(class);
+        }
+        let result = match line.strip_prefix("method ") {
+            Some(method) => parse_method(method).map(|method| class.vtable.push(method)),
+            None => parse_var(line).map(|field| class.fields.push(field)),
+        };
+        result.map_err(|e| format!("line {}: {}", number, e))?;
+    }
+    Err(format!("class {} is missing a closing '}}'", class.name))
+}
+
+fn parse_interface<'a>(
+    header: &str,
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<InterfaceDef, String> {
+    let mut interface = InterfaceDef {
+        name: header.trim_end_matches('{').trim().to_string(),
+        methods: Vec::new(),
+    };
+    for (number, line) in lines.by_ref() {
+        if line == "}" {
+            return Ok(interface);
+        }
+        let method = line
+            .strip_prefix("method ")
+            .and_then(|method| method.split_once('('))
+            .and_then(|(name, rest)| {
+                let (params, return_type) = rest.split_once(") -> ")?;
+                let params = split_types(params).into_iter().map(Type::from).collect();
+                Some((name.to_string(), params, Type::from(return_type)))
+            })
+            .ok_or_else(|| {
+                format!(
+                    "line {}: expected 'method name(Types) -> Type', found '{}'",
+                    number, line
+                )
+            })?;
+        interface.methods.push(method);
+    }
+    Err(format!("interface {} is missing a closing '}}'", interface.name))
+}
+
+fn parse_impl<'a>(
+    header: &str,
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<ImplDef, String> {
+    let (interface, target) = header
+        .trim_end_matches('{')
+        .trim()
+        .split_once(" for ")
+        .ok_or("expected 'impl Interface for Type {'")?;
+    let mut implementation = ImplDef {
+        interface: interface.to_string(),
+        target: Type::from(target),
+        methods: Vec::new(),
+    };
+    for (number, line) in lines.by_ref() {
+        if line == "}" {
+            return Ok(implementation);
+        }
+        let (method, function) = line
+            .split_once(" = ")
+            .ok_or_else(|| format!("line {}: expected 'method = function'", number))?;
+        implementation
+            .methods
+            .push((method.to_string(), function.to_string()));
+    }
+    Err(format!("impl {} for {} is missing a closing '}}'", interface, target))
+}
+
+fn parse_method(text: &str) -> Result<Method, String> {
+    let (signature, function) = text
+        .split_once(" = ")
+        .ok_or("expected 'method name(Types) -> Type = function'")?;
+    let (name, rest) = signature
+        .split_once('(')
+        .ok_or("expected '(' after the method name")?;
+    let (params, return_type) = rest
+        .split_once(") -> ")
+        .ok_or("expected ') -> ' after the parameter types")?;
+    let params: Vec<Type> = split_types(params).into_iter().map(Type::from).collect();
+    if params.is_empty() {
+        return Err(format!("method {} needs a receiver", name));
+    }
+    Ok(Method {
+        name: name.to_string(),
+        function: function.to_string(),
+        params,
+        return_type: Type::from(return_type),
+    })
+}
+
+fn parse_global(text: &str) -> Result<Global, String> {
+    let (mutable, text) = match text.strip_prefix("mut ") {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let (var, value) = match text.split_once(" = ") {
+        Some((var, value)) => (var, Some(Cursor::new(value).constant()?)),
+        None => (text, None),
+    };
+    Ok(Global {
+        var: parse_var(var)?,
+        value,
+        public: false,
+        mutable,
+    })
+}
+
+fn parse_function<'a>(
+    header: &str,
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    globals: &[Global],
+) -> Result<Function, String> {
+    let (name, rest) = header
+        .split_once('(')
+        .ok_or("expected '(' after the function name")?;
+    let (params, rest) = rest
+        .split_once(") -> ")
+        .ok_or("expected ') -> ' after the parameters")?;
+    let params = split_types(params)
+        .iter()
+        .map(|param| parse_var(param))
+        .collect::<Result<Vec<Var>, String>>()?;
+    let return_type = Type::from(rest.trim_end_matches('{').trim());
+
+    // Collect the body first, temporaries can be used in a block printed before the one defining them
+    let mut body = Vec::new();
+    for (number, line) in lines.by_ref() {
+        if line == "}" {
+            break;
+        }
+        body.push((number, line));
+    }
+    let mut temps = HashMap::new();
+    for (_, line) in &body {
+        let dest = line.split_once(" = ").map(|(dest, _)| dest);
+        if let Some((id, tp)) = dest
+            .and_then(|dest| dest.strip_prefix('%'))
+            .and_then(|dest| dest.split_once(": "))
+        {
+            let id = id
+                .parse::<usize>()
+                .map_err(|_| format!("invalid temporary '%{}'", id))?;
+            temps.insert(id, Type::from(tp));
+        }
+    }
+
+    let mut function = Function {
+        name: name.to_string(),
+        params,
+        return_type,
+        locals: Vec::new(),
+        blocks: Vec::new(),
+        attributes: Vec::new(),
+        public: false,
+    };
+    let mut instructions = Vec::new();
+    let mut current = None;
+    for (number, line) in body {
+        let result = (|| -> Result<(), String> {
+            if let Some(local) = line.strip_prefix("local ") {
+                function.locals.push(parse_var(local)?);
+            } else if let Some(label) = line.strip_suffix(':') {
+                if current.is_some() {
+                    return Err(format!(
+                        "block {} starts before the previous block ends",
+                        label
+                    ));
+                }
+                current = Some(parse_block_id(label)?);
+            } else if let Some(terminator) = parse_terminator(line, &temps)? {
+                let id = current.take().ok_or("terminator outside of a block")?;
+                function.blocks.push(BasicBlock {
+                    id,
+                    instructions: std::mem::take(&mut instructions),
+                    terminator,
+                });
+            } else {
+                if current.is_none() {
+                    return Err("instruction outside of a block".to_string());
+                }
+                instructions.push(parse_instruction(line, &function, globals, &temps)?);
+            }
+            Ok(())
+        })();
+        result.map_err(|e| format!("line {}: {}", number, e))?;
+    }
+    if let Some(id) = current {
+        return Err(format!("block {} has no terminator", id));
+    }
+    Ok(function)
+}
+
+fn parse_block_id(text: &str) -> Result<BlockId, String> {
+    text.strip_prefix("bb")
+        .and_then(|id| id.parse().ok())
+        .map(BlockId)
+        .ok_or_else(|| format!("invalid block label '{}'", text))
+}
+
+fn parse_terminator(
+    line: &str,
+    temps: &HashMap<usize, Type>,
+) -> Result<Option<Terminator>, String> {
+    let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let terminator = match keyword {
+        "ret" if rest.is_empty() => Terminator::Return(None),
+        "ret" => Terminator::Return(Some(Cursor::new(rest).operand(temps)?)),
+        "jump" => Terminator::Jump(parse_block_id(rest)?),
+        "branch" => {
+            let mut cursor = Cursor::new(rest);
+            let condition = cursor.operand(temps)?;
+            cursor.expect(", ")?;
+            let (then_block, else_block) = cursor
+                .rest()
+                .split_once(", ")
+                .ok_or("expected 'branch cond, bbN, bbM'")?;
+            Terminator::Branch {
+                condition,
+                then_block: parse_block_id(then_block)?,
+                else_block: parse_block_id(else_block)?,
+            }
+        }
+        "unreachable" => Terminator::Unreachable,
+        _ => return Ok(None),
+    };
+    Ok(Some(terminator))
+}
+
+fn parse_instruction(
+    line: &str,
+    function: &Function,
+    globals: &[Global],
+    temps: &HashMap<usize, Type>,
+) -> Result<Instruction, String> {
+    // Variables are declared before the blocks, so their types can be looked up by name.
+    // Anything else (like the prelude's `int_t`) takes the type implied by the instruction.
+    let var = |name: &str, implied: Type| -> Var {
+        function
+            .params
+            .iter()
+            .chain(function.locals.iter())
+            .chain(globals.iter().map(|global| &global.var))
+            .find(|var| var.name == name)
+            .cloned()
+            .unwrap_or(Var {
+                name: name.to_string(),
+                tp: implied,
+            })
+    };
+    let (dest, rest) = match line.split_once(" = ") {
+        Some((dest, rest)) if dest.starts_with('%') => {
+            let mut cursor = Cursor::new(dest);
+            (Some(cursor.temp(temps)?), rest)
+        }
+        _ => (None, line),
+    };
+    let dest_or_err = || {
+        dest.clone()
+            .ok_or(format!("'{}' needs a destination", line))
+    };
+    let (keyword, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    let mut cursor = Cursor::new(rest);
+    let instruction = match keyword {
+        "load" => {
+            let dest = dest_or_err()?;
+            Instruction::Load {
+                var: var(rest, dest.tp.clone()),
+                dest,
+            }
+        }
+        "store" => {
+            let (name, value) = rest.split_once(", ").ok_or("expected 'store var, value'")?;
+            let value = Cursor::new(value).operand(temps)?;
+            Instruction::Store {
+                var: var(name, value.tp()),
+                value,
+            }
+        }
+        "cast" => Instruction::Cast {
+            dest: dest_or_err()?,
+            value: cursor.operand(temps)?,
+        },
+        "call" => {
+            let (name, _) = rest.split_once('(').ok_or("expected 'call name(args)'")?;
+            cursor.advance(name.len() + 1);
+            let mut arguments = Vec::new();
+            while !cursor.rest().starts_with(')') {
+                if !arguments.is_empty() {
+                    cursor.expect(", ")?;
+                }
+                arguments.push(cursor.operand(temps)?);
+            }
+            Instruction::Call {
+                dest,
+                function: name.to_string(),
+                arguments,
+            }
+        }
+        "addr" => {
+            let dest = dest_or_err()?;
+            let implied = match &dest.tp {
+                Type::Pointer(tp) => *tp.clone(),
+                tp => tp.clone(),
+            };
+            Instruction::AddressOf {
+                var: var(rest, implied),
+                dest,
+            }
+        }
+        "deref" => Instruction::Deref {
+            dest: dest_or_err()?,
+            pointer: cursor.operand(temps)?,
+        },
+        "new" => Instruction::New {
+            dest: dest_or_err()?,
+            class: rest.to_string(),
+        },
+        "getfield" => {
+            let object = cursor.operand(temps)?;
+            cursor.expect(", ")?;
+            Instruction::GetField {
+                dest: dest_or_err()?,
+                object,
+                field: cursor.rest().to_string(),
+            }
+        }
+        "setfield" => {
+            let object = cursor.operand(temps)?;
+            cursor.expect(", ")?;
+            let field = cursor.word().to_string();
+            cursor.expect(", ")?;
+            Instruction::SetField {
+                object,
+                field,
+                value: cursor.operand(temps)?,
+            }
+        }
+        "closure" => {
+            let (name, _) = rest
+                .split_once('(')
+                .ok_or("expected 'closure name(captures)'")?;
+            cursor.advance(name.len() + 1);
+            let mut captures = Vec::new();
+            while !cursor.rest().starts_with(')') {
+                if !captures.is_empty() {
+                    cursor.expect(", ")?;
+                }
+                captures.push(cursor.operand(temps)?);
+            }
+            Instruction::MakeClosure {
+                dest: dest_or_err()?,
+                function: name.to_string(),
+                captures,
+            }
+        }
+        "ccall" => {
+            let mut arguments = vec![Operand::Temp(cursor.temp(temps)?)];
+            cursor.expect("(")?;
+            while !cursor.rest().starts_with(')') {
+                if arguments.len() > 1 {
+                    cursor.expect(", ")?;
+                }
+                arguments.push(cursor.operand(temps)?);
+            }
+            Instruction::CallClosure { dest, arguments }
+        }
+        "tuple" => {
+            let mut elements = vec![cursor.operand(temps)?];
+            while !cursor.rest().is_empty() {
+                cursor.expect(", ")?;
+                elements.push(cursor.operand(temps)?);
+            }
+            Instruction::MakeTuple {
+                dest: dest_or_err()?,
+                elements,
+            }
+        }
+        "getelem" => {
+            let tuple = cursor.operand(temps)?;
+            cursor.expect(", ")?;
+            let index = cursor.rest();
+            Instruction::GetElement {
+                dest: dest_or_err()?,
+                tuple,
+                index: index
+                    .parse()
+                    .map_err(|_| format!("invalid element index '{}'", index))?,
+            }
+        }
+        "variant" | "is" | "unwrap" => {
+            let (name, value) = rest.split_once(' ').unwrap_or((rest, ""));
+            let variant = [Variant::Some, Variant::None, Variant::Ok, Variant::Err]
+                .into_iter()
+                .find(|variant| variant.as_str() == name)
+                .ok_or_else(|| format!("unknown variant '{}'", name))?;
+            let mut cursor = Cursor::new(value);
+            match keyword {
+                "variant" => Instruction::MakeVariant {
+                    dest: dest_or_err()?,
+                    variant,
+                    value: match value {
+                        "" => None,
+                        _ => Some(cursor.operand(temps)?),
+                    },
+                },
+                "is" => Instruction::IsVariant {
+                    dest: dest_or_err()?,
+                    value: cursor.operand(temps)?,
+                    variant,
+                },
+                _ => Instruction::Unwrap {
+                    dest: dest_or_err()?,
+                    value: cursor.operand(temps)?,
+                    variant,
+                },
+            }
+        }
+        "todyn" => Instruction::ToDyn {
+            dest: dest_or_err()?,
+            value: cursor.operand(temps)?,
+        },
+        "vcall" | "dcall" => {
+            let expected = format!("expected '{} Type.method(args)'", keyword);
+            let (callee, _) = rest.split_once('(').ok_or(&expected)?;
+            // Class and interface names can be qualified with their module, method names can't
+            let (owner, method) = callee.rsplit_once('.').ok_or(&expected)?;
+            cursor.advance(callee.len() + 1);
+            let mut arguments = Vec::new();
+            while !cursor.rest().starts_with(')') {
+                if !arguments.is_empty() {
+                    cursor.expect(", ")?;
+                }
+                arguments.push(cursor.operand(temps)?);
+            }
+            if keyword == "vcall" {
+                Instruction::CallMethod {
+                    dest,
+                    class: owner.to_string(),
+                    method: method.to_string(),
+                    arguments,
+                }
+            } else {
+                Instruction::CallDyn {
+                    dest,
+                    interface: owner.to_string(),
+                    method: method.to_string(),
+                    arguments,
+                }
+            }
+        }
+        mnemonic => {
+            let op = BinOp::from_mnemonic(mnemonic)
+                .ok_or_else(|| format!("unknown instruction '{}'", mnemonic))?;
+            let left = cursor.operand(temps)?;
+            cursor.expect(", ")?;
+            Instruction::Binary {
+                dest: dest_or_err()?,
+                op,
+                left,
+                right: cursor.operand(temps)?,
+            }
+        }
+    };
+    Ok(instruction)
+}
+
+// Reads operands one at a time, string and char constants may contain commas
+struct Cursor<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(text: &'a str) -> Self {
+        Cursor { text, position: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn advance(&mut self, count: usize) {
+        self.position += count;
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        if self.rest().starts_with(text) {
+            self.advance(text.len());
+            Ok(())
+        } else {
+            Err(format!("expected '{}', found '{}'", text, self.rest()))
+        }
+    }
+
+    // Everything up to the next separator
+    fn word(&mut self) -> &'a str {
+        let rest = self.rest();
+        let end = rest.find([',', '(', ')', ':']).unwrap_or(rest.len());
+        self.advance(end);
+        &rest[..end]
+    }
+
+    fn temp(&mut self, temps: &HashMap<usize, Type>) -> Result<Temp, String> {
+        self.expect("%")?;
+        let word = self.word();
+        let id = word
+            .parse::<usize>()
+            .map_err(|_| format!("invalid temporary '%{}'", word))?;
+        let tp = temps
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| format!("temporary %{} is never defined", id))?;
+        Ok(Temp { id, tp })
+    }
+
+    fn operand(&mut self, temps: &HashMap<usize, Type>) -> Result<Operand, String> {
+        if self.rest().starts_with('%') {
+            self.temp(temps).map(Operand::Temp)
+        } else {
+            self.constant().map(Operand::Const)
+        }
+    }
+
+    fn constant(&mut self) -> Result<Constant, String> {
+        let rest = self.rest();
+        for (quote, make) in [
+            ('"', Constant::Str as fn(String) -> Constant),
+            ('\'', Constant::Char),
+        ] {
+            if let Some(inner) = rest.strip_prefix(quote) {
+                let end = inner
+                    .find(quote)
+                    .ok_or_else(|| format!("unterminated constant {}", rest))?;
+                self.advance(end + 2);
+                return Ok(make(inner[..end].to_string()));
+            }
+        }
+        let word = self.word();
+        match word {
+            "true" => Ok(Constant::Bool(true)),
+            "false" => Ok(Constant::Bool(false)),
+            "void" => Ok(Constant::Null),
+            _ => word
+                .parse()
+                .map(Constant::Int)
+                .map_err(|_| format!("invalid operand '{}'", word)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_module;
+    use crate::{compile, CompileOptions};
+
+    const PROGRAM: &str = "let mut counter = 0
+interface Shape |
+    func area(self) Int
+|
+class Square |
+    Int side
+    func area(self) Int |
+        return self.side * self.side
+    |
+|
+class Cube : Square |
+    func area(self) Int |
+        return 6 * super.area()
+    |
+|
+impl Shape for Square |
+    func area(self) Int |
+        return self.side * self.side
+    |
+|
+func half(n Int) Option<Int> |
+    if n / 2 * 2 == n |
+        return Some(n / 2)
+    |
+    return None
+|
+func split(n Int) (Int, Int) |
+    return n / 10, n - n / 10 * 10
+|
+func main() Int |
+    let shape dyn Shape = new Square(3)
+    let (tens, units) = split(42)
+    let add = func(x Int) Int | return x + tens |
+    counter = counter + add(units)
+    println(half(shape.area()).is_some())
+    return counter
+|
+";
+
+    #[test]
+    fn printed_module_parses_back_to_the_same_module() {
+        let module = compile(PROGRAM, &CompileOptions::default())
+            .unwrap_or_else(|errors| panic!("{}", errors[0]))
+            .module
+            .unwrap();
+        let text = module.to_string();
+        let parsed = parse_module(&text).unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(parsed.to_string(), text);
+        assert_eq!(parsed, module);
+    }
+
+    #[test]
+    fn block_without_a_terminator_is_rejected() {
+        let text = "func f() -> Int {\nbb0:\n    %0: Int = add 1, 2\n}\n";
+        assert_eq!(
+            parse_module(text).unwrap_err(),
+            "line 1: block bb0 has no terminator"
+        );
+    }
+}
@@ -1,9 +1,13 @@
-use crate::lex::*;
-use crate::parser::*;
-
 pub mod c_bindgen;
 pub mod color;
+pub mod control_flow;
+pub mod generics;
+pub mod interfaces;
 pub mod ir;
+pub mod ir_text;
+pub mod modules;
 pub mod optimiser;
 pub mod overloading;
+pub mod passes;
 pub mod type_checker;
+pub mod verifier;
@@ -0,0 +1,786 @@
+// Modules - `import util` or `import "path/util.tsl"` pulls in another file, resolved
+// relative to the importing file and then to each directory on the search path. Each imported module gets its own namespace: its
+// functions, structs, classes, interfaces and globals are renamed to `util.name`, and other modules refer to
+// the `pub` ones as `util::name`. The C backend mangles `util.name` to `util__name`, so
+// modules can't clash. All modules are merged into one AST, dependencies first, before
+// overload resolution.
+
+use super::generics::split_generic;
+use super::type_checker::{split_function_type, split_types};
+use crate::cache::hash;
+use crate::lex::tokenize;
+use crate::parser::*;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+// Reads the source of a module. The compiler never touches the filesystem itself.
+pub type Loader = dyn FnMut(&Path) -> Result<String, String>;
+
+// Functions, structs and globals defined by a module, by their unqualified names
+#[derive(Clone, Default)]
+struct Items {
+    all: HashSet<String>,
+    public: HashSet<String>,
+}
+
+#[derive(Clone)]
+struct Module {
+    path: PathBuf,
+    name: String,
+    items: Items,
+    fingerprint: String,
+    statements: Vec<AstNode>,
+}
+
+// An imported module, as the type checker sees it in the merged program
+#[derive(Debug, Clone)]
+pub struct ModuleInfo {
+    pub name: String,
+    // Hash of the module's source and of the fingerprints of the modules it imports, so
+    // it changes whenever anything checking the module depends on does
+    pub fingerprint: String,
+    // Qualified names of the module's items
+    pub items: HashSet<String>,
+}
+
+// All modules merged into one program
+pub struct Program {
+    pub ast: AstNode,
+    // Qualified names of every `pub` item
+    pub public: HashSet<String>,
+    // Every imported module's file, in dependency order
+    pub files: Vec<PathBuf>,
+    // Every imported module, in the same order
+    pub modules: Vec<ModuleInfo>,
+}
+
+struct ModuleGraph<'a> {
+    loader: &'a mut Loader,
+    search_path: &'a [PathBuf],
+    // Sources read while looking for a module, waiting to be loaded
+    sources: HashMap<PathBuf, String>,
+    // Loaded modules in dependency order, so imports come before the modules using them
+    modules: Vec<Module>,
+    // Modules that are still being loaded, to report import cycles
+    stack: Vec<PathBuf>,
+}
+
+// The modules the earlier parts of a program imported, see resolve_more_imports
+#[derive(Clone, Default)]
+pub struct Loaded {
+    // Without their statements, which went into the part that first imported them
+    modules: Vec<Module>,
+    // The items of each module the root imported, by module name
+    imports: HashMap<String, Items>,
+}
+
+pub fn resolve_imports(
+    root: AstNode,
+    path: &Path,
+    search_path: &[PathBuf],
+    loader: &mut Loader,
+) -> Result<Program, String> {
+    resolve_more_imports(root, path, search_path, loader, &mut Loaded::default())
+}
+
+// Resolves the imports of one more part of a program, like the next REPL input. Modules
+// an earlier part loaded aren't loaded again and their statements aren't merged in again,
+// but the root can still use what those parts imported.
+pub fn resolve_more_imports(
+    root: AstNode,
+    path: &Path,
+    search_path: &[PathBuf],
+    loader: &mut Loader,
+    loaded: &mut Loaded,
+) -> Result<Program, String> {
+    let path = normalize(path);
+    let known = loaded.modules.len();
+    let mut graph = ModuleGraph {
+        loader,
+        search_path,
+        sources: HashMap::new(),
+        modules: std::mem::take(&mut loaded.modules),
+        stack: vec![path.clone()],
+    };
+    let imports = graph.load_imports(&root, &path);
+    if imports.is_err() {
+        // A part that fails to import leaves nothing loaded
+        graph.modules.truncate(known);
+    }
+    loaded.modules = graph.modules;
+    loaded.imports.extend(imports?);
+    let mut items = Items::default();
+    collect_items(&root, &mut items);
+    // The root module isn't namespaced, so `main` stays `main`
+    let root = rewrite_module(root, None, &loaded.imports, &path)?;
+    let mut public = items.public;
+    let mut statements = Vec::new();
+    let mut files = Vec::new();
+    let mut modules = Vec::new();
+    for module in &mut loaded.modules[known..] {
+        files.push(module.path.clone());
+        modules.push(ModuleInfo {
+            items: module
+                .items
+                .all
+                .iter()
+                .map(|item| format!("{}.{}", module.name, item))
+                .collect(),
+            name: module.name.clone(),
+            fingerprint: module.fingerprint.clone(),
+        });
+        public.extend(
+            module
+                .items
+                .public
+                .iter()
+                .map(|item| format!("{}.{}", module.name, item)),
+        );
+        statements.append(&mut module.statements);
+    }
+    statements.extend(root.get_statements());
+    Ok(Program {
+        ast: AstNode::Block { statements },
+        public,
+        files,
+        modules,
+    })
+}
+
+impl ModuleGraph<'_> {
+    // Loads every module the AST imports, returning the items each one defines
+    fn load_imports(
+        &mut self,
+        ast: &AstNode,
+        importer: &Path,
+    ) -> Result<HashMap<String, Items>, String> {
+        let mut imports = HashMap::new();
+        for statement in ast.get_statements() {
+            if let AstNode::Import { path } = statement {
+                let path = self.find(importer, &path)?;
+                let index = self.load(&path)?;
+                let module = &self.modules[index];
+                imports.insert(module.name.clone(), module.items.clone());
+            }
+        }
+        Ok(imports)
+    }
+
+    // The first candidate that is already loaded or that the loader can read
+    fn find(&mut self, importer: &Path, import: &str) -> Result<PathBuf, String> {
+        let mut first_error = None;
+        for candidate in candidates(importer, import, self.search_path) {
+            let known = self.modules.iter().any(|module| module.path == candidate)
+                || self.stack.contains(&candidate)
+                || self.sources.contains_key(&candidate);
+            if known {
+                return Ok(candidate);
+            }
+            match (self.loader)(&candidate) {
+                Ok(source) => {
+                    self.sources.insert(candidate.clone(), source);
+                    return Ok(candidate);
+                }
+                Err(e) => {
+                    first_error.get_or_insert(format!(
+                        "Failed to import {}: {}",
+                        candidate.display(),
+                        e
+                    ));
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| format!("Failed to import {}", import)))
+    }
+
+    fn load(&mut self, path: &Path) -> Result<usize, String> {
+        if let Some(index) = self.modules.iter().position(|module| module.path == path) {
+            return Ok(index);
+        }
+        if let Some(start) = self.stack.iter().position(|module| module == path) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .chain(std::iter::once(&path.to_path_buf()))
+                .map(|module| module.display().to_string())
+                .collect();
+            return Err(format!("Import cycle: {}", cycle.join(" -> ")));
+        }
+        let name = module_name(path)?;
+        if let Some(other) = self.modules.iter().find(|module| module.name == name) {
+            return Err(format!(
+                "Two modules are named '{}': {} and {}",
+                name,
+                other.path.display(),
+                path.display()
+            ));
+        }
+
+        let source = match self.sources.remove(path) {
+            Some(source) => source,
+            None => (self.loader)(path)
+                .map_err(|e| format!("Failed to import {}: {}", path.display(), e))?,
+        };
+        let ast = Parser::new(tokenize(source.clone()))
+            .parse()
+            .map_err(|e| format!("Parse error in {}: {}", path.display(), e))?;
+        self.stack.push(path.to_path_buf());
+        let imports = self.load_imports(&ast, path)?;
+        self.stack.pop();
+        let mut parts = vec![name.as_bytes(), source.as_bytes()];
+        parts.extend(
+            self.modules
+                .iter()
+                .filter(|module| imports.contains_key(&module.name))
+                .map(|module| module.fingerprint.as_bytes()),
+        );
+        let fingerprint = hash(&parts);
+
+        let mut items = Items::default();
+        collect_items(&ast, &mut items);
+        let ast = rewrite_module(ast, Some((&name, &items)), &imports, path)?;
+        self.modules.push(Module {
+            path: path.to_path_buf(),
+            name,
+            items,
+            fingerprint,
+            statements: ast.get_statements(),
+        });
+        Ok(self.modules.len() - 1)
+    }
+}
+
+// `import util` means util.tsl, a string is a relative path. Both are looked for next to
+// the importing file first, then in each search path directory.
+fn candidates(importer: &Path, import: &str, search_path: &[PathBuf]) -> Vec<PathBuf> {
+    let file = if import.ends_with(".tsl") {
+        import.to_string()
+    } else {
+        format!("{}.tsl", import)
+    };
+    let directory = importer.parent().unwrap_or(Path::new(""));
+    let mut candidates = vec![normalize(&directory.join(&file))];
+    for directory in search_path {
+        let candidate = normalize(&directory.join(&file));
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+    candidates
+}
+
+// Removes `.` and `..` without asking the filesystem, so the same module imported through
+// different relative paths is only loaded once
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn module_name(path: &Path) -> Result<String, String> {
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(char::is_alphabetic)
+        && chars.all(|c| c.is_alphanumeric() || c == '_');
+    if valid {
+        Ok(name)
+    } else {
+        Err(format!(
+            "Module {} needs a file name that is a valid identifier",
+            path.display()
+        ))
+    }
+}
+
+// Top-level globals, structs and classes, and every function, nested ones included since they
+// are hoisted
+fn collect_items(ast: &AstNode, items: &mut Items) {
+    for statement in ast.get_statements() {
+        let (item, public) = match statement {
+            AstNode::Public { item } => (*item, true),
+            item => (item, false),
+        };
+        let item = match item {
+            AstNode::Generic { item, .. } => *item,
+            item => item,
+        };
+        let name = match &item {
+            AstNode::Assignment { variable, .. } => variable.clone(),
+            AstNode::Struct { name, .. } => name.clone(),
+            AstNode::Function { name, body, .. } => {
+                collect_functions(body, &mut items.all);
+                name.clone()
+            }
+            // Methods are renamed with their class, since `Point__norm` becomes `util.Point__norm`
+            AstNode::Class { name, methods, .. } => {
+                for method in methods {
+                    collect_functions(method, &mut items.all);
+                }
+                name.clone()
+            }
+            AstNode::Interface { name, .. } => name.clone(),
+            // An impl has no name of its own, but its methods are functions of the module
+            AstNode::Impl { methods, .. } => {
+                for method in methods {
+                    collect_functions(method, &mut items.all);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if public {
+            items.public.insert(name.clone());
+        }
+        items.all.insert(name);
+    }
+}
+
+fn collect_functions(node: &AstNode, items: &mut HashSet<String>) {
+    match node {
+        AstNode::Function { name, body, .. } => {
+            items.insert(name.clone());
+            collect_functions(body, items);
+        }
+        AstNode::Block { statements } => {
+            for statement in statements {
+                collect_functions(statement, items);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Names assigned in a function body shadow globals. Nested functions have their own.
+fn collect_locals(node: &AstNode, locals: &mut HashSet<String>) {
+    match node {
+        AstNode::Assignment { variable, .. } => {
+            locals.insert(variable.clone());
+        }
+        AstNode::Function { .. } => {}
+        AstNode::Block { statements } => {
+            for statement in statements {
+                collect_locals(statement, locals);
+            }
+        }
+        AstNode::If {
+            consequence,
+            alternative,
+            ..
+        } => {
+            collect_locals(consequence, locals);
+            if let Some(alternative) = alternative {
+                collect_locals(alternative, locals);
+            }
+        }
+        AstNode::While { body, .. } => collect_locals(body, locals),
+        _ => {}
+    }
+}
+
+struct Rewriter<'a> {
+    // The module's name and items, None for the root module
+    namespace: Option<(&'a str, &'a Items)>,
+    imports: &'a HashMap<String, Items>,
+    path: &'a Path,
+}
+
+fn rewrite_module(
+    ast: AstNode,
+    namespace: Option<(&str, &Items)>,
+    imports: &HashMap<String, Items>,
+    path: &Path,
+) -> Result<AstNode, String> {
+    let rewriter = Rewriter {
+        namespace,
+        imports,
+        path,
+    };
+    let mut statements = Vec::new();
+    for statement in ast.get_statements() {
+        match statement {
+            AstNode::Import { .. } => {}
+            AstNode::Eof if namespace.is_some() => {}
+            // Visibility only matters here, so later passes just see the item
+            AstNode::Public { item } => {
+                let mut item = *item;
+                rewriter.rewrite(&mut item, &HashSet::new())?;
+                statements.push(item);
+            }
+            mut statement => {
+                rewriter.rewrite(&mut statement, &HashSet::new())?;
+                statements.push(statement);
+            }
+        }
+    }
+    Ok(AstNode::Block { statements })
+}
+
+impl Rewriter<'_> {
+    // Turns `util::name` into `util.name`, and qualifies the module's own items
+    fn qualify(&self, name: &str, locals: &HashSet<String>) -> Result<String, String> {
+        if let Some((module, item)) = name.split_once("::") {
+            let Some(items) = self.imports.get(module) else {
+                return Err(format!(
+                    "Module '{}' is not imported in {}",
+                    module,
+                    self.path.display()
+                ));
+            };
+            if !items.all.contains(item) {
+                return Err(format!("Module '{}' has no item named '{}'", module, item));
+            }
+            if !items.public.contains(item) {
+                return Err(format!(
+                    "'{}' is private to module '{}', mark it 'pub' to use it from {}",
+                    item,
+                    module,
+                    self.path.display()
+                ));
+            }
+            return Ok(format!("{}.{}", module, item));
+        }
+        match self.namespace {
+            Some((module, items)) if items.all.contains(name) && !locals.contains(name) => {
+                Ok(format!("{}.{}", module, name))
+            }
+            _ => Ok(name.to_string()),
+        }
+    }
+
+    // Struct types can be qualified too, e.g. `shapes::Point*` or `dyn shapes::Shape`, and
+    // so can each type argument of a generic one and the types of a `Func(...)`
+    fn qualify_type(&self, tp: &mut String) -> Result<(), String> {
+        if let Some(interface) = tp.strip_prefix("dyn ") {
+            *tp = format!("dyn {}", self.qualify(interface, &HashSet::new())?);
+            return Ok(());
+        }
+        if let Some((mut params, mut ret)) = split_function_type(tp) {
+            for param in params.iter_mut() {
+                self.qualify_type(param)?;
+            }
+            self.qualify_type(&mut ret)?;
+            *tp = format!("Func({}) {}", params.join(", "), ret);
+            return Ok(());
+        }
+        if let Some(types) = tp.strip_prefix('(').and_then(|tp| tp.strip_suffix(')')) {
+            let mut types = split_types(types);
+            for tp in types.iter_mut() {
+                self.qualify_type(tp)?;
+            }
+            *tp = format!("({})", types.join(", "));
+            return Ok(());
+        }
+        let base = tp.trim_end_matches('*');
+        let pointers = &tp[base.len()..];
+        let qualified = match split_generic(base) {
+            Some((name, mut args)) => {
+                for arg in args.iter_mut() {
+                    self.qualify_type(arg)?;
+                }
+                let name = self.qualify(name, &HashSet::new())?;
+                format!("{}<{}>", name, args.join(", "))
+            }
+            None => self.qualify(base, &HashSet::new())?,
+        };
+        *tp = format!("{}{}", qualified, pointers);
+        Ok(())
+    }
+
+    fn rewrite(&self, node: &mut AstNode, locals: &HashSet<String>) -> Result<(), String> {
+        match node {
+            AstNode::Import { .. } => {
+                return Err(format!(
+                    "Imports must be at the top level of {}",
+                    self.path.display()
+                ))
+            }
+            AstNode::Public { .. } => {
+                return Err(format!(
+                    "'pub' items must be at the top level of {}",
+                    self.path.display()
+                ))
+            }
+            AstNode::Function {
+                name,
+                arguments,
+                return_type,
+                body,
+                ..
+            } => {
+                *name = self.qualify(name, &HashSet::new())?;
+                for (tp, _) in arguments.iter_mut() {
+                    self.qualify_type(tp)?;
+                }
+                self.qualify_type(return_type)?;
+                let mut locals: HashSet<String> =
+                    arguments.iter().map(|(_, arg)| arg.clone()).collect();
+                collect_locals(body, &mut locals);
+                return self.rewrite(body, &locals);
+            }
+            // Sees the locals of the function it is in as well as its own
+            AstNode::Lambda {
+                arguments,
+                return_type,
+                body,
+                ..
+            } => {
+                for (tp, _) in arguments.iter_mut() {
+                    self.qualify_type(tp)?;
+                }
+                self.qualify_type(return_type)?;
+                let mut locals = locals.clone();
+                locals.extend(arguments.iter().map(|(_, arg)| arg.clone()));
+                collect_locals(body, &mut locals);
+                return self.rewrite(body, &locals);
+            }
+            AstNode::Struct { name, fields } => {
+                *name = self.qualify(name, &HashSet::new())?;
+                for (tp, _) in fields.iter_mut() {
+                    self.qualify_type(tp)?;
+                }
+            }
+            AstNode::Class {
+                name,
+                parent,
+                fields,
+                ..
+            } => {
+                *name = self.qualify(name, &HashSet::new())?;
+                if let Some(parent) = parent {
+                    self.qualify_type(parent)?;
+                }
+                for (tp, _) in fields.iter_mut() {
+                    self.qualify_type(tp)?;
+                }
+            }
+            AstNode::Interface { name, methods } => {
+                *name = self.qualify(name, &HashSet::new())?;
+                for (_, params, return_type) in methods.iter_mut() {
+                    for tp in params.iter_mut() {
+                        self.qualify_type(tp)?;
+                    }
+                    self.qualify_type(return_type)?;
+                }
+            }
+            AstNode::Impl {
+                interface, target, ..
+            } => {
+                *interface = self.qualify(interface, &HashSet::new())?;
+                self.qualify_type(target)?;
+            }
+            AstNode::New { class, .. } | AstNode::SuperCall { class, .. } => {
+                self.qualify_type(class)?;
+            }
+            // The parameters are local to the item, only their bounds are qualified
+            AstNode::Generic { params, .. } => {
+                for bound in params.iter_mut().filter_map(|(_, bound)| bound.as_mut()) {
+                    *bound = self.qualify(bound, &HashSet::new())?;
+                }
+            }
+            // Outside a function, an assignment defines a global
+            AstNode::Assignment { variable, tp, .. } => {
+                *variable = self.qualify(variable, locals)?;
+                if let Some(tp) = tp {
+                    self.qualify_type(tp)?;
+                }
+            }
+            AstNode::Uninit { tp } => self.qualify_type(tp)?,
+            AstNode::Identifier { value } | AstNode::Variable { value } => {
+                *value = self.qualify(value, locals)?;
+            }
+            // A local holding a function value hides a function of its name
+            AstNode::FunctionCall { name, .. } => {
+                *name = self.qualify(name, locals)?;
+            }
+            _ => {}
+        }
+        for child in node.children_mut() {
+            self.rewrite(child, locals)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::interpret;
+    use crate::transpiler::ir::Instruction;
+    use crate::{compile_with_loader, CompileOptions, CompileOutput};
+    use std::path::Path;
+
+    const CONSTS: &str =
+        "pub let LIMIT = 42\nlet step = 2\npub func next(n Int) Int |\n    return n + step\n|\n";
+
+    // Compiles main.tsl, whose imports are looked up in modules by file name
+    fn compile_main(
+        source: &str,
+        modules: &'static [(&'static str, &'static str)],
+    ) -> Result<CompileOutput, String> {
+        let loader = move |path: &Path| {
+            modules
+                .iter()
+                .find(|(name, _)| Path::new(name) == path)
+                .map(|(_, source)| source.to_string())
+                .ok_or_else(|| "No such file".to_string())
+        };
+        compile_with_loader(
+            source,
+            Path::new("main.tsl"),
+            loader,
+            &CompileOptions::default(),
+        )
+        .map_err(|errors| errors[0].to_string())
+    }
+
+    // What main returns when the program is run on the interpreter
+    fn status(output: &CompileOutput) -> i32 {
+        interpret(output.ast.as_ref().unwrap()).unwrap()
+    }
+
+    fn error(source: &str, modules: &'static [(&'static str, &'static str)]) -> String {
+        match compile_main(source, modules) {
+            Ok(_) => panic!("{:?} compiled", source),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn pub_global_is_read_across_modules() {
+        let output = compile_main(
+            "import consts\nfunc main() Int |\n    return consts::LIMIT\n|\n",
+            &[("consts.tsl", CONSTS)],
+        )
+        .unwrap();
+        assert_eq!(status(&output), 42);
+        let module = output.module.unwrap();
+        let global = module
+            .globals
+            .iter()
+            .find(|g| g.var.name == "consts.LIMIT")
+            .unwrap();
+        assert!(global.public);
+        let main = module.functions.iter().find(|f| f.name == "main").unwrap();
+        assert!(main.blocks[0]
+            .instructions
+            .iter()
+            .any(|instruction| matches!(
+                instruction,
+                Instruction::Load { var, .. } if var.name == "consts.LIMIT"
+            )));
+    }
+
+    #[test]
+    fn pub_let_global_cant_be_assigned_from_another_module() {
+        assert_eq!(
+            error(
+                "import consts\nfunc main() Int |\n    consts::LIMIT = 3\n    return 0\n|\n",
+                &[("consts.tsl", CONSTS)],
+            ),
+            "Type error: Can't reassign 'consts.LIMIT', it is an immutable binding, declare it with 'let mut LIMIT' to change it"
+        );
+    }
+
+    #[test]
+    fn private_global_is_read_in_its_module() {
+        let output = compile_main(
+            "import consts\nfunc main() Int |\n    return consts::next(1)\n|\n",
+            &[("consts.tsl", CONSTS)],
+        )
+        .unwrap();
+        assert_eq!(status(&output), 3);
+        let module = output.module.unwrap();
+        let step = module
+            .globals
+            .iter()
+            .find(|g| g.var.name == "consts.step")
+            .unwrap();
+        assert!(!step.public);
+    }
+
+    #[test]
+    fn private_global_is_hidden_from_other_modules() {
+        assert_eq!(
+            error(
+                "import consts\nfunc main() Int |\n    return consts::step\n|\n",
+                &[("consts.tsl", CONSTS)],
+            ),
+            "Import error: 'step' is private to module 'consts', mark it 'pub' to use it from main.tsl"
+        );
+    }
+
+    #[test]
+    fn modules_imported_twice_are_loaded_once() {
+        let output = compile_main(
+            "import consts\nimport twice\nfunc main() Int |\n    return twice::twice() + consts::LIMIT\n|\n",
+            &[
+                ("consts.tsl", CONSTS),
+                (
+                    "twice.tsl",
+                    "import consts\npub func twice() Int |\n    return consts::LIMIT * 2\n|\n",
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(status(&output), 126);
+        assert_eq!(output.files.len(), 3);
+    }
+
+    #[test]
+    fn import_cycle_is_an_error() {
+        assert_eq!(
+            error(
+                "import a\nfunc main() Int |\n    return 0\n|\n",
+                &[
+                    ("a.tsl", "import b\npub func fa() Int |\n    return 1\n|\n"),
+                    ("b.tsl", "import a\npub func fb() Int |\n    return 2\n|\n"),
+                ],
+            ),
+            "Import error: Import cycle: a.tsl -> b.tsl -> a.tsl"
+        );
+    }
+
+    #[test]
+    fn missing_module_is_an_error() {
+        assert_eq!(
+            error("import nope\nfunc main() Int |\n    return 0\n|\n", &[]),
+            "Import error: Failed to import nope.tsl: No such file"
+        );
+    }
+
+    #[test]
+    fn missing_item_is_an_error() {
+        assert_eq!(
+            error(
+                "import consts\nfunc main() Int |\n    return consts::nope\n|\n",
+                &[("consts.tsl", CONSTS)],
+            ),
+            "Import error: Module 'consts' has no item named 'nope'"
+        );
+    }
+
+    #[test]
+    fn module_that_isnt_imported_is_an_error() {
+        assert_eq!(
+            error(
+                "func main() Int |\n    return consts::LIMIT\n|\n",
+                &[("consts.tsl", CONSTS)],
+            ),
+            "Import error: Module 'consts' is not imported in main.tsl"
+        );
+    }
+}
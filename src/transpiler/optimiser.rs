@@ -1 +1,1147 @@
+// IR optimisations. Inlining and folding run with -O, dead code elimination always runs.
+// Each one is a pass in passes.rs, which runs the verifier after it in debug builds.
 
+use super::ir::*;
+use super::type_checker::Type;
+use std::collections::{HashMap, HashSet};
+
+// Callees with at most this many instructions are inlined without needing @inline
+const INLINE_THRESHOLD: usize = 12;
+// Stops a caller growing without bound when every callee is marked @inline
+const MAX_INLINES_PER_FUNCTION: usize = 64;
+
+// Inlines calls to small functions and to functions marked @inline. Functions marked
+// @noinline and functions that can call themselves (directly or not) are never inlined.
+// Arguments are already evaluated, in order, before the call instruction, so copying them
+// into the callee's parameters keeps the original evaluation order.
+pub fn inline_functions(module: &mut Module) {
+    let recursive = recursive_functions(module);
+    let callees: HashMap<String, Function> = module
+        .functions
+        .iter()
+        .filter(|function| {
+            !recursive.contains(&function.name)
+                && !function.has_attribute("noinline")
+                && (function.has_attribute("inline") || size(function) <= INLINE_THRESHOLD)
+        })
+        .map(|function| (function.name.clone(), function.clone()))
+        .collect();
+    for function in &mut module.functions {
+        let mut inlined = 0;
+        while inlined < MAX_INLINES_PER_FUNCTION {
+            let call = function.blocks.iter().enumerate().find_map(|(b, block)| {
+                block
+                    .instructions
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, instruction)| match instruction {
+                        Instruction::Call { function, .. } if callees.contains_key(function) => {
+                            Some((b, i))
+                        }
+                        _ => None,
+                    })
+            });
+            let Some((block, index)) = call else {
+                break;
+            };
+            inline_call(function, block, index, &callees, inlined);
+            inlined += 1;
+        }
+        function.remove_unreachable_blocks();
+    }
+}
+
+fn size(function: &Function) -> usize {
+    function
+        .blocks
+        .iter()
+        .map(|block| block.instructions.len() + 1)
+        .sum()
+}
+
+fn calls(function: &Function) -> impl Iterator<Item = &String> {
+    function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instruction| match instruction {
+            Instruction::Call { function, .. } => Some(function),
+            _ => None,
+        })
+}
+
+// Functions that can reach themselves through the call graph
+fn recursive_functions(module: &Module) -> HashSet<String> {
+    let graph: HashMap<&String, Vec<&String>> = module
+        .functions
+        .iter()
+        .map(|function| (&function.name, calls(function).collect()))
+        .collect();
+    let mut recursive = HashSet::new();
+    for start in graph.keys() {
+        let mut seen = HashSet::new();
+        let mut stack = graph[start].clone();
+        while let Some(name) = stack.pop() {
+            if name == *start {
+                recursive.insert(name.clone());
+                break;
+            }
+            if seen.insert(name) {
+                stack.extend(graph.get(name).into_iter().flatten());
+            }
+        }
+    }
+    recursive
+}
+
+// Replaces the call at `index` in `block` with a copy of the callee's blocks.
+// The block is split in two around the call: the first half stores the arguments into
+// the callee's parameters and jumps into the copied body, and every return in the body
+// stores its value and jumps to the second half, which loads it into the call's result.
+fn inline_call(
+    caller: &mut Function,
+    block: usize,
+    index: usize,
+    callees: &HashMap<String, Function>,
+    count: usize,
+) {
+    let Instruction::Call {
+        dest,
+        function,
+        arguments,
+    } = caller.blocks[block].instructions[index].clone()
+    else {
+        return;
+    };
+    let callee = &callees[&function];
+
+    // Every name and number in the callee is made fresh so it can't clash with the caller's
+    let rename = |name: &str| -> String {
+        format!(
+            "{}::_i{}_{}",
+            caller.name,
+            count,
+            name.split("::").last().unwrap()
+        )
+    };
+    let is_callee_var = |name: &str| {
+        callee
+            .params
+            .iter()
+            .chain(callee.locals.iter())
+            .any(|var| var.name == name)
+    };
+    let fix_var = |var: &mut Var| {
+        if is_callee_var(&var.name) {
+            var.name = rename(&var.name);
+        }
+    };
+    let temp_offset = caller
+        .temps()
+        .iter()
+        .map(|temp| temp.id + 1)
+        .max()
+        .unwrap_or(0);
+    let fix_temp = |temp: &mut Temp| temp.id += temp_offset;
+    let fix_operand = |operand: &mut Operand| {
+        if let Operand::Temp(temp) = operand {
+            fix_temp(temp);
+        }
+    };
+    let block_offset = caller.blocks.iter().map(|b| b.id.0 + 1).max().unwrap_or(0);
+    let fix_block = |id: &mut BlockId| id.0 += block_offset;
+    let continuation =
+        BlockId(block_offset + callee.blocks.iter().map(|b| b.id.0 + 1).max().unwrap_or(0));
+
+    let params: Vec<Var> = callee
+        .params
+        .iter()
+        .map(|param| Var {
+            name: rename(&param.name),
+            tp: param.tp.clone(),
+        })
+        .collect();
+    caller.locals.extend(params.iter().cloned());
+    caller.locals.extend(callee.locals.iter().map(|local| Var {
+        name: rename(&local.name),
+        tp: local.tp.clone(),
+    }));
+    let result = dest.as_ref().map(|_| Var {
+        name: rename("result"),
+        tp: callee.return_type.clone(),
+    });
+    if let Some(result) = &result {
+        caller.locals.push(result.clone());
+    }
+
+    let mut body = callee.blocks.clone();
+    for callee_block in &mut body {
+        fix_block(&mut callee_block.id);
+        for instruction in &mut callee_block.instructions {
+            match instruction {
+                Instruction::Load { dest, var } | Instruction::AddressOf { dest, var } => {
+                    fix_temp(dest);
+                    fix_var(var);
+                }
+                Instruction::Store { var, value } => {
+                    fix_var(var);
+                    fix_operand(value);
+                }
+                Instruction::Binary {
+                    dest, left, right, ..
+                } => {
+                    fix_temp(dest);
+                    fix_operand(left);
+                    fix_operand(right);
+                }
+                Instruction::Cast { dest, value } => {
+                    fix_temp(dest);
+                    fix_operand(value);
+                }
+                Instruction::Deref { dest, pointer } => {
+                    fix_temp(dest);
+                    fix_operand(pointer);
+                }
+                Instruction::ToDyn { dest, value } => {
+                    fix_temp(dest);
+                    fix_operand(value);
+                }
+                Instruction::Call {
+                    dest, arguments, ..
+                }
+                | Instruction::CallMethod {
+                    dest, arguments, ..
+                }
+                | Instruction::CallDyn {
+                    dest, arguments, ..
+                }
+                | Instruction::CallClosure { dest, arguments } => {
+                    if let Some(dest) = dest {
+                        fix_temp(dest);
+                    }
+                    arguments.iter_mut().for_each(fix_operand);
+                }
+                Instruction::MakeClosure { dest, captures, .. }
+                | Instruction::MakeTuple {
+                    dest,
+                    elements: captures,
+                } => {
+                    fix_temp(dest);
+                    captures.iter_mut().for_each(fix_operand);
+                }
+                Instruction::GetElement { dest, tuple, .. } => {
+                    fix_temp(dest);
+                    fix_operand(tuple);
+                }
+                Instruction::MakeVariant { dest, value, .. } => {
+                    fix_temp(dest);
+                    value.iter_mut().for_each(fix_operand);
+                }
+                Instruction::IsVariant { dest, value, .. }
+                | Instruction::Unwrap { dest, value, .. } => {
+                    fix_temp(dest);
+                    fix_operand(value);
+                }
+                Instruction::New { dest, .. } => fix_temp(dest),
+                Instruction::GetField { dest, object, .. } => {
+                    fix_temp(dest);
+                    fix_operand(object);
+                }
+                Instruction::SetField { object, value, .. } => {
+                    fix_operand(object);
+                    fix_operand(value);
+                }
+            }
+        }
+        callee_block.terminator = match callee_block.terminator.clone() {
+            Terminator::Return(value) => {
+                if let (Some(result), Some(mut value)) = (&result, value) {
+                    fix_operand(&mut value);
+                    callee_block.instructions.push(Instruction::Store {
+                        var: result.clone(),
+                        value,
+                    });
+                }
+                Terminator::Jump(continuation)
+            }
+            Terminator::Jump(mut target) => {
+                fix_block(&mut target);
+                Terminator::Jump(target)
+            }
+            Terminator::Branch {
+                mut condition,
+                mut then_block,
+                mut else_block,
+            } => {
+                fix_operand(&mut condition);
+                fix_block(&mut then_block);
+                fix_block(&mut else_block);
+                Terminator::Branch {
+                    condition,
+                    then_block,
+                    else_block,
+                }
+            }
+            Terminator::Unreachable => Terminator::Unreachable,
+        };
+    }
+
+    let split = &mut caller.blocks[block];
+    let mut after = split.instructions.split_off(index);
+    after.remove(0); // The call itself
+    if let (Some(dest), Some(result)) = (dest, &result) {
+        after.insert(
+            0,
+            Instruction::Load {
+                dest,
+                var: result.clone(),
+            },
+        );
+    }
+    let terminator = std::mem::replace(
+        &mut split.terminator,
+        Terminator::Jump(BlockId(callee.blocks[0].id.0 + block_offset)),
+    );
+    for (param, argument) in params.into_iter().zip(arguments) {
+        split.instructions.push(Instruction::Store {
+            var: param,
+            value: argument,
+        });
+    }
+    caller.blocks.extend(body);
+    caller.blocks.push(BasicBlock {
+        id: continuation,
+        instructions: after,
+        terminator,
+    });
+}
+
+// Dead code elimination. Branches on constant conditions become jumps, blocks nothing
+// jumps to are dropped, and then anything main can't reach - functions, the globals
+// they don't mention and the structs, classes and interfaces only they used - is removed.
+// Creating an instance of a class reaches every function in its vtable, and making a dyn
+// every function of its impl. Locals that are stored to but never read are dropped too.
+// This is what keeps the unused parts of prelude.tsl out of the generated C, and keeps
+// it free of C compilers' unused variable warnings.
+pub fn eliminate_dead_code(module: &mut Module) {
+    for function in &mut module.functions {
+        for block in &mut function.blocks {
+            if let Terminator::Branch {
+                condition: Operand::Const(Constant::Bool(condition)),
+                then_block,
+                else_block,
+            } = block.terminator
+            {
+                let target = if condition { then_block } else { else_block };
+                block.terminator = Terminator::Jump(target);
+            }
+        }
+        function.remove_unreachable_blocks();
+        // Dropping an unused temporary's load can leave a local unread, and dropping the
+        // stores to an unread local can leave the temporaries they stored unused
+        while remove_unused_temps(function) | remove_unread_locals(function) {}
+    }
+    remove_unused_functions(module);
+    remove_unused_globals(module);
+    remove_unused_structs(module);
+}
+
+// Instructions without side effects whose result is never used, such as loads left
+// behind once folding has replaced every use of their temporary
+fn remove_unused_temps(function: &mut Function) -> bool {
+    let mut used = HashSet::new();
+    for block in &function.blocks {
+        let terminator = match &block.terminator {
+            Terminator::Return(Some(value)) => Some(value),
+            Terminator::Branch { condition, .. } => Some(condition),
+            _ => None,
+        };
+        for operand in block
+            .instructions
+            .iter()
+            .flat_map(Instruction::operands)
+            .chain(terminator)
+        {
+            if let Operand::Temp(temp) = operand {
+                used.insert(temp.id);
+            }
+        }
+    }
+    let mut removed = false;
+    for block in &mut function.blocks {
+        block.instructions.retain(|instruction| {
+            let pure = matches!(
+                instruction,
+                Instruction::Load { .. }
+                    | Instruction::AddressOf { .. }
+                    | Instruction::Binary { .. }
+                    | Instruction::Cast { .. }
+                    | Instruction::MakeTuple { .. }
+                    | Instruction::GetElement { .. }
+                    | Instruction::IsVariant { .. }
+            );
+            let unused = pure && !used.contains(&instruction.dest().unwrap().id);
+            removed |= unused;
+            !unused
+        });
+    }
+    removed
+}
+
+// Storing to a local that is never loaded, nor has its address taken, does nothing
+fn remove_unread_locals(function: &mut Function) -> bool {
+    let read: HashSet<String> = function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instruction| match instruction {
+            Instruction::Load { var, .. } | Instruction::AddressOf { var, .. } => {
+                Some(var.name.clone())
+            }
+            _ => None,
+        })
+        .collect();
+    let unread: HashSet<String> = function
+        .locals
+        .iter()
+        .filter(|local| !read.contains(&local.name))
+        .map(|local| local.name.clone())
+        .collect();
+    function
+        .locals
+        .retain(|local| !unread.contains(&local.name));
+    for block in &mut function.blocks {
+        block.instructions.retain(|instruction| {
+            !matches!(instruction, Instruction::Store { var, .. } if unread.contains(&var.name))
+        });
+    }
+    !unread.is_empty()
+}
+
+// Globals no function mentions, once the unused functions are gone. Like functions,
+// they are all kept without a main.
+fn remove_unused_globals(module: &mut Module) {
+    if !module
+        .functions
+        .iter()
+        .any(|function| function.name == "main")
+    {
+        return;
+    }
+    let mentioned: HashSet<&String> = module
+        .functions
+        .iter()
+        .flat_map(|function| &function.blocks)
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instruction| match instruction {
+            Instruction::Load { var, .. }
+            | Instruction::AddressOf { var, .. }
+            | Instruction::Store { var, .. } => Some(&var.name),
+            _ => None,
+        })
+        .collect();
+    module
+        .globals
+        .retain(|global| mentioned.contains(&global.var.name));
+}
+
+// Without a main there is no way to tell what is used, so everything is kept
+fn remove_unused_functions(module: &mut Module) {
+    if !module
+        .functions
+        .iter()
+        .any(|function| function.name == "main")
+    {
+        return;
+    }
+    let mut used = HashSet::from(["main".to_string()]);
+    let mut stack = vec!["main".to_string()];
+    while let Some(name) = stack.pop() {
+        let Some(function) = module.functions.iter().find(|f| f.name == name) else {
+            continue;
+        };
+        for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+            let reached: Vec<&String> = match instruction {
+                Instruction::Call { function, .. } | Instruction::MakeClosure { function, .. } => {
+                    vec![function]
+                }
+                Instruction::New { class, .. } => module
+                    .class(class)
+                    .map(|class| class.vtable.iter().map(|slot| &slot.function).collect())
+                    .unwrap_or_default(),
+                Instruction::ToDyn { dest, value } => match &dest.tp {
+                    Type::Dyn(interface) => module
+                        .impl_of(interface, &value.tp())
+                        .map(|i| i.methods.iter().map(|(_, function)| function).collect())
+                        .unwrap_or_default(),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            for function in reached {
+                if used.insert(function.clone()) {
+                    stack.push(function.clone());
+                }
+            }
+        }
+    }
+    module
+        .functions
+        .retain(|function| used.contains(&function.name));
+}
+
+fn remove_unused_structs(module: &mut Module) {
+    fn mention(tp: &Type, names: &mut Vec<String>) {
+        match tp {
+            Type::DataTp(name) | Type::Dyn(name) => names.push(name.clone()),
+            Type::Pointer(tp) | Type::ConstPointer(tp) | Type::Option(tp) => mention(tp, names),
+            Type::Result(tp, error) => {
+                mention(tp, names);
+                mention(error, names);
+            }
+            Type::Function(params, return_type) => {
+                for tp in params.iter().chain(std::iter::once(return_type.as_ref())) {
+                    mention(tp, names);
+                }
+            }
+            Type::Tuple(types) => {
+                for tp in types {
+                    mention(tp, names);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut mentioned = Vec::new();
+    for global in &module.globals {
+        mention(&global.var.tp, &mut mentioned);
+    }
+    for instruction in module
+        .functions
+        .iter()
+        .flat_map(|function| &function.blocks)
+        .flat_map(|block| &block.instructions)
+    {
+        match instruction {
+            Instruction::New { class, .. } | Instruction::CallMethod { class, .. } => {
+                mentioned.push(class.clone())
+            }
+            Instruction::CallDyn { interface, .. } => mentioned.push(interface.clone()),
+            _ => {}
+        }
+    }
+    for function in &module.functions {
+        mention(&function.return_type, &mut mentioned);
+        for var in function.params.iter().chain(function.locals.iter()) {
+            mention(&var.tp, &mut mentioned);
+        }
+        for temp in function.temps() {
+            mention(&temp.tp, &mut mentioned);
+        }
+    }
+    // Fields of a used struct or class keep their own types alive, and so does a parent
+    let mut used = HashSet::new();
+    while let Some(name) = mentioned.pop() {
+        if !used.insert(name.clone()) {
+            continue;
+        }
+        if let Some(def) = module.structs.iter().find(|def| def.name == name) {
+            for field in &def.fields {
+                mention(&field.tp, &mut mentioned);
+            }
+        }
+        if let Some(class) = module.class(&name) {
+            for field in &class.fields {
+                mention(&field.tp, &mut mentioned);
+            }
+            mentioned.extend(class.parent.clone());
+            for slot in &class.vtable {
+                for tp in slot.params.iter().chain([&slot.return_type]) {
+                    mention(tp, &mut mentioned);
+                }
+            }
+        }
+        if let Some(interface) = module.interface(&name) {
+            for (_, params, return_type) in &interface.methods {
+                for tp in params.iter().chain([return_type]) {
+                    mention(tp, &mut mentioned);
+                }
+            }
+        }
+    }
+    module.structs.retain(|def| used.contains(&def.name));
+    module.classes.retain(|class| used.contains(&class.name));
+    module
+        .interfaces
+        .retain(|interface| used.contains(&interface.name));
+    module
+        .impls
+        .retain(|implementation| used.contains(&implementation.interface));
+}
+
+// Constant folding and propagation. Instructions whose operands are all constants are
+// evaluated at compile time, and their results replace every use of the temporary.
+// Locals that only ever hold one constant are propagated into their loads.
+pub fn fold_constants(module: &mut Module) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for function in &mut module.functions {
+        if let Err(e) = fold_function(function) {
+            errors.push(e);
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn fold_function(function: &mut Function) -> Result<(), String> {
+    loop {
+        let constant_locals = constant_locals(function);
+        let mut folded: HashMap<usize, Constant> = HashMap::new();
+        let mut changed = false;
+
+        for block in &mut function.blocks {
+            // Store to load forwarding within the block
+            let mut stored: HashMap<String, Constant> = HashMap::new();
+            let mut kept = Vec::new();
+            for mut instruction in std::mem::take(&mut block.instructions) {
+                substitute(&mut instruction, &folded);
+                let result = match &instruction {
+                    Instruction::Load { dest, var } => stored
+                        .get(&var.name)
+                        .or_else(|| constant_locals.get(&var.name))
+                        .cloned()
+                        .map(|constant| (dest.id, constant)),
+                    Instruction::Store { var, value } => {
+                        match value {
+                            Operand::Const(constant) => {
+                                stored.insert(var.name.clone(), constant.clone())
+                            }
+                            _ => stored.remove(&var.name),
+                        };
+                        None
+                    }
+                    Instruction::Binary {
+                        dest,
+                        op,
+                        left,
+                        right,
+                    } => {
+                        if matches!(op, BinOp::Div | BinOp::Rem)
+                            && *right == Operand::Const(Constant::Int(0))
+                        {
+                            return Err(format!(
+                                "Division by zero in function '{}'",
+                                function.name
+                            ));
+                        }
+                        fold_binary(*op, left, right).map(|constant| (dest.id, constant))
+                    }
+                    Instruction::Cast { dest, value } => {
+                        fold_cast(value, &dest.tp).map(|constant| (dest.id, constant))
+                    }
+                    // A call could write through a pointer to any local whose address was taken
+                    Instruction::Call { .. }
+                    | Instruction::CallMethod { .. }
+                    | Instruction::CallDyn { .. }
+                    | Instruction::CallClosure { .. } => {
+                        stored.clear();
+                        None
+                    }
+                    Instruction::AddressOf { var, .. } => {
+                        stored.remove(&var.name);
+                        None
+                    }
+                    Instruction::Deref { .. }
+                    | Instruction::ToDyn { .. }
+                    | Instruction::New { .. }
+                    | Instruction::GetField { .. }
+                    | Instruction::SetField { .. }
+                    | Instruction::MakeClosure { .. }
+                    | Instruction::MakeTuple { .. }
+                    | Instruction::GetElement { .. }
+                    | Instruction::MakeVariant { .. }
+                    | Instruction::IsVariant { .. }
+                    | Instruction::Unwrap { .. } => None,
+                };
+                match result {
+                    Some((id, constant)) => {
+                        folded.insert(id, constant);
+                        changed = true;
+                    }
+                    None => kept.push(instruction),
+                }
+            }
+            block.instructions = kept;
+        }
+
+        // Temporaries can be used in blocks before the one defining them, so patch every block
+        for block in &mut function.blocks {
+            for instruction in &mut block.instructions {
+                substitute(instruction, &folded);
+            }
+            match &mut block.terminator {
+                Terminator::Return(Some(value)) => substitute_operand(value, &folded),
+                Terminator::Branch { condition, .. } => substitute_operand(condition, &folded),
+                _ => {}
+            }
+        }
+
+        if !changed {
+            return Ok(());
+        }
+    }
+}
+
+// Locals that are stored to exactly once, with a constant, and never have their address taken
+fn constant_locals(function: &Function) -> HashMap<String, Constant> {
+    let mut stores: HashMap<&str, Vec<&Operand>> = HashMap::new();
+    let mut address_taken = Vec::new();
+    for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+        match instruction {
+            Instruction::Store { var, value } => stores.entry(&var.name).or_default().push(value),
+            Instruction::AddressOf { var, .. } => address_taken.push(&var.name),
+            _ => {}
+        }
+    }
+    function
+        .locals
+        .iter()
+        .filter(|local| !address_taken.contains(&&local.name))
+        .filter_map(|local| match stores.get(local.name.as_str())?.as_slice() {
+            [Operand::Const(constant)] => Some((local.name.clone(), constant.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+fn substitute_operand(operand: &mut Operand, folded: &HashMap<usize, Constant>) {
+    if let Operand::Temp(temp) = operand
+        && let Some(constant) = folded.get(&temp.id)
+    {
+        *operand = Operand::Const(constant.clone());
+    }
+}
+
+fn substitute(instruction: &mut Instruction, folded: &HashMap<usize, Constant>) {
+    match instruction {
+        Instruction::Store { value, .. }
+        | Instruction::Cast { value, .. }
+        | Instruction::Deref { pointer: value, .. }
+        | Instruction::GetField { object: value, .. }
+        | Instruction::ToDyn { value, .. }
+        | Instruction::GetElement { tuple: value, .. }
+        | Instruction::IsVariant { value, .. }
+        | Instruction::Unwrap { value, .. } => substitute_operand(value, folded),
+        Instruction::MakeVariant { value, .. } => {
+            if let Some(value) = value {
+                substitute_operand(value, folded);
+            }
+        }
+        Instruction::Binary { left, right, .. }
+        | Instruction::SetField {
+            object: left,
+            value: right,
+            ..
+        } => {
+            substitute_operand(left, folded);
+            substitute_operand(right, folded);
+        }
+        Instruction::Call { arguments, .. }
+        | Instruction::CallMethod { arguments, .. }
+        | Instruction::CallDyn { arguments, .. }
+        | Instruction::CallClosure { arguments, .. }
+        | Instruction::MakeClosure {
+            captures: arguments,
+            ..
+        }
+        | Instruction::MakeTuple {
+            elements: arguments,
+            ..
+        } => {
+            for argument in arguments {
+                substitute_operand(argument, folded);
+            }
+        }
+        Instruction::Load { .. } | Instruction::AddressOf { .. } | Instruction::New { .. } => {}
+    }
+}
+
+// C's int is 32 bits, so folding is done on i32 to give the same answer the program would
+fn as_int(constant: &Constant) -> Option<i32> {
+    match constant {
+        Constant::Int(value) => i32::try_from(*value).ok(),
+        _ => None,
+    }
+}
+
+// The code of a char constant, as long as it is a single plain character
+fn char_code(value: &str) -> Option<i32> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c != '\\' && c.is_ascii() => Some(c as i32),
+        _ => None,
+    }
+}
+
+fn fold_binary(op: BinOp, left: &Operand, right: &Operand) -> Option<Constant> {
+    use Constant::*;
+    let (left, right) = match (left, right) {
+        (Operand::Const(left), Operand::Const(right)) => (left, right),
+        // `false && x` and `true || x` don't depend on x, which has already been evaluated
+        (Operand::Const(Bool(value)), _) | (_, Operand::Const(Bool(value)))
+            if (op == BinOp::And && !value) || (op == BinOp::Or && *value) =>
+        {
+            return Some(Bool(*value))
+        }
+        _ => return None,
+    };
+    match (left, right) {
+        (Int(_), Int(_)) => {
+            let (a, b) = (as_int(left)?, as_int(right)?);
+            let result = match op {
+                BinOp::Add => Int(a.wrapping_add(b) as i128),
+                BinOp::Sub => Int(a.wrapping_sub(b) as i128),
+                BinOp::Mul => Int(a.wrapping_mul(b) as i128),
+                BinOp::Div => Int(a.checked_div(b)? as i128),
+                BinOp::Rem => Int(a.checked_rem(b)? as i128),
+                BinOp::Eq => Bool(a == b),
+                BinOp::Ne => Bool(a != b),
+                BinOp::Lt => Bool(a < b),
+                BinOp::Gt => Bool(a > b),
+                BinOp::Le => Bool(a <= b),
+                BinOp::Ge => Bool(a >= b),
+                BinOp::And | BinOp::Or => return None,
+            };
+            Some(result)
+        }
+        (Bool(a), Bool(b)) => match op {
+            BinOp::Eq => Some(Bool(a == b)),
+            BinOp::Ne => Some(Bool(a != b)),
+            BinOp::And => Some(Bool(*a && *b)),
+            BinOp::Or => Some(Bool(*a || *b)),
+            _ => None,
+        },
+        (Char(a), Char(b)) => {
+            let (a, b) = (char_code(a)?, char_code(b)?);
+            match op {
+                BinOp::Eq => Some(Bool(a == b)),
+                BinOp::Ne => Some(Bool(a != b)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// asInt, asChar and boolToInt on literals
+fn fold_cast(value: &Operand, tp: &Type) -> Option<Constant> {
+    let Operand::Const(constant) = value else {
+        return None;
+    };
+    match (constant, tp) {
+        (Constant::Char(value), Type::Integer) => {
+            char_code(value).map(|code| Constant::Int(code as i128))
+        }
+        (Constant::Bool(value), Type::Integer) => Some(Constant::Int(*value as i128)),
+        (Constant::Int(_), Type::Integer) | (Constant::Char(_), Type::Char) => {
+            Some(constant.clone())
+        }
+        (Constant::Int(value), Type::Char) => {
+            // Only printable characters can be written back out as a char literal
+            let c = char::from_u32(u32::try_from(*value).ok()?)?;
+            (c.is_ascii_graphic() || c == ' ')
+                .then_some(c)
+                .filter(|c| *c != '\'' && *c != '\\')
+                .map(|c| Constant::Char(c.to_string()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{eliminate_dead_code, fold_constants, inline_functions, INLINE_THRESHOLD};
+    use crate::transpiler::ir::{Constant, Instruction, Module, Operand, Terminator};
+    use crate::transpiler::ir_text::parse_module;
+    use crate::transpiler::verifier::verify_module;
+
+    // Inlines into every function of the module in text, checking the result still verifies
+    fn inlined(text: &str) -> Module {
+        let mut module = parse_module(text).unwrap();
+        inline_functions(&mut module);
+        verify_module(&module).unwrap();
+        module
+    }
+
+    // The functions the named function still calls, in order
+    fn calls_in<'a>(module: &'a Module, name: &str) -> Vec<&'a str> {
+        let function = module.functions.iter().find(|f| f.name == name).unwrap();
+        super::calls(function).map(String::as_str).collect()
+    }
+
+    // A function returning Int with the given attributes, adding one to its argument n times
+    fn adder(name: &str, attributes: &str, n: usize) -> String {
+        let mut text = format!(
+            "{}func {}({}::x: Int) -> Int {{\nbb0:\n",
+            attributes, name, name
+        );
+        text += &format!("    %0: Int = load {}::x\n", name);
+        for i in 1..=n {
+            text += &format!("    %{}: Int = add %{}, 1\n", i, i - 1);
+        }
+        text + &format!("    ret %{}\n}}\n", n)
+    }
+
+    const MAIN_CALLING_F: &str =
+        "pub func main() -> Int {\nbb0:\n    %0: Int = call f(1)\n    ret %0\n}\n";
+
+    #[test]
+    fn small_functions_are_inlined() {
+        let module = inlined(&(adder("f", "", 2) + MAIN_CALLING_F));
+        assert!(calls_in(&module, "main").is_empty());
+    }
+
+    #[test]
+    fn functions_over_the_threshold_are_only_inlined_with_the_attribute() {
+        let module = inlined(&(adder("f", "", INLINE_THRESHOLD) + MAIN_CALLING_F));
+        assert_eq!(calls_in(&module, "main"), ["f"]);
+        let module = inlined(&(adder("f", "@inline\n", INLINE_THRESHOLD) + MAIN_CALLING_F));
+        assert!(calls_in(&module, "main").is_empty());
+    }
+
+    #[test]
+    fn noinline_functions_are_never_inlined() {
+        let module = inlined(&(adder("f", "@noinline\n", 1) + MAIN_CALLING_F));
+        assert_eq!(calls_in(&module, "main"), ["f"]);
+    }
+
+    #[test]
+    fn recursive_functions_are_never_inlined() {
+        let module = inlined(
+            &("func f(f::x: Int) -> Int {\nbb0:\n    %0: Int = call f(1)\n    ret %0\n}\n"
+                .to_string()
+                + MAIN_CALLING_F),
+        );
+        assert_eq!(calls_in(&module, "main"), ["f"]);
+        assert_eq!(calls_in(&module, "f"), ["f"]);
+    }
+
+    #[test]
+    fn mutually_recursive_functions_are_never_inlined() {
+        let module = inlined(
+            &("func f(f::x: Int) -> Int {\nbb0:\n    %0: Int = call g(1)\n    ret %0\n}\nfunc g(g::x: Int) -> Int {\nbb0:\n    %0: Int = call f(1)\n    ret %0\n}\n"
+                .to_string()
+                + MAIN_CALLING_F),
+        );
+        assert_eq!(calls_in(&module, "main"), ["f"]);
+        assert_eq!(calls_in(&module, "f"), ["g"]);
+        assert_eq!(calls_in(&module, "g"), ["f"]);
+    }
+
+    #[test]
+    fn arguments_are_evaluated_in_order_before_the_inlined_body() {
+        let module = inlined(
+            &(adder("first", "@noinline\n", 1)
+                + &adder("second", "@noinline\n", 1)
+                + "func pair(pair::a: Int, pair::b: Int) -> Int {\nbb0:\n    %0: Int = load pair::a\n    %1: Int = load pair::b\n    %2: Int = sub %0, %1\n    ret %2\n}\npub func main() -> Int {\nbb0:\n    %0: Int = call first(1)\n    %1: Int = call second(2)\n    %2: Int = call pair(%0, %1)\n    ret %2\n}\n"),
+        );
+        assert_eq!(calls_in(&module, "main"), ["first", "second"]);
+        let entry = &module.functions[3].blocks[0];
+        let stores: Vec<(&str, &Operand)> = entry
+            .instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Store { var, value } => Some((var.name.as_str(), value)),
+                _ => None,
+            })
+            .collect();
+        let [(a, first), (b, second)] = stores[..] else {
+            panic!("{:?} should store both arguments", entry.instructions);
+        };
+        assert!(a.ends_with("_a") && b.ends_with("_b"), "{} {}", a, b);
+        assert_eq!([first, second].map(ToString::to_string), ["%0", "%1"]);
+    }
+
+    fn eliminated(text: &str) -> Module {
+        let mut module = parse_module(text).unwrap();
+        eliminate_dead_code(&mut module);
+        module
+    }
+
+    fn function_names(module: &Module) -> Vec<&str> {
+        module.functions.iter().map(|f| f.name.as_str()).collect()
+    }
+
+    #[test]
+    fn branch_on_a_constant_becomes_a_jump_and_the_other_side_is_dropped() {
+        let module = eliminated(
+            "func main() -> Int {\nbb0:\n    branch false, bb1, bb2\nbb1:\n    ret 1\nbb2:\n    ret 2\n}\n",
+        );
+        let blocks = &module.functions[0].blocks;
+        assert_eq!(blocks.len(), 2);
+        let Terminator::Jump(target) = blocks[0].terminator else {
+            panic!("{:?} is not a jump", blocks[0].terminator);
+        };
+        let target = blocks.iter().find(|block| block.id == target).unwrap();
+        assert_eq!(
+            target.terminator,
+            Terminator::Return(Some(Operand::Const(Constant::Int(2))))
+        );
+    }
+
+    #[test]
+    fn block_nothing_jumps_to_is_dropped() {
+        let module = eliminated("func main() -> Int {\nbb0:\n    ret 0\nbb1:\n    jump bb0\n}\n");
+        assert_eq!(module.functions[0].blocks.len(), 1);
+    }
+
+    #[test]
+    fn functions_main_cant_reach_are_removed() {
+        let module = eliminated(
+            "func unused() -> Int {\nbb0:\n    %0: Int = call used()\n    ret %0\n}\nfunc used() -> Int {\nbb0:\n    %0: Int = call used()\n    ret %0\n}\npub func main() -> Int {\nbb0:\n    %0: Int = call used()\n    ret %0\n}\n",
+        );
+        assert_eq!(function_names(&module), ["used", "main"]);
+    }
+
+    #[test]
+    fn every_function_is_kept_without_a_main() {
+        let module = eliminated("func unused() -> Void {\nbb0:\n    ret\n}\n");
+        assert_eq!(function_names(&module), ["unused"]);
+    }
+
+    #[test]
+    fn structs_no_remaining_function_mentions_are_removed() {
+        let module = eliminated(
+            "struct Inner {\n    n: Int\n}\nstruct Outer {\n    inner: Inner\n}\nstruct Unused {\n    n: Int\n}\nstruct OnlyInDeadCode {\n    n: Int\n}\nfunc dead(dead::p: OnlyInDeadCode*) -> Void {\nbb0:\n    ret\n}\nfunc show(show::o: Outer) -> Void {\nbb0:\n    ret\n}\npub func main() -> Int {\n    local main::o: Outer\nbb0:\n    %0: Outer = load main::o\n    call show(%0)\n    ret 0\n}\n",
+        );
+        let names: Vec<&str> = module.structs.iter().map(|def| def.name.as_str()).collect();
+        assert_eq!(names, ["Inner", "Outer"]);
+    }
+
+    #[test]
+    fn locals_that_are_never_read_are_dropped_with_their_stores() {
+        let module = eliminated(
+            "pub func main() -> Int {\n    local main::unread: Int\n    local main::read: Int\nbb0:\n    %0: Int = call next()\n    store main::unread, %0\n    store main::read, 2\n    %1: Int = load main::read\n    ret %1\n}\nfunc next() -> Int {\nbb0:\n    ret 1\n}\n",
+        );
+        let main = &module.functions[0];
+        let locals: Vec<&str> = main.locals.iter().map(|var| var.name.as_str()).collect();
+        assert_eq!(locals, ["main::read"]);
+        // The call is kept for its side effects
+        assert_eq!(main.blocks[0].instructions.len(), 3);
+        assert!(matches!(
+            main.blocks[0].instructions[0],
+            Instruction::Call { .. }
+        ));
+    }
+
+    #[test]
+    fn unused_results_are_dropped_along_with_what_they_read() {
+        let module = eliminated(
+            "pub func main() -> Int {\n    local main::n: Int\nbb0:\n    store main::n, 1\n    %0: Int = load main::n\n    %1: Int = add %0, 1\n    %2: Bool = lt %1, 3\n    ret 0\n}\n",
+        );
+        let main = &module.functions[0];
+        assert!(main.locals.is_empty());
+        assert!(main.blocks[0].instructions.is_empty());
+    }
+
+    #[test]
+    fn globals_no_function_mentions_are_removed() {
+        let module = eliminated(
+            "global used: Int = 1\nglobal unused: Int = 2\nglobal only_in_dead_code: Int = 3\nfunc dead() -> Int {\nbb0:\n    %0: Int = load only_in_dead_code\n    ret %0\n}\npub func main() -> Int {\nbb0:\n    %0: Int = load used\n    ret %0\n}\n",
+        );
+        let globals: Vec<&str> = module.globals.iter().map(|g| g.var.name.as_str()).collect();
+        assert_eq!(globals, ["used"]);
+    }
+
+    // Folds a function f returning tp whose single block is body
+    fn fold(tp: &str, body: &str) -> Result<Module, String> {
+        let text = format!("func f(f::x: Int) -> {} {{\nbb0:\n{}\n}}\n", tp, body);
+        let mut module = parse_module(&text).unwrap();
+        fold_constants(&mut module).map(|_| module)
+    }
+
+    // What f returns once folded, which has to be a constant
+    fn folded(tp: &str, body: &str) -> Constant {
+        let module = fold(tp, body).unwrap();
+        let block = &module.functions[0].blocks[0];
+        assert!(block.instructions.is_empty(), "{:?}", block.instructions);
+        match &block.terminator {
+            Terminator::Return(Some(Operand::Const(constant))) => constant.clone(),
+            terminator => panic!("{:?} doesn't return a constant", terminator),
+        }
+    }
+
+    #[test]
+    fn arithmetic_is_folded_through_temps() {
+        let body = "%0: Int = add 2, 3\n%1: Int = mul %0, 4\n%2: Int = sub %1, 6\n%3: Int = div %2, 4\nret %3";
+        assert_eq!(folded("Int", body), Constant::Int(3));
+    }
+
+    #[test]
+    fn arithmetic_wraps_like_a_c_int() {
+        let body = "%0: Int = add 2147483647, 1\nret %0";
+        assert_eq!(folded("Int", body), Constant::Int(-2147483648));
+    }
+
+    #[test]
+    fn comparisons_are_folded() {
+        assert_eq!(
+            folded("Bool", "%0: Bool = lt 1, 2\nret %0"),
+            Constant::Bool(true)
+        );
+        assert_eq!(
+            folded("Bool", "%0: Bool = ne 'a', 'a'\nret %0"),
+            Constant::Bool(false)
+        );
+    }
+
+    #[test]
+    fn casts_of_literals_are_folded() {
+        assert_eq!(
+            folded("Int", "%0: Int = cast 'A'\nret %0"),
+            Constant::Int(65)
+        );
+        assert_eq!(
+            folded("Char", "%0: Char = cast 66\nret %0"),
+            Constant::Char("B".to_string())
+        );
+        assert_eq!(
+            folded("Int", "%0: Int = cast true\nret %0"),
+            Constant::Int(1)
+        );
+    }
+
+    #[test]
+    fn cast_to_an_unprintable_char_is_kept() {
+        let module = fold("Char", "%0: Char = cast 10\nret %0").unwrap();
+        assert_eq!(module.functions[0].blocks[0].instructions.len(), 1);
+    }
+
+    #[test]
+    fn local_stored_once_is_propagated() {
+        let text = "func f() -> Int {\n    local f::n: Int\nbb0:\n    store f::n, 5\n    jump bb1\nbb1:\n    %0: Int = load f::n\n    %1: Int = add %0, 1\n    ret %1\n}\n";
+        let mut module = parse_module(text).unwrap();
+        fold_constants(&mut module).unwrap();
+        assert_eq!(
+            module.functions[0].blocks[1].terminator,
+            Terminator::Return(Some(Operand::Const(Constant::Int(6))))
+        );
+    }
+
+    #[test]
+    fn operands_that_arent_constant_are_kept() {
+        let module = fold("Int", "%0: Int = load f::x\n%1: Int = add %0, 1\nret %1").unwrap();
+        assert_eq!(module.functions[0].blocks[0].instructions.len(), 2);
+    }
+
+    #[test]
+    fn division_by_a_constant_zero_is_an_error() {
+        assert_eq!(
+            fold("Int", "%0: Int = load f::x\n%1: Int = div %0, 0\nret %1").unwrap_err(),
+            "Division by zero in function 'f'"
+        );
+    }
+}
@@ -1,18 +1,34 @@
 use super::type_checker::Type;
 use crate::parser::*;
-use crate::transpiler::type_checker::{self, *};
-use crate::types::*;
+use crate::transpiler::type_checker::*;
 use std::collections::HashMap;
 
-pub type FunctionSignatures = HashMap<String, HashMap<String, (Vec<Type>, Type)>>;
+// The overloads of each function, by their new names, in the order they are defined
+pub type FunctionSignatures = HashMap<String, Vec<(String, (Vec<Type>, Type))>>;
 
 pub fn static_dispatch(ast: &mut AstNode, checker: TypeChecker) -> Result<(), String> {
     let mut function_counter: HashMap<String, usize> = HashMap::new();
     let mut function_signatures: FunctionSignatures = HashMap::new();
 
-    // First pass: Count function definitions and store return types
-    fn count_function_definitions(
-        node: &AstNode,
+    // First pass: Count function definitions
+    fn count_function_definitions(node: &AstNode, function_counter: &mut HashMap<String, usize>) {
+        match node {
+            AstNode::Function { name, .. } => {
+                *function_counter.entry(name.clone()).or_insert(0) += 1;
+            }
+            AstNode::Block { statements } => {
+                for stmt in statements {
+                    count_function_definitions(stmt, function_counter);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Second pass: Rename functions that have multiple definitions and store the
+    // signature of every definition
+    fn rename_functions(
+        node: &mut AstNode,
         function_counter: &mut HashMap<String, usize>,
         function_signatures: &mut FunctionSignatures,
     ) {
@@ -20,180 +36,257 @@ pub fn static_dispatch(ast: &mut AstNode, checker: TypeChecker) -> Result<(), St
             AstNode::Function {
                 name,
                 arguments,
+                body,
                 return_type,
                 ..
             } => {
-                *function_counter.entry(name.clone()).or_insert(0) += 1;
                 let arg_types: Vec<Type> = arguments
                     .iter()
                     .map(|(ty, _)| Type::from(ty.clone()))
                     .collect();
                 let ret_type = Type::from(return_type.clone());
+                let mut new_name = name.clone();
+                if let Some(&count) = function_counter.get(name)
+                    && count > 1
+                {
+                    new_name = format!("{}_{}", name, count - 1);
+                    *function_counter.get_mut(name).unwrap() -= 1;
+                }
                 function_signatures
                     .entry(name.clone())
                     .or_default()
-                    .insert(name.clone(), (arg_types, ret_type));
+                    .push((new_name.clone(), (arg_types, ret_type)));
+                *name = new_name;
+                rename_functions(body, function_counter, function_signatures);
             }
             AstNode::Block { statements } => {
                 for stmt in statements {
-                    count_function_definitions(stmt, function_counter, function_signatures);
+                    rename_functions(stmt, function_counter, function_signatures);
                 }
             }
             _ => {}
         }
     }
 
-    // Check if functions with the same name have the same return type
-    fn check_return_types(function_signatures: &FunctionSignatures) -> Result<(), String> {
-        for (name, signatures) in function_signatures {
-            if signatures.len() > 1 {
-                let first_return_type = &signatures.values().next().unwrap().1;
-                for (_, (_, return_type)) in signatures {
-                    if return_type != first_return_type {
-                        return Err(format!(
-                            "Function '{}' has multiple definitions with different return types",
-                            name
-                        ));
-                    }
-                }
-            }
-        }
-        Ok(())
-    }
+    // First pass: Count function definitions
+    count_function_definitions(ast, &mut function_counter);
 
     // Second pass: Rename functions that have multiple definitions
-    fn rename_functions(
-        node: &mut AstNode,
-        function_counter: &mut HashMap<String, usize>,
-        function_signatures: &mut FunctionSignatures,
-    ) {
+    rename_functions(ast, &mut function_counter, &mut function_signatures);
+
+    // Third pass: Rename function calls
+    rename_function_calls(ast, &function_signatures, &checker, &None, "");
+
+    Ok(())
+}
+
+// The overloads of the parts of a program dispatched so far, to dispatch one part at a
+// time like the REPL does. A definition with the signature of a known one replaces it and
+// takes its name, any other overload gets a name of its own, `name_1` and so on.
+#[derive(Clone, Default)]
+pub struct Overloads {
+    signatures: FunctionSignatures,
+}
+
+impl Overloads {
+    pub fn dispatch(&mut self, ast: &mut AstNode, checker: &TypeChecker) {
+        self.name_definitions(ast);
+        rename_function_calls(ast, &self.signatures, checker, &None, "");
+    }
+
+    fn name_definitions(&mut self, node: &mut AstNode) {
         match node {
             AstNode::Function {
                 name,
                 arguments,
-                body,
                 return_type,
+                body,
+                ..
             } => {
-                if let Some(&count) = function_counter.get(name) {
-                    if count > 1 {
-                        let arg_types: Vec<Type> = arguments
-                            .iter()
-                            .map(|(ty, _)| Type::from(ty.clone()))
-                            .collect();
-                        let ret_type = Type::from(return_type.clone());
-                        let new_name = format!("{}_{}", name, function_counter[name] - 1);
-                        *function_counter.get_mut(name).unwrap() -= 1;
-                        function_signatures
-                            .entry(name.clone())
-                            .or_default()
-                            .insert(new_name.clone(), (arg_types, ret_type));
-                        *name = new_name;
+                let signature: (Vec<Type>, Type) = (
+                    arguments
+                        .iter()
+                        .map(|(ty, _)| Type::from(ty.clone()))
+                        .collect(),
+                    Type::from(return_type.clone()),
+                );
+                let overloads = self.signatures.entry(name.clone()).or_default();
+                match overloads.iter().position(|(_, known)| *known == signature) {
+                    Some(index) => *name = overloads[index].0.clone(),
+                    None => {
+                        if !overloads.is_empty() {
+                            let mut number = overloads.len();
+                            while overloads
+                                .iter()
+                                .any(|(known, _)| *known == format!("{}_{}", name, number))
+                            {
+                                number += 1;
+                            }
+                            *name = format!("{}_{}", name, number);
+                        }
+                        overloads.push((name.clone(), signature));
                     }
                 }
-                rename_functions(body, function_counter, function_signatures);
+                self.name_definitions(body);
             }
             AstNode::Block { statements } => {
-                for stmt in statements {
-                    rename_functions(stmt, function_counter, function_signatures);
+                for statement in statements {
+                    self.name_definitions(statement);
                 }
             }
             _ => {}
         }
     }
+}
 
-    // Third pass: Rename function calls
-    fn rename_function_calls(
-        node: &mut AstNode,
-        function_signatures: &FunctionSignatures,
-        type_checker: &TypeChecker,
-        assignment_type: &Option<Type>,
-    ) {
-        match node {
-            AstNode::FunctionCall { name, arguments } => {
-                if let Some(signatures) = function_signatures.get(name) {
-                    if signatures.len() > 1 {
-                        let arg_types: Vec<Type> = arguments
-                            .iter_mut()
-                            .map(|arg| arg.get_type(type_checker))
-                            .collect();
-                        if let Some(expected_return_type) = assignment_type {
-                            for (new_name, (sig_args, ret_type)) in signatures {
-                                if *sig_args == arg_types && *ret_type == *expected_return_type {
-                                    *name = new_name.clone();
-                                    break;
-                                }
-                            }
-                        } else {
-                            for (new_name, (sig_args, _)) in signatures {
-                                if *sig_args == arg_types {
-                                    *name = new_name.clone();
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-                for arg in arguments {
-                    rename_function_calls(arg, function_signatures, type_checker, assignment_type);
-                }
+// The type of a call's argument, which can be a parameter of the function making the
+// call, the result of a call that isn't overloaded or the result of a method call
+fn argument_type(
+    arg: &mut AstNode,
+    function_signatures: &FunctionSignatures,
+    type_checker: &TypeChecker,
+    function: &str,
+) -> Type {
+    match arg {
+        AstNode::Identifier { value } => {
+            match type_checker
+                .symbol_table
+                .get(&format!("{}::{}", function, value))
+            {
+                Some(tp) => tp.clone(),
+                None => arg.get_type(type_checker),
             }
-            AstNode::Block { statements } => {
-                for stmt in statements {
-                    rename_function_calls(stmt, function_signatures, type_checker, assignment_type);
+        }
+        AstNode::FunctionCall { name, .. }
+            if function_signatures
+                .get(name)
+                .is_none_or(|signatures| signatures.len() == 1) =>
+        {
+            type_checker
+                .function_table
+                .get(name)
+                .map(|(_, returns)| returned(returns))
+                .unwrap_or(Type::NotMentioned)
+        }
+        AstNode::MethodCall { object, method, .. } => {
+            let tp = argument_type(object, function_signatures, type_checker, function);
+            type_checker
+                .method_return(&tp, method)
+                .unwrap_or(Type::NotMentioned)
+        }
+        AstNode::FieldAccess { object, field } => {
+            let tp = argument_type(object, function_signatures, type_checker, function);
+            type_checker
+                .field_type(&tp, field)
+                .unwrap_or(Type::NotMentioned)
+        }
+        AstNode::SuperCall { class, method, .. } => type_checker
+            .classes
+            .get(class.as_str())
+            .and_then(|info| info.parent.as_ref())
+            .and_then(|parent| type_checker.method_signature(parent, method).ok())
+            .map(|(_, return_type)| return_type)
+            .unwrap_or(Type::NotMentioned),
+        _ => arg.get_type(type_checker),
+    }
+}
+
+// Third pass: Rename function calls
+fn rename_function_calls(
+    node: &mut AstNode,
+    function_signatures: &FunctionSignatures,
+    type_checker: &TypeChecker,
+    assignment_type: &Option<Type>,
+    function: &str,
+) {
+    match node {
+        AstNode::FunctionCall { name, arguments } => {
+            if let Some(signatures) = function_signatures.get(name)
+                && signatures.len() > 1
+            {
+                let arg_types: Vec<Type> = arguments
+                    .iter_mut()
+                    .map(|arg| argument_type(arg, function_signatures, type_checker, function))
+                    .collect();
+                // Overloads that only differ in their return type are told apart by the
+                // type assigned to, without one the first defined is called
+                let chosen = signatures.iter().find(|(_, (sig_args, ret_type))| {
+                    *sig_args == arg_types
+                        && assignment_type.as_ref().is_none_or(|tp| ret_type == tp)
+                });
+                if let Some((new_name, _)) = chosen {
+                    *name = new_name.clone();
                 }
             }
-            AstNode::Function { body, .. } => {
-                rename_function_calls(body, function_signatures, type_checker, assignment_type);
-            }
-            AstNode::Return { value } => {
-                rename_function_calls(value, function_signatures, type_checker, assignment_type);
-            }
-            AstNode::Pointer { value } => {
-                rename_function_calls(value, function_signatures, type_checker, assignment_type);
+            for arg in arguments {
+                rename_function_calls(
+                    arg,
+                    function_signatures,
+                    type_checker,
+                    assignment_type,
+                    function,
+                );
             }
-            AstNode::Dereference { value } => {
-                rename_function_calls(value, function_signatures, type_checker, assignment_type);
-            }
-            AstNode::Assignment {
-                variable,
-                tp,
-                value,
-            } => {
-                if let Some(tp) = tp {
-                    rename_function_calls(
-                        value,
-                        function_signatures,
-                        type_checker,
-                        &Some(Type::from(tp.clone())),
-                    );
-                } else {
-                    rename_function_calls(value, function_signatures, type_checker, &None);
-                }
+        }
+        AstNode::Function { name, body, .. } => {
+            rename_function_calls(
+                body,
+                function_signatures,
+                type_checker,
+                assignment_type,
+                name,
+            );
+        }
+        AstNode::Assignment { tp, value, .. } => {
+            if let Some(tp) = tp {
+                rename_function_calls(
+                    value,
+                    function_signatures,
+                    type_checker,
+                    &Some(Type::from(tp.clone())),
+                    function,
+                );
+            } else {
+                rename_function_calls(value, function_signatures, type_checker, &None, function);
             }
-            _ => {
-                for child in node.children_mut() {
-                    rename_function_calls(
-                        child,
-                        function_signatures,
-                        type_checker,
-                        assignment_type,
-                    );
-                }
+        }
+        _ => {
+            for child in node.children_mut() {
+                rename_function_calls(
+                    child,
+                    function_signatures,
+                    type_checker,
+                    assignment_type,
+                    function,
+                );
             }
         }
     }
+}
 
-    // First pass: Count function definitions and store return types
-    count_function_definitions(ast, &mut function_counter, &mut function_signatures);
-
-    // Check return types
-    check_return_types(&function_signatures)?;
-
-    // Second pass: Rename functions that have multiple definitions
-    rename_functions(ast, &mut function_counter, &mut function_signatures);
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::interpret;
+    use crate::{compile, CompileOptions};
 
-    // Third pass: Rename function calls
-    rename_function_calls(ast, &function_signatures, &checker, &None);
+    fn status(source: &str) -> i32 {
+        let output = compile(source, &CompileOptions::default())
+            .unwrap_or_else(|errors| panic!("{}", errors[0]));
+        interpret(output.ast.as_ref().unwrap()).unwrap()
+    }
 
-    Ok(())
+    #[test]
+    fn overloads_differing_in_return_type_are_told_apart_by_the_assignment() {
+        // cast(Int) is defined to return a Char first and a Bool later
+        for _ in 0..20 {
+            assert_eq!(
+                status("func main() Int |\n    let c = cast(65)\n    return asint(c)\n|\n"),
+                65
+            );
+        }
+        assert_eq!(
+            status("func main() Int |\n    let b Bool = cast(65)\n    let n Int = cast(b)\n    return n\n|\n"),
+            1
+        );
+    }
 }
@@ -0,0 +1,569 @@
+// Pass manager - the compiler pipeline is a list of named passes run in order over a
+// shared Compilation. Passes can be skipped, dumped after and timed by name, and extra
+// passes (lints, rewrites) can be inserted anywhere without touching the built-in ones.
+
+use super::c_bindgen::{c_bindgen_module, c_bindgen_prelude};
+use super::interfaces::{self, Interface, Interfaces};
+use super::ir::{self, Module};
+use super::modules::{self, Loader, ModuleInfo};
+use super::type_checker::TypeChecker;
+use super::verifier::verify_after;
+use super::{control_flow, generics, ir_text, optimiser, overloading};
+use crate::lex::{tokenize, Token};
+use crate::parser::{AstNode, Parser};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// Everything the pipeline has produced so far. Each pass reads what earlier passes
+// left behind and fills in its own part.
+#[derive(Default)]
+pub struct Compilation {
+    pub source: String,
+    // Where the source came from, imports are resolved relative to it
+    pub path: PathBuf,
+    // Reads imported modules, imports are an error without one
+    pub loader: Option<Box<Loader>>,
+    // Directories imports are looked for in after the importing file's own
+    pub search_path: Vec<PathBuf>,
+    // Qualified names of the `pub` functions, structs and globals
+    pub public: HashSet<String>,
+    // The files of every imported module
+    pub imported: Vec<PathBuf>,
+    // Every imported module, in dependency order
+    pub modules: Vec<ModuleInfo>,
+    // Checked interfaces of modules from earlier builds. Without it every module is checked.
+    pub interfaces: Option<Box<Interfaces>>,
+    // (fingerprint, interface) of each module type checking didn't find an interface for
+    pub checked: Vec<(String, Interface)>,
+    pub tokens: Vec<Token>,
+    pub ast: Option<AstNode>,
+    // The parser's checker until type checking runs, then the real one
+    pub checker: Option<TypeChecker>,
+    pub module: Option<Module>,
+    pub c_code: Option<String>,
+    pub warnings: Vec<String>,
+}
+
+impl Compilation {
+    pub fn new(source: String) -> Self {
+        Compilation {
+            source,
+            ..Default::default()
+        }
+    }
+
+    pub fn ast_mut(&mut self) -> Result<&mut AstNode, String> {
+        self.ast
+            .as_mut()
+            .ok_or_else(|| "there is no AST, the parse pass has not run".to_string())
+    }
+
+    pub fn module_mut(&mut self) -> Result<&mut Module, String> {
+        self.module
+            .as_mut()
+            .ok_or_else(|| "there is no IR, the lower pass has not run".to_string())
+    }
+
+    // The most lowered form the compilation has reached, for --print-after
+    pub fn dump(&self) -> String {
+        if let Some(c_code) = &self.c_code {
+            c_code.clone()
+        } else if let Some(module) = &self.module {
+            module.to_string()
+        } else if let Some(ast) = &self.ast {
+            format!("{:#?}\n", ast)
+        } else {
+            format!("{:#?}\n", self.tokens)
+        }
+    }
+}
+
+pub trait Pass {
+    fn name(&self) -> &str;
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String>;
+}
+
+// Wraps a closure as a pass, for small custom passes
+pub struct FnPass<F: FnMut(&mut Compilation) -> Result<(), String>> {
+    name: String,
+    run: F,
+}
+
+impl<F: FnMut(&mut Compilation) -> Result<(), String>> FnPass<F> {
+    pub fn new(name: impl Into<String>, run: F) -> Self {
+        FnPass {
+            name: name.into(),
+            run,
+        }
+    }
+}
+
+impl<F: FnMut(&mut Compilation) -> Result<(), String>> Pass for FnPass<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        (self.run)(compilation)
+    }
+}
+
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    skip: Vec<String>,
+    print_after: Vec<String>,
+    stop_after: Option<String>,
+    pub timings: Vec<(String, Duration)>,
+    // What the compilation looked like after each pass named with print_after
+    pub dumps: Vec<(String, String)>,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, pass: impl Pass + 'static) {
+        self.passes.push(Box::new(pass));
+    }
+
+    pub fn insert_before(&mut self, before: &str, pass: impl Pass + 'static) -> Result<(), String> {
+        let index = self.index_of(before)?;
+        self.passes.insert(index, Box::new(pass));
+        Ok(())
+    }
+
+    pub fn insert_after(&mut self, after: &str, pass: impl Pass + 'static) -> Result<(), String> {
+        let index = self.index_of(after)?;
+        self.passes.insert(index + 1, Box::new(pass));
+        Ok(())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    fn index_of(&self, name: &str) -> Result<usize, String> {
+        self.passes
+            .iter()
+            .position(|pass| pass.name() == name)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown pass '{}', the passes are: {}",
+                    name,
+                    self.names().join(", ")
+                )
+            })
+    }
+
+    pub fn skip(&mut self, name: &str) -> Result<(), String> {
+        self.index_of(name)?;
+        self.skip.push(name.to_string());
+        Ok(())
+    }
+
+    pub fn print_after(&mut self, name: &str) -> Result<(), String> {
+        self.index_of(name)?;
+        self.print_after.push(name.to_string());
+        Ok(())
+    }
+
+    // Ends the pipeline after the named pass, e.g. after typecheck for `tsl check`
+    pub fn stop_after(&mut self, name: &str) -> Result<(), String> {
+        self.index_of(name)?;
+        self.stop_after = Some(name.to_string());
+        Ok(())
+    }
+
+    // Runs every pass in order, stopping at the first one that fails. Once there is IR,
+    // it is verified after each pass in debug builds.
+    pub fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        for pass in &mut self.passes {
+            let name = pass.name().to_string();
+            if self.skip.contains(&name) {
+                continue;
+            }
+            let start = Instant::now();
+            let result = pass.run(compilation);
+            self.timings.push((name.clone(), start.elapsed()));
+            result?;
+            if let Some(module) = &compilation.module {
+                verify_after(&name, module)?;
+            }
+            if self.print_after.contains(&name) {
+                self.dumps.push((name.clone(), compilation.dump()));
+            }
+            if self.stop_after.as_ref() == Some(&name) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The standard pipeline for TSL source. Optimisations other than dead code elimination
+// only run with -O.
+pub fn source_pipeline(optimise: bool) -> PassManager {
+    let mut manager = PassManager::new();
+    manager.add(Lex);
+    manager.add(Parse);
+    manager.add(Imports);
+    manager.add(Monomorphize);
+    manager.add(StaticDispatch);
+    manager.add(ResolveNames);
+    manager.add(TypeCheck);
+    manager.add(Lower);
+    add_ir_passes(&mut manager, optimise);
+    manager
+}
+
+// The pipeline for IR text written by --emit=ir
+pub fn ir_pipeline(optimise: bool) -> PassManager {
+    let mut manager = PassManager::new();
+    manager.add(ParseIr);
+    add_ir_passes(&mut manager, optimise);
+    manager
+}
+
+fn add_ir_passes(manager: &mut PassManager, optimise: bool) {
+    if optimise {
+        manager.add(Inline);
+        manager.add(FoldConstants);
+    }
+    // Unused prelude functions are dropped even without -O
+    manager.add(EliminateDeadCode);
+    manager.add(CodeGen);
+}
+
+pub struct Lex;
+pub struct Parse;
+pub struct Imports;
+pub struct Monomorphize;
+pub struct StaticDispatch;
+pub struct ResolveNames;
+pub struct TypeCheck;
+pub struct Lower;
+pub struct ParseIr;
+pub struct Inline;
+pub struct FoldConstants;
+pub struct EliminateDeadCode;
+pub struct CodeGen;
+
+impl Pass for Lex {
+    fn name(&self) -> &str {
+        "lex"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        compilation.tokens = tokenize(compilation.source.clone());
+        Ok(())
+    }
+}
+
+impl Pass for Parse {
+    fn name(&self) -> &str {
+        "parse"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        let mut parser = Parser::new(compilation.tokens.clone());
+        compilation.ast = Some(parser.parse().map_err(|e| format!("Parse error: {}", e))?);
+        compilation.checker = Some(parser.temp_checker);
+        Ok(())
+    }
+}
+
+impl Pass for Imports {
+    fn name(&self) -> &str {
+        "imports"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        let ast = compilation
+            .ast
+            .take()
+            .unwrap_or(AstNode::Block { statements: vec![] });
+        let mut no_loader = |_: &std::path::Path| -> Result<String, String> {
+            Err("there is no module loader, compile from a file to use imports".to_string())
+        };
+        let loader: &mut Loader = match &mut compilation.loader {
+            Some(loader) => loader.as_mut(),
+            None => &mut no_loader,
+        };
+        let program =
+            modules::resolve_imports(ast, &compilation.path, &compilation.search_path, loader)
+                .map_err(|e| format!("Import error: {}", e))?;
+        compilation.ast = Some(program.ast);
+        compilation.public = program.public;
+        compilation.imported = program.files;
+        compilation.modules = program.modules;
+        Ok(())
+    }
+}
+
+impl Pass for Monomorphize {
+    fn name(&self) -> &str {
+        "monomorphize"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        generics::monomorphize(compilation.ast_mut()?).map_err(|e| format!("Type error: {}", e))
+    }
+}
+
+impl Pass for StaticDispatch {
+    fn name(&self) -> &str {
+        "dispatch"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        let mut checker = compilation.checker.take().unwrap_or_default();
+        let ast = compilation.ast_mut()?;
+        checker.prelude(ast);
+        overloading::static_dispatch(ast, checker).map_err(|e| format!("Error: {}", e))
+    }
+}
+
+impl Pass for ResolveNames {
+    fn name(&self) -> &str {
+        "resolve"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        let ast = compilation.ast_mut()?;
+        *ast = ir::resolve_names(ast);
+        Ok(())
+    }
+}
+
+impl Pass for TypeCheck {
+    fn name(&self) -> &str {
+        "typecheck"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        let mut checker = TypeChecker::new();
+        let Some(ast) = &mut compilation.ast else {
+            return Err("there is no AST, the parse pass has not run".to_string());
+        };
+        checker.prelude(ast);
+        let result = match &mut compilation.interfaces {
+            Some(found) => {
+                interfaces::check_program(&mut checker, ast, &compilation.modules, found.as_mut())
+                    .map(|checked| compilation.checked = checked)
+            }
+            None => checker.check(ast).map(|_| ()),
+        };
+        compilation.warnings.append(&mut checker.warnings);
+        compilation.checker = Some(checker);
+        result.map_err(|e| format!("Type error: {}", e))
+    }
+}
+
+impl Pass for Lower {
+    fn name(&self) -> &str {
+        "lower"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        let (Some(ast), Some(checker)) = (&compilation.ast, &compilation.checker) else {
+            return Err("lowering needs a type checked AST".to_string());
+        };
+        let mut module = ir::lower(ast, checker).map_err(|e| format!("Lowering error: {}", e))?;
+        let public = &compilation.public;
+        for function in &mut module.functions {
+            let name = control_flow::source_name(&function.name);
+            function.public = name == "main" || public.contains(name);
+        }
+        for global in &mut module.globals {
+            global.public = public.contains(&global.var.name);
+        }
+        compilation.module = Some(module);
+        Ok(())
+    }
+}
+
+impl Pass for ParseIr {
+    fn name(&self) -> &str {
+        "parse-ir"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        let module = ir_text::parse_module(&compilation.source)
+            .map_err(|e| format!("IR parse error: {}", e))?;
+        compilation.module = Some(module);
+        Ok(())
+    }
+}
+
+impl Pass for Inline {
+    fn name(&self) -> &str {
+        "inline"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        optimiser::inline_functions(compilation.module_mut()?);
+        Ok(())
+    }
+}
+
+impl Pass for FoldConstants {
+    fn name(&self) -> &str {
+        "fold"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        optimiser::fold_constants(compilation.module_mut()?)
+            .map_err(|e| format!("Optimisation error: {}", e))
+    }
+}
+
+impl Pass for EliminateDeadCode {
+    fn name(&self) -> &str {
+        "dce"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        optimiser::eliminate_dead_code(compilation.module_mut()?);
+        Ok(())
+    }
+}
+
+impl Pass for CodeGen {
+    fn name(&self) -> &str {
+        "codegen"
+    }
+
+    fn run(&mut self, compilation: &mut Compilation) -> Result<(), String> {
+        let module = compilation.module_mut()?;
+        compilation.c_code = Some(c_bindgen_prelude() + &c_bindgen_module(module));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{source_pipeline, Compilation, FnPass, PassManager};
+
+    // A pass that records that it ran in the compilation's warnings
+    fn recording(name: &'static str) -> FnPass<impl FnMut(&mut Compilation) -> Result<(), String>> {
+        FnPass::new(name, move |compilation: &mut Compilation| {
+            compilation.warnings.push(name.to_string());
+            Ok(())
+        })
+    }
+
+    fn manager() -> PassManager {
+        let mut manager = PassManager::new();
+        manager.add(recording("a"));
+        manager.add(recording("b"));
+        manager.add(recording("c"));
+        manager
+    }
+
+    // The passes that ran, in order
+    fn run(manager: &mut PassManager) -> Vec<String> {
+        let mut compilation = Compilation::new(String::new());
+        manager.run(&mut compilation).unwrap();
+        compilation.warnings
+    }
+
+    #[test]
+    fn passes_run_in_the_order_they_were_added() {
+        assert_eq!(run(&mut manager()), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn inserted_passes_run_next_to_the_named_pass() {
+        let mut manager = manager();
+        manager.insert_before("b", recording("before_b")).unwrap();
+        manager.insert_after("b", recording("after_b")).unwrap();
+        manager.insert_after("c", recording("last")).unwrap();
+        assert_eq!(
+            run(&mut manager),
+            ["a", "before_b", "b", "after_b", "c", "last"]
+        );
+    }
+
+    #[test]
+    fn skipped_passes_dont_run() {
+        let mut manager = manager();
+        manager.skip("b").unwrap();
+        assert_eq!(run(&mut manager), ["a", "c"]);
+    }
+
+    #[test]
+    fn stop_after_ends_the_pipeline() {
+        let mut manager = manager();
+        manager.stop_after("b").unwrap();
+        assert_eq!(run(&mut manager), ["a", "b"]);
+    }
+
+    #[test]
+    fn print_after_dumps_after_each_named_pass() {
+        let mut manager = manager();
+        manager.print_after("a").unwrap();
+        manager.print_after("c").unwrap();
+        run(&mut manager);
+        let dumped: Vec<&str> = manager
+            .dumps
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(dumped, ["a", "c"]);
+    }
+
+    #[test]
+    fn a_failing_pass_stops_the_pipeline() {
+        let mut manager = manager();
+        manager
+            .insert_after(
+                "a",
+                FnPass::new("fail", |_: &mut Compilation| Err("failed".to_string())),
+            )
+            .unwrap();
+        let mut compilation = Compilation::new(String::new());
+        assert_eq!(manager.run(&mut compilation).unwrap_err(), "failed");
+        assert_eq!(compilation.warnings, ["a"]);
+    }
+
+    #[test]
+    fn unknown_pass_names_are_rejected() {
+        let mut manager = manager();
+        let error = "Unknown pass 'd', the passes are: a, b, c";
+        assert_eq!(manager.skip("d").unwrap_err(), error);
+        assert_eq!(manager.print_after("d").unwrap_err(), error);
+        assert_eq!(manager.stop_after("d").unwrap_err(), error);
+        assert_eq!(
+            manager.insert_before("d", recording("e")).unwrap_err(),
+            error
+        );
+        assert_eq!(
+            manager.insert_after("d", recording("e")).unwrap_err(),
+            error
+        );
+        assert_eq!(run(&mut manager), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn source_pipeline_only_inlines_and_folds_with_optimise() {
+        let front = [
+            "lex",
+            "parse",
+            "imports",
+            "monomorphize",
+            "dispatch",
+            "resolve",
+            "typecheck",
+            "lower",
+        ];
+        let mut expected = front.to_vec();
+        expected.extend(["dce", "codegen"]);
+        assert_eq!(source_pipeline(false).names(), expected);
+        let mut expected = front.to_vec();
+        expected.extend(["inline", "fold", "dce", "codegen"]);
+        assert_eq!(source_pipeline(true).names(), expected);
+    }
+}
@@ -37,7 +37,7 @@ struct str {
 typedef struct str Str;
 
 
-Str* newStr() {
+static inline Str* newStr() {
     Str* s = (Str*)malloc(sizeof(Str));
     s->s = (char*)malloc(1);
     s->s[0] = '\0';
@@ -46,7 +46,7 @@ Str* newStr() {
     return s;
 }
 
-void append(Str* s, char c) {
+static inline void append(Str* s, char c) {
     if (s->len == s->cap) {
         s->cap *= 2;
         char* old = s->s;
@@ -59,43 +59,64 @@ void append(Str* s, char c) {
     s->s[s->len] = '\0';
 }
 
-void appendStr(Str* s, char* str) {
+static inline void appendStr(Str* s, char* str) {
     for (int i = 0; i < strlen(str); i++) {
         append(s, str[i]);
     }
 }
 
-void appendInt(Str* s, int n) {
+static inline void appendInt(Str* s, int n) {
     char str[12];
     sprintf(str, "%d", n);
     appendStr(s, str);
 }
 
-void freeStr(Str* s) {
+static inline void freeStr(Str* s) {
     free(s->s);
     free(s);
 }
 
 
-void tsl_log(char* s) {
+static inline void tsl_log(char* s) {
     printf("%s", s);
 }
 
-void tsl_log_int(int n) {
+static inline void tsl_log_int(int n) {
     printf("%d", n);
 }
 
-char* new_buffer(int size) {
+static inline char* new_buffer(int size) {
     return (char*)malloc(size);
 }
 
-void input(char* buf) {
+static inline void input(char* buf) {
     scanf("%s", buf);
 }
 
-void log_char(char c) {
+static inline void log_char(char c) {
     printf("%c", c);
 }
 
+// A heap copy of a value, for a dyn of a type that isn't a pointer
+static inline void* tsl_box(const void* value, size_t size) {
+    void* copy = malloc(size);
+    memcpy(copy, value, size);
+    return copy;
+}
+
+// Stops the program, like an unwrap() of an Option or Result that doesn't hold a value
+static inline void tsl_panic(const char* message) {
+    fflush(stdout);
+    fprintf(stderr, "Runtime error: %s\n", message);
+    exit(1);
+}
+
+// A function value, called with its environment first. function is cast to its real
+// type at each call.
+typedef struct {
+    void (*function)(void);
+    void* env;
+} tsl_closure;
+
 // ==================== End of prelude.c =================
 
@@ -1,11 +1,12 @@
 // This stores the type checker for the transpiler.
 // Structs we will need: Type Registry, Type Checker, Type, Function, and TypeName.
 
-use crate::lex::*;
 use crate::parser::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use super::c_bindgen::AsC;
+use super::c_bindgen::{AsC, CType};
+use super::control_flow;
+use super::ir::c_symbol;
 
 impl AsC for Type {
     fn as_c(&self) -> String {
@@ -14,45 +15,125 @@ impl AsC for Type {
             Type::Float => "float".to_string(),
             Type::Bool => "bool".to_string(),
             Type::String => "String".to_string(),
-            Type::Function => "void".to_string(),
+            Type::Function(..) => "tsl_closure".to_string(),
             Type::Void => "void".to_string(),
-            Type::DataTp(name) => name.clone(),
+            Type::DataTp(name) => c_symbol(name),
             Type::NotMentioned => "NotMentioned".to_string(),
-            Type::Pointer(tp) => format!("{}*", tp.as_c()),
+            Type::Pointer(tp) => CType::new(tp.as_c(), true, false).as_c(),
+            Type::ConstPointer(tp) => CType::new(tp.as_c(), true, true).as_c(),
             Type::Char => "char".to_string(),
+            Type::Dyn(interface) => format!("{}__dyn", c_symbol(interface)),
+            Type::Tuple(types) => tuple_symbol(types),
+            Type::Option(tp) if option_is_pointer(tp) => tp.as_c(),
+            Type::Option(tp) => format!("tsl_option__{}", symbol_part(tp)),
+            Type::Result(tp, error) => {
+                format!("tsl_result__{}__{}", symbol_part(tp), symbol_part(error))
+            }
         }
     }
 }
 
+// An Option of a pointer is just the pointer, with Void (NULL) for None. Pointers can't
+// be Void otherwise, so that is the only way to have one that points at nothing.
+pub fn option_is_pointer(tp: &Type) -> bool {
+    matches!(tp, Type::Pointer(_) | Type::ConstPointer(_))
+}
+
+// The struct a tuple is generated as, `(Int, String*)` is `tsl_tuple2__int__StringPtr`
+pub fn tuple_symbol(types: &[Type]) -> String {
+    let types: Vec<String> = types.iter().map(symbol_part).collect();
+    format!("tsl_tuple{}__{}", types.len(), types.join("__"))
+}
+
+// A type as part of the name of a struct generated for the types holding it
+fn symbol_part(tp: &Type) -> String {
+    tp.as_c().replace(" const", "Const").replace('*', "Ptr")
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Integer,
     Float,
     Bool,
     String,
-    Function,
+    Function(Vec<Type>, Box<Type>), // `Func(Int, Int) Int`, a function value
     Void,
     DataTp(String),
     NotMentioned,
     Pointer(Box<Type>),
+    ConstPointer(Box<Type>), // `const T*`, a pointer that can't change what it points at
     Char,
+    Dyn(String),                  // `dyn Interface`, a value of any type implementing it
+    Tuple(Vec<Type>),             // `(Int, String)`, with at least two types
+    Option(Box<Type>),            // `Option<T>`, Some value or None
+    Result(Box<Type>, Box<Type>), // `Result<T, E>`, an Ok value or an Err error
+}
+
+impl Type {
+    // Whether every part of the type is known. `None` on its own is an Option of a type
+    // that only where it is used can tell, and `Ok` and `Err` leave out the other type.
+    pub fn is_known(&self) -> bool {
+        match self {
+            Type::NotMentioned => false,
+            Type::Pointer(tp) | Type::ConstPointer(tp) | Type::Option(tp) => tp.is_known(),
+            Type::Result(tp, error) => tp.is_known() && error.is_known(),
+            Type::Tuple(types) => types.iter().all(Type::is_known),
+            Type::Function(params, ret) => params.iter().all(Type::is_known) && ret.is_known(),
+            _ => true,
+        }
+    }
 }
 
 impl From<&str> for Type {
     fn from(name: &str) -> Self {
+        if let Some(interface) = name.strip_prefix("dyn ") {
+            return Type::Dyn(interface.trim().to_string());
+        }
+        if let Some(pointer) = name.strip_prefix("const ") {
+            return match Type::from(pointer.trim()) {
+                Type::Pointer(tp) => Type::ConstPointer(tp),
+                tp => tp,
+            };
+        }
+        if let Some((params, ret)) = split_function_type(name) {
+            return Type::Function(
+                params.into_iter().map(Type::from).collect(),
+                Box::new(Type::from(ret)),
+            );
+        }
+        if let Some(types) = name
+            .strip_prefix('(')
+            .and_then(|name| name.strip_suffix(')'))
+        {
+            return Type::Tuple(split_types(types).into_iter().map(Type::from).collect());
+        }
+        // Built in, unlike other `Name<...>` types, which are monomorphized
+        let args = |generic: &str| {
+            name.strip_prefix(generic)
+                .and_then(|name| name.strip_prefix('<'))
+                .and_then(|name| name.strip_suffix('>'))
+                .map(split_types)
+        };
+        if let Some([tp]) = args("Option").as_deref() {
+            return Type::Option(Box::new(Type::from(tp.as_str())));
+        }
+        if let Some([tp, error]) = args("Result").as_deref() {
+            return Type::Result(
+                Box::new(Type::from(tp.as_str())),
+                Box::new(Type::from(error.as_str())),
+            );
+        }
         match name {
             "Int" => Type::Integer,
             "Float" => Type::Float,
             "Bool" => Type::Bool,
             "String" => Type::String,
-            "Func" => Type::Function,
             "Void" => Type::Void,
             "NotMentioned" => Type::NotMentioned,
             "Int*" => Type::Pointer(Box::new(Type::Integer)),
             "Float*" => Type::Pointer(Box::new(Type::Float)),
             "Bool*" => Type::Pointer(Box::new(Type::Bool)),
             "String*" => Type::Pointer(Box::new(Type::String)),
-            "Func*" => Type::Pointer(Box::new(Type::Function)),
             "Void*" => Type::Pointer(Box::new(Type::Void)),
             "Char" => Type::Char,
             "Char*" => Type::Pointer(Box::new(Type::Char)),
@@ -71,32 +152,7 @@ impl From<&str> for Type {
 
 impl From<String> for Type {
     fn from(name: String) -> Self {
-        match name.as_str() {
-            "Int" => Type::Integer,
-            "Float" => Type::Float,
-            "Bool" => Type::Bool,
-            "String" => Type::String,
-            "Func" => Type::Function,
-            "Void" => Type::Void,
-            "NotMentioned" => Type::NotMentioned,
-            "Int*" => Type::Pointer(Box::new(Type::Integer)),
-            "Float*" => Type::Pointer(Box::new(Type::Float)),
-            "Bool*" => Type::Pointer(Box::new(Type::Bool)),
-            "String*" => Type::Pointer(Box::new(Type::String)),
-            "Func*" => Type::Pointer(Box::new(Type::Function)),
-            "Void*" => Type::Pointer(Box::new(Type::Void)),
-            "Char" => Type::Char,
-            "Char*" => Type::Pointer(Box::new(Type::Char)),
-            _ => {
-                if name.ends_with('*') {
-                    Type::Pointer(Box::new(Type::DataTp(
-                        name.trim_end_matches('*').to_owned(),
-                    )))
-                } else {
-                    Type::DataTp(name)
-                }
-            } // Custom data type - might not exist :)
-        }
+        Type::from(name.as_str())
     }
 }
 
@@ -107,23 +163,269 @@ impl From<Type> for String {
             Type::Float => "Float".to_owned(),
             Type::Bool => "Bool".to_owned(),
             Type::String => "String".to_owned(),
-            Type::Function => "Func".to_owned(),
+            Type::Function(params, ret) => format!(
+                "Func({}) {}",
+                params
+                    .into_iter()
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                String::from(*ret)
+            ),
             Type::Void => "Void".to_owned(),
             Type::DataTp(name) => name,
             Type::NotMentioned => "UnNamed".to_owned(),
             Type::Pointer(tp) => format!("{}*", String::from(*tp)),
+            Type::ConstPointer(tp) => format!("const {}*", String::from(*tp)),
             Type::Char => "Char".to_owned(),
+            Type::Dyn(interface) => format!("dyn {}", interface),
+            Type::Tuple(types) => format!(
+                "({})",
+                types
+                    .into_iter()
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Type::Option(tp) => format!("Option<{}>", String::from(*tp)),
+            Type::Result(tp, error) => {
+                format!("Result<{}, {}>", String::from(*tp), String::from(*error))
+            }
         }
     }
 }
 
+// Splits a list of type names at the commas not inside `<>` or `()`
+pub fn split_types(list: &str) -> Vec<String> {
+    let mut types = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                types.push(list[start..i].trim().to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !list[start..].trim().is_empty() {
+        types.push(list[start..].trim().to_string());
+    }
+    types
+}
+
+// The parameter and return type names of a `Func(A, B) R` type name
+pub fn split_function_type(name: &str) -> Option<(Vec<String>, String)> {
+    let rest = name.strip_prefix("Func(")?;
+    let mut depth = 1;
+    let close = rest.char_indices().find_map(|(i, c)| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        (depth == 0).then_some(i)
+    })?;
+    Some((
+        split_types(&rest[..close]),
+        rest[close + 1..].trim().to_string(),
+    ))
+}
+
+// What a function_table entry's return types make a call evaluate to, a tuple when
+// the function returns several values
+pub fn returned(returns: &[Type]) -> Type {
+    match returns {
+        [tp] => tp.clone(),
+        _ => Type::Tuple(returns.to_vec()),
+    }
+}
+
+// The return types a function declared to return tp gets in the function_table
+pub fn return_types(tp: Type) -> Vec<Type> {
+    match tp {
+        Type::Tuple(types) => types,
+        tp => vec![tp],
+    }
+}
+
+// The parameter types and return type of a method Option and Result have built in
+pub fn builtin_method(tp: &Type, method: &str) -> Option<(Vec<Type>, Type)> {
+    match (tp, method) {
+        (Type::Option(_), "is_some" | "is_none") | (Type::Result(..), "is_ok" | "is_err") => {
+            Some((vec![], Type::Bool))
+        }
+        // Stops the program when there is no value
+        (Type::Option(tp) | Type::Result(tp, _), "unwrap") => Some((vec![], *tp.clone())),
+        (Type::Option(tp) | Type::Result(tp, _), "unwrap_or") => {
+            Some((vec![*tp.clone()], *tp.clone()))
+        }
+        (Type::Result(_, error), "unwrap_err") => Some((vec![], *error.clone())),
+        _ => None,
+    }
+}
+
+// Checks the returns of a function body against its return type, and any function
+// nested in it
+fn check_return_statements(
+    node: &mut AstNode,
+    expected_return_type: &Type,
+    func_name: &str,
+    type_checker: &mut TypeChecker,
+    errors: &mut Vec<String>,
+) {
+    match node {
+        AstNode::Return { value } => match type_checker.check(value) {
+            Ok(ret_type) => {
+                if !type_checker.assignable(expected_return_type, &ret_type) {
+                    errors.push(format!(
+                        "Function '{}' returns {:?} but declared as {:?}",
+                        func_name, ret_type, expected_return_type
+                    ));
+                }
+            }
+            Err(e) => errors.push(e),
+        },
+        AstNode::If {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            check_return_statements(
+                condition,
+                expected_return_type,
+                func_name,
+                type_checker,
+                errors,
+            );
+            check_return_statements(
+                consequence,
+                expected_return_type,
+                func_name,
+                type_checker,
+                errors,
+            );
+            if let Some(alt) = alternative {
+                check_return_statements(alt, expected_return_type, func_name, type_checker, errors);
+            }
+        }
+        AstNode::While { condition, body } => {
+            check_return_statements(
+                condition,
+                expected_return_type,
+                func_name,
+                type_checker,
+                errors,
+            );
+            check_return_statements(body, expected_return_type, func_name, type_checker, errors);
+        }
+        AstNode::Block { statements } => {
+            for statement in statements {
+                check_return_statements(
+                    statement,
+                    expected_return_type,
+                    func_name,
+                    type_checker,
+                    errors,
+                );
+            }
+        }
+        AstNode::Function {
+            name,
+            arguments,
+            return_type,
+            body,
+            ..
+        } => {
+            let arg_types = arguments
+                .iter()
+                .map(|(tp, _)| Type::from(tp.clone()))
+                .collect();
+            let return_type = Type::from(return_type.clone());
+            type_checker
+                .function_table
+                .insert(name.clone(), (arg_types, return_types(return_type.clone())));
+            type_checker.function_returns.push(return_type.clone());
+            check_return_statements(body, &return_type, name, type_checker, errors);
+            type_checker.function_returns.pop();
+            type_checker.check_control_flow(name, &return_type, body, errors);
+        }
+        _ => match type_checker.check(node) {
+            // Dropping a Result would drop its error with it
+            Ok(Type::Result(..))
+                if matches!(
+                    node,
+                    AstNode::FunctionCall { .. }
+                        | AstNode::MethodCall { .. }
+                        | AstNode::Call { .. }
+                ) =>
+            {
+                errors.push(format!(
+                    "The Result of {} in '{}' is ignored, handle its error or pass it on with '?'",
+                    control_flow::describe(node).unwrap_or_default(),
+                    control_flow::source_name(func_name)
+                ))
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(e),
+        },
+    }
+}
+
+// What a class declares itself, see TypeChecker::ancestry for what it inherits
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+    pub parent: Option<String>,
+    pub fields: Vec<(String, Type)>,
+    // Method names, each implemented by the function method_symbol gives
+    pub methods: Vec<String>,
+}
+
+// The methods an interface asks for, as (name, parameter types after self, return type)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interface {
+    pub methods: Vec<(String, Vec<Type>, Type)>,
+}
+
+// An `impl Interface for Type`, target is the type self has
+#[derive(Debug, Clone, PartialEq)]
+pub struct Impl {
+    pub interface: String,
+    pub target: Type,
+    // (method, function), each function named by impl_symbol
+    pub methods: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
 pub struct TypeChecker {
     pub symbol_table: HashMap<String, Type>,
     pub function_table: HashMap<String, (Vec<Type>, Vec<Type>)>,
-    scope_name: String,
+    pub classes: HashMap<String, Class>,
+    // The fields of each struct, in declaration order
+    pub structs: HashMap<String, Vec<(String, Type)>>,
+    pub interfaces: HashMap<String, Interface>,
+    pub impls: Vec<Impl>,
+    pub warnings: Vec<String>,
+    // The return types of the functions being checked, innermost last, for `?`
+    function_returns: Vec<Type>,
+    // Variables declared with `let` rather than `let mut`, which can't be assigned again
+    immutable: HashSet<String>,
+    // For each `if` or `while` block being checked, innermost last, the variables a `let`
+    // in it hides and whether they were immutable, which they are again after the block.
+    // What a block declares first stays in immutable, lowering still needs to know.
+    hidden: Vec<Vec<(String, bool)>>,
     reached_eof: bool,
 }
 
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TypeChecker {
     pub fn new() -> Self {
         let mut symbol_table = HashMap::new();
@@ -134,18 +436,67 @@ impl TypeChecker {
         TypeChecker {
             symbol_table,
             function_table,
-            scope_name: "".to_string(),
+            classes: HashMap::new(),
+            structs: HashMap::new(),
+            interfaces: HashMap::new(),
+            impls: Vec::new(),
+            warnings: Vec::new(),
+            function_returns: Vec::new(),
+            immutable: HashSet::new(),
+            hidden: Vec::new(),
             reached_eof: false,
         }
     }
 
+    // Records whether a variable declared with binding can be assigned again
+    pub fn declare(&mut self, variable: &str, binding: Binding) {
+        if binding != Binding::Assign
+            && self.symbol_table.contains_key(variable)
+            && let Some(hidden) = self.hidden.last_mut()
+            && !hidden.iter().any(|(name, _)| name == variable)
+        {
+            hidden.push((variable.to_string(), self.immutable.contains(variable)));
+        }
+        match binding {
+            Binding::Let => {
+                self.immutable.insert(variable.to_string());
+            }
+            Binding::LetMut => {
+                self.immutable.remove(variable);
+            }
+            Binding::Assign => {}
+        }
+    }
+
+    // Checks the block of an `if` or `while`, whose `let`s only hide variables inside it
+    fn check_block(&mut self, block: &mut AstNode) -> Result<Type, String> {
+        self.hidden.push(Vec::new());
+        let result = self.check(block);
+        for (variable, immutable) in self.hidden.pop().unwrap_or_default() {
+            if immutable {
+                self.immutable.insert(variable);
+            } else {
+                self.immutable.remove(&variable);
+            }
+        }
+        result
+    }
+
+    pub fn is_immutable(&self, variable: &str) -> bool {
+        self.immutable.contains(variable)
+    }
+
+    pub fn immutables(&self) -> impl Iterator<Item = &String> {
+        self.immutable.iter()
+    }
+
     pub fn prelude(&mut self, node: &AstNode) {
         match node {
             AstNode::Function {
                 name,
                 arguments,
                 return_type,
-                body: _,
+                ..
             } => {
                 let arg_types = arguments
                     .clone()
@@ -154,7 +505,7 @@ impl TypeChecker {
                     .collect();
                 let return_type = Type::from(return_type.clone());
                 self.function_table
-                    .insert(name.clone(), (arg_types, vec![return_type.clone()]));
+                    .insert(name.clone(), (arg_types, return_types(return_type.clone())));
 
                 for (tp, argname) in arguments {
                     self.symbol_table
@@ -166,6 +517,90 @@ impl TypeChecker {
                     self.prelude(statement);
                 }
             }
+            AstNode::Class {
+                name,
+                parent,
+                fields,
+                methods,
+            } => {
+                let mut class = Class {
+                    parent: parent.clone(),
+                    fields: fields
+                        .iter()
+                        .map(|(tp, field)| (field.clone(), Type::from(tp.clone())))
+                        .collect(),
+                    methods: Vec::new(),
+                };
+                for method in methods {
+                    if let AstNode::Function { name: function, .. } = method {
+                        class.methods.push(method_name(name, function).to_string());
+                    }
+                    self.prelude(method);
+                }
+                self.classes.insert(name.clone(), class);
+            }
+            AstNode::Struct { name, fields } => {
+                let fields = fields
+                    .iter()
+                    .map(|(tp, field)| (field.clone(), Type::from(tp.clone())))
+                    .collect();
+                self.structs.insert(name.clone(), fields);
+            }
+            AstNode::Interface { name, methods } => {
+                let methods = methods
+                    .iter()
+                    .map(|(method, params, return_type)| {
+                        (
+                            method.clone(),
+                            params.iter().map(|tp| Type::from(tp.as_str())).collect(),
+                            Type::from(return_type.as_str()),
+                        )
+                    })
+                    .collect();
+                self.interfaces.insert(name.clone(), Interface { methods });
+            }
+            AstNode::Impl {
+                interface,
+                target,
+                methods,
+            } => {
+                let mut implementation = Impl {
+                    interface: interface.clone(),
+                    target: Type::from(impl_receiver(target)),
+                    methods: Vec::new(),
+                };
+                for method in methods {
+                    if let AstNode::Function { name: function, .. } = method {
+                        implementation
+                            .methods
+                            .push((impl_method_name(function).to_string(), function.clone()));
+                    }
+                    self.prelude(method);
+                }
+                // The parser's first pass already saw this impl
+                self.impls.retain(|other| {
+                    other.interface != implementation.interface
+                        || other.target != implementation.target
+                        || other.methods != implementation.methods
+                });
+                self.impls.push(implementation);
+            }
+            // A global can be used by functions defined before it. A plain `name = value`
+            // declares nothing.
+            AstNode::Assignment {
+                variable,
+                tp: Some(tp),
+                binding,
+                value,
+            } if *binding != Binding::Assign
+                || matches!(value.as_ref(), AstNode::Uninit { .. }) =>
+            {
+                let tp = Type::from(tp.clone());
+                if tp.is_known() {
+                    self.symbol_table.insert(variable.clone(), tp);
+                }
+                self.declare(variable, *binding);
+            }
             _ => {}
         }
 
@@ -237,6 +672,484 @@ impl TypeChecker {
         self.symbol_table.insert("void_t".to_string(), Type::Void);
     }
 
+    // The class followed by its parent, its parent's parent and so on
+    pub fn ancestry(&self, class: &str) -> Result<Vec<(&str, &Class)>, String> {
+        let mut ancestry: Vec<(&str, &Class)> = Vec::new();
+        let mut next = Some(class);
+        while let Some(name) = next {
+            if ancestry.iter().any(|(seen, _)| *seen == name) {
+                return Err(format!("Class {} inherits from itself", class));
+            }
+            let (name, info) = self
+                .classes
+                .get_key_value(name)
+                .ok_or_else(|| format!("Undefined class: {}", name))?;
+            next = info.parent.as_deref();
+            ancestry.push((name.as_str(), info));
+        }
+        Ok(ancestry)
+    }
+
+    // Every field of the class, inherited ones first, with the class declaring it. A
+    // struct's fields are all declared by the struct
+    pub fn class_fields(&self, class: &str) -> Result<Vec<(String, String, Type)>, String> {
+        if let Some(fields) = self.structs.get(class) {
+            return Ok(fields
+                .iter()
+                .map(|(field, tp)| (class.to_string(), field.clone(), tp.clone()))
+                .collect());
+        }
+        let ancestry = self.ancestry(class)?;
+        Ok(ancestry
+            .iter()
+            .rev()
+            .flat_map(|(owner, info)| {
+                info.fields
+                    .iter()
+                    .map(|(field, tp)| (owner.to_string(), field.clone(), tp.clone()))
+            })
+            .collect())
+    }
+
+    // The nearest class (the class itself or a parent) that implements the method
+    pub fn method_owner(&self, class: &str, method: &str) -> Result<String, String> {
+        self.ancestry(class)?
+            .iter()
+            .find(|(_, info)| info.methods.iter().any(|m| m == method))
+            .map(|(owner, _)| owner.to_string())
+            .ok_or_else(|| format!("{} has no method '{}'", class, method))
+    }
+
+    // The furthest class up that declares the method, overrides share its vtable slot
+    pub fn method_root(&self, class: &str, method: &str) -> Result<String, String> {
+        self.ancestry(class)?
+            .iter()
+            .rev()
+            .find(|(_, info)| info.methods.iter().any(|m| m == method))
+            .map(|(root, _)| root.to_string())
+            .ok_or_else(|| format!("{} has no method '{}'", class, method))
+    }
+
+    // The parameter types (self first) and return type of a method of the class
+    pub fn method_signature(&self, class: &str, method: &str) -> Result<(Vec<Type>, Type), String> {
+        let function = method_symbol(&self.method_owner(class, method)?, method);
+        self.function_table
+            .get(&function)
+            .map(|(params, returns)| (params.clone(), returned(returns)))
+            .ok_or_else(|| format!("Undefined function: {}", function))
+    }
+
+    // The type a call of method on a tp returns, looked up the way MethodCall checks it
+    pub fn method_return(&self, tp: &Type, method: &str) -> Option<Type> {
+        if let Some((_, return_type)) = builtin_method(tp, method) {
+            return Some(return_type);
+        }
+        if let Some(class) = self.class_of(tp)
+            && let Ok((_, return_type)) = self.method_signature(&class, method)
+        {
+            return Some(return_type);
+        }
+        if let Type::Dyn(interface) = tp {
+            return self
+                .interface_method(interface, method)
+                .ok()
+                .map(|(_, return_type)| return_type);
+        }
+        let (_, function) = self.impl_method(tp, method).ok()??;
+        self.function_table
+            .get(&function)
+            .map(|(_, returns)| returned(returns))
+    }
+
+    // The type of a field of a tp, looked up the way FieldAccess checks it
+    pub fn field_type(&self, tp: &Type, field: &str) -> Option<Type> {
+        let readable = match tp {
+            Type::Tuple(types) => return types.get(field.parse::<usize>().ok()?).cloned(),
+            Type::ConstPointer(tp) => Type::Pointer(tp.clone()),
+            tp => tp.clone(),
+        };
+        self.class_fields(&self.record_of(&readable)?)
+            .ok()?
+            .into_iter()
+            .find(|(_, name, _)| name == field)
+            .map(|(_, _, tp)| tp)
+    }
+
+    // The class a pointer type points at
+    fn class_of(&self, tp: &Type) -> Option<String> {
+        match tp {
+            Type::Pointer(tp) => match tp.as_ref() {
+                Type::DataTp(name) if self.classes.contains_key(name) => Some(name.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // The class or struct a pointer type points at, whose fields can be accessed
+    pub fn record_of(&self, tp: &Type) -> Option<String> {
+        match tp {
+            Type::Pointer(tp) => match tp.as_ref() {
+                Type::DataTp(name) if self.structs.contains_key(name) => Some(name.clone()),
+                _ => self.class_of(&Type::Pointer(tp.clone())),
+            },
+            _ => None,
+        }
+    }
+
+    // The impl of the interface a value of type tp uses, a `Dog*` uses one for `Animal*`
+    // when Dog has none of its own
+    pub fn impl_of(&self, tp: &Type, interface: &str) -> Option<&Impl> {
+        self.receivers(tp).into_iter().find_map(|receiver| {
+            self.impls
+                .iter()
+                .find(|i| i.interface == interface && i.target == receiver)
+        })
+    }
+
+    // Whether a value of type tp can be used as a `dyn interface`
+    pub fn implements(&self, tp: &Type, interface: &str) -> bool {
+        *tp == Type::Dyn(interface.to_string()) || self.impl_of(tp, interface).is_some()
+    }
+
+    // The function an impl method called on a tp runs, statically dispatched, with the
+    // type its self has
+    pub fn impl_method(&self, tp: &Type, method: &str) -> Result<Option<(Type, String)>, String> {
+        for receiver in self.receivers(tp) {
+            let found: Vec<&Impl> = self
+                .impls
+                .iter()
+                .filter(|i| i.target == receiver && i.methods.iter().any(|(m, _)| m == method))
+                .collect();
+            match found.as_slice() {
+                [] => continue,
+                [implementation] => {
+                    let (_, function) = implementation
+                        .methods
+                        .iter()
+                        .find(|(m, _)| m == method)
+                        .expect("the impl has the method");
+                    return Ok(Some((receiver, function.clone())));
+                }
+                _ => {
+                    return Err(format!(
+                        "Method '{}' of {:?} is ambiguous, it comes from {}",
+                        method,
+                        receiver,
+                        found
+                            .iter()
+                            .map(|i| i.interface.as_str())
+                            .collect::<Vec<_>>()
+                            .join(" and ")
+                    ))
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // The parameter types (after self) and return type of a method of the interface
+    pub fn interface_method(
+        &self,
+        interface: &str,
+        method: &str,
+    ) -> Result<(Vec<Type>, Type), String> {
+        let info = self
+            .interfaces
+            .get(interface)
+            .ok_or_else(|| format!("Undefined interface: {}", interface))?;
+        info.methods
+            .iter()
+            .find(|(name, _, _)| name == method)
+            .map(|(_, params, return_type)| (params.clone(), return_type.clone()))
+            .ok_or_else(|| format!("Interface {} has no method '{}'", interface, method))
+    }
+
+    // tp, then each of its ancestors when it is a class pointer
+    fn receivers(&self, tp: &Type) -> Vec<Type> {
+        match self.class_of(tp).map(|class| self.ancestry(&class)) {
+            Some(Ok(ancestry)) => ancestry
+                .iter()
+                .map(|(name, _)| Type::Pointer(Box::new(Type::DataTp(name.to_string()))))
+                .collect(),
+            _ => vec![tp.clone()],
+        }
+    }
+
+    // A value of type actual can be used where expected is, a `Dog*` is an `Animal*` too
+    // and any type with an impl of an interface is a `dyn` of it. A tuple can be used
+    // where each of its elements can, and an Option or Result where what it holds can.
+    // The type None, Ok or Err leaves out fits any type.
+    pub fn assignable(&self, expected: &Type, actual: &Type) -> bool {
+        if expected == actual {
+            return true;
+        }
+        let holds = |expected: &Type, actual: &Type| {
+            *actual == Type::NotMentioned || self.assignable(expected, actual)
+        };
+        match (expected, actual) {
+            (Type::Option(expected), Type::Option(actual)) => return holds(expected, actual),
+            (Type::Result(expected, expected_error), Type::Result(actual, actual_error)) => {
+                return holds(expected, actual) && holds(expected_error, actual_error)
+            }
+            _ => {}
+        }
+        if let Type::Dyn(interface) = expected {
+            return self.implements(actual, interface);
+        }
+        // Anything a pointer can point at, a const pointer can too
+        if let (Type::ConstPointer(expected), Type::Pointer(actual) | Type::ConstPointer(actual)) =
+            (expected, actual)
+        {
+            return self.assignable(
+                &Type::Pointer(expected.clone()),
+                &Type::Pointer(actual.clone()),
+            );
+        }
+        if let (Type::Tuple(expected), Type::Tuple(actual)) = (expected, actual) {
+            return expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(actual)
+                    .all(|(expected, actual)| self.assignable(expected, actual));
+        }
+        match (self.class_of(expected), self.class_of(actual)) {
+            (Some(expected), Some(actual)) => self
+                .ancestry(&actual)
+                .is_ok_and(|ancestry| ancestry.iter().any(|(name, _)| *name == expected)),
+            _ => false,
+        }
+    }
+
+    fn check_arguments(
+        &mut self,
+        callee: &str,
+        expected: &[Type],
+        arguments: &mut [AstNode],
+    ) -> Result<(), String> {
+        if expected.len() != arguments.len() {
+            return Err(format!(
+                "{} expected {} arguments but found {}",
+                callee,
+                expected.len(),
+                arguments.len()
+            ));
+        }
+        for (argument, expected) in arguments.iter_mut().zip(expected) {
+            let actual = self.check(argument)?;
+            if !self.assignable(expected, &actual) {
+                return Err(format!(
+                    "{} expected argument of type {:?} but found {:?}",
+                    callee, expected, actual
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_class(
+        &mut self,
+        name: &str,
+        parent: &Option<String>,
+        methods: &mut [AstNode],
+    ) -> Result<(), String> {
+        let mut errors = Vec::new();
+        if let Some(parent) = parent
+            && !self.classes.contains_key(parent)
+        {
+            return Err(format!(
+                "Class {} inherits from undefined class {}",
+                name, parent
+            ));
+        }
+        let ancestry = self.ancestry(name)?;
+        let mut fields: Vec<&str> = Vec::new();
+        for (_, info) in ancestry.iter().rev() {
+            for (field, _) in &info.fields {
+                if fields.contains(&field.as_str()) {
+                    errors.push(format!(
+                        "Class {} has more than one field named '{}'",
+                        name, field
+                    ));
+                }
+                fields.push(field);
+            }
+        }
+        let own = &ancestry[0].1.methods;
+        for (index, method) in own.iter().enumerate() {
+            if own[..index].contains(method) {
+                errors.push(format!(
+                    "Class {} defines method '{}' more than once",
+                    name, method
+                ));
+            }
+        }
+        // An override has to fit the vtable slot it replaces
+        if let Some(parent) = parent {
+            for method in own {
+                let Ok((inherited, inherited_return)) = self.method_signature(parent, method)
+                else {
+                    continue;
+                };
+                let (params, return_type) = self.method_signature(name, method)?;
+                if params[1..] != inherited[1..] || return_type != inherited_return {
+                    errors.push(format!(
+                        "Method '{}' of {} has a different signature than the one it overrides in {}",
+                        method,
+                        name,
+                        self.method_owner(parent, method)?
+                    ));
+                }
+            }
+        }
+        for method in methods.iter_mut() {
+            if let Err(e) = self.check(method) {
+                errors.push(e);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    fn check_struct(&self, name: &str) -> Result<(), String> {
+        let fields = &self.structs[name];
+        let mut errors = Vec::new();
+        for (index, (field, _)) in fields.iter().enumerate() {
+            if fields[..index].iter().any(|(other, _)| other == field) {
+                errors.push(format!(
+                    "Struct {} has more than one field named '{}'",
+                    name, field
+                ));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    fn check_interface(&mut self, name: &str) -> Result<(), String> {
+        let methods = &self.interfaces[name].methods;
+        let mut errors = Vec::new();
+        for (index, (method, _, _)) in methods.iter().enumerate() {
+            if methods[..index].iter().any(|(other, _, _)| other == method) {
+                errors.push(format!(
+                    "Interface {} declares method '{}' more than once",
+                    name, method
+                ));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    // An impl has exactly the interface's methods, each with the signature it declares
+    fn check_impl(
+        &mut self,
+        interface: &str,
+        target: &str,
+        methods: &mut [AstNode],
+    ) -> Result<(), String> {
+        let info = self
+            .interfaces
+            .get(interface)
+            .cloned()
+            .ok_or_else(|| format!("impl for {} of undefined interface {}", target, interface))?;
+        let receiver = Type::from(impl_receiver(target));
+        let mut errors = Vec::new();
+        if self
+            .impls
+            .iter()
+            .filter(|i| i.interface == interface && i.target == receiver)
+            .count()
+            > 1
+        {
+            errors.push(format!("{} implements {} more than once", target, interface));
+        }
+        let implemented: Vec<String> = methods
+            .iter()
+            .filter_map(|method| match method {
+                AstNode::Function { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        for (method, params, return_type) in &info.methods {
+            let Some(function) = implemented
+                .iter()
+                .find(|function| impl_method_name(function) == method)
+            else {
+                errors.push(format!(
+                    "impl {} for {} is missing method '{}'",
+                    interface, target, method
+                ));
+                continue;
+            };
+            let (actual_params, actual_returns) = &self.function_table[function];
+            if actual_params[1..] != params[..] || returned(actual_returns) != *return_type {
+                errors.push(format!(
+                    "Method '{}' of impl {} for {} has a different signature than {} declares",
+                    method, interface, target, interface
+                ));
+            }
+        }
+        for function in &implemented {
+            let method = impl_method_name(function);
+            if !info.methods.iter().any(|(name, _, _)| name == method) {
+                errors.push(format!(
+                    "Interface {} has no method '{}' for impl {} for {} to implement",
+                    interface, method, interface, target
+                ));
+            }
+        }
+        for method in methods.iter_mut() {
+            if let Err(e) = self.check(method) {
+                errors.push(e);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    // Non-Void functions must return on every path, and anything after a return is reported
+    fn check_control_flow(
+        &mut self,
+        name: &str,
+        return_type: &Type,
+        body: &AstNode,
+        errors: &mut Vec<String>,
+    ) {
+        let func_name = control_flow::source_name(name);
+        if *return_type != Type::Void && !control_flow::always_returns(body) {
+            errors.push(format!(
+                "Function '{}' does not return a value of type {:?} on every path",
+                func_name, return_type
+            ));
+        }
+        for statement in control_flow::unreachable_statements(body) {
+            self.warnings.push(format!(
+                "Unreachable {} in function '{}'",
+                statement, func_name
+            ));
+        }
+    }
+
+    // The type of an expression at the top level of a program this checker has already
+    // checked, e.g. one the REPL has run
+    pub fn check_expression(&mut self, node: &mut AstNode) -> Result<Type, String> {
+        self.reached_eof = false;
+        self.check(node)
+    }
+
     pub fn check(&mut self, node: &mut AstNode) -> Result<Type, String> {
         match node {
             AstNode::Number { value: _ } => Ok(Type::Integer),
@@ -253,7 +1166,7 @@ impl TypeChecker {
                     "+" | "-" | "*" | "/" | "%" => {
                         if left_type == Type::Integer && right_type == Type::Integer {
                             Ok(Type::Integer)
-                        } else if let Type::Pointer(tp) = left_type.clone()
+                        } else if let Type::Pointer(_) | Type::ConstPointer(_) = left_type
                             && right_type == Type::Integer
                         {
                             Ok(left_type)
@@ -267,7 +1180,13 @@ impl TypeChecker {
                         }
                     }
                     "==" | "!=" => {
-                        if left_type == right_type {
+                        // These are structs in C, which == can't compare
+                        if let Type::Tuple(_) | Type::Option(_) | Type::Result(..) = left_type {
+                            Err(format!(
+                                "cannot compare {:?} values with {}",
+                                left_type, operator.value
+                            ))
+                        } else if left_type == right_type {
                             Ok(Type::Bool)
                         } else {
                             Err(format!(
@@ -286,7 +1205,7 @@ impl TypeChecker {
                             ))
                         }
                     }
-                    "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                    "<" | ">" | "<=" | ">=" => {
                         if left_type == Type::Integer && right_type == Type::Integer {
                             Ok(Type::Bool)
                         } else {
@@ -303,7 +1222,28 @@ impl TypeChecker {
                 variable,
                 tp,
                 value,
+                binding,
             } => {
+                let name = variable.rsplit("::").next().unwrap_or(variable);
+                // A module's global `util.name` is declared as `name`
+                let declared = name.rsplit('.').next().unwrap_or(name);
+                // Globals keep their names in functions, so this covers imported ones too
+                if *binding == Binding::Assign && self.immutable.contains(variable) {
+                    return Err(format!(
+                        "Can't reassign '{}', it is an immutable binding, declare it with 'let mut {}' to change it",
+                        name, declared
+                    ));
+                }
+                // `x Int =` with no value declares x
+                if *binding == Binding::Assign
+                    && !matches!(value.as_ref(), AstNode::Uninit { .. })
+                    && !self.symbol_table.contains_key(variable)
+                {
+                    return Err(format!(
+                        "Can't assign to '{}', it isn't declared, declare it with 'let mut {}'",
+                        name, declared
+                    ));
+                }
                 let expected = match tp {
                     Some(t) => Type::from(t.clone()),
                     None => Type::NotMentioned,
@@ -312,20 +1252,25 @@ impl TypeChecker {
 
                 // If type is NotMentioned, check the type of the variable
 
-                if let Some(existing_type) = self.symbol_table.get(variable) {
-                    if *existing_type != value_type && *existing_type != Type::NotMentioned {
-                        return Err(format!(
-                            "Type error: cannot assign {:?} to variable of type {:?}",
-                            value_type, existing_type
-                        ));
-                    }
+                let existing_type = self
+                    .symbol_table
+                    .get(variable)
+                    .filter(|tp| **tp != Type::NotMentioned)
+                    .cloned();
+                if let Some(existing_type) = &existing_type
+                    && !self.assignable(existing_type, &value_type)
+                {
+                    return Err(format!(
+                        "Type error: cannot assign {:?} to variable of type {:?}",
+                        value_type, existing_type
+                    ));
                 }
                 // Debug print all relevant variables
                 // println!(
                 //     "Variable: {:?}, Expected: {:?}, Value: {:?}",
                 //     variable, expected, value_type
                 // );
-                if expected != Type::NotMentioned && expected != value_type {
+                if expected != Type::NotMentioned && !self.assignable(&expected, &value_type) {
                     self.symbol_table
                         .insert(variable.clone(), value_type.clone());
                     return Err(format!(
@@ -334,9 +1279,23 @@ impl TypeChecker {
                     ));
                 }
 
-                self.symbol_table
-                    .insert(variable.clone(), value_type.clone());
-                Ok(value_type)
+                // A variable keeps the type it was declared with, even when a subclass is stored
+                let tp = existing_type.unwrap_or(match expected {
+                    Type::NotMentioned => value_type,
+                    expected => expected,
+                });
+                if !tp.is_known() {
+                    return Err(format!(
+                        "Can't tell the type of '{}' from {:?}, declare it like 'let {} Option<Int> = None'",
+                        variable.rsplit("::").next().unwrap_or(variable),
+                        tp,
+                        variable.rsplit("::").next().unwrap_or(variable)
+                    ));
+                }
+                // Before the variable is known, so a `let` that hides one is seen to
+                self.declare(variable, *binding);
+                self.symbol_table.insert(variable.clone(), tp.clone());
+                Ok(tp)
             }
             AstNode::Variable { value } => self
                 .symbol_table
@@ -357,9 +1316,9 @@ impl TypeChecker {
                     ));
                 }
 
-                self.check(consequence)?;
+                self.check_block(consequence)?;
                 if let Some(alt) = alternative {
-                    self.check(alt)?;
+                    self.check_block(alt)?;
                 }
 
                 Ok(Type::Void)
@@ -369,9 +1328,27 @@ impl TypeChecker {
                 arguments,
                 return_type,
                 body,
+                attributes,
             } => {
                 let mut errors = Vec::new();
-                let mut return_found = false;
+
+                for attribute in attributes.iter() {
+                    if attribute != "inline" && attribute != "noinline" {
+                        errors.push(format!(
+                            "Unknown attribute '@{}' on function '{}'",
+                            attribute,
+                            control_flow::source_name(name)
+                        ));
+                    }
+                }
+                if attributes.iter().any(|a| a == "inline")
+                    && attributes.iter().any(|a| a == "noinline")
+                {
+                    errors.push(format!(
+                        "Function '{}' can't be both @inline and @noinline",
+                        control_flow::source_name(name)
+                    ));
+                }
 
                 // Add function arguments to symbol table
                 for (tp, argname) in arguments.iter() {
@@ -379,174 +1356,17 @@ impl TypeChecker {
                         .insert(format!("{}::{}", name, argname), Type::from(tp.clone()));
                 }
 
-                fn check_return_statements(
-                    node: &mut AstNode,
-                    expected_return_type: &Type,
-                    func_name: &str,
-                    type_checker: &mut TypeChecker,
-                    errors: &mut Vec<String>,
-                    return_found: &mut bool,
-                ) {
-                    match node {
-                        AstNode::Return { ref mut value } => match type_checker.check(value) {
-                            Ok(ret_type) => {
-                                if ret_type != *expected_return_type {
-                                    errors.push(format!(
-                                        "Function '{}' returns {:?} but declared as {:?}",
-                                        func_name, ret_type, expected_return_type
-                                    ));
-                                }
-                                *return_found = true;
-                            }
-                            Err(e) => errors.push(e),
-                        },
-                        AstNode::If {
-                            condition,
-                            consequence,
-                            alternative,
-                        } => {
-                            check_return_statements(
-                                condition,
-                                expected_return_type,
-                                func_name,
-                                type_checker,
-                                errors,
-                                return_found,
-                            );
-                            check_return_statements(
-                                consequence,
-                                expected_return_type,
-                                func_name,
-                                type_checker,
-                                errors,
-                                return_found,
-                            );
-                            if let Some(alt) = alternative {
-                                check_return_statements(
-                                    alt,
-                                    expected_return_type,
-                                    func_name,
-                                    type_checker,
-                                    errors,
-                                    return_found,
-                                );
-                            }
-                        }
-                        AstNode::While { condition, body } => {
-                            check_return_statements(
-                                condition,
-                                expected_return_type,
-                                func_name,
-                                type_checker,
-                                errors,
-                                return_found,
-                            );
-                            check_return_statements(
-                                body,
-                                expected_return_type,
-                                func_name,
-                                type_checker,
-                                errors,
-                                return_found,
-                            );
-                        }
-                        AstNode::Block { statements } => {
-                            for statement in statements {
-                                check_return_statements(
-                                    statement,
-                                    expected_return_type,
-                                    func_name,
-                                    type_checker,
-                                    errors,
-                                    return_found,
-                                );
-                            }
-                        }
-                        AstNode::Function {
-                            name,
-                            arguments,
-                            return_type,
-                            body,
-                        } => {
-                            let arg_types = arguments
-                                .iter()
-                                .map(|(tp, _)| Type::from(tp.clone()))
-                                .collect();
-                            let return_type = Type::from(return_type.clone());
-                            type_checker
-                                .function_table
-                                .insert(name.clone(), (arg_types, vec![return_type.clone()]));
-                            check_return_statements(
-                                body,
-                                &return_type,
-                                name,
-                                type_checker,
-                                errors,
-                                return_found,
-                            );
-                        }
-                        AstNode::Assignment {
-                            variable,
-                            tp,
-                            value,
-                        } => {
-                            let expected = match tp {
-                                Some(t) => Type::from(t.clone()),
-                                None => Type::NotMentioned,
-                            };
-                            let value_type_res = type_checker.check(value);
-
-                            // if error, panic
-                            let value_type = match value_type_res {
-                                Ok(tp) => tp,
-                                Err(e) => panic!("Error: {}", e),
-                            };
-
-                            if let Some(existing_type) = type_checker.symbol_table.get(variable) {
-                                if *existing_type != value_type {
-                                    errors.push(format!(
-                                        "Type error: cannot assign {:?} to variable of type {:?}",
-                                        value_type, existing_type
-                                    ));
-                                }
-                            }
-                            if expected != Type::NotMentioned && expected != value_type {
-                                type_checker
-                                    .symbol_table
-                                    .insert(variable.clone(), value_type.clone());
-                                errors.push(format!(
-                                    "Expected type {:?} but found {:?}",
-                                    expected,
-                                    value_type.clone()
-                                ));
-                            }
-
-                            type_checker
-                                .symbol_table
-                                .insert(variable.clone(), value_type.clone());
-                        }
-                        _ => match type_checker.check(node) {
-                            Ok(_) => {}
-                            Err(e) => errors.push(e),
-                        },
-                    }
-                }
-
+                self.function_returns.push(Type::from(return_type.clone()));
                 check_return_statements(
                     body,
                     &Type::from(return_type.clone()),
                     name,
                     self,
                     &mut errors,
-                    &mut return_found,
                 );
+                self.function_returns.pop();
 
-                if !return_found {
-                    errors.push(format!(
-                        "Function '{}' must have a return statement",
-                        name.split("_").collect::<Vec<&str>>()[0]
-                    ));
-                }
+                self.check_control_flow(name, &Type::from(return_type.clone()), body, &mut errors);
 
                 if errors.is_empty() {
                     let arg_types = arguments
@@ -555,7 +1375,7 @@ impl TypeChecker {
                         .collect();
                     let return_type = Type::from(return_type.clone());
                     self.function_table
-                        .insert(name.clone(), (arg_types, vec![return_type.clone()]));
+                        .insert(name.clone(), (arg_types, return_types(return_type.clone())));
                     Ok(return_type)
                 } else {
                     Err(errors.join("\n"))
@@ -575,7 +1395,7 @@ impl TypeChecker {
                     ));
                 }
 
-                self.check(body)?;
+                self.check_block(body)?;
                 Ok(Type::Void)
             }
             AstNode::Block { statements } => {
@@ -592,11 +1412,71 @@ impl TypeChecker {
                     Err(errors.join("\n"))
                 }
             }
-            AstNode::Identifier { value } => self
-                .symbol_table
-                .get(value)
-                .cloned()
-                .ok_or_else(|| format!("Undefined variable: {}", value)),
+            AstNode::Identifier { value } => {
+                if let Some(tp) = self.symbol_table.get(value) {
+                    return Ok(tp.clone());
+                }
+                // A function named as a value, which resolve_names scoped like a variable
+                let function = value.rsplit("::").next().unwrap_or(value);
+                match self.function_table.get(function) {
+                    Some((params, returns)) => {
+                        Ok(Type::Function(params.clone(), Box::new(returned(returns))))
+                    }
+                    None => Err(format!("Undefined variable: {}", value)),
+                }
+            }
+            AstNode::Lambda {
+                name,
+                arguments,
+                return_type,
+                body,
+                captures,
+            } => {
+                // Its copies of the variables it captures start out as the originals, and
+                // are only mutable when those are
+                for (outer, inner) in captures.iter() {
+                    let tp = self
+                        .symbol_table
+                        .get(outer)
+                        .cloned()
+                        .ok_or_else(|| format!("Undefined variable: {}", outer))?;
+                    self.symbol_table.insert(inner.clone(), tp);
+                    let binding = if self.immutable.contains(outer) {
+                        Binding::Let
+                    } else {
+                        Binding::LetMut
+                    };
+                    self.declare(inner, binding);
+                }
+                for (tp, argname) in arguments.iter() {
+                    self.symbol_table
+                        .insert(format!("{}::{}", name, argname), Type::from(tp.clone()));
+                }
+                let return_type = Type::from(return_type.clone());
+                let mut errors = Vec::new();
+                self.function_returns.push(return_type.clone());
+                check_return_statements(body, &return_type, name, self, &mut errors);
+                self.function_returns.pop();
+                self.check_control_flow(name, &return_type, body, &mut errors);
+                if errors.is_empty() {
+                    Ok(Type::Function(
+                        arguments
+                            .iter()
+                            .map(|(tp, _)| Type::from(tp.clone()))
+                            .collect(),
+                        Box::new(return_type),
+                    ))
+                } else {
+                    Err(errors.join("\n"))
+                }
+            }
+            AstNode::Call { callee, arguments } => match self.check(callee)? {
+                Type::Function(params, ret) => {
+                    self.check_arguments("Function value", &params, arguments)?;
+                    Ok(*ret)
+                }
+                tp => Err(format!("Can't call a value of type {:?}", tp)),
+            },
             AstNode::FunctionCall { name, arguments } => {
                 // If function is printf, we don't need to check the arguments
                 if name == "newStr" {
@@ -621,7 +1501,7 @@ impl TypeChecker {
                     }
                     for (arg, expected) in arguments.iter_mut().zip(arg_types.iter()) {
                         let actual = self.check(arg)?;
-                        if actual != *expected {
+                        if !self.assignable(expected, &actual) {
                             return Err(format!(
                                 "{} Expected argument of type {:?} but found {:?}",
                                 name, expected, actual
@@ -629,8 +1509,18 @@ impl TypeChecker {
                         }
                     }
 
-                    Ok(return_types[0].clone())
+                    Ok(returned(&return_types))
+                } else if let Some(Type::Function(params, ret)) =
+                    self.symbol_table.get(name).cloned()
+                {
+                    // A global holding a function value
+                    self.check_arguments(name, &params, arguments)?;
+                    Ok(*ret)
                 } else {
+                    // An argument that is undefined too is the first thing to fix
+                    for arg in arguments.iter_mut() {
+                        self.check(arg)?;
+                    }
                     Err(format!("Undefined function: {}", name))
                 }
             }
@@ -641,10 +1531,21 @@ impl TypeChecker {
                 self.reached_eof = true;
                 Ok(Type::Void)
             }
-            AstNode::Pointer { value } => Ok(Type::Pointer(Box::new(self.check(value)?))),
+            // The address of a variable declared with `let` can't be used to change it
+            AstNode::Pointer { value } => {
+                let tp = Box::new(self.check(value)?);
+                match value.as_ref() {
+                    AstNode::Identifier { value } | AstNode::Variable { value }
+                        if self.immutable.contains(value) =>
+                    {
+                        Ok(Type::ConstPointer(tp))
+                    }
+                    _ => Ok(Type::Pointer(tp)),
+                }
+            }
             AstNode::Dereference { value } => {
                 match self.check(value)? {
-                    Type::Pointer(tp) => {
+                    Type::Pointer(tp) | Type::ConstPointer(tp) => {
                         // If type is a String pointer, return a char
                         if *tp == Type::DataTp("Str".to_owned()) {
                             Ok(Type::Char)
@@ -657,7 +1558,286 @@ impl TypeChecker {
                 }
             }
             AstNode::Comment { value: _ } => Ok(Type::Void),
+            AstNode::Class {
+                name,
+                parent,
+                methods,
+                ..
+            } => {
+                self.check_class(name, parent, methods)?;
+                Ok(Type::Void)
+            }
+            AstNode::Struct { name, .. } => {
+                self.check_struct(name)?;
+                Ok(Type::Void)
+            }
+            AstNode::Interface { name, .. } => {
+                self.check_interface(name)?;
+                Ok(Type::Void)
+            }
+            AstNode::Impl {
+                interface,
+                target,
+                methods,
+            } => {
+                self.check_impl(interface, target, methods)?;
+                Ok(Type::Void)
+            }
+            AstNode::Tuple { elements } => {
+                let mut types = Vec::new();
+                for element in elements.iter_mut() {
+                    match self.check(element)? {
+                        Type::Void => return Err("A tuple can't hold a Void value".to_string()),
+                        tp => types.push(tp),
+                    }
+                }
+                Ok(Type::Tuple(types))
+            }
+            AstNode::Variant { variant, value } => {
+                let tp = match value {
+                    Some(value) => self.check(value)?,
+                    None => Type::NotMentioned,
+                };
+                let left_out = Box::new(Type::NotMentioned);
+                Ok(match variant.as_str() {
+                    "Some" | "None" => Type::Option(Box::new(tp)),
+                    "Ok" => Type::Result(Box::new(tp), left_out),
+                    _ => Type::Result(left_out, Box::new(tp)),
+                })
+            }
+            // `value?` is what value holds, or returns None or the Err from the function
+            AstNode::Try { value } => {
+                let tp = self.check(value)?;
+                let returns = self.function_returns.last();
+                match (&tp, returns) {
+                    (Type::Option(held), Some(Type::Option(_))) => Ok(*held.clone()),
+                    (Type::Result(held, error), Some(Type::Result(_, expected))) => {
+                        if !self.assignable(expected, error) && **error != Type::NotMentioned {
+                            return Err(format!(
+                                "'?' can't return the error {:?} from a function whose errors are {:?}",
+                                error, expected
+                            ));
+                        }
+                        Ok(*held.clone())
+                    }
+                    (Type::Option(_) | Type::Result(..), returns) => {
+                        let kind = match tp {
+                            Type::Option(_) => "an Option",
+                            _ => "a Result",
+                        };
+                        Err(format!(
+                            "'?' on {:?} can only be used in a function returning {}{}",
+                            tp,
+                            kind,
+                            returns
+                                .map(|returns| format!(", not {:?}", returns))
+                                .unwrap_or_default()
+                        ))
+                    }
+                    _ => Err(format!("'?' needs an Option or Result, found {:?}", tp)),
+                }
+            }
+            AstNode::FieldAccess { object, field } => {
+                let tp = self.check(object)?;
+                if let Type::Tuple(types) = &tp {
+                    return field
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|index| types.get(index))
+                        .cloned()
+                        .ok_or_else(|| format!("{:?} has no element {}", tp, field));
+                }
+                // Fields can be read through a const pointer
+                let readable = match &tp {
+                    Type::ConstPointer(class) => Type::Pointer(class.clone()),
+                    tp => tp.clone(),
+                };
+                let class = self
+                    .record_of(&readable)
+                    .ok_or_else(|| format!("Can't access field '{}' of {:?}", field, tp))?;
+                self.class_fields(&class)?
+                    .into_iter()
+                    .find(|(_, name, _)| name == field)
+                    .map(|(_, _, tp)| tp)
+                    .ok_or_else(|| format!("{} has no field '{}'", class, field))
+            }
+            AstNode::FieldAssignment {
+                object,
+                field,
+                value,
+            } => {
+                match self.check(object)? {
+                    Type::Tuple(_) => {
+                        return Err(format!(
+                            "Can't assign to element {} of a tuple, tuples can't be changed",
+                            field
+                        ))
+                    }
+                    tp @ Type::ConstPointer(_) => {
+                        return Err(format!(
+                            "Can't assign to field '{}' through {:?}, a const pointer",
+                            field, tp
+                        ))
+                    }
+                    _ => {}
+                }
+                let object = AstNode::FieldAccess {
+                    object: object.clone(),
+                    field: field.clone(),
+                };
+                let field_type = self.check(&mut object.clone())?;
+                let value_type = self.check(value)?;
+                if !self.assignable(&field_type, &value_type) {
+                    return Err(format!(
+                        "cannot assign {:?} to field '{}' of type {:?}",
+                        value_type, field, field_type
+                    ));
+                }
+                Ok(Type::Void)
+            }
+            AstNode::MethodCall {
+                object,
+                method,
+                arguments,
+            } => {
+                let tp = self.check(object)?;
+                if let Some((params, return_type)) = builtin_method(&tp, method) {
+                    let callee = format!("{:?}.{}", tp, method);
+                    self.check_arguments(&callee, &params, arguments)?;
+                    return Ok(return_type);
+                }
+                // Methods can change self, which a const pointer doesn't allow
+                if let Type::ConstPointer(_) = tp {
+                    return Err(format!(
+                        "Can't call method '{}' through {:?}, a const pointer",
+                        method, tp
+                    ));
+                }
+                // A class's own methods come first, then those of its impls
+                if let Some(class) = self.class_of(&tp)
+                    && self.method_owner(&class, method).is_ok()
+                {
+                    let (params, return_type) = self.method_signature(&class, method)?;
+                    let callee = format!("{}.{}", class, method);
+                    self.check_arguments(&callee, &params[1..], arguments)?;
+                    return Ok(return_type);
+                }
+                if let Type::Dyn(interface) = &tp {
+                    let (params, return_type) = self.interface_method(interface, method)?;
+                    let callee = format!("{}.{}", interface, method);
+                    self.check_arguments(&callee, &params, arguments)?;
+                    return Ok(return_type);
+                }
+                let (_, function) = self
+                    .impl_method(&tp, method)?
+                    .ok_or_else(|| format!("Can't call method '{}' on {:?}", method, tp))?;
+                let (params, returns) = self.function_table[&function].clone();
+                let callee = format!("{:?}.{}", tp, method);
+                self.check_arguments(&callee, &params[1..], arguments)?;
+                Ok(returned(&returns))
+            }
+            AstNode::SuperCall {
+                class,
+                method,
+                arguments,
+            } => {
+                let parent = self
+                    .classes
+                    .get(class.as_str())
+                    .and_then(|info| info.parent.clone())
+                    .ok_or_else(|| format!("{} has no parent class for 'super'", class))?;
+                let (params, return_type) = self.method_signature(&parent, method)?;
+                // The first argument is self, which is always a subclass of parent
+                self.check(&mut arguments[0])?;
+                self.check_arguments(
+                    &format!("super.{}", method),
+                    &params[1..],
+                    &mut arguments[1..],
+                )?;
+                Ok(return_type)
+            }
+            AstNode::New { class, arguments } => {
+                let fields: Vec<Type> = self
+                    .class_fields(class)?
+                    .into_iter()
+                    .map(|(_, _, tp)| tp)
+                    .collect();
+                self.check_arguments(&format!("new {}", class), &fields, arguments)?;
+                Ok(Type::Pointer(Box::new(Type::DataTp(class.clone()))))
+            }
             _ => Err(format!("Unsupported node: {:?}", node)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, CompileOptions};
+
+    // The first error checking the program gives, if any
+    fn check(source: &str) -> Option<String> {
+        let options = CompileOptions {
+            stop_after: Some("typecheck".to_string()),
+            ..Default::default()
+        };
+        compile(source, &options)
+            .err()
+            .map(|errors| errors[0].to_string())
+    }
+
+    #[test]
+    fn only_let_mut_variables_can_be_reassigned() {
+        assert_eq!(
+            check("func main() Int |\n    let mut x = 1\n    x = 2\n    return x\n|\n"),
+            None
+        );
+        assert_eq!(
+            check("func main() Int |\n    let x = 1\n    x = 2\n    return x\n|\n"),
+            Some("Type error: Can't reassign 'x', it is an immutable binding, declare it with 'let mut x' to change it".to_string())
+        );
+    }
+
+    #[test]
+    fn assigning_a_variable_that_was_never_declared_is_an_error() {
+        assert_eq!(
+            check("func main() Int |\n    x = 1\n    return 0\n|\n"),
+            Some(
+                "Type error: Can't assign to 'x', it isn't declared, declare it with 'let mut x'"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check("total = 1\nfunc main() Int |\n    return 0\n|\n"),
+            Some(
+                "Type error: Can't assign to 'total', it isn't declared, declare it with 'let mut total'"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn a_let_in_a_block_only_hides_the_variable_inside_it() {
+        let main = "func main() Int |\n    return 0\n|\n";
+        assert_eq!(
+            check(&format!(
+                "let mut x = 1\nlet c = true\nif c |\n    let x = 2\n|\nx = 5\n{}",
+                main
+            )),
+            None
+        );
+        assert_eq!(
+            check(&format!(
+                "let k = 1\nlet c = true\nwhile c |\n    let mut k = 2\n    k = 3\n|\nk = 4\n{}",
+                main
+            )),
+            Some("Type error: Can't reassign 'k', it is an immutable binding, declare it with 'let mut k' to change it".to_string())
+        );
+        assert_eq!(
+            check(&format!(
+                "let mut n = 1\nlet c = true\nif c |\n    let n = 2\n    if c |\n        let mut n = 3\n        n = 4\n    |\n|\nn = 5\n{}",
+                main
+            )),
+            None
+        );
+    }
+}
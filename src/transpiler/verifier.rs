@@ -0,0 +1,972 @@
+// IR verifier - checks that a Module is well formed before it is handed to the next pass.
+// Runs after every IR pass in debug builds, so a broken pass is caught where it happens
+// instead of showing up as a C compiler error.
+
+use super::ir::*;
+use super::type_checker::Type;
+use std::collections::{HashMap, HashSet};
+
+// Where in a function a problem was found, used to pinpoint error messages
+enum Location {
+    Instruction(BlockId, usize),
+    Terminator(BlockId),
+    Function,
+}
+
+struct Verifier<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    errors: Vec<String>,
+}
+
+pub fn verify_module(module: &Module) -> Result<(), String> {
+    let mut errors = Vec::new();
+    let instantiated: HashSet<&str> = module
+        .functions
+        .iter()
+        .flat_map(|function| &function.blocks)
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instruction| match instruction {
+            Instruction::New { class, .. } => Some(class.as_str()),
+            _ => None,
+        })
+        .collect();
+    for (index, class) in module.classes.iter().enumerate() {
+        let instantiated = instantiated.contains(class.name.as_str());
+        if let Err(e) = verify_class(module, index, class, instantiated) {
+            errors.push(format!("in class '{}': {}", class.name, e));
+        }
+    }
+    let used = module.dyn_impls();
+    for (index, implementation) in module.impls.iter().enumerate() {
+        let used = used.iter().any(|other| std::ptr::eq(*other, implementation));
+        if let Err(e) = verify_impl(module, index, implementation, used) {
+            errors.push(format!(
+                "in impl {} for {}: {}",
+                implementation.interface,
+                String::from(implementation.target.clone()),
+                e
+            ));
+        }
+    }
+    let mut names = HashSet::new();
+    for function in &module.functions {
+        if !names.insert(&function.name) {
+            errors.push(format!(
+                "function '{}' is defined more than once",
+                function.name
+            ));
+        }
+        let mut verifier = Verifier {
+            module,
+            function,
+            errors: Vec::new(),
+        };
+        verifier.verify();
+        errors.extend(verifier.errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+// A class comes after its parent and extends the parent's vtable, and every slot is
+// filled by a function that fits it. Dead code elimination drops the functions of
+// classes that are never instantiated, so only theirs may be missing.
+fn verify_class(
+    module: &Module,
+    index: usize,
+    class: &ClassDef,
+    instantiated: bool,
+) -> Result<(), String> {
+    if module.classes[..index]
+        .iter()
+        .any(|other| other.name == class.name)
+    {
+        return Err("class is defined more than once".to_string());
+    }
+    let inherited = match &class.parent {
+        Some(parent) => match module.classes[..index].iter().find(|c| c.name == *parent) {
+            Some(parent) => parent.vtable.as_slice(),
+            None => return Err(format!("parent '{}' is not defined before it", parent)),
+        },
+        None => &[],
+    };
+    if inherited.len() > class.vtable.len()
+        || inherited
+            .iter()
+            .zip(&class.vtable)
+            .any(|(slot, own)| slot.name != own.name || slot.params != own.params)
+    {
+        return Err("vtable doesn't start with the parent's slots".to_string());
+    }
+    for slot in &class.vtable {
+        if !module.is_subclass(&class.name, slot.declared_by()) {
+            return Err(format!(
+                "slot '{}' takes a receiver that isn't a parent class",
+                slot.name
+            ));
+        }
+        let Some(function) = module.functions.iter().find(|f| f.name == slot.function) else {
+            if !instantiated {
+                continue;
+            }
+            return Err(format!(
+                "slot '{}' uses undefined function '{}'",
+                slot.name, slot.function
+            ));
+        };
+        let params: Vec<&Type> = function.params.iter().map(|param| &param.tp).collect();
+        if params != slot.params.iter().collect::<Vec<&Type>>()
+            || function.return_type != slot.return_type
+        {
+            return Err(format!(
+                "'{}' doesn't fit the signature of slot '{}'",
+                slot.function, slot.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// An impl fills every method of its interface, in order, with a function taking the
+// target as self. Like classes, only impls used as a dyn must still have their functions.
+fn verify_impl(
+    module: &Module,
+    index: usize,
+    implementation: &ImplDef,
+    used: bool,
+) -> Result<(), String> {
+    if module.impls[..index].iter().any(|other| {
+        other.interface == implementation.interface && other.target == implementation.target
+    }) {
+        return Err("impl is defined more than once".to_string());
+    }
+    let interface = module
+        .interface(&implementation.interface)
+        .ok_or_else(|| format!("undefined interface '{}'", implementation.interface))?;
+    let names: Vec<&String> = interface.methods.iter().map(|(name, _, _)| name).collect();
+    if names
+        != implementation
+            .methods
+            .iter()
+            .map(|(method, _)| method)
+            .collect::<Vec<&String>>()
+    {
+        return Err(format!("methods don't match the interface's {:?}", names));
+    }
+    for ((method, params, return_type), (_, function)) in
+        interface.methods.iter().zip(&implementation.methods)
+    {
+        let Some(function) = module.functions.iter().find(|f| f.name == *function) else {
+            if !used {
+                continue;
+            }
+            return Err(format!(
+                "method '{}' uses undefined function '{}'",
+                method, function
+            ));
+        };
+        let types: Vec<&Type> = function.params.iter().map(|param| &param.tp).collect();
+        let expected: Vec<&Type> = std::iter::once(&implementation.target)
+            .chain(params)
+            .collect();
+        if types != expected || function.return_type != *return_type
+        {
+            return Err(format!(
+                "'{}' doesn't fit the signature of method '{}'",
+                function.name, method
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Runs the verifier after a pass, but only in debug builds
+pub fn verify_after(pass: &str, module: &Module) -> Result<(), String> {
+    if cfg!(debug_assertions) {
+        verify_module(module).map_err(|e| format!("IR verification failed after {}:\n{}", pass, e))
+    } else {
+        Ok(())
+    }
+}
+
+fn compatible(expected: &Type, actual: &Type) -> bool {
+    // `void` is the null pointer, so it can stand in for any pointer
+    // and C converts a pointer to a const one without a cast
+    expected == actual
+        || (*actual == Type::Void
+            && matches!(
+                expected,
+                Type::Pointer(_) | Type::ConstPointer(_) | Type::String
+            ))
+        || matches!((expected, actual), (Type::ConstPointer(expected), Type::Pointer(actual)) if expected == actual)
+}
+
+impl Verifier<'_> {
+    fn error(&mut self, location: Location, message: String) {
+        let place = match location {
+            Location::Instruction(block, index) => format!("{}, instruction {}", block, index),
+            Location::Terminator(block) => format!("{}, terminator", block),
+            Location::Function => "signature".to_string(),
+        };
+        self.errors.push(format!(
+            "in '{}' at {}: {}",
+            self.function.name, place, message
+        ));
+    }
+
+    fn verify(&mut self) {
+        let function = self.function;
+        if function.blocks.is_empty() {
+            self.error(Location::Function, "function has no blocks".to_string());
+            return;
+        }
+        let mut ids = HashSet::new();
+        for block in &function.blocks {
+            if !ids.insert(block.id) {
+                self.error(
+                    Location::Terminator(block.id),
+                    format!("block {} is defined more than once", block.id),
+                );
+            }
+        }
+        for block in &function.blocks {
+            for target in block.terminator.successors() {
+                if !ids.contains(&target) {
+                    self.error(
+                        Location::Terminator(block.id),
+                        format!("jump to undefined block {}", target),
+                    );
+                }
+            }
+        }
+        if !self.errors.is_empty() {
+            return;
+        }
+
+        let dominators = dominators(function);
+        let mut definitions: HashMap<usize, (BlockId, usize, Type)> = HashMap::new();
+        for block in &function.blocks {
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                if let Some(dest) = instruction.dest()
+                    && definitions
+                        .insert(dest.id, (block.id, index, dest.tp.clone()))
+                        .is_some()
+                {
+                    self.error(
+                        Location::Instruction(block.id, index),
+                        format!("{} is defined more than once", dest),
+                    );
+                }
+            }
+        }
+
+        for block in &function.blocks {
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                let location = || Location::Instruction(block.id, index);
+                for operand in instruction.operands() {
+                    self.check_use(
+                        operand,
+                        block.id,
+                        index,
+                        &definitions,
+                        &dominators,
+                        location(),
+                    );
+                }
+                if let Err(e) = self.check_instruction(instruction) {
+                    self.error(location(), e);
+                }
+            }
+            let uses: Vec<&Operand> = match &block.terminator {
+                Terminator::Return(Some(value)) => vec![value],
+                Terminator::Branch { condition, .. } => vec![condition],
+                _ => vec![],
+            };
+            for operand in uses {
+                let index = block.instructions.len();
+                self.check_use(
+                    operand,
+                    block.id,
+                    index,
+                    &definitions,
+                    &dominators,
+                    Location::Terminator(block.id),
+                );
+            }
+            if let Err(e) = self.check_terminator(&block.terminator) {
+                self.error(Location::Terminator(block.id), e);
+            }
+        }
+    }
+
+    // A temporary can only be used where its definition is guaranteed to have run
+    fn check_use(
+        &mut self,
+        operand: &Operand,
+        block: BlockId,
+        index: usize,
+        definitions: &HashMap<usize, (BlockId, usize, Type)>,
+        dominators: &HashMap<BlockId, HashSet<BlockId>>,
+        location: Location,
+    ) {
+        let Operand::Temp(temp) = operand else {
+            return;
+        };
+        match definitions.get(&temp.id) {
+            None => self.error(location, format!("{} is used but never defined", temp)),
+            Some((_, _, tp)) if *tp != temp.tp => self.error(
+                location,
+                format!("{} is used as {:?} but defined as {:?}", temp, temp.tp, tp),
+            ),
+            Some((def_block, def_index, _)) => {
+                let dominated = if *def_block == block {
+                    *def_index < index
+                } else {
+                    dominators
+                        .get(&block)
+                        .is_some_and(|doms| doms.contains(def_block))
+                };
+                if !dominated {
+                    self.error(
+                        location,
+                        format!(
+                            "use of {} is not dominated by its definition in {}",
+                            temp, def_block
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_var(&self, var: &Var) -> Result<(), String> {
+        // Scoped names belong to a function, anything else is a global or a prelude constant
+        if !var.name.contains("::") {
+            return Ok(());
+        }
+        match self
+            .function
+            .params
+            .iter()
+            .chain(self.function.locals.iter())
+            .find(|local| local.name == var.name)
+        {
+            Some(local) if local.tp == var.tp => Ok(()),
+            Some(local) => Err(format!(
+                "variable '{}' is used as {:?} but declared as {:?}",
+                var.name, var.tp, local.tp
+            )),
+            None => Err(format!("variable '{}' is not declared", var.name)),
+        }
+    }
+
+    fn check_instruction(&self, instruction: &Instruction) -> Result<(), String> {
+        match instruction {
+            Instruction::Load { dest, var } => {
+                self.check_var(var)?;
+                if dest.tp != var.tp {
+                    return Err(format!(
+                        "load of {:?} variable '{}' into {:?} {}",
+                        var.tp, var.name, dest.tp, dest
+                    ));
+                }
+            }
+            Instruction::Store { var, value } => {
+                self.check_var(var)?;
+                if self
+                    .module
+                    .globals
+                    .iter()
+                    .any(|global| global.var.name == var.name && !global.mutable)
+                {
+                    return Err(format!("store to '{}', a global that isn't mut", var.name));
+                }
+                if !compatible(&var.tp, &value.tp()) {
+                    return Err(format!(
+                        "store of {:?} into {:?} variable '{}'",
+                        value.tp(),
+                        var.tp,
+                        var.name
+                    ));
+                }
+            }
+            Instruction::Binary {
+                dest,
+                op,
+                left,
+                right,
+            } => {
+                let tp = op.result_type(&left.tp(), &right.tp())?;
+                if tp != dest.tp {
+                    return Err(format!(
+                        "{} produces {:?} but {} is {:?}",
+                        op.mnemonic(),
+                        tp,
+                        dest,
+                        dest.tp
+                    ));
+                }
+            }
+            Instruction::Cast { dest, value } => {
+                let scalar = |tp: &Type| matches!(tp, Type::Integer | Type::Char | Type::Bool);
+                let value_type = value.tp();
+                // Class pointers can be cast up or down their own hierarchy
+                let related = match (
+                    class_pointer(&dest.tp, self.module),
+                    class_pointer(&value_type, self.module),
+                ) {
+                    (Some(to), Some(from)) => {
+                        self.module.is_subclass(to, from) || self.module.is_subclass(from, to)
+                    }
+                    _ => false,
+                };
+                // A lambda's environment is passed as a void* and cast back to its struct
+                let environment = value_type == Type::Pointer(Box::new(Type::Void))
+                    && matches!(dest.tp, Type::Pointer(_));
+                if !related && !environment && (!scalar(&dest.tp) || !scalar(&value_type)) {
+                    return Err(format!("cannot cast {:?} to {:?}", value_type, dest.tp));
+                }
+            }
+            Instruction::New { dest, class } => {
+                let record = self.module.structs.iter().any(|def| def.name == *class);
+                if self.module.class(class).is_none() && !record {
+                    return Err(format!("new of undefined class '{}'", class));
+                }
+                let allocated = if record {
+                    dest.tp == Type::Pointer(Box::new(Type::DataTp(class.clone())))
+                } else {
+                    class_pointer(&dest.tp, self.module) == Some(class)
+                };
+                if !allocated {
+                    return Err(format!("new {} stored in {:?} {}", class, dest.tp, dest));
+                }
+            }
+            Instruction::GetField {
+                dest,
+                object,
+                field,
+            } => {
+                let tp = self.field_type(object, field)?;
+                if dest.tp != tp {
+                    return Err(format!(
+                        "field '{}' is {:?} but {} is {:?}",
+                        field, tp, dest, dest.tp
+                    ));
+                }
+            }
+            Instruction::SetField {
+                object,
+                field,
+                value,
+            } => {
+                let tp = self.field_type(object, field)?;
+                if !compatible(&tp, &value.tp()) {
+                    return Err(format!(
+                        "store of {:?} into {:?} field '{}'",
+                        value.tp(),
+                        tp,
+                        field
+                    ));
+                }
+            }
+            Instruction::CallMethod {
+                dest,
+                class,
+                method,
+                arguments,
+            } => {
+                let slot = self
+                    .module
+                    .class(class)
+                    .and_then(|def| def.vtable.iter().find(|slot| slot.name == *method))
+                    .ok_or_else(|| format!("'{}' has no method '{}'", class, method))?;
+                if slot.declared_by() != class {
+                    return Err(format!(
+                        "'{}' is called through {} instead of {}, which declares it",
+                        method,
+                        class,
+                        slot.declared_by()
+                    ));
+                }
+                let types: Vec<Type> = arguments.iter().map(Operand::tp).collect();
+                if types.len() != slot.params.len()
+                    || slot
+                        .params
+                        .iter()
+                        .zip(&types)
+                        .any(|(param, argument)| !compatible(param, argument))
+                {
+                    return Err(format!(
+                        "call to '{}.{}' passes {:?} but it takes {:?}",
+                        class, method, types, slot.params
+                    ));
+                }
+                match dest {
+                    Some(dest) if dest.tp != slot.return_type => {
+                        return Err(format!(
+                            "'{}.{}' returns {:?} but {} is {:?}",
+                            class, method, slot.return_type, dest, dest.tp
+                        ))
+                    }
+                    None if slot.return_type != Type::Void => {
+                        return Err(format!(
+                            "result of '{}.{}' ({:?}) is not stored",
+                            class, method, slot.return_type
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+            Instruction::Call {
+                dest,
+                function,
+                arguments,
+            } => {
+                // Functions from prelude.c aren't in the module, so they can't be checked here
+                let Some(callee) = self.module.functions.iter().find(|f| f.name == *function)
+                else {
+                    return Ok(());
+                };
+                if callee.params.len() != arguments.len() {
+                    return Err(format!(
+                        "call to '{}' passes {} arguments but it takes {}",
+                        function,
+                        arguments.len(),
+                        callee.params.len()
+                    ));
+                }
+                for (param, argument) in callee.params.iter().zip(arguments) {
+                    if !compatible(&param.tp, &argument.tp()) {
+                        return Err(format!(
+                            "call to '{}' passes {:?} for parameter '{}' of type {:?}",
+                            function,
+                            argument.tp(),
+                            param.name,
+                            param.tp
+                        ));
+                    }
+                }
+                match dest {
+                    Some(dest) if dest.tp != callee.return_type => {
+                        return Err(format!(
+                            "'{}' returns {:?} but {} is {:?}",
+                            function, callee.return_type, dest, dest.tp
+                        ))
+                    }
+                    None if callee.return_type != Type::Void => {
+                        return Err(format!(
+                            "result of '{}' ({:?}) is not stored",
+                            function, callee.return_type
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+            Instruction::AddressOf { dest, var } => {
+                self.check_var(var)?;
+                let pointee = match &dest.tp {
+                    Type::Pointer(tp) | Type::ConstPointer(tp) => Some(tp.as_ref()),
+                    _ => None,
+                };
+                if pointee != Some(&var.tp) {
+                    return Err(format!(
+                        "address of {:?} variable '{}' stored in {:?} {}",
+                        var.tp, var.name, dest.tp, dest
+                    ));
+                }
+            }
+            Instruction::ToDyn { dest, value } => {
+                let Type::Dyn(interface) = &dest.tp else {
+                    return Err(format!("todyn into {:?} {}, which isn't a dyn", dest.tp, dest));
+                };
+                if self.module.impl_of(interface, &value.tp()).is_none() {
+                    return Err(format!(
+                        "{:?} has no impl of {} to make a dyn with",
+                        value.tp(),
+                        interface
+                    ));
+                }
+            }
+            Instruction::CallDyn {
+                dest,
+                interface,
+                method,
+                arguments,
+            } => {
+                let (params, return_type) = self
+                    .module
+                    .interface(interface)
+                    .and_then(|def| def.methods.iter().find(|(name, _, _)| name == method))
+                    .map(|(_, params, return_type)| (params, return_type))
+                    .ok_or_else(|| format!("'{}' has no method '{}'", interface, method))?;
+                let types: Vec<Type> = arguments.iter().map(Operand::tp).collect();
+                if types.first() != Some(&Type::Dyn(interface.clone()))
+                    || types.len() != params.len() + 1
+                    || params
+                        .iter()
+                        .zip(&types[1..])
+                        .any(|(param, argument)| !compatible(param, argument))
+                {
+                    return Err(format!(
+                        "call to '{}.{}' passes {:?} but it takes a dyn then {:?}",
+                        interface, method, types, params
+                    ));
+                }
+                match dest {
+                    Some(dest) if dest.tp != *return_type => {
+                        return Err(format!(
+                            "'{}.{}' returns {:?} but {} is {:?}",
+                            interface, method, return_type, dest, dest.tp
+                        ))
+                    }
+                    None if *return_type != Type::Void => {
+                        return Err(format!(
+                            "result of '{}.{}' ({:?}) is not stored",
+                            interface, method, return_type
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+            Instruction::MakeClosure {
+                dest,
+                function,
+                captures,
+            } => {
+                let Type::Function(params, return_type) = &dest.tp else {
+                    return Err(format!(
+                        "closure of '{}' in {:?} {}, which isn't a function value",
+                        function, dest.tp, dest
+                    ));
+                };
+                let callee = self
+                    .module
+                    .functions
+                    .iter()
+                    .find(|f| f.name == *function)
+                    .ok_or_else(|| format!("closure of undefined function '{}'", function))?;
+                let takes: Vec<Type> = callee.params.iter().map(|param| param.tp.clone()).collect();
+                if takes.first() != Some(&Type::Pointer(Box::new(Type::Void)))
+                    || takes[1..] != params[..]
+                    || callee.return_type != **return_type
+                {
+                    return Err(format!(
+                        "closure of '{}', which takes {:?} and returns {:?}, in {:?} {}",
+                        function, takes, callee.return_type, dest.tp, dest
+                    ));
+                }
+                let fields = match self
+                    .module
+                    .structs
+                    .iter()
+                    .find(|def| def.name == env_symbol(function))
+                {
+                    Some(env) => env.fields.iter().map(|var| var.tp.clone()).collect(),
+                    None => Vec::new(),
+                };
+                let types: Vec<Type> = captures.iter().map(Operand::tp).collect();
+                if types.len() != fields.len()
+                    || fields
+                        .iter()
+                        .zip(&types)
+                        .any(|(field, capture)| !compatible(field, capture))
+                {
+                    return Err(format!(
+                        "closure of '{}' captures {:?} but its environment holds {:?}",
+                        function, types, fields
+                    ));
+                }
+            }
+            Instruction::CallClosure { dest, arguments } => {
+                let Some(Type::Function(params, return_type)) = arguments.first().map(Operand::tp)
+                else {
+                    return Err("call through something that isn't a function value".to_string());
+                };
+                let types: Vec<Type> = arguments[1..].iter().map(Operand::tp).collect();
+                if types.len() != params.len()
+                    || params
+                        .iter()
+                        .zip(&types)
+                        .any(|(param, argument)| !compatible(param, argument))
+                {
+                    return Err(format!(
+                        "call through {} passes {:?} but it takes {:?}",
+                        arguments[0], types, params
+                    ));
+                }
+                match dest {
+                    Some(dest) if dest.tp != *return_type => {
+                        return Err(format!(
+                            "{} returns {:?} but {} is {:?}",
+                            arguments[0], return_type, dest, dest.tp
+                        ))
+                    }
+                    None if *return_type != Type::Void => {
+                        return Err(format!(
+                            "result of {} ({:?}) is not stored",
+                            arguments[0], return_type
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+            Instruction::Deref { pointer, .. } => match pointer.tp() {
+                Type::Pointer(_) | Type::ConstPointer(_) | Type::String => {}
+                tp => return Err(format!("cannot dereference {:?}", tp)),
+            },
+            Instruction::MakeTuple { dest, elements } => {
+                let types: Vec<Type> = elements.iter().map(Operand::tp).collect();
+                let fits = match &dest.tp {
+                    Type::Tuple(expected) => {
+                        expected.len() == types.len()
+                            && expected
+                                .iter()
+                                .zip(&types)
+                                .all(|(expected, actual)| compatible(expected, actual))
+                    }
+                    _ => false,
+                };
+                if !fits {
+                    return Err(format!(
+                        "tuple of {:?} in {:?} {}",
+                        types, dest.tp, dest
+                    ));
+                }
+            }
+            Instruction::GetElement { dest, tuple, index } => {
+                let Type::Tuple(types) = tuple.tp() else {
+                    return Err(format!("element {} of {}, which isn't a tuple", index, tuple));
+                };
+                match types.get(*index) {
+                    Some(tp) if *tp == dest.tp => {}
+                    Some(tp) => {
+                        return Err(format!(
+                            "element {} of {} is {:?} but {} is {:?}",
+                            index, tuple, tp, dest, dest.tp
+                        ))
+                    }
+                    None => return Err(format!("{} has no element {}", tuple, index)),
+                }
+            }
+            Instruction::MakeVariant {
+                dest,
+                variant,
+                value,
+            } => {
+                let fits = match (variant.held(&dest.tp), value) {
+                    (Some(held), Some(value)) => compatible(held, &value.tp()),
+                    (Some(held), None) => *held == Type::Void,
+                    (None, None) => matches!(dest.tp, Type::Option(_)),
+                    (None, Some(_)) => false,
+                };
+                if !fits {
+                    return Err(format!(
+                        "{} of {:?} in {:?} {}",
+                        variant.as_str(),
+                        value.as_ref().map(Operand::tp),
+                        dest.tp,
+                        dest
+                    ));
+                }
+            }
+            Instruction::IsVariant { dest, value, .. } => {
+                if dest.tp != Type::Bool {
+                    return Err(format!("variant test into {:?} {}", dest.tp, dest));
+                }
+                if !matches!(value.tp(), Type::Option(_) | Type::Result(..)) {
+                    return Err(format!("{} isn't an Option or Result", value));
+                }
+            }
+            Instruction::Unwrap {
+                dest,
+                value,
+                variant,
+            } => {
+                let tp = value.tp();
+                match variant.held(&tp) {
+                    Some(held) if *held == dest.tp => {}
+                    Some(held) => {
+                        return Err(format!(
+                            "{} of {} is {:?} but {} is {:?}",
+                            variant.as_str(),
+                            value,
+                            held,
+                            dest,
+                            dest.tp
+                        ))
+                    }
+                    None => {
+                        return Err(format!(
+                            "{} of {}, which is {:?}",
+                            variant.as_str(),
+                            value,
+                            tp
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Fields are only reached through a pointer to the class declaring them, or to a
+    // struct like a lambda's environment
+    fn field_type(&self, object: &Operand, field: &str) -> Result<Type, String> {
+        let tp = object.tp();
+        if let Type::Pointer(pointee) = &tp
+            && let Type::DataTp(name) = pointee.as_ref()
+            && let Some(def) = self.module.structs.iter().find(|def| def.name == *name)
+        {
+            return def
+                .fields
+                .iter()
+                .find(|var| var.name == field)
+                .map(|var| var.tp.clone())
+                .ok_or_else(|| format!("'{}' doesn't have a field '{}'", name, field));
+        }
+        let class = class_pointer(&tp, self.module)
+            .and_then(|class| self.module.class(class))
+            .ok_or_else(|| format!("field '{}' of {:?}, which isn't a class pointer", field, tp))?;
+        class
+            .fields
+            .iter()
+            .find(|var| var.name == field)
+            .map(|var| var.tp.clone())
+            .ok_or_else(|| format!("'{}' doesn't declare a field '{}'", class.name, field))
+    }
+
+    fn check_terminator(&self, terminator: &Terminator) -> Result<(), String> {
+        let return_type = &self.function.return_type;
+        match terminator {
+            Terminator::Return(Some(value)) if !compatible(return_type, &value.tp()) => {
+                Err(format!(
+                    "returns {:?} from a function returning {:?}",
+                    value.tp(),
+                    return_type
+                ))
+            }
+            Terminator::Return(None) if *return_type != Type::Void => Err(format!(
+                "returns nothing from a function returning {:?}",
+                return_type
+            )),
+            Terminator::Branch { condition, .. } if condition.tp() != Type::Bool => Err(format!(
+                "branch condition is {:?}, expected Bool",
+                condition.tp()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Iterative dataflow - a block's dominators are itself plus the blocks dominating all of
+// its predecessors. Blocks the entry can't reach are dominated by everything.
+fn dominators(function: &Function) -> HashMap<BlockId, HashSet<BlockId>> {
+    let entry = function.blocks[0].id;
+    let all: HashSet<BlockId> = function.blocks.iter().map(|block| block.id).collect();
+    let mut predecessors: HashMap<BlockId, Vec<BlockId>> = HashMap::new();
+    for block in &function.blocks {
+        for successor in block.terminator.successors() {
+            predecessors.entry(successor).or_default().push(block.id);
+        }
+    }
+    let mut dominators: HashMap<BlockId, HashSet<BlockId>> = function
+        .blocks
+        .iter()
+        .map(|block| {
+            let initial = if block.id == entry {
+                HashSet::from([entry])
+            } else {
+                all.clone()
+            };
+            (block.id, initial)
+        })
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in function.blocks.iter().skip(1) {
+            let mut new = predecessors
+                .get(&block.id)
+                .into_iter()
+                .flatten()
+                .map(|pred| dominators[pred].clone())
+                .reduce(|a, b| a.intersection(&b).cloned().collect())
+                .unwrap_or_else(|| all.clone());
+            new.insert(block.id);
+            if new != dominators[&block.id] {
+                dominators.insert(block.id, new);
+                changed = true;
+            }
+        }
+    }
+    dominators
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify_module;
+    use crate::transpiler::ir::{Function, Module};
+    use crate::transpiler::ir_text::parse_module;
+    use crate::transpiler::type_checker::Type;
+
+    fn verify(text: &str) -> Result<(), String> {
+        verify_module(&parse_module(text).unwrap())
+    }
+
+    #[test]
+    fn well_formed_function_is_accepted() {
+        let text = "func pick(pick::c: Bool) -> Int {\n    local pick::x: Int\nbb0:\n    store pick::x, 1\n    %0: Bool = load pick::c\n    branch %0, bb1, bb2\nbb1:\n    store pick::x, 2\n    jump bb2\nbb2:\n    %1: Int = load pick::x\n    ret %1\n}\n";
+        assert_eq!(verify(text), Ok(()));
+    }
+
+    #[test]
+    fn use_not_dominated_by_its_definition_is_rejected() {
+        let text = "func pick(pick::c: Bool) -> Int {\nbb0:\n    %0: Bool = load pick::c\n    branch %0, bb1, bb2\nbb1:\n    %1: Int = add 1, 2\n    jump bb2\nbb2:\n    ret %1\n}\n";
+        assert_eq!(
+            verify(text).unwrap_err(),
+            "in 'pick' at bb2, terminator: use of %1 is not dominated by its definition in bb1"
+        );
+    }
+
+    #[test]
+    fn function_without_blocks_is_rejected() {
+        let module = Module {
+            functions: vec![Function {
+                name: "empty".to_string(),
+                params: Vec::new(),
+                return_type: Type::Void,
+                locals: Vec::new(),
+                blocks: Vec::new(),
+                attributes: Vec::new(),
+                public: false,
+            }],
+            ..Module::default()
+        };
+        assert_eq!(
+            verify_module(&module).unwrap_err(),
+            "in 'empty' at signature: function has no blocks"
+        );
+    }
+
+    #[test]
+    fn returning_the_wrong_type_is_rejected() {
+        let text = "func one() -> Int {\nbb0:\n    ret true\n}\n";
+        assert_eq!(
+            verify(text).unwrap_err(),
+            "in 'one' at bb0, terminator: returns Bool from a function returning Integer"
+        );
+    }
+
+    #[test]
+    fn loading_into_a_temp_of_another_type_is_rejected() {
+        let text = "func f(f::c: Bool) -> Int {\nbb0:\n    %0: Int = load f::c\n    ret %0\n}\n";
+        assert_eq!(
+            verify(text).unwrap_err(),
+            "in 'f' at bb0, instruction 0: load of Bool variable 'f::c' into Integer %0"
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use std::any::Any;
-use std::cell::Cell;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Id {
@@ -51,6 +52,8 @@ impl From<&str> for TypeName {
     }
 }
 
+// Box<dyn Value> is a Value too, call these on the inner value (`boxed.as_ref()`)
+// rather than the box, or as_any() returns the box itself
 pub trait Value: Any + std::fmt::Debug {
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
@@ -71,6 +74,12 @@ impl Registry {
     }
 }
 
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // Functions to register variables, functions, and classes
 impl Registry {
     pub fn register_variable<T: Value>(&mut self, value: T) -> Id {
@@ -81,6 +90,14 @@ impl Registry {
         *(self.id) += 1;
         (*(self).id - 1).into()
     }
+
+    // For values whose concrete type is only known at runtime
+    pub fn register_boxed(&mut self, value: Box<dyn Value>) -> Id {
+        let id = self.id.clone();
+        self.variables.insert(id.clone(), (value.typename(), value));
+        *(self.id) += 1;
+        id
+    }
 }
 
 // Functions to get variables, functions, and classes
@@ -90,16 +107,9 @@ impl Registry {
     }
 }
 
-// Functions to get mutable variables, functions, and classes
-impl Registry {
-    fn get_variable_mut(&mut self, id: Id) -> Option<&mut (TypeName, Box<dyn Value>)> {
-        self.variables.get_mut(&id)
-    }
-}
-
 // Functions to remove variables, functions, and classes
 impl Registry {
-    fn remove_variable(&mut self, id: Id) -> Option<(TypeName, Box<dyn Value>)> {
+    pub fn remove_variable(&mut self, id: Id) -> Option<(TypeName, Box<dyn Value>)> {
         self.variables.remove(&id)
     }
 }
@@ -110,6 +120,10 @@ impl Registry {
         self.variables
             .insert(id.clone(), (value.typename(), Box::new(value)));
     }
+
+    pub fn set_boxed(&mut self, id: &Id, value: Box<dyn Value>) {
+        self.variables.insert(id.clone(), (value.typename(), value));
+    }
 }
 
 type Type = Box<dyn Fn(Vec<Box<dyn Value>>) -> Box<dyn Value>>;
@@ -127,7 +141,7 @@ impl std::fmt::Debug for Function {
 #[derive(Debug, Clone)]
 pub struct ClassBase {
     typename: TypeName,
-    functable: HashMap<String, Id>,
+    functable: HashMap<String, Id>, // Method name -> Function ID
     field_names: Vec<String>,
     parent: Option<Id>, // Parent Class Base ID
 }
@@ -141,29 +155,180 @@ impl ClassBase {
             parent: None,
         }
     }
+
+    pub fn set_parent(&mut self, parent: Id) {
+        self.parent = Some(parent);
+    }
+
+    // function is the ID of a registered Function, it gets the instance as its first
+    // argument. The interpreter registers the name of a TSL function instead.
+    pub fn add_method(&mut self, name: impl Into<String>, function: Id) {
+        self.functable.insert(name.into(), function);
+    }
+
+    pub fn name(&self) -> &TypeName {
+        &self.typename
+    }
+
+    // Only the fields this class declares, see Registry::class_fields for inherited ones
+    pub fn field_names(&self) -> &[String] {
+        &self.field_names
+    }
+
+    pub fn parent(&self) -> Option<&Id> {
+        self.parent.as_ref()
+    }
+
+    // Only the methods this class declares, see Registry::lookup_method for inherited ones
+    pub fn method(&self, name: &str) -> Option<&Id> {
+        self.functable.get(name)
+    }
 }
 
 #[derive(Debug)]
 pub struct ClassInstance {
     class_base: Id,
-    variables: HashMap<String, Id>,
+    variables: HashMap<String, Id>, // Field name -> Variable ID
 }
 
 impl ClassInstance {
-    // New function takes the class base id, values of the fields
-    pub fn new(class_base: Id, values: Vec<Box<dyn Value>>, registry: &mut Registry) -> Self {
-        // Register all the spaces for the fields but don't set the values
+    // New function takes the class base id and the values of the fields, inherited fields
+    // first, and registers a variable for each field
+    pub fn new(
+        class_base: Id,
+        values: Vec<Box<dyn Value>>,
+        registry: &mut Registry,
+    ) -> Result<Self, String> {
+        let field_names = registry.class_fields(&class_base)?;
+        if field_names.len() != values.len() {
+            return Err(format!(
+                "{} has {} fields but {} values were given",
+                registry.get_class(&class_base)?.name().as_str(),
+                field_names.len(),
+                values.len()
+            ));
+        }
         let mut variables = HashMap::new();
-        // use a while loop to iterate over the field names and values to set the values
-        let mut i = 0;
-        while i < values.len() {}
-        Self {
+        for (name, value) in field_names.into_iter().zip(values) {
+            variables.insert(name, registry.register_boxed(value));
+        }
+        Ok(Self {
             class_base,
             variables,
+        })
+    }
+
+    pub fn class_base(&self) -> &Id {
+        &self.class_base
+    }
+
+    // The ID of the variable holding the field
+    pub fn field(&self, name: &str) -> Option<&Id> {
+        self.variables.get(name)
+    }
+
+    pub fn get_field<'a>(&self, name: &str, registry: &'a Registry) -> Option<&'a dyn Value> {
+        let id = self.field(name)?;
+        registry.get_variable(id).map(|(_, value)| value.as_ref())
+    }
+
+    pub fn set_field(
+        &self,
+        name: &str,
+        value: Box<dyn Value>,
+        registry: &mut Registry,
+    ) -> Result<(), String> {
+        let id = self
+            .field(name)
+            .ok_or_else(|| format!("No field named '{}'", name))?;
+        registry.set_boxed(id, value);
+        Ok(())
+    }
+
+    // Removes the fields' variables, the instance is unusable afterwards
+    pub fn free(self, registry: &mut Registry) {
+        for (_, id) in self.variables {
+            registry.remove_variable(id);
         }
     }
 }
 
+// Functions for classes and their instances
+impl Registry {
+    pub fn get_class(&self, id: &Id) -> Result<&ClassBase, String> {
+        self.get_variable(id)
+            .and_then(|(_, value)| value.as_ref().as_any().downcast_ref::<ClassBase>())
+            .ok_or_else(|| format!("Variable {} is not a class", **id))
+    }
+
+    pub fn get_instance(&self, id: &Id) -> Result<&ClassInstance, String> {
+        self.get_variable(id)
+            .and_then(|(_, value)| value.as_ref().as_any().downcast_ref::<ClassInstance>())
+            .ok_or_else(|| format!("Variable {} is not a class instance", **id))
+    }
+
+    // The class followed by its parent, its parent's parent and so on
+    fn ancestry(&self, class: &Id) -> Result<Vec<&ClassBase>, String> {
+        let mut ancestry = Vec::new();
+        let mut seen = Vec::new();
+        let mut next = Some(class.clone());
+        while let Some(id) = next {
+            if seen.contains(&id) {
+                return Err(format!(
+                    "Class {} inherits from itself",
+                    self.get_class(class)?.name().as_str()
+                ));
+            }
+            let base = self.get_class(&id)?;
+            next = base.parent().cloned();
+            seen.push(id);
+            ancestry.push(base);
+        }
+        Ok(ancestry)
+    }
+
+    // Every field of an instance of the class, inherited fields first
+    pub fn class_fields(&self, class: &Id) -> Result<Vec<String>, String> {
+        let ancestry = self.ancestry(class)?;
+        Ok(ancestry
+            .iter()
+            .rev()
+            .flat_map(|base| base.field_names().iter().cloned())
+            .collect())
+    }
+
+    // Finds the method in the class, falling back to its parents, nearest first
+    pub fn lookup_method(&self, class: &Id, name: &str) -> Result<Id, String> {
+        let ancestry = self.ancestry(class)?;
+        ancestry
+            .iter()
+            .find_map(|base| base.method(name).cloned())
+            .ok_or_else(|| format!("{} has no method '{}'", ancestry[0].name().as_str(), name))
+    }
+
+    // Calls the method with a pointer to the instance in front of the arguments
+    pub fn call_method(
+        &self,
+        instance: &Id,
+        name: &str,
+        mut arguments: Vec<Box<dyn Value>>,
+    ) -> Result<Box<dyn Value>, String> {
+        let class = self.get_instance(instance)?.class_base();
+        let method = self.lookup_method(class, name)?;
+        let function = self
+            .get_variable(&method)
+            .and_then(|(_, value)| value.as_ref().as_any().downcast_ref::<Function>())
+            .ok_or_else(|| format!("Method '{}' is not a function", name))?;
+        arguments.insert(
+            0,
+            Box::new(TslPointer {
+                target: instance.clone(),
+            }),
+        );
+        Ok((function.function)(arguments))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct TslString {
     pub value: String,
@@ -188,6 +353,30 @@ pub struct TslInt {
     pub value: i128,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TslBool {
+    pub value: bool,
+}
+
+// A C char, a byte rather than a Rust char
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TslChar {
+    pub value: u8,
+}
+
+// Points at a registered variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TslPointer {
+    pub target: Id,
+}
+
+// Memory from new_buffer, shared by every pointer into it
+#[derive(Debug, Clone)]
+pub struct TslBuffer {
+    pub bytes: Rc<RefCell<Vec<u8>>>,
+    pub offset: usize,
+}
+
 #[macro_export]
 macro_rules! register_variable {
     ($registry:ident, $value:expr, $store:ident) => {
@@ -198,14 +387,14 @@ macro_rules! register_variable {
 #[macro_export]
 macro_rules! int {
     ($value:expr) => {
-        $crate::TslInt { value: $value }
+        $crate::types::TslInt { value: $value }
     };
 }
 
 #[macro_export]
 macro_rules! string {
     ($value:expr) => {
-        $crate::TslString {
+        $crate::types::TslString {
             value: $value.to_string(),
         }
     };
@@ -256,7 +445,15 @@ implement_names!(
     Box<dyn Value>,
     "Value",
     TslNull,
-    "Null"
+    "Null",
+    TslBool,
+    "Bool",
+    TslChar,
+    "Char",
+    TslPointer,
+    "Pointer",
+    TslBuffer,
+    "Buffer"
 );
 
 implement_many!(
@@ -266,5 +463,195 @@ implement_many!(
     ClassInstance,
     Function,
     Box<dyn Value>,
-    TslNull
+    TslNull,
+    TslBool,
+    TslChar,
+    TslPointer,
+    TslBuffer
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(registry: &Registry, instance: &ClassInstance, field: &str) -> i128 {
+        instance
+            .get_field(field, registry)
+            .and_then(|value| value.as_any().downcast_ref::<TslInt>())
+            .map(|int| int.value)
+            .expect("an Int field")
+    }
+
+    fn method(registry: &mut Registry, result: i128) -> Id {
+        registry.register_variable(Function {
+            function: Box::new(move |_| Box::new(TslInt { value: result })),
+        })
+    }
+
+    fn result(value: Box<dyn Value>) -> i128 {
+        value
+            .as_ref()
+            .as_any()
+            .downcast_ref::<TslInt>()
+            .unwrap()
+            .value
+    }
+
+    // Shape { sides, area() = 1, name() = 2 } and Square: Shape { width, area() = 3 }
+    fn shapes(registry: &mut Registry) -> (Id, Id) {
+        let mut shape = ClassBase::new("Shape", vec!["sides".to_string()]);
+        shape.add_method("area", method(registry, 1));
+        shape.add_method("name", method(registry, 2));
+        let shape = registry.register_variable(shape);
+        let mut square = ClassBase::new("Square", vec!["width".to_string()]);
+        square.set_parent(shape.clone());
+        square.add_method("area", method(registry, 3));
+        let square = registry.register_variable(square);
+        (shape, square)
+    }
+
+    #[test]
+    fn new_registers_each_field() {
+        let mut registry = Registry::new();
+        let point = registry.register_variable(ClassBase::new(
+            "Point",
+            vec!["x".to_string(), "y".to_string()],
+        ));
+        let values: Vec<Box<dyn Value>> = vec![Box::new(int!(1)), Box::new(int!(2))];
+        let instance = ClassInstance::new(point.clone(), values, &mut registry).unwrap();
+        assert_eq!(instance.class_base(), &point);
+        assert_eq!(value(&registry, &instance, "x"), 1);
+        assert_eq!(value(&registry, &instance, "y"), 2);
+        assert_ne!(instance.field("x"), instance.field("y"));
+        assert!(instance.get_field("z", &registry).is_none());
+    }
+
+    #[test]
+    fn new_rejects_the_wrong_number_of_values() {
+        let mut registry = Registry::new();
+        let point = registry.register_variable(ClassBase::new(
+            "Point",
+            vec!["x".to_string(), "y".to_string()],
+        ));
+        let error = ClassInstance::new(point, vec![Box::new(int!(1))], &mut registry).unwrap_err();
+        assert_eq!(error, "Point has 2 fields but 1 values were given");
+    }
+
+    #[test]
+    fn new_needs_a_class() {
+        let mut registry = Registry::new();
+        let not_a_class = registry.register_variable(int!(1));
+        assert!(ClassInstance::new(not_a_class, Vec::new(), &mut registry).is_err());
+    }
+
+    #[test]
+    fn set_field_updates_the_registry() {
+        let mut registry = Registry::new();
+        let counter =
+            registry.register_variable(ClassBase::new("Counter", vec!["count".to_string()]));
+        let instance = ClassInstance::new(counter, vec![Box::new(int!(0))], &mut registry).unwrap();
+        instance
+            .set_field("count", Box::new(int!(5)), &mut registry)
+            .unwrap();
+        assert_eq!(value(&registry, &instance, "count"), 5);
+        assert!(instance
+            .set_field("missing", Box::new(int!(5)), &mut registry)
+            .is_err());
+    }
+
+    #[test]
+    fn free_removes_the_fields() {
+        let mut registry = Registry::new();
+        let counter =
+            registry.register_variable(ClassBase::new("Counter", vec!["count".to_string()]));
+        let instance = ClassInstance::new(counter, vec![Box::new(int!(0))], &mut registry).unwrap();
+        let field = instance.field("count").unwrap().clone();
+        instance.free(&mut registry);
+        assert!(registry.get_variable(&field).is_none());
+    }
+
+    #[test]
+    fn subclasses_inherit_fields_first() {
+        let mut registry = Registry::new();
+        let (_, square) = shapes(&mut registry);
+        assert_eq!(registry.class_fields(&square).unwrap(), ["sides", "width"]);
+        let values: Vec<Box<dyn Value>> = vec![Box::new(int!(4)), Box::new(int!(10))];
+        let instance = ClassInstance::new(square, values, &mut registry).unwrap();
+        assert_eq!(value(&registry, &instance, "sides"), 4);
+        assert_eq!(value(&registry, &instance, "width"), 10);
+    }
+
+    #[test]
+    fn methods_fall_back_to_the_parent() {
+        let mut registry = Registry::new();
+        let (shape, square) = shapes(&mut registry);
+        let own = registry.lookup_method(&square, "area").unwrap();
+        let inherited = registry.lookup_method(&square, "name").unwrap();
+        assert_ne!(own, registry.lookup_method(&shape, "area").unwrap());
+        assert_eq!(inherited, registry.lookup_method(&shape, "name").unwrap());
+        assert_eq!(
+            registry.lookup_method(&square, "perimeter").unwrap_err(),
+            "Square has no method 'perimeter'"
+        );
+    }
+
+    #[test]
+    fn call_method_dispatches_on_the_instance_class() {
+        let mut registry = Registry::new();
+        let (shape, square) = shapes(&mut registry);
+        let values: Vec<Box<dyn Value>> = vec![Box::new(int!(4)), Box::new(int!(10))];
+        let instance = ClassInstance::new(square, values, &mut registry).unwrap();
+        let instance = registry.register_variable(instance);
+        let base = ClassInstance::new(shape, vec![Box::new(int!(3))], &mut registry).unwrap();
+        let base = registry.register_variable(base);
+        assert_eq!(
+            result(registry.call_method(&instance, "area", vec![]).unwrap()),
+            3
+        );
+        assert_eq!(
+            result(registry.call_method(&instance, "name", vec![]).unwrap()),
+            2
+        );
+        assert_eq!(
+            result(registry.call_method(&base, "area", vec![]).unwrap()),
+            1
+        );
+    }
+
+    #[test]
+    fn methods_get_the_instance_first() {
+        let mut registry = Registry::new();
+        let receiver = registry.register_variable(Function {
+            function: Box::new(|arguments| {
+                let pointer = arguments[0].as_ref().as_any().downcast_ref::<TslPointer>();
+                Box::new(TslInt {
+                    value: pointer.map_or(-1, |pointer| *pointer.target as i128),
+                })
+            }),
+        });
+        let mut class = ClassBase::new("Thing", Vec::new());
+        class.add_method("id", receiver);
+        let class = registry.register_variable(class);
+        let instance = ClassInstance::new(class, Vec::new(), &mut registry).unwrap();
+        let instance = registry.register_variable(instance);
+        let id = registry.call_method(&instance, "id", vec![]).unwrap();
+        assert_eq!(result(id), *instance as i128);
+    }
+
+    #[test]
+    fn inheritance_cycles_are_errors() {
+        let mut registry = Registry::new();
+        let a = registry.register_variable(ClassBase::new("A", Vec::new()));
+        let mut b = ClassBase::new("B", Vec::new());
+        b.set_parent(a.clone());
+        let b = registry.register_variable(b);
+        let mut a_base = registry.get_class(&a).unwrap().clone();
+        a_base.set_parent(b);
+        registry.set_variable(&a, a_base);
+        assert_eq!(
+            registry.class_fields(&a).unwrap_err(),
+            "Class A inherits from itself"
+        );
+        assert!(registry.lookup_method(&a, "anything").is_err());
+    }
+}
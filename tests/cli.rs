@@ -0,0 +1,194 @@
+// Tests for the tsl binary itself: its exit statuses, what --emit prints and the flags
+// that change what is compiled or how errors look. Each test works in a directory of
+// its own, since transpile and build write their output next to the program.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const PROGRAM: &str = "func main() Int |\n    println(2 + 3)\n    return 3\n|\n";
+const TYPE_ERROR: &str = "func main() Int |\n    let x Int = true\n    return 0\n|\n";
+// Uses nothing from the prelude
+const BARE: &str = "func main() Int |\n    return 4\n|\n";
+
+// A directory for one test, removed when the test ends
+struct TestDir(PathBuf);
+
+impl std::ops::Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// A fresh directory for the test holding the given files
+fn dir_with(test: &str, files: &[(&str, &str)]) -> TestDir {
+    let dir = std::env::temp_dir().join(format!("tsl-cli-{}-{}", std::process::id(), test));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, contents) in files {
+        std::fs::write(dir.join(name), contents).unwrap();
+    }
+    TestDir(dir)
+}
+
+fn tsl(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tsl"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("NO_COLOR")
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn a_program_that_checks_exits_with_0() {
+    let dir = dir_with("check-ok", &[("ok.tsl", PROGRAM)]);
+    let output = tsl(&dir, &["check", "ok.tsl"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn a_type_error_exits_with_1() {
+    let dir = dir_with("check-error", &[("bad.tsl", TYPE_ERROR)]);
+    let output = tsl(&dir, &["check", "bad.tsl"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "Type error: Expected type Integer but found Bool\n"
+    );
+}
+
+#[test]
+fn a_missing_file_exits_with_1() {
+    let dir = dir_with("missing", &[]);
+    let output = tsl(&dir, &["check", "missing.tsl"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn bad_usage_exits_with_2() {
+    let dir = dir_with("usage", &[("ok.tsl", PROGRAM)]);
+    for args in [
+        &[][..],
+        &["frobnicate", "ok.tsl"],
+        &["check", "ok.tsl", "extra.tsl"],
+        &["check", "ok.tsl", "--no-such-flag"],
+        &["check", "ok.tsl", "--emit=bytecode"],
+        &["check", "ok.tsl", "--color=sometimes"],
+        &["check", "ok.tsl", "-q", "-v"],
+        // No filename and no tsl.toml
+        &["build"],
+    ] {
+        let output = tsl(&dir, args);
+        assert_eq!(output.status.code(), Some(2), "tsl {:?}", args);
+        assert!(stderr(&output).contains("Usage: tsl"), "tsl {:?}", args);
+    }
+}
+
+#[test]
+fn help_exits_with_0() {
+    let dir = dir_with("help", &[]);
+    let output = tsl(&dir, &["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("tsl - compiles TSL programs to C\n"));
+}
+
+#[test]
+fn run_and_interpret_exit_with_the_programs_status() {
+    let dir = dir_with("run", &[("ok.tsl", PROGRAM)]);
+    for command in ["run", "interpret"] {
+        let output = tsl(&dir, &[command, "ok.tsl"]);
+        assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "5\n");
+    }
+}
+
+#[test]
+fn emit_prints_each_stage_instead_of_building() {
+    let dir = dir_with("emit", &[("ok.tsl", PROGRAM)]);
+    let emitted = |kind: &str| {
+        let output = tsl(&dir, &["transpile", "ok.tsl", &format!("--emit={}", kind)]);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        stdout(&output)
+    };
+    assert!(emitted("tokens").starts_with("Token { "));
+    assert!(emitted("ast").starts_with("Block {"));
+    assert!(emitted("ir").contains("\npub func main() -> Int {\n"));
+    assert!(emitted("c").contains("int main("));
+    assert!(!dir.join("output.c").exists());
+}
+
+#[test]
+fn emitted_ir_can_be_built_again() {
+    let dir = dir_with("emit-ir", &[("ok.tsl", PROGRAM)]);
+    let output = tsl(&dir, &["transpile", "ok.tsl", "--emit=ir"]);
+    std::fs::write(dir.join("ok.ir"), &output.stdout).unwrap();
+    let output = tsl(&dir, &["run", "ok.ir"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "5\n");
+}
+
+#[test]
+fn no_prelude_leaves_the_prelude_out() {
+    let dir = dir_with("no-prelude", &[("ok.tsl", PROGRAM), ("bare.tsl", BARE)]);
+    let output = tsl(&dir, &["check", "ok.tsl", "--no-prelude"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "Type error: Undefined function: println\n");
+
+    let output = tsl(
+        &dir,
+        &["transpile", "bare.tsl", "--no-prelude", "--emit=ir"],
+    );
+    assert_eq!(
+        stdout(&output),
+        "\npub func main() -> Int {\nbb0:\n    ret 4\n}\n"
+    );
+    let output = tsl(&dir, &["run", "bare.tsl", "--no-prelude"]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+}
+
+#[test]
+fn color_always_colors_errors_and_never_doesnt() {
+    let dir = dir_with("color", &[("bad.tsl", TYPE_ERROR)]);
+    let output = tsl(&dir, &["check", "bad.tsl", "--color=always"]);
+    assert!(
+        stderr(&output).starts_with("\x1b[31mType error"),
+        "{:?}",
+        stderr(&output)
+    );
+    for color in ["--color=never", "--color=auto"] {
+        // stderr isn't a terminal here, so auto doesn't color either
+        let output = tsl(&dir, &["check", "bad.tsl", color]);
+        assert!(!stderr(&output).contains('\x1b'), "{}", color);
+        assert_eq!(output.status.code(), Some(1));
+    }
+}
+
+#[test]
+fn generated_c_builds_with_all_warnings_as_errors() {
+    let program = "let LIMIT = 3\nlet unused = 4\nfunc count(n Int) Int |\n    let mut i = 0\n    let ignored = n * 2\n    while i < n |\n        i = i + 1\n    |\n    return i\n|\nfunc main() Int |\n    let (a, b) = (count(LIMIT), 1)\n    if a == 3 |\n        println(a + b)\n    |\n    return 0\n|\n";
+    let dir = dir_with("werror", &[("warn.tsl", program)]);
+    for optimise in [&[][..], &["-O"]] {
+        let mut args = vec!["run", "warn.tsl", "--cflags", "-Wall -Werror"];
+        args.extend(optimise);
+        let output = tsl(&dir, &args);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "4\n");
+    }
+}